@@ -0,0 +1,793 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::{env, fs, io};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::MetadataCommand;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use terrors::OneOf;
+
+use crate::error::IoError;
+use crate::output::OutputContext;
+use crate::stitch::cargo_status;
+use crate::tools::{self, Tool};
+use crate::util::expand_env_vars;
+use crate::wrapper::PATCHED_CRATES_DIR;
+use crate::{PATCHED_DIR_OVERRIDE_ENV, PATCHES_DIR_ENV, PATCHES_PATH_ENV};
+
+/// Name of the optional per-crate configuration file in `stitches/<crate>/`.
+pub const CONFIG_FILE: &str = "stitch.toml";
+
+/// Per-crate configuration read from `stitches/<crate>/stitch.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct StitchConfig {
+    /// Update the patched directory in place instead of recreating it on every change.
+    pub keep_patched: bool,
+    /// Whether the crate's stitches are applied at all.  Unset means enabled.
+    pub enabled: Option<bool>,
+    /// Stitch a vendored crate's sources in place, instead of a copy of them in
+    /// `target/cargo-stitch/`.  Ignored for other crates.
+    pub no_copy: bool,
+    /// The stitches are for a crates.io dependency, in `stitches/<crate>-<version>/`,
+    /// rather than a workspace member.  Builds then stitch registry crates, as with
+    /// `--external`.
+    pub external: bool,
+    pub order: OrderConfig,
+    pub copy: CopyConfig,
+    pub patch: PatchConfig,
+    pub tools: ToolsConfig,
+    /// The `[features]` section: the Cargo features each stitch, named by its path
+    /// relative to the crate's stitch directory, needs to be applied, e.g.
+    /// `"002-simd.patch" = ["simd"]`.  Takes precedence over the front matter.
+    pub features: BTreeMap<String, Vec<String>>,
+    /// Keys cargo-stitch does not know about, e.g. from a newer version.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+/// The `[order]` section: an explicit application order that overrides the
+/// alphabetical default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct OrderConfig {
+    pub stitches: Vec<String>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+/// The `[copy]` section: how crate sources are copied before stitching.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CopyConfig {
+    /// Hard-link source files instead of copying them.  Unset in a per-crate
+    /// `stitch.toml` means "inherit from `stitches/stitch.toml`".
+    pub hardlink: Option<bool>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+/// The program that applies `.patch` files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatchBackend {
+    /// POSIX `patch`.
+    #[default]
+    Patch,
+    /// `git apply`, which is stricter about context but understands every `git diff`.
+    Git,
+}
+
+/// The `[patch]` section: how the crate's `.patch` files are applied.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PatchConfig {
+    /// The program that applies the patches.  Unset in a per-crate `stitch.toml` means
+    /// "inherit from `stitches/stitch.toml`", and `patch` if that does not set it either.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<PatchBackend>,
+    /// The number of leading path components to strip (`patch -p`).  Unset means 1, as
+    /// for the `a/` and `b/` prefixes of `git diff`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip: Option<u32>,
+    /// Extra arguments passed to every `patch` (or `git apply`) invocation, e.g.
+    /// `["--posix"]`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_args: Vec<String>,
+    #[serde(flatten, skip_serializing)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+/// The `[tools]` section: the oldest version of each external tool the stitches work
+/// with, e.g. `patch = "2.7"`, overriding cargo-stitch's own minimums.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ToolsConfig {
+    pub min_versions: BTreeMap<String, String>,
+}
+
+impl ToolsConfig {
+    /// The oldest supported version of `tool`: the one set here, if any, or else
+    /// cargo-stitch's own minimum.
+    pub fn min_version<'a>(&'a self, tool: &'a Tool) -> &'a str {
+        self.min_versions
+            .get(tool.name)
+            .map_or(tool.min_version, String::as_str)
+    }
+
+    /// Fill in the tools this section does not set from `defaults`, e.g. the
+    /// workspace-wide `stitches/stitch.toml`.
+    pub fn inherit(&mut self, defaults: &ToolsConfig) {
+        for (name, version) in &defaults.min_versions {
+            self.min_versions
+                .entry(name.clone())
+                .or_insert_with(|| version.clone());
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.min_versions.is_empty()
+    }
+}
+
+impl PatchConfig {
+    pub fn backend(&self) -> PatchBackend {
+        self.backend.unwrap_or_default()
+    }
+
+    /// The number of leading path components to strip: `file_strip`, the strip level the
+    /// patch file itself asks for, or `strip`, or 1.
+    pub fn strip_level(&self, file_strip: Option<u32>) -> u32 {
+        file_strip.or(self.strip).unwrap_or(1)
+    }
+
+    /// The arguments to pass to `patch` or `git apply`, besides the patch file and
+    /// directory.  `file_strip` is the strip level the patch file itself asks for, which
+    /// takes precedence over `strip`.
+    pub fn args(&self, file_strip: Option<u32>) -> Vec<String> {
+        let mut args = vec![format!("-p{}", self.strip_level(file_strip))];
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.backend.is_none() && self.strip.is_none() && self.extra_args.is_empty()
+    }
+}
+
+impl StitchConfig {
+    /// Load `stitch.toml` from `dir`, returning `None` if the file does not exist.
+    /// Warnings about unknown keys are printed as `output` says.
+    pub fn load(
+        dir: &Utf8Path,
+        output: &OutputContext,
+    ) -> Result<Option<(Utf8PathBuf, Self)>, OneOf<(IoError,)>> {
+        let path = dir.join(CONFIG_FILE);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(OneOf::new(IoError(e))),
+        };
+
+        let mut config: Self = toml::from_str(&contents).map_err(|e| {
+            OneOf::new(IoError(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid {path}: {e}"),
+            )))
+        })?;
+        if let Some((name, version)) = config
+            .tools
+            .min_versions
+            .iter()
+            .find(|(_, version)| tools::parse_min_version(version).is_none())
+        {
+            return Err(OneOf::new(IoError(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid {path}: `tools.{name} = \"{version}\"` is not a version number"),
+            ))));
+        }
+
+        for key in config.unknown_keys() {
+            cargo_status(
+                output,
+                "Warning",
+                &format!("{path}: unknown key `{key}` is ignored"),
+            );
+        }
+
+        config.expand_env_vars(output);
+
+        Ok(Some((path, config)))
+    }
+
+    /// Expand the environment variables in the string values, as [`expand_env_vars`] does.
+    fn expand_env_vars(&mut self, output: &OutputContext) {
+        expand_all(&mut self.order.stitches, output);
+        expand_all(&mut self.patch.extra_args, output);
+    }
+
+    /// The keys cargo-stitch does not know about, as dotted paths (e.g. `patch.strp`).
+    /// They are ignored rather than rejected, so that a `stitch.toml` written for a newer
+    /// version still works.
+    fn unknown_keys(&self) -> Vec<String> {
+        let sections = [
+            ("", &self.unknown),
+            ("order.", &self.order.unknown),
+            ("copy.", &self.copy.unknown),
+            ("patch.", &self.patch.unknown),
+        ];
+        let unknown_tools = self
+            .tools
+            .min_versions
+            .keys()
+            .filter(|name| !tools::TOOLS.iter().any(|tool| tool.name == name.as_str()))
+            .map(|name| format!("tools.{name}"));
+        sections
+            .into_iter()
+            .flat_map(|(prefix, keys)| keys.keys().map(move |key| format!("{prefix}{key}")))
+            .chain(unknown_tools)
+            .collect()
+    }
+}
+
+/// Workspace-wide settings from `[workspace.metadata.stitch]` in the workspace's
+/// `Cargo.toml`, along with the `[package.metadata.stitch]` of its members.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceMetadata {
+    /// The directory holding the stitch sets, relative to the workspace root.  Unset
+    /// means `stitches`.
+    pub stitches_dir: Option<Utf8PathBuf>,
+    /// The directory holding the patched copies of the crates, relative to the
+    /// workspace root.  Unset means `cargo-stitch` in cargo's target directory.
+    pub patched_dir: Option<Utf8PathBuf>,
+    /// The strip level for `.patch` files whose crate's `stitch.toml` (and
+    /// `stitches/stitch.toml`) do not set `[patch] strip`.  Unset means 1.
+    pub default_strip: Option<u32>,
+    /// Only stitch these crates, if set.
+    pub enabled_packages: Option<Vec<String>>,
+    /// The workspace's `Cargo.toml`, if it has a `[workspace.metadata.stitch]` table.
+    #[serde(skip)]
+    pub manifest: Option<Utf8PathBuf>,
+    /// The `[package.metadata.stitch]` of each workspace member that has one.
+    #[serde(skip)]
+    pub packages: HashMap<String, PackageMetadata>,
+    /// Directories searched for stitch sets before `stitches_dir`, in priority order,
+    /// from `CARGO_STITCH_PATCHES_PATH`.
+    #[serde(skip)]
+    pub patches_path: Vec<Utf8PathBuf>,
+    /// Keys cargo-stitch does not know about, e.g. from a newer version.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+/// Per-crate settings from `[package.metadata.stitch]` in the crate's `Cargo.toml`.
+/// They override the workspace-wide settings, and are overridden by the crate's
+/// `stitch.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PackageMetadata {
+    /// Whether the crate's stitches are applied at all.  Unset means enabled.
+    pub enabled: Option<bool>,
+    /// Extra arguments for every `patch` (or `git apply`) invocation, after those from
+    /// `[patch] extra_args`.
+    pub extra_patch_args: Vec<String>,
+    /// Extra arguments for every `ast-grep scan` invocation.
+    pub extra_sg_args: Vec<String>,
+    /// The number of leading path components to strip (`patch -p`).
+    pub strip: Option<u32>,
+    /// The crate's `Cargo.toml`.
+    #[serde(skip)]
+    pub manifest: Utf8PathBuf,
+    /// Keys cargo-stitch does not know about, e.g. from a newer version.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+impl WorkspaceMetadata {
+    /// Read `[workspace.metadata.stitch]` from the `Cargo.toml` of the workspace at
+    /// `workspace_root`, and `[package.metadata.stitch]` from those of its members, with
+    /// `cargo metadata`.  Warnings about unknown keys are printed as `output` says.
+    ///
+    /// A workspace `cargo metadata` cannot read has no settings: cargo itself reports
+    /// what is wrong with it as soon as it builds.
+    ///
+    /// `CARGO_STITCH_PATCHES_DIR` and `CARGO_STITCH_TARGET_DIR`, if set, take precedence
+    /// over `stitches_dir` and `patched_dir`, and `CARGO_STITCH_PATCHES_PATH` sets
+    /// `patches_path`.
+    pub fn load(
+        workspace_root: &Utf8Path,
+        output: &OutputContext,
+    ) -> Result<Self, OneOf<(IoError,)>> {
+        let mut workspace = Self::load_manifests(workspace_root, output)?;
+        if let Some(dir) = env_dir(PATCHES_DIR_ENV) {
+            workspace.stitches_dir = Some(dir);
+        }
+        if let Some(dir) = env_dir(PATCHED_DIR_OVERRIDE_ENV) {
+            workspace.patched_dir = Some(dir);
+        }
+        if let Some(path) = env::var_os(PATCHES_PATH_ENV) {
+            workspace.patches_path = split_path(&path);
+        }
+        Ok(workspace)
+    }
+
+    fn load_manifests(
+        workspace_root: &Utf8Path,
+        output: &OutputContext,
+    ) -> Result<Self, OneOf<(IoError,)>> {
+        let Ok(metadata) = MetadataCommand::new()
+            .current_dir(workspace_root)
+            .no_deps()
+            .exec()
+        else {
+            return Ok(Self::default());
+        };
+
+        let manifest = workspace_root.join("Cargo.toml");
+        let mut workspace = match parse_stitch_table::<Self>(
+            &metadata.workspace_metadata,
+            "workspace",
+            &manifest,
+            output,
+        )? {
+            Some(workspace) => Self {
+                manifest: Some(manifest),
+                ..workspace
+            },
+            None => Self::default(),
+        };
+
+        for package in metadata.workspace_packages() {
+            if let Some(mut settings) = parse_stitch_table::<PackageMetadata>(
+                &package.metadata,
+                "package",
+                &package.manifest_path,
+                output,
+            )? {
+                settings.manifest = package.manifest_path.clone();
+                workspace
+                    .packages
+                    .insert(package.name.to_string(), settings);
+            }
+        }
+
+        Ok(workspace)
+    }
+
+    /// The directory holding the stitch sets of the workspace at `workspace_root`.  An
+    /// absolute `stitches_dir` is used as is.
+    pub fn stitches_dir(&self, workspace_root: &Utf8Path) -> Utf8PathBuf {
+        workspace_root.join(
+            self.stitches_dir
+                .as_deref()
+                .unwrap_or(Utf8Path::new("stitches")),
+        )
+    }
+
+    /// The directories holding the stitch sets of the workspace at `workspace_root`, in
+    /// priority order: those of `patches_path`, then [`stitches_dir`](Self::stitches_dir).
+    pub fn stitches_dirs(&self, workspace_root: &Utf8Path) -> Vec<Utf8PathBuf> {
+        self.patches_path
+            .iter()
+            .map(|dir| workspace_root.join(dir))
+            .chain([self.stitches_dir(workspace_root)])
+            .collect()
+    }
+
+    /// The directory holding the patched copies of the crates of the workspace at
+    /// `workspace_root`, whose build output goes to `target_dir`.
+    pub fn patched_dir(&self, workspace_root: &Utf8Path, target_dir: &Utf8Path) -> Utf8PathBuf {
+        match &self.patched_dir {
+            Some(dir) => workspace_root.join(dir),
+            None => target_dir.join(PATCHED_CRATES_DIR),
+        }
+    }
+
+    /// Whether `enabled_packages` lets the stitches of `krate` be applied.
+    pub fn is_enabled(&self, krate: &str) -> bool {
+        self.enabled_packages
+            .as_ref()
+            .is_none_or(|packages| packages.iter().any(|p| p == krate))
+    }
+}
+
+/// The directory in the environment variable `var`, if it is set and not empty.
+pub(crate) fn env_dir(var: &str) -> Option<Utf8PathBuf> {
+    env::var(var)
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(Utf8PathBuf::from)
+}
+
+/// The directories in `path`, a list separated as in `PATH`, skipping empty entries and
+/// those that are not UTF-8.
+fn split_path(path: &OsStr) -> Vec<Utf8PathBuf> {
+    env::split_paths(path)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .filter_map(|dir| Utf8PathBuf::from_path_buf(dir).ok())
+        .collect()
+}
+
+/// A `[*.metadata.stitch]` table, which keeps the keys cargo-stitch does not know about and
+/// may refer to environment variables.
+trait StitchTable {
+    fn unknown_keys(&self) -> impl Iterator<Item = &String>;
+
+    /// Expand the environment variables in the string values, as [`expand_env_vars`] does.
+    fn expand_env_vars(&mut self, output: &OutputContext);
+}
+
+impl StitchTable for WorkspaceMetadata {
+    fn unknown_keys(&self) -> impl Iterator<Item = &String> {
+        self.unknown.keys()
+    }
+
+    fn expand_env_vars(&mut self, output: &OutputContext) {
+        for dir in [&mut self.stitches_dir, &mut self.patched_dir]
+            .into_iter()
+            .flatten()
+        {
+            *dir = expand_env_vars(dir.as_str(), output).into();
+        }
+        if let Some(packages) = &mut self.enabled_packages {
+            expand_all(packages, output);
+        }
+    }
+}
+
+impl StitchTable for PackageMetadata {
+    fn unknown_keys(&self) -> impl Iterator<Item = &String> {
+        self.unknown.keys()
+    }
+
+    fn expand_env_vars(&mut self, output: &OutputContext) {
+        expand_all(&mut self.extra_patch_args, output);
+        expand_all(&mut self.extra_sg_args, output);
+    }
+}
+
+/// Expand the environment variables in each of `values`.
+fn expand_all(values: &mut [String], output: &OutputContext) {
+    for value in values {
+        *value = expand_env_vars(value, output);
+    }
+}
+
+/// The settings under `stitch` in `metadata`, the `[<section>.metadata]` table of
+/// `manifest`, or `None` if there are none.
+fn parse_stitch_table<T: DeserializeOwned + StitchTable>(
+    metadata: &serde_json::Value,
+    section: &str,
+    manifest: &Utf8Path,
+    output: &OutputContext,
+) -> Result<Option<T>, OneOf<(IoError,)>> {
+    let Some(value) = metadata.get("stitch") else {
+        return Ok(None);
+    };
+
+    let mut settings = T::deserialize(value).map_err(|e| {
+        OneOf::new(IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid [{section}.metadata.stitch] in {manifest}: {e}"),
+        )))
+    })?;
+
+    for key in settings.unknown_keys() {
+        cargo_status(
+            output,
+            "Warning",
+            &format!("{manifest}: unknown key `{section}.metadata.stitch.{key}` is ignored"),
+        );
+    }
+    settings.expand_env_vars(output);
+
+    Ok(Some(settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_missing_file_returns_none() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        assert!(
+            StitchConfig::load(dir, &OutputContext::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn load_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "[order]\nstitches = [\"002-impl.patch\", \"001-types.patch\"]\n",
+        )
+        .unwrap();
+
+        let (path, config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, dir.join(CONFIG_FILE));
+        assert_eq!(
+            config.order.stitches,
+            vec!["002-impl.patch", "001-types.patch"]
+        );
+    }
+
+    #[test]
+    fn load_copy_hardlink() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join(CONFIG_FILE), "[copy]\nhardlink = false\n").unwrap();
+
+        let (_, config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.copy.hardlink, Some(false));
+    }
+
+    #[test]
+    fn load_empty_file_uses_defaults() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join(CONFIG_FILE), "").unwrap();
+
+        let (_, config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert!(!config.keep_patched);
+        assert!(!config.no_copy);
+        assert!(config.order.stitches.is_empty());
+        assert_eq!(config.copy.hardlink, None);
+    }
+
+    #[test]
+    fn load_patch_options() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "enabled = false\n\n[patch]\nstrip = 2\nextra_args = [\"--posix\"]\n",
+        )
+        .unwrap();
+
+        let (_, config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.enabled, Some(false));
+        assert!(!config.external);
+        assert_eq!(config.patch.strip, Some(2));
+        assert_eq!(config.patch.args(None), vec!["-p2", "--posix"]);
+        assert_eq!(config.patch.args(Some(0)), vec!["-p0", "--posix"]);
+        assert!(config.unknown_keys().is_empty());
+    }
+
+    #[test]
+    fn load_expands_env_vars() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "[order]\nstitches = [\"$$1.patch\"]\n\n\
+             [patch]\nextra_args = [\"-d\", \"${CARGO_MANIFEST_DIR}\", \"-F\", \"$CARGO_STITCH_UNSET_VAR\"]\n",
+        )
+        .unwrap();
+
+        let (_, config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.order.stitches, ["$1.patch"]);
+        assert_eq!(
+            config.patch.extra_args,
+            [
+                "-d",
+                env!("CARGO_MANIFEST_DIR"),
+                "-F",
+                "$CARGO_STITCH_UNSET_VAR"
+            ]
+        );
+    }
+
+    #[test]
+    fn load_patch_backend() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join(CONFIG_FILE), "[patch]\nbackend = \"git\"\n").unwrap();
+
+        let (_, config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.patch.backend(), PatchBackend::Git);
+        assert!(!config.patch.is_default());
+        assert_eq!(PatchConfig::default().backend(), PatchBackend::Patch);
+
+        fs::write(dir.join(CONFIG_FILE), "[patch]\nbackend = \"quilt\"\n").unwrap();
+        assert!(StitchConfig::load(dir, &OutputContext::default()).is_err());
+    }
+
+    #[test]
+    fn load_external() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join(CONFIG_FILE), "external = true\n").unwrap();
+
+        let (_, config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert!(config.external);
+    }
+
+    #[test]
+    fn patch_args_default_to_strip_one() {
+        let config = PatchConfig::default();
+        assert!(config.is_default());
+        assert_eq!(config.args(None), vec!["-p1"]);
+    }
+
+    #[test]
+    fn load_collects_unknown_keys() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "keep_pached = true\n\n[patch]\nstrp = 2\n\n[future]\nkey = 1\n",
+        )
+        .unwrap();
+
+        let (_, config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.enabled, None);
+        assert_eq!(
+            config.unknown_keys(),
+            vec!["future", "keep_pached", "patch.strp"]
+        );
+    }
+
+    #[test]
+    fn load_tools_min_versions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "[tools]\nast-grep = \"0.30\"\nquilt = \"1.0\"\n",
+        )
+        .unwrap();
+
+        let (_, mut config) = StitchConfig::load(dir, &OutputContext::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.tools.min_version(&tools::AST_GREP), "0.30");
+        assert_eq!(config.tools.min_version(&tools::SED), "4.2");
+        assert_eq!(config.unknown_keys(), vec!["tools.quilt"]);
+
+        let mut defaults = ToolsConfig::default();
+        defaults
+            .min_versions
+            .extend([("ast-grep", "0.40"), ("sed", "4.8")].map(|(k, v)| (k.into(), v.into())));
+        config.tools.inherit(&defaults);
+        assert_eq!(config.tools.min_version(&tools::AST_GREP), "0.30");
+        assert_eq!(config.tools.min_version(&tools::SED), "4.8");
+
+        fs::write(dir.join(CONFIG_FILE), "[tools]\nsed = \"latest\"\n").unwrap();
+        let err = StitchConfig::load(dir, &OutputContext::default()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("`tools.sed = \"latest\"` is not a version number")
+        );
+    }
+
+    #[test]
+    fn load_invalid_toml_fails() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join(CONFIG_FILE), "[order\n").unwrap();
+
+        let err = StitchConfig::load(dir, &OutputContext::default()).unwrap_err();
+        assert!(err.to_string().contains("stitch.toml"));
+    }
+
+    #[test]
+    fn workspace_metadata_from_cargo_toml() {
+        let manifest = Utf8Path::new("/ws/Cargo.toml");
+        let root = Utf8Path::new("/ws");
+        let target = Utf8Path::new("/ws/target");
+        let parse = |value: &serde_json::Value| {
+            parse_stitch_table::<WorkspaceMetadata>(
+                value,
+                "workspace",
+                manifest,
+                &OutputContext::default(),
+            )
+        };
+
+        assert!(parse(&serde_json::Value::Null).unwrap().is_none());
+        let metadata = WorkspaceMetadata::default();
+        assert_eq!(metadata.stitches_dir(root), "/ws/stitches");
+        assert_eq!(
+            metadata.patched_dir(root, target),
+            "/ws/target/cargo-stitch"
+        );
+        assert!(metadata.is_enabled("crate-a"));
+
+        let value = serde_json::json!({
+            "stitch": {
+                "stitches_dir": "patches",
+                "patched_dir": "build/stitched",
+                "default_strip": 0,
+                "enabled_packages": ["crate-a"],
+                "strip": 2,
+            }
+        });
+        let metadata = parse(&value).unwrap().unwrap();
+        assert_eq!(metadata.stitches_dir(root), "/ws/patches");
+        assert_eq!(metadata.patched_dir(root, target), "/ws/build/stitched");
+        assert_eq!(metadata.default_strip, Some(0));
+        assert!(metadata.is_enabled("crate-a"));
+        assert!(!metadata.is_enabled("crate-b"));
+        assert_eq!(metadata.unknown_keys().collect::<Vec<_>>(), ["strip"]);
+
+        let value = serde_json::json!({
+            "stitch": {
+                "stitches_dir": "${CARGO_MANIFEST_DIR}/stitches",
+                "enabled_packages": ["$CARGO_STITCH_UNSET_VAR", "price-$$"],
+            }
+        });
+        let metadata = parse(&value).unwrap().unwrap();
+        assert_eq!(
+            metadata.stitches_dir(root),
+            format!("{}/stitches", env!("CARGO_MANIFEST_DIR"))
+        );
+        assert!(metadata.is_enabled("$CARGO_STITCH_UNSET_VAR"));
+        assert!(metadata.is_enabled("price-$"));
+
+        let value = serde_json::json!({ "stitch": { "default_strip": "one" } });
+        assert!(parse(&value).is_err());
+    }
+
+    #[test]
+    fn package_metadata_from_cargo_toml() {
+        let value = serde_json::json!({
+            "docs": { "rs": { "all-features": true } },
+            "stitch": {
+                "enabled": false,
+                "extra_patch_args": ["--posix"],
+                "extra_sg_args": ["--no-ignore", "hidden"],
+                "strip": 0,
+            }
+        });
+        let metadata = parse_stitch_table::<PackageMetadata>(
+            &value,
+            "package",
+            Utf8Path::new("/ws/crate-a/Cargo.toml"),
+            &OutputContext::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(metadata.enabled, Some(false));
+        assert_eq!(metadata.extra_patch_args, ["--posix"]);
+        assert_eq!(metadata.extra_sg_args, ["--no-ignore", "hidden"]);
+        assert_eq!(metadata.strip, Some(0));
+        assert_eq!(metadata.unknown_keys().count(), 0);
+    }
+
+    #[test]
+    fn split_path_skips_empty_entries() {
+        let path = env::join_paths(["/home/me/.cargo-stitch", "", "shared"]).unwrap();
+        assert_eq!(
+            split_path(&path),
+            [
+                Utf8PathBuf::from("/home/me/.cargo-stitch"),
+                Utf8PathBuf::from("shared")
+            ]
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use camino::Utf8PathBuf;
 
 #[derive(Debug)]
@@ -11,16 +13,69 @@ impl std::fmt::Display for IoError {
 
 pub struct PatchFailed {
     pub file: Utf8PathBuf,
+    /// How many hunks `patch` could not apply, over every file of the patch; 0 if it
+    /// did not say, e.g. because a file to patch is missing.
+    pub failed_hunks: u32,
+    /// How many hunks `patch` tried to apply to the files that had failed hunks.
+    pub total_hunks: u32,
+    /// The `.rej` files `patch` saved the failed hunks to, relative to the directory the
+    /// patch was applied to, or where the wrapper moved them to.
+    pub reject_files: Vec<Utf8PathBuf>,
     pub output: String,
 }
 
+impl PatchFailed {
+    /// The failure to apply `file`, with the hunk counts and reject files read from
+    /// `output`, what `patch` printed, e.g.
+    /// `1 out of 3 hunks FAILED -- saving rejects to file src/lib.rs.rej`.
+    pub fn new(file: Utf8PathBuf, output: String) -> Self {
+        let mut failed_hunks = 0;
+        let mut total_hunks = 0;
+        let mut reject_files = Vec::new();
+        for line in output.lines() {
+            let Some((counts, rest)) = line.split_once(" FAILED") else {
+                continue;
+            };
+            let words: Vec<_> = counts.split_whitespace().collect();
+            let [failed, "out", "of", total, "hunk" | "hunks"] = words.as_slice() else {
+                continue;
+            };
+            let (Ok(failed), Ok(total)) = (failed.parse::<u32>(), total.parse::<u32>()) else {
+                continue;
+            };
+            failed_hunks += failed;
+            total_hunks += total;
+            if let Some(reject) = rest.strip_prefix(" -- saving rejects to file ") {
+                reject_files.push(Utf8PathBuf::from(reject.trim()));
+            }
+        }
+
+        Self {
+            file,
+            failed_hunks,
+            total_hunks,
+            reject_files,
+            output,
+        }
+    }
+}
+
 impl std::fmt::Display for PatchFailed {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "failed to apply patch: {}", self.file)?;
-        if !self.output.is_empty() {
-            write!(f, "\n{}", self.output.trim_end())?;
+        if self.total_hunks > 0 {
+            write!(
+                f,
+                " ({} of {} hunks failed",
+                self.failed_hunks, self.total_hunks
+            )?;
+            if !self.reject_files.is_empty() {
+                let rejects: Vec<_> = self.reject_files.iter().map(|r| r.as_str()).collect();
+                write!(f, ", rejects saved to {}", rejects.join(", "))?;
+            }
+            write!(f, ")")?;
         }
-        Ok(())
+        write_tool_output(f, &self.output)
     }
 }
 
@@ -32,11 +87,40 @@ pub struct AstGrepFailed {
 impl std::fmt::Display for AstGrepFailed {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "failed to apply ast-grep rule: {}", self.file)?;
-        if !self.output.is_empty() {
-            write!(f, "\n{}", self.output.trim_end())?;
+        write_tool_output(f, &self.output)
+    }
+}
+
+/// A stitch that is a script for an interpreter, such as a `.sed` file, failed to run.
+pub struct ScriptFailed {
+    pub file: Utf8PathBuf,
+    /// The program that ran the script, e.g. `sed`.
+    pub interpreter: &'static str,
+    pub output: String,
+}
+
+impl std::fmt::Display for ScriptFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to run {} script: {}",
+            self.interpreter, self.file
+        )?;
+        write_tool_output(f, &self.output)
+    }
+}
+
+/// Write what a failed tool printed under the line naming the stitch, indented so that it
+/// reads as part of the same error.
+fn write_tool_output(f: &mut std::fmt::Formatter<'_>, output: &str) -> std::fmt::Result {
+    for line in output.trim_end().lines() {
+        if line.is_empty() {
+            writeln!(f)?;
+        } else {
+            write!(f, "\n    {line}")?;
         }
-        Ok(())
     }
+    Ok(())
 }
 
 pub struct CargoFailed(pub i32);
@@ -67,18 +151,91 @@ impl std::fmt::Display for MissingTool {
     }
 }
 
-pub struct MissingWorkspaceRoot(pub Utf8PathBuf);
+/// An installed tool is older than the stitches need, e.g. an `ast-grep` that predates
+/// a rule's syntax.  `found` is the tool's `--version` line.
+#[derive(Debug)]
+pub struct IncompatibleToolVersion {
+    pub tool: &'static str,
+    pub found: String,
+    pub required: String,
+}
 
-impl std::fmt::Display for MissingWorkspaceRoot {
+impl std::fmt::Display for IncompatibleToolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is too old: found {}, but the stitches need {} {} or later",
+            self.tool, self.found, self.tool, self.required
+        )
+    }
+}
+
+impl std::error::Error for IncompatibleToolVersion {}
+
+/// For the error types that have no room left for an [`IncompatibleToolVersion`], as for
+/// [`ChecksumMismatch`].
+impl From<IncompatibleToolVersion> for IoError {
+    fn from(e: IncompatibleToolVersion) -> Self {
+        IoError(std::io::Error::new(std::io::ErrorKind::Unsupported, e))
+    }
+}
+
+/// A tool applying a stitch did not finish within `CARGO_STITCH_TIMEOUT_SECS`, and was
+/// killed, e.g. because it waited for input that never came.
+#[derive(Debug)]
+pub struct StitchTimeout {
+    pub tool: &'static str,
+    pub path: PathBuf,
+    pub elapsed_secs: u64,
+}
+
+impl std::fmt::Display for StitchTimeout {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "could not find workspace root from manifest directory: {}",
+            "{} was killed after {}s applying {}; set CARGO_STITCH_TIMEOUT_SECS to give it \
+             longer, or to 0 for no limit",
+            self.tool,
+            self.elapsed_secs,
+            self.path.display()
+        )
+    }
+}
+
+impl std::error::Error for StitchTimeout {}
+
+/// For the error types that have no room left for a [`StitchTimeout`], as for
+/// [`ChecksumMismatch`].
+impl From<StitchTimeout> for IoError {
+    fn from(e: StitchTimeout) -> Self {
+        IoError(std::io::Error::new(std::io::ErrorKind::TimedOut, e))
+    }
+}
+
+/// The built-in patch applier cannot apply a patch, which is then left to `patch`, for
+/// the reason given, e.g. "it has `rename from` lines".
+#[derive(Debug)]
+pub struct UnsupportedPatchFormat(pub String);
+
+impl std::fmt::Display for UnsupportedPatchFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the built-in patch applier cannot apply this patch: {}",
             self.0
         )
     }
 }
 
+/// No cargo workspace contains the directory cargo-stitch was run from, which it holds.
+pub struct MissingWorkspaceRoot(pub Utf8PathBuf);
+
+impl std::fmt::Display for MissingWorkspaceRoot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not find a cargo workspace containing: {}", self.0)
+    }
+}
+
 pub struct MissingStitchSet(pub String);
 
 impl std::fmt::Display for MissingStitchSet {
@@ -87,16 +244,44 @@ impl std::fmt::Display for MissingStitchSet {
     }
 }
 
+/// A stitch file does not have the SHA-256 its crate's `.checksums` file lists for it.
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub file: Utf8PathBuf,
+    pub expected: String,
+    pub got: String,
+}
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "checksum mismatch for {}: expected {}, got {}",
+            self.file, self.expected, self.got
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// For the error types that have no room left for a [`ChecksumMismatch`], which stays
+/// available as the source of the I/O error.
+impl From<ChecksumMismatch> for IoError {
+    fn from(e: ChecksumMismatch) -> Self {
+        IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn patch_failed_display_empty_output() {
-        let err = PatchFailed {
-            file: Utf8PathBuf::from("stitches/default/crate-a/001.patch"),
-            output: String::new(),
-        };
+        let err = PatchFailed::new(
+            Utf8PathBuf::from("stitches/default/crate-a/001.patch"),
+            String::new(),
+        );
         assert_eq!(
             err.to_string(),
             "failed to apply patch: stitches/default/crate-a/001.patch"
@@ -105,13 +290,49 @@ mod tests {
 
     #[test]
     fn patch_failed_display_with_output() {
-        let err = PatchFailed {
-            file: Utf8PathBuf::from("fix.patch"),
-            output: "Hunk #1 FAILED\n".to_string(),
-        };
+        let err = PatchFailed::new(
+            Utf8PathBuf::from("fix.patch"),
+            "Hunk #1 FAILED\n".to_string(),
+        );
         assert_eq!(
             err.to_string(),
-            "failed to apply patch: fix.patch\nHunk #1 FAILED"
+            "failed to apply patch: fix.patch\n    Hunk #1 FAILED"
+        );
+    }
+
+    #[test]
+    fn patch_failed_display_indents_every_line() {
+        let err = PatchFailed::new(
+            Utf8PathBuf::from("fix.patch"),
+            "patching file src/lib.rs\nHunk #1 FAILED at 1.\n\n1 out of 1 hunk FAILED\n"
+                .to_string(),
+        );
+        assert_eq!(
+            err.to_string(),
+            "failed to apply patch: fix.patch (1 of 1 hunks failed)\n    patching file src/lib.rs\n    Hunk #1 FAILED at 1.\n\n    1 out of 1 hunk FAILED"
+        );
+    }
+
+    #[test]
+    fn patch_failed_reads_hunks_and_rejects() {
+        let err = PatchFailed::new(
+            Utf8PathBuf::from("fix.patch"),
+            "1 out of 3 hunks FAILED -- saving rejects to file src/lib.rs.rej\n\
+             2 out of 2 hunks FAILED -- saving rejects to file src/main.rs.rej\n"
+                .to_string(),
+        );
+        assert_eq!((err.failed_hunks, err.total_hunks), (3, 5));
+        assert_eq!(
+            err.reject_files,
+            [
+                Utf8PathBuf::from("src/lib.rs.rej"),
+                Utf8PathBuf::from("src/main.rs.rej")
+            ]
+        );
+        assert_eq!(
+            err.to_string().lines().next().unwrap(),
+            "failed to apply patch: fix.patch (3 of 5 hunks failed, rejects saved to \
+             src/lib.rs.rej, src/main.rs.rej)"
         );
     }
 
@@ -132,7 +353,20 @@ mod tests {
         };
         assert_eq!(
             err.to_string(),
-            "failed to apply ast-grep rule: rule.yaml\nerror details"
+            "failed to apply ast-grep rule: rule.yaml\n    error details"
+        );
+    }
+
+    #[test]
+    fn script_failed_display_with_output() {
+        let err = ScriptFailed {
+            file: Utf8PathBuf::from("001-rename.sed"),
+            interpreter: "sed",
+            output: "sed: -e expression #1, char 3: unterminated `s' command\n".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "failed to run sed script: 001-rename.sed\n    sed: -e expression #1, char 3: unterminated `s' command"
         );
     }
 
@@ -160,12 +394,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn incompatible_tool_version_display() {
+        let err = IncompatibleToolVersion {
+            tool: "ast-grep",
+            found: "ast-grep 0.19.3".to_string(),
+            required: "0.20.0".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "ast-grep is too old: found ast-grep 0.19.3, but the stitches need ast-grep 0.20.0 or later"
+        );
+    }
+
     #[test]
     fn missing_workspace_root_display() {
         let err = MissingWorkspaceRoot(Utf8PathBuf::from("/tmp/foo"));
         assert_eq!(
             err.to_string(),
-            "could not find workspace root from manifest directory: /tmp/foo"
+            "could not find a cargo workspace containing: /tmp/foo"
         );
     }
 
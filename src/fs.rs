@@ -1,10 +1,38 @@
-use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::symlink;
+use std::process::Command;
 use std::time::SystemTime;
+use std::{env, fs, thread};
 
-use camino::{Utf8Path, Utf8PathBuf};
-use cargo_metadata::MetadataCommand;
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use cargo_metadata::{MetadataCommand, Package};
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+use tempfile::TempDir;
 
+use crate::JOBS_ENV;
+use crate::hash::STAMP_FILE;
+
+/// Set by newer versions of Cargo to the root of the workspace being built.
+const CARGO_WORKSPACE_ROOT_ENV: &str = "CARGO_WORKSPACE_ROOT";
+
+/// Find the root of the workspace containing `manifest_dir`.
+///
+/// `$CARGO_WORKSPACE_ROOT` is used if it holds `manifest_dir`.  Otherwise the common
+/// layouts are recognised by reading the `Cargo.toml` files above `manifest_dir`; anything
+/// else (e.g. an explicit `package.workspace` path) is left to `cargo metadata`.
 pub fn find_workspace_root(manifest_dir: &Utf8Path) -> Option<Utf8PathBuf> {
+    if let Ok(root) = std::env::var(CARGO_WORKSPACE_ROOT_ENV)
+        && let Some(root) = workspace_root_from_env(&root, manifest_dir)
+    {
+        return Some(root);
+    }
+    if let Some(root) = find_workspace_root_fast(manifest_dir) {
+        return Some(root);
+    }
+
     let metadata = MetadataCommand::new()
         .current_dir(manifest_dir)
         .no_deps()
@@ -14,7 +42,142 @@ pub fn find_workspace_root(manifest_dir: &Utf8Path) -> Option<Utf8PathBuf> {
     Some(metadata.workspace_root)
 }
 
-pub fn copy_dir_recursive(src: &Utf8Path, dst: &Utf8Path) -> std::io::Result<()> {
+/// The workspace root `root` from `$CARGO_WORKSPACE_ROOT`, if it is one that holds
+/// `manifest_dir`: a value left over from another workspace is ignored.
+fn workspace_root_from_env(root: &str, manifest_dir: &Utf8Path) -> Option<Utf8PathBuf> {
+    let root = Utf8Path::new(root);
+    (root.is_absolute() && manifest_dir.starts_with(root) && root.join("Cargo.toml").is_file())
+        .then(|| root.to_owned())
+}
+
+/// The parts of a `Cargo.toml` that decide where its workspace root is.
+#[derive(Deserialize)]
+struct CargoManifest {
+    workspace: Option<IgnoredAny>,
+    package: Option<CargoManifestPackage>,
+}
+
+#[derive(Deserialize)]
+struct CargoManifestPackage {
+    workspace: Option<String>,
+}
+
+/// Find the workspace root without running `cargo metadata`: the nearest directory at or
+/// above `dir` whose `Cargo.toml` has a `[workspace]` table, or, if there is none, the
+/// nearest package, which is then a workspace of its own.
+///
+/// Returns `None` if there is no `Cargo.toml` at all, or if one cannot be read or parsed,
+/// or sets `package.workspace`.
+fn find_workspace_root_fast(dir: &Utf8Path) -> Option<Utf8PathBuf> {
+    let mut nearest_package = None;
+
+    for ancestor in dir.ancestors() {
+        let manifest = ancestor.join("Cargo.toml");
+        if !manifest.is_file() {
+            continue;
+        }
+
+        let contents = fs::read_to_string(&manifest).ok()?;
+        let manifest: CargoManifest = toml::from_str(&contents).ok()?;
+
+        if manifest.workspace.is_some() {
+            return Some(ancestor.to_owned());
+        }
+        if let Some(package) = manifest.package
+            && nearest_package.is_none()
+        {
+            if package.workspace.is_some() {
+                return None;
+            }
+            nearest_package = Some(ancestor.to_owned());
+        }
+    }
+
+    nearest_package
+}
+
+/// `$CARGO_HOME`, defaulting to `~/.cargo`.
+pub fn cargo_home() -> io::Result<Utf8PathBuf> {
+    if let Ok(home) = std::env::var("CARGO_HOME") {
+        return Ok(Utf8PathBuf::from(home));
+    }
+
+    let home = std::env::var("HOME").map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "neither CARGO_HOME nor HOME is set",
+        )
+    })?;
+    Ok(Utf8PathBuf::from(home).join(".cargo"))
+}
+
+/// Returns the workspace members of the workspace rooted at `workspace_root`.
+pub fn workspace_packages(workspace_root: &Utf8Path) -> io::Result<Vec<Package>> {
+    let metadata = MetadataCommand::new()
+        .current_dir(workspace_root)
+        .no_deps()
+        .exec()
+        .map_err(|e| io::Error::other(format!("cargo metadata failed: {e}")))?;
+
+    Ok(metadata.workspace_packages().into_iter().cloned().collect())
+}
+
+/// Returns the packages of the workspace rooted at `workspace_root` and all of their
+/// dependencies.
+pub fn dependency_packages(workspace_root: &Utf8Path) -> io::Result<Vec<Package>> {
+    let metadata = MetadataCommand::new()
+        .current_dir(workspace_root)
+        .exec()
+        .map_err(|e| io::Error::other(format!("cargo metadata failed: {e}")))?;
+
+    Ok(metadata.packages)
+}
+
+/// Returns the dependencies of the workspace rooted at `workspace_root` whose sources are
+/// in cargo's registry cache, like those from crates.io.
+pub fn registry_packages(workspace_root: &Utf8Path) -> io::Result<Vec<Package>> {
+    let registry_src = cargo_home()?.join("registry").join("src");
+
+    Ok(dependency_packages(workspace_root)?
+        .into_iter()
+        .filter(|p| p.manifest_path.starts_with(&registry_src))
+        .collect())
+}
+
+/// Create a temporary directory, returning the guard along with its UTF-8 path.
+pub fn temp_dir() -> io::Result<(TempDir, Utf8PathBuf)> {
+    let dir = tempfile::tempdir()?;
+    let path = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).map_err(|p| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("temporary directory is not valid UTF-8: {}", p.display()),
+        )
+    })?;
+    Ok((dir, path))
+}
+
+/// Options controlling how `copy_dir_recursive` copies files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CopyOptions {
+    /// Hard-link files instead of copying them, falling back to a copy if linking fails
+    /// (e.g. across file systems).  Only safe if nothing writes to the copies in place.
+    pub hardlink: bool,
+}
+
+/// Copy the crate sources in `src` to `dst`, skipping `target/` and `.git/`.
+///
+/// Symlinks are recreated rather than followed.  A relative link that leads out of `src`
+/// is made absolute, so that it still points to the same file from `dst`.
+pub fn copy_dir_recursive(src: &Utf8Path, dst: &Utf8Path, options: &CopyOptions) -> io::Result<()> {
+    copy_tree(src, src, dst, options)
+}
+
+fn copy_tree(
+    root: &Utf8Path,
+    src: &Utf8Path,
+    dst: &Utf8Path,
+    options: &CopyOptions,
+) -> io::Result<()> {
     fs::create_dir_all(dst)?;
 
     for entry in src.read_dir_utf8()? {
@@ -28,61 +191,406 @@ pub fn copy_dir_recursive(src: &Utf8Path, dst: &Utf8Path) -> std::io::Result<()>
 
         let src_path = entry.path();
         let dst_path = dst.join(file_name);
-
-        if src_path.is_dir() {
-            copy_dir_recursive(src_path, &dst_path)?;
-        } else {
-            fs::copy(src_path, &dst_path)?;
+        let file_type = entry.file_type()?;
+
+        if file_type.is_symlink() {
+            symlink(symlink_target(root, src_path)?, &dst_path)?;
+        } else if file_type.is_dir() {
+            copy_tree(root, src_path, &dst_path, options)?;
+        } else if !options.hardlink || fs::hard_link(src_path, &dst_path).is_err() {
+            copy_file(src_path, &dst_path)?;
         }
     }
     Ok(())
 }
 
-/// Sentinel file written inside `patched_dir` after a successful patch run.
-/// Its mtime is used to determine whether re-patching is needed.
-const SENTINEL_FILE: &str = ".cargo-stitch";
+/// The number of files from which [`copy_sources`] copies them in parallel.
+const PARALLEL_COPY_MIN_FILES: usize = 256;
+
+/// Copy the crate sources in `src` to `dst` as [`copy_dir_recursive`] does, but with
+/// [`copy_dir_recursive_parallel`] if there are enough files to make it worthwhile and
+/// `CARGO_STITCH_JOBS` allows more than one thread.
+pub fn copy_sources(src: &Utf8Path, dst: &Utf8Path, options: &CopyOptions) -> io::Result<()> {
+    let jobs = copy_jobs();
+    if jobs > 1 && has_files(src, PARALLEL_COPY_MIN_FILES)? {
+        copy_dir_recursive_parallel(src, dst, options, jobs)
+    } else {
+        copy_dir_recursive(src, dst, options)
+    }
+}
+
+/// Whether there are at least `count` files under `src`, outside `target/` and `.git/`.
+/// Stops looking as soon as it has found them.
+fn has_files(src: &Utf8Path, count: usize) -> io::Result<bool> {
+    fn count_files(dir: &Utf8Path, remaining: &mut usize) -> io::Result<()> {
+        for entry in dir.read_dir_utf8()? {
+            if *remaining == 0 {
+                break;
+            }
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if entry.file_name() != "target" && entry.file_name() != ".git" {
+                    count_files(entry.path(), remaining)?;
+                }
+            } else if !file_type.is_symlink() {
+                *remaining -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    let mut remaining = count;
+    count_files(src, &mut remaining)?;
+    Ok(remaining == 0)
+}
+
+/// Like [`copy_dir_recursive`], but with the files copied concurrently on a pool of
+/// `parallelism` threads, once every directory has been created.
+pub fn copy_dir_recursive_parallel(
+    src: &Utf8Path,
+    dst: &Utf8Path,
+    options: &CopyOptions,
+    parallelism: usize,
+) -> io::Result<()> {
+    CopyPlan::collect(src, dst)?.execute(options, parallelism)
+}
+
+/// The number of threads to copy sources and stitch crates on, from `CARGO_STITCH_JOBS`:
+/// the number of logical CPUs by default.  A value that is not a positive number is ignored.
+pub fn copy_jobs() -> usize {
+    env::var(JOBS_ENV)
+        .ok()
+        .and_then(|jobs| jobs.trim().parse().ok())
+        .filter(|&jobs| jobs > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, Into::into))
+}
+
+/// What [`copy_dir_recursive`] would do, as lists: the directories to create, in an order
+/// where each comes after its parent, the symlinks to recreate and the files to copy.
+#[derive(Debug, Default)]
+struct CopyPlan {
+    dirs: Vec<Utf8PathBuf>,
+    symlinks: Vec<(Utf8PathBuf, Utf8PathBuf)>,
+    files: Vec<(Utf8PathBuf, Utf8PathBuf)>,
+}
+
+impl CopyPlan {
+    fn collect(src: &Utf8Path, dst: &Utf8Path) -> io::Result<Self> {
+        let mut plan = Self::default();
+        plan.collect_tree(src, src, dst)?;
+        Ok(plan)
+    }
+
+    fn collect_tree(&mut self, root: &Utf8Path, src: &Utf8Path, dst: &Utf8Path) -> io::Result<()> {
+        self.dirs.push(dst.to_owned());
+
+        for entry in src.read_dir_utf8()? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            if file_name == "target" || file_name == ".git" {
+                continue;
+            }
+
+            let src_path = entry.path();
+            let dst_path = dst.join(file_name);
+            let file_type = entry.file_type()?;
+
+            if file_type.is_symlink() {
+                self.symlinks
+                    .push((symlink_target(root, src_path)?, dst_path));
+            } else if file_type.is_dir() {
+                self.collect_tree(root, src_path, &dst_path)?;
+            } else {
+                self.files.push((src_path.to_owned(), dst_path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Create the directories and symlinks in order, then copy the files on a pool of
+    /// `parallelism` threads.
+    fn execute(self, options: &CopyOptions, parallelism: usize) -> io::Result<()> {
+        for dir in &self.dirs {
+            fs::create_dir_all(dir)?;
+        }
+        for (target, link) in &self.symlinks {
+            symlink(target, link)?;
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(parallelism)
+            .build()
+            .map_err(io::Error::other)?;
+        pool.install(|| {
+            self.files.par_iter().try_for_each(|(src, dst)| {
+                if options.hardlink && fs::hard_link(src, dst).is_ok() {
+                    return Ok(());
+                }
+                copy_file(src, dst)
+            })
+        })
+    }
+}
+
+/// Copy the file `src` to `dst`, as a copy-on-write clone where the file system supports
+/// it, and as a plain copy otherwise.
+#[cfg(feature = "reflink")]
+fn copy_file(src: &Utf8Path, dst: &Utf8Path) -> io::Result<()> {
+    reflink_copy::reflink_or_copy(src, dst)?;
+    // Keep the permissions, as `fs::copy` does.
+    fs::set_permissions(dst, fs::metadata(src)?.permissions())
+}
+
+#[cfg(not(feature = "reflink"))]
+fn copy_file(src: &Utf8Path, dst: &Utf8Path) -> io::Result<()> {
+    fs::copy(src, dst).map(|_| ())
+}
+
+/// Create a symlink at `link` pointing to `target`.  Windows tells file symlinks from
+/// directory ones, so `target` (relative to `link`'s directory) is looked at to pick one.
+#[cfg(windows)]
+fn symlink(target: impl AsRef<Utf8Path>, link: impl AsRef<Utf8Path>) -> io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    let (target, link) = (target.as_ref(), link.as_ref());
+    let resolved = link.parent().unwrap_or(link).join(target);
+    if resolved.is_dir() {
+        symlink_dir(target, link)
+    } else {
+        symlink_file(target, link)
+    }
+}
 
-/// Returns `true` if `patched_dir` was patched more recently than any file in
-/// `manifest_dir` (checked recursively) or any of the given `stitch_files`.
+/// Run `cmd` in place of the current process, which ends with its exit code.  Returns
+/// only if `cmd` cannot be run.
 ///
-/// Falls back to `false` on any I/O error to guarantee correctness.
-pub fn patched_dir_is_up_to_date(
-    patched_dir: &Utf8Path,
-    manifest_dir: &Utf8Path,
-    stitch_files: &[&Utf8Path],
-) -> bool {
-    let sentinel = patched_dir.join(SENTINEL_FILE);
-    let Ok(meta) = fs::metadata(sentinel.as_std_path()) else {
-        return false;
-    };
-    let Ok(sentinel_mtime) = meta.modified() else {
-        return false;
-    };
+/// On Unix this is `exec`, so that the process cargo started is `cmd` itself.  Windows
+/// has no `exec`: `cmd` is spawned and waited for instead.
+#[cfg(unix)]
+pub(crate) fn exec_or_spawn_and_exit(cmd: &mut Command) -> io::Error {
+    use std::os::unix::process::CommandExt;
+
+    cmd.exec()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn exec_or_spawn_and_exit(cmd: &mut Command) -> io::Error {
+    match cmd.spawn().and_then(|mut child| child.wait()) {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => e,
+    }
+}
+
+/// The target for a copy of `link`, a symlink under `root`: the same as that of `link`,
+/// unless it is a relative path leading out of `root`, which is resolved to an absolute
+/// one.
+fn symlink_target(root: &Utf8Path, link: &Utf8Path) -> io::Result<Utf8PathBuf> {
+    let target = link.read_link_utf8()?;
+    if target.is_absolute() {
+        return Ok(target);
+    }
+
+    let resolved = normalize_lexically(&link.parent().unwrap_or(root).join(&target));
+    if resolved.starts_with(normalize_lexically(root)) {
+        Ok(target)
+    } else {
+        Ok(resolved)
+    }
+}
+
+/// `path` with its `.` and `..` components resolved, without following symlinks.
+fn normalize_lexically(path: &Utf8Path) -> Utf8PathBuf {
+    let mut normalized = Utf8PathBuf::new();
+    for component in path.components() {
+        match component {
+            Utf8Component::CurDir => {}
+            Utf8Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Make `dst` mirror `src`, touching only what differs.
+///
+/// Files whose content already matches are left alone so their mtimes are preserved,
+/// which lets rustc's incremental compilation reuse work from the previous build.
+/// Changed files are written to a temporary sibling and renamed into place, so a
+/// concurrent reader never sees a partially written file.  Entries in `dst` that no
+/// longer exist in `src` are removed, except for the stamp file.
+pub fn sync_dir(src: &Utf8Path, dst: &Utf8Path) -> io::Result<()> {
+    fs::create_dir_all(dst)?;
+
+    for entry in dst.read_dir_utf8()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == STAMP_FILE {
+            continue;
+        }
+
+        // Symlinks count as files here, whatever they point to.
+        let is_dir = entry.file_type()?.is_dir();
+        let in_src = fs::symlink_metadata(src.join(name));
+        if !in_src.is_ok_and(|meta| meta.is_dir() == is_dir) {
+            if is_dir {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+
+    for entry in src.read_dir_utf8()? {
+        let entry = entry?;
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        let tmp_path = dst.join(format!(".{}.{}", entry.file_name(), std::process::id()));
+        let file_type = entry.file_type()?;
+
+        if file_type.is_symlink() {
+            let target = src_path.read_link_utf8()?;
+            if dst_path.read_link_utf8().ok() != Some(target.clone()) {
+                symlink(&target, &tmp_path)?;
+                fs::rename(&tmp_path, &dst_path)?;
+            }
+        } else if file_type.is_dir() {
+            sync_dir(src_path, &dst_path)?;
+        } else if !fs::symlink_metadata(&dst_path).is_ok_and(|meta| meta.is_file())
+            || fs::read(&dst_path).ok() != Some(fs::read(src_path)?)
+        {
+            fs::copy(src_path, &tmp_path)?;
+            fs::rename(&tmp_path, &dst_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Make the files at `paths`, relative to both `src` and `dst`, in `dst` match those in
+/// `src`: write those `src` has, as [`sync_dir`] does, and remove the others.
+pub fn sync_files(src: &Utf8Path, dst: &Utf8Path, paths: &[Utf8PathBuf]) -> io::Result<()> {
+    for path in paths {
+        let src_path = src.join(path);
+        let dst_path = dst.join(path);
+        if fs::symlink_metadata(&src_path).is_ok() {
+            if let Some(parent) = dst_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let tmp_path = dst_path.with_file_name(format!(
+                ".{}.{}",
+                path.file_name().unwrap_or_default(),
+                std::process::id()
+            ));
+            fs::copy(&src_path, &tmp_path)?;
+            fs::rename(&tmp_path, &dst_path)?;
+        } else if fs::symlink_metadata(&dst_path).is_ok() {
+            fs::remove_file(&dst_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compare two directory trees byte for byte, returning the paths (relative to the
+/// roots, sorted) of files that differ or exist on only one side.  Entries whose file
+/// name is in `ignore` are skipped at every level.
+pub fn compare_dirs(a: &Utf8Path, b: &Utf8Path, ignore: &[&str]) -> io::Result<Vec<Utf8PathBuf>> {
+    let mut a_files = Vec::new();
+    let mut b_files = Vec::new();
+    list_files(a, a, ignore, &mut a_files)?;
+    list_files(b, b, ignore, &mut b_files)?;
+
+    let mut paths: Vec<_> = a_files.into_iter().chain(b_files).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut differing = Vec::new();
+    for path in paths {
+        let same = match (fs::read(a.join(&path)), fs::read(b.join(&path))) {
+            (Ok(a), Ok(b)) => a == b,
+            (Err(e), _) | (_, Err(e)) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => false,
+        };
+        if !same {
+            differing.push(path);
+        }
+    }
+    Ok(differing)
+}
 
-    for &stitch_file in stitch_files {
-        if is_newer_than(stitch_file, sentinel_mtime) {
-            return false;
+/// Run `diff -ruN` between the trees `orig` and `patched`, returning the unified diff
+/// (empty if they are identical).
+///
+/// When the two trees are siblings, `diff` is run from their parent so that the paths in
+/// the diff start with their names, e.g. `a/src/lib.rs` and `b/src/lib.rs`.
+pub fn diff_dirs(orig: &Utf8Path, patched: &Utf8Path) -> io::Result<String> {
+    let mut diff = Command::new("diff");
+    diff.arg("-ruN");
+    match (orig.parent(), orig.file_name(), patched.file_name()) {
+        (Some(parent), Some(a), Some(b)) if patched.parent() == Some(parent) => {
+            diff.args([a, b]).current_dir(parent);
+        }
+        _ => {
+            diff.args([orig, patched]);
         }
     }
+    let output = diff.output()?;
+
+    // diff exits with 0 when the inputs are identical, 1 when they differ.
+    match output.status.code() {
+        Some(0 | 1) => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        _ => Err(io::Error::other(format!(
+            "diff failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
 
-    !any_file_newer_than(manifest_dir, sentinel_mtime)
+/// The `.rs` files under `dir`, recursively, relative to `dir` and sorted.
+pub fn rust_files(dir: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>> {
+    let mut files = Vec::new();
+    list_files(dir, dir, &[], &mut files)?;
+    files.retain(|f| f.extension() == Some("rs"));
+    files.sort();
+    Ok(files)
 }
 
-/// Write (or overwrite) the sentinel file that records when patching last completed.
-pub fn write_sentinel(patched_dir: &Utf8Path) -> std::io::Result<()> {
-    fs::write(patched_dir.join(SENTINEL_FILE), b"")
+/// The `.rej` files that `patch` left under `dir` for the hunks it could not apply,
+/// recursively, relative to `dir` and sorted.
+pub fn reject_files(dir: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>> {
+    let mut files = Vec::new();
+    list_files(dir, dir, &[], &mut files)?;
+    files.retain(|f| f.extension() == Some("rej"));
+    files.sort();
+    Ok(files)
 }
 
-fn is_newer_than(path: &Utf8Path, threshold: SystemTime) -> bool {
-    fs::metadata(path.as_std_path())
-        .and_then(|m| m.modified())
-        .is_ok_and(|mtime| mtime > threshold)
+fn list_files(
+    root: &Utf8Path,
+    dir: &Utf8Path,
+    ignore: &[&str],
+    files: &mut Vec<Utf8PathBuf>,
+) -> io::Result<()> {
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        if ignore.contains(&entry.file_name()) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            list_files(root, entry.path(), ignore, files)?;
+        } else if let Ok(relative) = entry.path().strip_prefix(root) {
+            files.push(relative.to_owned());
+        }
+    }
+    Ok(())
 }
 
 /// Returns `true` if any entry in `dir` (recursively) has an mtime newer than
 /// `threshold`.  Skips `target` and `.git` to mirror `copy_dir_recursive`.
 /// Returns `true` on I/O errors to err on the side of re-patching.
-fn any_file_newer_than(dir: &Utf8Path, threshold: SystemTime) -> bool {
+pub fn any_file_newer_than(dir: &Utf8Path, threshold: SystemTime) -> bool {
     let Ok(entries) = dir.read_dir_utf8() else {
         return true;
     };
@@ -113,6 +621,39 @@ fn any_file_newer_than(dir: &Utf8Path, threshold: SystemTime) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn find_workspace_root_fast_leaves_explicit_workspace_to_cargo() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(tmp.path()).unwrap();
+        let member = root.join("elsewhere/member");
+        fs::create_dir_all(&member).unwrap();
+        fs::write(
+            member.join("Cargo.toml"),
+            "[package]\nname = \"member\"\nworkspace = \"../../ws\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(find_workspace_root_fast(&member), None);
+    }
+
+    #[test]
+    fn workspace_root_from_env_must_hold_manifest_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(tmp.path()).unwrap();
+        let member = root.join("member");
+        fs::create_dir_all(&member).unwrap();
+        fs::write(root.join("Cargo.toml"), "[workspace]\n").unwrap();
+
+        assert_eq!(
+            workspace_root_from_env(root.as_str(), &member),
+            Some(root.to_owned())
+        );
+        assert_eq!(workspace_root_from_env(member.as_str(), root), None);
+        // No `Cargo.toml`, or not an absolute path.
+        assert_eq!(workspace_root_from_env(member.as_str(), &member), None);
+        assert_eq!(workspace_root_from_env("member", &member), None);
+    }
+
     #[test]
     fn copy_dir_recursive_basic() {
         let tmp = tempfile::tempdir().unwrap();
@@ -123,7 +664,7 @@ mod tests {
         fs::write(src.join("a.rs"), "fn a() {}").unwrap();
         fs::write(src.join("sub/b.rs"), "fn b() {}").unwrap();
 
-        copy_dir_recursive(&src, &dst).unwrap();
+        copy_dir_recursive(&src, &dst, &CopyOptions::default()).unwrap();
 
         assert_eq!(fs::read_to_string(dst.join("a.rs")).unwrap(), "fn a() {}");
         assert_eq!(
@@ -132,6 +673,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn copy_dir_recursive_parallel_copies_every_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = Utf8Path::from_path(tmp.path()).unwrap().join("src");
+        let dst = Utf8Path::from_path(tmp.path()).unwrap().join("dst");
+
+        for i in 0..50 {
+            let dir = src.join(format!("mod{}/sub", i % 7));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("f{i}.rs")), format!("fn f{i}() {{}}")).unwrap();
+        }
+        fs::create_dir_all(src.join("target/debug")).unwrap();
+        fs::write(src.join("target/debug/out"), "skipped").unwrap();
+
+        copy_dir_recursive_parallel(&src, &dst, &CopyOptions::default(), 4).unwrap();
+
+        assert!(compare_dirs(&src, &dst, &["target"]).unwrap().is_empty());
+        assert!(!dst.join("target").exists());
+
+        assert!(has_files(&src, 50).unwrap());
+        assert!(!has_files(&src, 51).unwrap());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn copy_dir_recursive_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let src = Utf8Path::from_path(tmp.path()).unwrap().join("src");
+        let dst = Utf8Path::from_path(tmp.path()).unwrap().join("dst");
+
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("gen.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(src.join("gen.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        copy_dir_recursive(&src, &dst, &CopyOptions::default()).unwrap();
+
+        let mode = fs::metadata(dst.join("gen.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_to_string(dst.join("gen.sh")).unwrap(),
+            "#!/bin/sh\n"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn copy_dir_recursive_hardlink() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let src = Utf8Path::from_path(tmp.path()).unwrap().join("src");
+        let dst = Utf8Path::from_path(tmp.path()).unwrap().join("dst");
+
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/b.rs"), "fn b() {}").unwrap();
+
+        copy_dir_recursive(&src, &dst, &CopyOptions { hardlink: true }).unwrap();
+
+        let src_meta = fs::metadata(src.join("sub/b.rs")).unwrap();
+        let dst_meta = fs::metadata(dst.join("sub/b.rs")).unwrap();
+        assert_eq!(src_meta.ino(), dst_meta.ino());
+    }
+
     #[test]
     fn copy_dir_recursive_skips_target_and_git() {
         let tmp = tempfile::tempdir().unwrap();
@@ -144,7 +753,7 @@ mod tests {
         fs::write(src.join(".git/HEAD"), "ref").unwrap();
         fs::write(src.join("lib.rs"), "code").unwrap();
 
-        copy_dir_recursive(&src, &dst).unwrap();
+        copy_dir_recursive(&src, &dst, &CopyOptions::default()).unwrap();
 
         assert!(dst.join("lib.rs").exists());
         assert!(!dst.join("target").exists());
@@ -152,82 +761,184 @@ mod tests {
     }
 
     #[test]
-    fn patched_dir_up_to_date_no_sentinel() {
+    fn copy_dir_recursive_preserves_symlinks() {
         let tmp = tempfile::tempdir().unwrap();
-        let patched = Utf8Path::from_path(tmp.path()).unwrap().join("patched");
-        let manifest = Utf8Path::from_path(tmp.path()).unwrap().join("manifest");
-        fs::create_dir_all(&patched).unwrap();
-        fs::create_dir_all(&manifest).unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let src = base.join("crate/src");
+        let dst = base.join("dst");
+
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::create_dir_all(base.join("shared")).unwrap();
+        fs::write(src.join("a.rs"), "fn a() {}").unwrap();
+        fs::write(base.join("shared/b.rs"), "fn b() {}").unwrap();
+        symlink("../a.rs", src.join("sub/inside.rs")).unwrap();
+        symlink("../../shared/b.rs", src.join("outside.rs")).unwrap();
+        symlink(base.join("shared/b.rs"), src.join("absolute.rs")).unwrap();
+
+        copy_dir_recursive(&src, &dst, &CopyOptions::default()).unwrap();
+
+        assert_eq!(
+            dst.join("sub/inside.rs").read_link_utf8().unwrap(),
+            "../a.rs"
+        );
+        assert_eq!(
+            dst.join("outside.rs").read_link_utf8().unwrap(),
+            base.join("shared/b.rs")
+        );
+        assert_eq!(
+            dst.join("absolute.rs").read_link_utf8().unwrap(),
+            base.join("shared/b.rs")
+        );
+        assert_eq!(
+            fs::read_to_string(dst.join("sub/inside.rs")).unwrap(),
+            "fn a() {}"
+        );
+    }
 
-        assert!(!patched_dir_is_up_to_date(&patched, &manifest, &[]));
+    #[test]
+    fn normalize_lexically_resolves_dots() {
+        assert_eq!(
+            normalize_lexically(Utf8Path::new("/ws/crate/src/../../shared/./b.rs")),
+            "/ws/shared/b.rs"
+        );
     }
 
     #[test]
-    fn patched_dir_up_to_date_fresh() {
+    fn sync_dir_updates_only_changed_files() {
         let tmp = tempfile::tempdir().unwrap();
         let base = Utf8Path::from_path(tmp.path()).unwrap();
-        let patched = base.join("patched");
-        let manifest = base.join("manifest");
-        fs::create_dir_all(&patched).unwrap();
-        fs::create_dir_all(&manifest).unwrap();
-
-        // Create source file first
-        fs::write(manifest.join("lib.rs"), "code").unwrap();
-        // Small delay to ensure sentinel is newer
+        let src = base.join("src");
+        let dst = base.join("dst");
+
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("same.rs"), "same").unwrap();
+        fs::write(src.join("sub/changed.rs"), "new").unwrap();
+        fs::create_dir_all(dst.join("sub")).unwrap();
+        fs::write(dst.join("same.rs"), "same").unwrap();
+        fs::write(dst.join("sub/changed.rs"), "old").unwrap();
+
+        let before = fs::metadata(dst.join("same.rs"))
+            .unwrap()
+            .modified()
+            .unwrap();
         std::thread::sleep(std::time::Duration::from_millis(50));
-        // Then write sentinel
-        write_sentinel(&patched).unwrap();
 
-        assert!(patched_dir_is_up_to_date(&patched, &manifest, &[]));
+        sync_dir(&src, &dst).unwrap();
+
+        let after = fs::metadata(dst.join("same.rs"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(before, after);
+        assert_eq!(
+            fs::read_to_string(dst.join("sub/changed.rs")).unwrap(),
+            "new"
+        );
     }
 
     #[test]
-    fn patched_dir_stale_stitch_file_newer() {
+    fn sync_dir_removes_stale_entries_but_keeps_stamp() {
         let tmp = tempfile::tempdir().unwrap();
         let base = Utf8Path::from_path(tmp.path()).unwrap();
-        let patched = base.join("patched");
-        let manifest = base.join("manifest");
-        fs::create_dir_all(&patched).unwrap();
-        fs::create_dir_all(&manifest).unwrap();
+        let src = base.join("src");
+        let dst = base.join("dst");
 
-        write_sentinel(&patched).unwrap();
-        std::thread::sleep(std::time::Duration::from_millis(50));
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("lib.rs"), "code").unwrap();
+        fs::write(src.join("was_dir"), "now a file").unwrap();
+        fs::create_dir_all(dst.join("stale_dir")).unwrap();
+        fs::create_dir_all(dst.join("was_dir")).unwrap();
+        fs::write(dst.join("stale.rs"), "old").unwrap();
+        fs::write(dst.join(STAMP_FILE), "stamp").unwrap();
 
-        // Stitch file written after sentinel
-        let stitch = base.join("fix.patch");
-        fs::write(&stitch, "patch").unwrap();
+        sync_dir(&src, &dst).unwrap();
 
-        let stitch_ref = Utf8Path::new(stitch.as_str());
-        assert!(!patched_dir_is_up_to_date(
-            &patched,
-            &manifest,
-            &[stitch_ref]
-        ));
+        assert_eq!(fs::read_to_string(dst.join("lib.rs")).unwrap(), "code");
+        assert_eq!(
+            fs::read_to_string(dst.join("was_dir")).unwrap(),
+            "now a file"
+        );
+        assert!(!dst.join("stale.rs").exists());
+        assert!(!dst.join("stale_dir").exists());
+        assert!(dst.join(STAMP_FILE).exists());
     }
 
     #[test]
-    fn patched_dir_stale_source_file_newer() {
+    fn sync_dir_replaces_files_and_symlinks() {
         let tmp = tempfile::tempdir().unwrap();
         let base = Utf8Path::from_path(tmp.path()).unwrap();
-        let patched = base.join("patched");
-        let manifest = base.join("manifest");
-        fs::create_dir_all(&patched).unwrap();
-        fs::create_dir_all(&manifest).unwrap();
+        let src = base.join("src");
+        let dst = base.join("dst");
 
-        write_sentinel(&patched).unwrap();
-        std::thread::sleep(std::time::Duration::from_millis(50));
-
-        // Source file written after sentinel
-        fs::write(manifest.join("lib.rs"), "new code").unwrap();
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(src.join("lib.rs"), "code").unwrap();
+        symlink("lib.rs", src.join("link.rs")).unwrap();
+        symlink("lib.rs", src.join("was_file.rs")).unwrap();
+        fs::write(src.join("was_link.rs"), "code").unwrap();
+        fs::write(dst.join("lib.rs"), "code").unwrap();
+        symlink("old.rs", dst.join("link.rs")).unwrap();
+        fs::write(dst.join("was_file.rs"), "code").unwrap();
+        symlink("lib.rs", dst.join("was_link.rs")).unwrap();
+
+        sync_dir(&src, &dst).unwrap();
+
+        assert_eq!(dst.join("link.rs").read_link_utf8().unwrap(), "lib.rs");
+        assert_eq!(dst.join("was_file.rs").read_link_utf8().unwrap(), "lib.rs");
+        assert!(
+            fs::symlink_metadata(dst.join("was_link.rs"))
+                .unwrap()
+                .is_file()
+        );
+    }
 
-        assert!(!patched_dir_is_up_to_date(&patched, &manifest, &[]));
+    #[test]
+    fn compare_dirs_reports_changed_and_one_sided_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let a = base.join("a");
+        let b = base.join("b");
+
+        fs::create_dir_all(a.join("src")).unwrap();
+        fs::create_dir_all(b.join("src")).unwrap();
+        fs::write(a.join("src/same.rs"), "same").unwrap();
+        fs::write(b.join("src/same.rs"), "same").unwrap();
+        fs::write(a.join("src/changed.rs"), "old").unwrap();
+        fs::write(b.join("src/changed.rs"), "new").unwrap();
+        fs::write(a.join("only_a.rs"), "").unwrap();
+        fs::write(b.join("only_b.rs"), "").unwrap();
+        fs::write(b.join(STAMP_FILE), "").unwrap();
+
+        let differing = compare_dirs(&a, &b, &[STAMP_FILE]).unwrap();
+        assert_eq!(
+            differing,
+            vec![
+                Utf8PathBuf::from("only_a.rs"),
+                Utf8PathBuf::from("only_b.rs"),
+                Utf8PathBuf::from("src/changed.rs"),
+            ]
+        );
     }
 
     #[test]
-    fn write_sentinel_creates_file() {
+    fn rust_files_are_relative_and_sorted() {
         let tmp = tempfile::tempdir().unwrap();
         let dir = Utf8Path::from_path(tmp.path()).unwrap();
-        write_sentinel(dir).unwrap();
-        assert!(dir.join(SENTINEL_FILE).exists());
+
+        fs::create_dir_all(dir.join("src/sub")).unwrap();
+        fs::write(dir.join("src/sub/b.rs"), "").unwrap();
+        fs::write(dir.join("src/a.rs"), "").unwrap();
+        fs::write(dir.join("build.rs"), "").unwrap();
+        fs::write(dir.join("Cargo.toml"), "").unwrap();
+        fs::write(dir.join(STAMP_FILE), "").unwrap();
+
+        assert_eq!(
+            rust_files(dir).unwrap(),
+            vec![
+                Utf8PathBuf::from("build.rs"),
+                Utf8PathBuf::from("src/a.rs"),
+                Utf8PathBuf::from("src/sub/b.rs"),
+            ]
+        );
     }
 }
@@ -0,0 +1,492 @@
+use std::fs;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::stitch::StitchSet;
+
+/// Name of the file inside `patched_dir` that records the [`StitchState`] it was produced
+/// from.
+pub const STATE_FILE: &str = ".stitch-state.json";
+
+/// Name of the file inside `patched_dir` that records the [`StitchStamp`] of the inputs it
+/// was produced from.
+pub const STAMP_FILE: &str = ".stitch-stamp";
+
+/// What a crate's patched directory was made from, and when, stored as JSON in its
+/// [`STATE_FILE`] once its stitches are applied.
+///
+/// Two states with the same sources and stitch files, as [`StitchState::matches`] tells,
+/// produce the same patched output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StitchState {
+    /// When the stitches were applied, in seconds since the Unix epoch.
+    pub applied_at: u64,
+    /// The input files of the stitch set, in application order.
+    pub stitches: Vec<StitchFileHash>,
+    /// The SHA-256 of the source files, as [`hash_dir`] computes it.
+    pub source_hash: String,
+}
+
+/// A stitch file and the hex-encoded SHA-256 of its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StitchFileHash {
+    pub path: Utf8PathBuf,
+    pub sha256: String,
+}
+
+impl StitchState {
+    /// Hash the files under `src_dir` (skipping `target` and `.git`, like
+    /// `copy_dir_recursive`) and the input files of `stitch_set`, as applied now.
+    pub fn compute(src_dir: &Utf8Path, stitch_set: &StitchSet) -> io::Result<Self> {
+        let source_hash = hash_dir(src_dir)?;
+
+        let stitches = stitch_set
+            .input_paths()
+            .map(|path| {
+                Ok(StitchFileHash {
+                    path: path.to_owned(),
+                    sha256: sha256_hex(&fs::read(path)?),
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        let applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Ok(Self {
+            applied_at,
+            stitches,
+            source_hash,
+        })
+    }
+
+    /// Whether `other` was made from the same sources and stitch files, in the same order.
+    ///
+    /// Stitch files are told apart by name, not by full path, so that moving the
+    /// workspace does not invalidate its patched directories.
+    pub fn matches(&self, other: &StitchState) -> bool {
+        let names = |state: &StitchState| {
+            state
+                .stitches
+                .iter()
+                .map(|s| (s.path.file_name().map(str::to_owned), s.sha256.clone()))
+                .collect::<Vec<_>>()
+        };
+        self.source_hash == other.source_hash && names(self) == names(other)
+    }
+
+    /// Read a previously stored state, returning `None` if it is missing or unreadable.
+    pub fn load(path: &Utf8Path) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn store(&self, path: &Utf8Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, format!("{json}\n"))
+    }
+}
+
+/// Name of the directory in the patched root that keeps a [`StitchRecord`] of every
+/// [`StitchState`] the wrapper stores, for `cargo stitch log`.  `cargo stitch clean` leaves
+/// it alone unless given `--including-history`.
+pub const HISTORY_DIR: &str = ".history";
+
+/// A [`StitchState`] stored for crate `package`, kept in [`HISTORY_DIR`] as
+/// `<applied_at>-<package>.json` after the patched directory it describes is replaced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StitchRecord {
+    pub package: String,
+    /// How many stitches were applied.
+    pub applied: usize,
+    #[serde(flatten)]
+    pub state: StitchState,
+}
+
+impl StitchRecord {
+    pub fn store(&self, history_dir: &Utf8Path) -> io::Result<()> {
+        fs::create_dir_all(history_dir)?;
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        let name = format!("{}-{}.json", self.state.applied_at, self.package);
+        fs::write(history_dir.join(name), format!("{json}\n"))
+    }
+
+    /// Every record in `history_dir`, oldest first, skipping those that cannot be read.
+    pub fn load_all(history_dir: &Utf8Path) -> io::Result<Vec<Self>> {
+        let entries = match history_dir.read_dir_utf8() {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut records = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.path().extension() != Some("json") {
+                continue;
+            }
+            if let Some(record) = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|contents| serde_json::from_str::<Self>(&contents).ok())
+            {
+                records.push(record);
+            }
+        }
+        records.sort_by(|a, b| {
+            (a.state.applied_at, &a.package).cmp(&(b.state.applied_at, &b.package))
+        });
+        Ok(records)
+    }
+}
+
+/// Hex-encoded SHA-256 of the path, size and mtime of a crate's source files and stitch
+/// files.
+///
+/// Unlike a [`StitchState`], nothing is read, so it is cheap enough to check on every rustc
+/// invocation; and unlike comparing mtimes with the time of the last patch run, it also
+/// changes when a file is removed or renamed, or replaced by one with an older mtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StitchStamp(String);
+
+impl StitchStamp {
+    /// Stamp the files under `src_dir` (skipping `target` and `.git`, like
+    /// `copy_dir_recursive`) and `stitch_files`, both in path order.
+    pub fn compute(src_dir: &Utf8Path, stitch_files: &[&Utf8Path]) -> io::Result<Self> {
+        let mut files = Vec::new();
+        collect_files(src_dir, src_dir, &mut files)?;
+        files.sort();
+
+        let mut stitch_files = stitch_files.to_vec();
+        stitch_files.sort();
+
+        let mut hasher = Sha256::new();
+
+        for relative in &files {
+            stamp_file(&mut hasher, relative.as_str(), &src_dir.join(relative))?;
+        }
+
+        for path in stitch_files {
+            stamp_file(&mut hasher, path.as_str(), path)?;
+        }
+
+        Ok(Self(hex(&hasher.finalize())))
+    }
+
+    /// Read a previously stored stamp, returning `None` if it is missing or unreadable.
+    pub fn load(path: &Utf8Path) -> Option<Self> {
+        load_hex(path).map(Self)
+    }
+
+    pub fn store(&self, path: &Utf8Path) -> io::Result<()> {
+        fs::write(path, format!("{}\n", self.0))
+    }
+}
+
+/// The hex-encoded SHA-256 stored in `path`, if it holds one.
+fn load_hex(path: &Utf8Path) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    let hash = contents.trim();
+    let valid = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+    valid.then(|| hash.to_string())
+}
+
+/// Hex-encoded SHA-256 of the files under `dir` (skipping `target` and `.git`), in path
+/// order, each with its path, so that moving bytes between files, or swapping two files,
+/// changes the result.  Also used to tell whether applying a stitch changed anything.
+pub fn hash_dir(dir: &Utf8Path) -> io::Result<String> {
+    let mut files = Vec::new();
+    collect_files(dir, dir, &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for relative in &files {
+        hash_file(&mut hasher, relative.as_str(), &dir.join(relative))?;
+    }
+
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Hex-encoded SHA-256 of `data`, as used in cargo's `.cargo-checksum.json` files.
+pub fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hex(&hasher.finalize())
+}
+
+fn hash_file(hasher: &mut Sha256, name: &str, path: &Utf8Path) -> io::Result<()> {
+    let contents = fs::read(path)?;
+    hasher.update(name.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(&(contents.len() as u64).to_le_bytes());
+    hasher.update(&contents);
+    Ok(())
+}
+
+fn stamp_file(hasher: &mut Sha256, name: &str, path: &Utf8Path) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    hasher.update(name.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(&metadata.len().to_le_bytes());
+    hasher.update(&mtime.to_le_bytes());
+    Ok(())
+}
+
+/// Collect the paths of all files under `dir`, relative to `root`.
+fn collect_files(root: &Utf8Path, dir: &Utf8Path, files: &mut Vec<Utf8PathBuf>) -> io::Result<()> {
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "target" || name == ".git" {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(root, path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            files.push(relative.to_owned());
+        }
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorkspaceMetadata;
+    use crate::output::OutputContext;
+
+    #[test]
+    fn records_round_trip_oldest_first() {
+        let tmp = tempfile::tempdir().unwrap();
+        let history = Utf8Path::from_path(tmp.path()).unwrap().join(HISTORY_DIR);
+        assert!(StitchRecord::load_all(&history).unwrap().is_empty());
+
+        let record = |package: &str, applied_at| StitchRecord {
+            package: package.to_string(),
+            applied: 1,
+            state: StitchState {
+                applied_at,
+                stitches: Vec::new(),
+                source_hash: String::new(),
+            },
+        };
+        let records = [record("b", 20), record("a", 10), record("a", 30)];
+        for record in &records {
+            record.store(&history).unwrap();
+        }
+        fs::write(history.join("garbage.json"), "{").unwrap();
+
+        let loaded = StitchRecord::load_all(&history).unwrap();
+        let order: Vec<_> = loaded
+            .iter()
+            .map(|r| (r.package.as_str(), r.state.applied_at))
+            .collect();
+        assert_eq!(order, [("a", 10), ("b", 20), ("a", 30)]);
+        assert_eq!(loaded[0], records[1]);
+    }
+
+    #[test]
+    fn sha256_known_vectors() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn compute_changes_with_source_contents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "fn a() {}").unwrap();
+
+        let set = StitchSet::default();
+        let before = StitchState::compute(dir, &set).unwrap();
+        assert!(StitchState::compute(dir, &set).unwrap().matches(&before));
+
+        fs::write(dir.join("src/lib.rs"), "fn b() {}").unwrap();
+        assert!(!StitchState::compute(dir, &set).unwrap().matches(&before));
+
+        // Swapping the contents of two files changes the hash too.
+        fs::write(dir.join("src/lib.rs"), "fn a() {}").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        let two = StitchState::compute(dir, &set).unwrap();
+        fs::write(dir.join("src/lib.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn a() {}").unwrap();
+        assert_ne!(
+            StitchState::compute(dir, &set).unwrap().source_hash,
+            two.source_hash
+        );
+    }
+
+    #[test]
+    fn compute_hashes_each_stitch_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "fn a() {}").unwrap();
+        let stitches_dir = dir.join("stitches");
+        let stitches = stitches_dir.join("crate-a");
+        fs::create_dir_all(&stitches).unwrap();
+        fs::write(stitches.join("001-fix.sed"), "s/a/b/\n").unwrap();
+
+        let set = StitchSet::discover_all(
+            &[&stitches_dir],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap()
+        .remove("crate-a")
+        .unwrap();
+        let state = StitchState::compute(&dir.join("src"), &set).unwrap();
+        assert_eq!(
+            state.stitches,
+            vec![StitchFileHash {
+                path: stitches.join("001-fix.sed"),
+                sha256: sha256_hex(b"s/a/b/\n"),
+            }]
+        );
+
+        fs::write(stitches.join("001-fix.sed"), "s/a/c/\n").unwrap();
+        assert!(
+            !StitchState::compute(&dir.join("src"), &set)
+                .unwrap()
+                .matches(&state)
+        );
+    }
+
+    #[test]
+    fn compute_ignores_target_and_git() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join("lib.rs"), "code").unwrap();
+
+        let set = StitchSet::default();
+        let before = StitchState::compute(dir, &set).unwrap();
+
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("target/out"), "binary").unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(".git/HEAD"), "ref").unwrap();
+
+        assert!(StitchState::compute(dir, &set).unwrap().matches(&before));
+    }
+
+    #[test]
+    fn hash_dir_changes_with_contents_and_new_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join("lib.rs"), "code").unwrap();
+
+        let before = hash_dir(dir).unwrap();
+        assert_eq!(hash_dir(dir).unwrap(), before);
+
+        fs::write(dir.join("lib.rs"), "other code").unwrap();
+        let changed = hash_dir(dir).unwrap();
+        assert_ne!(changed, before);
+
+        fs::write(dir.join("new.rs"), "").unwrap();
+        assert_ne!(hash_dir(dir).unwrap(), changed);
+    }
+
+    #[test]
+    fn store_and_load_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join("lib.rs"), "code").unwrap();
+
+        let state = StitchState::compute(dir, &StitchSet::default()).unwrap();
+        let path = dir.join(STATE_FILE);
+        state.store(&path).unwrap();
+
+        assert_eq!(StitchState::load(&path), Some(state));
+    }
+
+    #[test]
+    fn stamp_changes_with_sizes_mtimes_and_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("lib.rs"), "code").unwrap();
+        let first = base.join("001-fix.patch");
+        let second = base.join("002-fix.patch");
+        fs::write(&first, "patch").unwrap();
+        fs::write(&second, "patch").unwrap();
+
+        let stamp = |stitches: &[&Utf8Path]| StitchStamp::compute(&src, stitches).unwrap();
+        let before = stamp(&[&first, &second]);
+        assert_eq!(stamp(&[&second, &first]), before);
+
+        // A stitch file removed, without anything getting a newer mtime.
+        assert_ne!(stamp(&[&first]), before);
+
+        // A source file renamed, keeping its mtime.
+        fs::rename(src.join("lib.rs"), src.join("main.rs")).unwrap();
+        assert_ne!(stamp(&[&first, &second]), before);
+        fs::rename(src.join("main.rs"), src.join("lib.rs")).unwrap();
+        assert_eq!(stamp(&[&first, &second]), before);
+
+        // A source file rewritten.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(src.join("lib.rs"), "code").unwrap();
+        assert_ne!(stamp(&[&first, &second]), before);
+    }
+
+    #[test]
+    fn stamp_fails_for_missing_stitch_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        let missing = dir.join("001-gone.patch");
+        assert!(StitchStamp::compute(dir, &[&missing]).is_err());
+    }
+
+    #[test]
+    fn stamp_store_and_load_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join("lib.rs"), "code").unwrap();
+
+        let stamp = StitchStamp::compute(dir, &[]).unwrap();
+        let path = dir.join(STAMP_FILE);
+        assert_eq!(StitchStamp::load(&path), None);
+        stamp.store(&path).unwrap();
+
+        assert_eq!(StitchStamp::load(&path), Some(stamp));
+    }
+
+    #[test]
+    fn load_missing_or_invalid_returns_none() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        assert_eq!(StitchState::load(&dir.join("missing")), None);
+
+        fs::write(dir.join("bogus"), "not a hash").unwrap();
+        assert_eq!(StitchState::load(&dir.join("bogus")), None);
+        assert_eq!(StitchStamp::load(&dir.join("bogus")), None);
+    }
+}
@@ -1,25 +1,79 @@
+use std::collections::HashMap;
 use std::env;
-use std::process::Command;
+use std::path::Path;
+use std::process::{Command, ExitCode};
 
-use terrors::OneOf;
+use camino::{Utf8Path, Utf8PathBuf};
 
-#[cfg(not(unix))]
-compile_error!("cargo-stitch only supports Unix platforms (Linux, macOS, BSD)");
+use terrors::{E2, OneOf};
 
+mod config;
 mod error;
 mod fs;
+mod hash;
+mod output;
+mod patch;
 mod stitch;
 mod subcommand;
+mod tools;
+mod util;
+mod vendor;
 mod wrapper;
 
 pub use error::{
-    AstGrepFailed, CargoFailed, IoError, MissingEnvVar, MissingStitchSet, MissingTool,
-    MissingWorkspaceRoot, PatchFailed,
+    AstGrepFailed, CargoFailed, ChecksumMismatch, IncompatibleToolVersion, IoError, MissingEnvVar,
+    MissingStitchSet, MissingTool, MissingWorkspaceRoot, PatchFailed, ScriptFailed, StitchTimeout,
+};
+pub use stitch::{
+    Stitch, StitchConflict, StitchKind, StitchMeta, StitchResult, StitchSet, ValidationResult,
 };
 
 pub const WRAPPER_ENV: &str = "__CARGO_STITCH_WRAP";
 pub const WORKSPACE_ROOT_ENV: &str = "__CARGO_STITCH_WORKSPACE_ROOT";
 pub const STITCH_MANIFEST_ENV: &str = "__CARGO_STITCH_MANIFEST";
+pub const KEEP_PATCHED_ENV: &str = "__CARGO_STITCH_KEEP_PATCHED";
+/// Where the patched copies go, set when not in `target/cargo-stitch/`.
+pub const PATCHED_DIR_ENV: &str = "__CARGO_STITCH_PATCHED_DIR";
+pub const VENDOR_DIR_ENV: &str = "__CARGO_STITCH_VENDOR_DIR";
+/// Cargo's `registry/src/` directory, set when crates.io dependencies are stitched.
+pub const REGISTRY_SRC_ENV: &str = "__CARGO_STITCH_REGISTRY_SRC";
+/// User-facing switch for JSON diagnostics, also set by `cargo stitch --json` so that the
+/// wrapper reports in JSON too.
+pub const JSON_ENV: &str = "CARGO_STITCH_JSON";
+/// User-facing switch for how much is reported: `quiet`, `normal` or `verbose`.  Also
+/// set by `cargo stitch --quiet` and `--verbose`, so that the wrapper reports as much.
+pub const VERBOSITY_ENV: &str = "CARGO_STITCH_VERBOSITY";
+/// User-facing switch for coloured status lines: `auto`, `always` or `never`.  Also set by
+/// `cargo stitch --color`, and by `cargo stitch` to what `auto` resolved to, since the
+/// wrapper's stderr is never a terminal.
+pub const COLOR_ENV: &str = "CARGO_STITCH_COLOR";
+/// User-facing, comma-separated list of the packages to stitch, also set by
+/// `cargo stitch <command> --package <name>`.  Other packages are built from their
+/// original sources.
+pub const PACKAGES_ENV: &str = "CARGO_STITCH_PACKAGES";
+/// The file the wrapper appends its JSON diagnostics to, for `cargo stitch` to print.
+pub const EVENTS_FILE_ENV: &str = "__CARGO_STITCH_EVENTS";
+/// User-facing override for the workspace root the wrapper uses to recognise relative
+/// source paths, for builds where the workspace is mounted elsewhere (e.g. containers).
+pub const WORKSPACE_ROOT_OVERRIDE_ENV: &str = "STITCH_WORKSPACE_ROOT";
+/// User-facing override for the directory holding the stitch sets: absolute, or relative
+/// to the workspace root.  Takes precedence over `stitches_dir` in
+/// `[workspace.metadata.stitch]`.
+pub const PATCHES_DIR_ENV: &str = "CARGO_STITCH_PATCHES_DIR";
+/// User-facing, `:`-separated list of directories searched for stitch sets before the
+/// workspace's own, e.g. `~/.cargo-stitch`: absolute, or relative to the workspace root.
+/// Earlier directories take priority.
+pub const PATCHES_PATH_ENV: &str = "CARGO_STITCH_PATCHES_PATH";
+/// User-facing override for the directory holding the patched copies: absolute, or
+/// relative to the workspace root.  Takes precedence over `patched_dir` in
+/// `[workspace.metadata.stitch]` and over `CARGO_TARGET_DIR`.
+pub const PATCHED_DIR_OVERRIDE_ENV: &str = "CARGO_STITCH_TARGET_DIR";
+/// User-facing limit, in seconds, on how long a tool may take to apply one stitch before
+/// it is killed: 60 by default, and no limit if 0.
+pub const TIMEOUT_ENV: &str = "CARGO_STITCH_TIMEOUT_SECS";
+/// User-facing number of threads used to copy the sources of a large crate before it is
+/// stitched: the number of logical CPUs by default.
+pub const JOBS_ENV: &str = "CARGO_STITCH_JOBS";
 
 pub type Error = OneOf<(
     IoError,
@@ -30,31 +84,279 @@ pub type Error = OneOf<(
     MissingTool,
     MissingWorkspaceRoot,
     MissingStitchSet,
+    ScriptFailed,
 )>;
 
-pub(crate) fn check_required_tools(
-    need_patch: bool,
-    need_sg: bool,
-) -> Result<(), OneOf<(MissingTool,)>> {
-    if need_patch && Command::new("patch").arg("--version").output().is_err() {
-        return Err(OneOf::new(error::MissingTool("patch")));
+/// Check that every tool the stitches in `stitch_sets` are applied with can be run, and
+/// is not older than they need.
+///
+/// The version each tool reports is printed in verbose mode.  A tool that is too old is
+/// an [`IncompatibleToolVersion`], carried as an [`IoError`], except for an optional tool,
+/// which only gets a warning.  A version that cannot be told is given the benefit of the
+/// doubt.
+pub(crate) fn check_required_tools<'a>(
+    stitch_sets: impl IntoIterator<Item = &'a stitch::StitchSet>,
+    output: &output::OutputContext,
+) -> Result<(), OneOf<(MissingTool, IoError)>> {
+    let stitch_sets: Vec<_> = stitch_sets.into_iter().collect();
+    for tool in tools::TOOLS {
+        if !stitch_sets.iter().any(|set| set.needs(tool)) {
+            continue;
+        }
+        // Whether the tool runs at all is what matters most: BSD `sed` rejects `--version`.
+        let Ok(version_output) = Command::new(tool.name).arg("--version").output() else {
+            // An optional tool is only missed if a stitch turns out to need it.
+            if tool.optional {
+                continue;
+            }
+            return Err(OneOf::new(error::MissingTool(tool.name)));
+        };
+        let Some(found) = tools::version_line(&version_output) else {
+            continue;
+        };
+        if output.is_verbose() {
+            stitch::cargo_status(output, "Using", &found);
+        }
+
+        let required = min_tool_version(tool, &stitch_sets);
+        let Some((version, min)) =
+            tools::parse_version(&found).zip(tools::parse_min_version(required))
+        else {
+            continue;
+        };
+        if tools::meets_minimum(&version, &min) {
+            continue;
+        }
+        let incompatible = error::IncompatibleToolVersion {
+            tool: tool.name,
+            found,
+            required: required.to_string(),
+        };
+        if tool.optional {
+            stitch::cargo_status(output, "Warning", &incompatible.to_string());
+        } else {
+            return Err(OneOf::new(IoError::from(incompatible)));
+        }
     }
 
-    if need_sg && Command::new("ast-grep").arg("--version").output().is_err() {
-        return Err(OneOf::new(error::MissingTool("ast-grep")));
+    Ok(())
+}
+
+/// The source directories of the crates whose stitches are in the directories `names` of a
+/// stitch set: workspace members, and for `<crate>-<version>` directories, the
+/// dependencies in cargo's registry cache.  Names that are neither are left out.
+pub(crate) fn crate_source_dirs<'a>(
+    workspace_root: &Utf8Path,
+    names: impl IntoIterator<Item = &'a String>,
+) -> std::io::Result<HashMap<String, Utf8PathBuf>> {
+    let mut dirs: HashMap<_, _> = fs::workspace_packages(workspace_root)?
+        .into_iter()
+        .filter_map(|p| Some((p.name.to_string(), p.manifest_path.parent()?.to_owned())))
+        .collect();
+
+    // Finding the registry dependencies resolves the whole dependency graph, so it is
+    // only done when there are stitches for crates that are not members.
+    if names.into_iter().any(|name| !dirs.contains_key(name)) {
+        for p in fs::registry_packages(workspace_root)? {
+            let key = wrapper::registry_key(p.name.as_str(), &p.version.to_string());
+            if let Some(dir) = p.manifest_path.parent() {
+                dirs.entry(key).or_insert_with(|| dir.to_owned());
+            }
+        }
+    }
+
+    Ok(dirs)
+}
+
+/// The oldest version of `tool` that every one of `stitch_sets` that needs it works
+/// with: the highest of their `[tools]` minimums, which default to cargo-stitch's own.
+pub(crate) fn min_tool_version<'a>(
+    tool: &'a tools::Tool,
+    stitch_sets: &[&'a stitch::StitchSet],
+) -> &'a str {
+    stitch_sets
+        .iter()
+        .filter(|set| set.needs(tool))
+        .map(|set| set.min_version(tool))
+        .max_by_key(|version| tools::parse_min_version(version))
+        .unwrap_or(tool.min_version)
+}
+
+/// Check that every stitch in the `default` stitch set of the workspace at
+/// `workspace_root` (in the directory set by `[workspace.metadata.stitch]`, if any)
+/// applies cleanly, without building anything or writing to `target/`.
+///
+/// Each crate's stitches are applied in order to a scratch copy of its source, as
+/// `cargo stitch check --report-format human` does.  The stitches in a
+/// `<crate>-<version>` directory are checked against that version of the crates.io
+/// dependency, as found in cargo's registry cache.
+///
+/// # Errors
+/// Returns the [`PatchFailed`], [`AstGrepFailed`] or [`ScriptFailed`] error of the first
+/// stitch that does not apply, with the output of the failing tool; [`MissingTool`] if `patch` or
+/// `ast-grep` is needed but not installed; or an [`IoError`] if `cargo metadata` fails, a
+/// crate with stitches is neither a member nor a crates.io dependency of the workspace, or
+/// its source cannot be copied.
+pub fn check_stitches(workspace_root: &Path) -> Result<(), Error> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let workspace_root = Utf8Path::from_path(workspace_root).ok_or_else(|| {
+        io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "workspace root is not valid UTF-8: {}",
+                workspace_root.display()
+            ),
+        ))
+    })?;
+
+    let manifest = discover_default(workspace_root)?;
+    check_required_tools(manifest.values(), &output::OutputContext::from_env())
+        .map_err(OneOf::broaden)?;
+
+    let sources = crate_source_dirs(workspace_root, manifest.keys()).map_err(io)?;
+
+    let mut pkg_names: Vec<_> = manifest.keys().collect();
+    pkg_names.sort();
+
+    for pkg_name in pkg_names {
+        let manifest_dir = sources.get(pkg_name).ok_or_else(|| {
+            io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "crate `{pkg_name}` has stitches but is neither a member nor a crates.io \
+                     dependency of the workspace"
+                ),
+            ))
+        })?;
+
+        let (_guard, scratch) = fs::temp_dir().map_err(io)?;
+        fs::copy_dir_recursive(manifest_dir, &scratch, &fs::CopyOptions::default()).map_err(io)?;
+        manifest[pkg_name]
+            .apply_quietly(&scratch)
+            .map_err(OneOf::broaden)?;
     }
 
     Ok(())
 }
 
+/// Apply the stitch file `stitch` to a scratch copy of `src_dir` and report which files it
+/// changes, without touching `src_dir` or writing to `target/`.
+///
+/// The stitch is applied with the options of the crate it belongs to in the `default`
+/// stitch set of the workspace at `workspace_root`, but on its own: it is up to the caller
+/// to pass a `src_dir` that the stitches before it have been applied to, if it needs them.
+///
+/// # Errors
+/// Returns the [`PatchFailed`], [`AstGrepFailed`] or [`ScriptFailed`] error of the stitch
+/// if it does not apply, with the output of the failing tool; or an [`IoError`] if
+/// `stitch` is not one of the workspace's stitch files, or `src_dir` cannot be copied.
+pub fn validate_stitch(
+    workspace_root: &Path,
+    stitch: &Path,
+    src_dir: &Path,
+) -> Result<ValidationResult, Error> {
+    let io = |e| OneOf::new(IoError(e));
+    let not_found =
+        |message: String| io(std::io::Error::new(std::io::ErrorKind::NotFound, message));
+
+    let workspace_root = utf8_path(workspace_root).map_err(io)?;
+    let src_dir = utf8_path(src_dir).map_err(io)?;
+    let stitch_file = std::fs::canonicalize(stitch).map_err(io)?;
+
+    let manifest = discover_default(workspace_root)?;
+
+    let (set, found) = manifest
+        .values()
+        .find_map(|set| {
+            set.iter()
+                .find(|s| std::fs::canonicalize(s.path()).is_ok_and(|p| p == stitch_file))
+                .map(|s| (set, s))
+        })
+        .ok_or_else(|| {
+            not_found(format!(
+                "{} is not a stitch file of the workspace",
+                stitch.display()
+            ))
+        })?;
+    check_required_tools([set], &output::OutputContext::from_env()).map_err(OneOf::broaden)?;
+
+    found.validate(src_dir, set).map_err(OneOf::broaden)
+}
+
+/// Find the stitches of the `default` stitch set of the workspace at `workspace_root` (in
+/// the directory set by `[workspace.metadata.stitch]`, if any), by crate, without applying
+/// them.
+///
+/// A crate whose stitches are disabled has none, and is left out.
+///
+/// # Errors
+/// Returns an [`IoError`] if a stitch directory or a `stitch.toml` cannot be read, or if a
+/// stitch file does not match its checksum in `.checksums`.
+pub fn discover_stitches(workspace_root: &Path) -> Result<HashMap<String, StitchSet>, Error> {
+    discover_default(utf8_path(workspace_root).map_err(|e| OneOf::new(IoError(e)))?)
+}
+
+/// The stitches of the `default` set of the workspace at `workspace_root`, in the
+/// directory set by `[workspace.metadata.stitch]`, if any, and in those of
+/// `CARGO_STITCH_PATCHES_PATH`.
+fn discover_default(
+    workspace_root: &Utf8Path,
+) -> Result<HashMap<String, stitch::StitchSet>, Error> {
+    let output = output::OutputContext::from_env();
+    let metadata =
+        config::WorkspaceMetadata::load(workspace_root, &output).map_err(OneOf::broaden)?;
+    let dirs: Vec<_> = metadata
+        .stitches_dirs(workspace_root)
+        .into_iter()
+        .map(|dir| dir.join("default"))
+        .collect();
+    let dirs: Vec<_> = dirs.iter().map(Utf8PathBuf::as_path).collect();
+    stitch::StitchSet::discover_all(&dirs, &metadata, &output).map_err(|e| match e.to_enum() {
+        E2::A(e) => OneOf::new(e),
+        // `Error` has no room left for it.
+        E2::B(mismatch) => OneOf::new(IoError::from(mismatch)),
+    })
+}
+
+/// `path` as a UTF-8 path, which every path cargo-stitch works with must be.
+fn utf8_path(path: &Path) -> std::io::Result<&Utf8Path> {
+    Utf8Path::from_path(path).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("path is not valid UTF-8: {}", path.display()),
+        )
+    })
+}
+
 /// Run the cargo-stitch process
+///
+/// Returns the exit code the process should terminate with: subcommands that
+/// report problems without failing outright (e.g. `summarize --exit-code`)
+/// return a non-zero code instead of an error.
+///
 /// # Errors
 /// Returns an error if a required tool (like `patch` or `ast-grep`) is missing,
 /// or if an underlying cargo build or patch operation fails.
-pub fn run() -> Result<(), Error> {
+pub fn run() -> Result<ExitCode, Error> {
     if env::var_os(WRAPPER_ENV).is_some() {
-        wrapper::run_wrapper().map_err(OneOf::broaden)
+        output::init_tracing();
+        let output = output::OutputContext::from_env();
+        match wrapper::run_wrapper(&output) {
+            Ok(()) => Ok(ExitCode::SUCCESS),
+            // With JSON diagnostics, the error is an event like any other, rather than the
+            // plain line `main` would print.
+            Err(e) if output.is_json() => {
+                wrapper::report_error(&output, &e);
+                Ok(ExitCode::FAILURE)
+            }
+            Err(e) => Err(OneOf::broaden(e)),
+        }
     } else {
-        subcommand::run_subcommand().map_err(OneOf::broaden)
+        subcommand::run_subcommand().map_err(|e| match e.narrow::<ChecksumMismatch, _>() {
+            // `Error` has no room left for it.
+            Ok(mismatch) => OneOf::new(IoError::from(mismatch)),
+            Err(e) => OneOf::broaden(e),
+        })
     }
 }
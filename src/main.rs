@@ -1,6 +1,11 @@
-fn main() {
-    if let Err(e) = cargo_stitch::run() {
-        eprintln!("cargo-stitch: {e}");
-        std::process::exit(1);
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match cargo_stitch::run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("cargo-stitch: {e}");
+            ExitCode::FAILURE
+        }
     }
 }
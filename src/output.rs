@@ -0,0 +1,328 @@
+//! How cargo-stitch reports what it does: cargo-style status lines for people, or
+//! newline-delimited JSON for tools, with `--json` or `CARGO_STITCH_JSON=1`.
+//!
+//! The JSON format is stable: every line on stderr is one object whose `type` is one of
+//! `stitch-applied`, `error` or `status`, and new fields may be added but existing ones
+//! are not removed or renamed.
+//!
+//! Cargo swallows any line of JSON that a rustc wrapper prints, taking it for a compiler
+//! message, so the wrapper appends its events to a file instead (`EVENTS_FILE_ENV`), which
+//! `cargo stitch` prints once cargo is done.
+//!
+//! How much is reported is set by `--quiet` or `--verbose`, or `CARGO_STITCH_VERBOSITY`,
+//! and whether status lines are coloured by `--color`, `CARGO_STITCH_COLOR` or `NO_COLOR`.
+//!
+//! What cargo-stitch does internally is logged with `tracing`, for debugging, and printed
+//! when `RUST_LOG` is set.
+
+use std::env;
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::{IsTerminal, Write};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Serialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::stitch::{Stitch, cargo_status};
+use crate::{COLOR_ENV, EVENTS_FILE_ENV, JSON_ENV, VERBOSITY_ENV};
+
+/// The format diagnostics are printed in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputMode {
+    #[default]
+    Human,
+    Json,
+}
+
+/// How much is reported.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verbosity {
+    /// Errors only.
+    Quiet,
+    /// A status line per stitch applied, and per crate stitched.
+    #[default]
+    Normal,
+    /// As `Normal`, plus everything the tools applying stitches print.
+    Verbose,
+}
+
+impl Verbosity {
+    /// The verbosity named `name`, as `CARGO_STITCH_VERBOSITY` spells it.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "quiet" => Some(Self::Quiet),
+            "normal" => Some(Self::Normal),
+            "verbose" => Some(Self::Verbose),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Quiet => "quiet",
+            Self::Normal => "normal",
+            Self::Verbose => "verbose",
+        }
+    }
+}
+
+/// Whether status lines are coloured.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColorChoice {
+    /// When stderr is a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// The choice named `name`, as `--color` and `CARGO_STITCH_COLOR` spell it.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "always" => Some(Self::Always),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
+/// What every function that prints diagnostics needs to know about printing them.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct OutputContext {
+    pub mode: OutputMode,
+    pub verbosity: Verbosity,
+    pub color: ColorChoice,
+    /// Where to append JSON events instead of stderr.
+    pub events_file: Option<Utf8PathBuf>,
+}
+
+/// One JSON diagnostic.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Event<'a> {
+    /// A stitch was applied to a crate.
+    StitchApplied {
+        package: &'a str,
+        stitch: &'a str,
+        kind: &'a str,
+    },
+    /// Something failed; `package` and `stitch` are given when they are known.
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        package: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stitch: Option<&'a str>,
+        message: String,
+    },
+    /// Any other status line, e.g. `Fresh` or `Warning`.
+    Status { status: &'a str, message: &'a str },
+}
+
+impl OutputContext {
+    pub(crate) const JSON: Self = Self {
+        mode: OutputMode::Json,
+        verbosity: Verbosity::Normal,
+        color: ColorChoice::Auto,
+        events_file: None,
+    };
+
+    /// The output asked for by `CARGO_STITCH_JSON`, `CARGO_STITCH_VERBOSITY` and
+    /// `CARGO_STITCH_COLOR`, which is how `--json`, `--quiet`, `--verbose` and `--color`
+    /// reach the wrapper.  Any value of `CARGO_STITCH_JSON` other than empty or `0` selects
+    /// JSON; unknown verbosities and colour choices are ignored.
+    pub(crate) fn from_env() -> Self {
+        let verbosity = env::var(VERBOSITY_ENV)
+            .ok()
+            .and_then(|name| Verbosity::from_name(&name))
+            .unwrap_or_default();
+        let color = env::var(COLOR_ENV)
+            .ok()
+            .and_then(|name| ColorChoice::from_name(&name))
+            .unwrap_or_default();
+
+        match env::var_os(JSON_ENV) {
+            Some(value) if !value.is_empty() && value != "0" => Self {
+                verbosity,
+                color,
+                events_file: env::var(EVENTS_FILE_ENV).ok().map(Utf8PathBuf::from),
+                ..Self::JSON
+            },
+            _ => Self {
+                verbosity,
+                color,
+                ..Self::default()
+            },
+        }
+    }
+
+    /// Whether status lines should be coloured: as asked for, or, by default, when stderr
+    /// is a terminal and `NO_COLOR` is not set (see <https://no-color.org>).
+    pub(crate) fn use_color(&self) -> bool {
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::io::stderr().is_terminal()
+            }
+        }
+    }
+
+    pub(crate) fn is_json(&self) -> bool {
+        self.mode == OutputMode::Json
+    }
+
+    pub(crate) fn is_quiet(&self) -> bool {
+        self.verbosity == Verbosity::Quiet
+    }
+
+    pub(crate) fn is_verbose(&self) -> bool {
+        self.verbosity == Verbosity::Verbose
+    }
+
+    /// Emit a `status` event; `cargo_status` prints the human version.
+    pub(crate) fn status_event(&self, status: &str, message: &str) {
+        self.emit(&Event::Status { status, message });
+    }
+
+    /// Emit a `stitch-applied` event for `stitch` in `package`.  In human mode this prints
+    /// nothing: `StitchSet::apply` prints status lines specific to each kind of stitch.
+    pub(crate) fn stitch_applied(&self, package: &str, stitch: &Stitch) {
+        if self.is_json() && !self.is_quiet() {
+            self.emit(&Event::StitchApplied {
+                package,
+                stitch: file_name(stitch.path()),
+                kind: stitch.kind().as_str(),
+            });
+        }
+    }
+
+    /// Report a failure, in `package` and caused by the stitch file `stitch` if known.
+    pub(crate) fn error(
+        &self,
+        package: Option<&str>,
+        stitch: Option<&Utf8Path>,
+        message: &dyn Display,
+    ) {
+        match self.mode {
+            OutputMode::Human => match package {
+                Some(package) => cargo_status(self, "Error", &format!("{package}: {message}")),
+                None => cargo_status(self, "Error", &message.to_string()),
+            },
+            OutputMode::Json => self.emit(&Event::Error {
+                package,
+                stitch: stitch.map(file_name),
+                message: message.to_string(),
+            }),
+        }
+    }
+
+    /// Print `event` as one line of JSON, to the events file if there is one.
+    fn emit(&self, event: &Event<'_>) {
+        let Ok(mut line) = serde_json::to_string(event) else {
+            return;
+        };
+        line.push('\n');
+
+        // A single append of the whole line, so that the lines of concurrent wrappers do
+        // not interleave.
+        let appended = self.events_file.as_ref().is_some_and(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(line.as_bytes()))
+                .is_ok()
+        });
+        if !appended {
+            let _ = std::io::stderr().lock().write_all(line.as_bytes());
+        }
+    }
+}
+
+fn file_name(path: &Utf8Path) -> &str {
+    path.file_name().unwrap_or(path.as_str())
+}
+
+/// Print the `tracing` events of cargo-stitch to stderr when `RUST_LOG` is set, filtered
+/// as it says (e.g. `RUST_LOG=cargo_stitch=debug`).  Cargo passes it on to the wrapper,
+/// whose events are printed along with rustc's output.
+pub(crate) fn init_tracing() {
+    if env::var_os(EnvFilter::DEFAULT_ENV).is_none() {
+        return;
+    }
+    // Only fails if a subscriber is already set, e.g. by a program using the library.
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .try_init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_names_round_trip() {
+        for verbosity in [Verbosity::Quiet, Verbosity::Normal, Verbosity::Verbose] {
+            assert_eq!(Verbosity::from_name(verbosity.name()), Some(verbosity));
+        }
+        assert_eq!(Verbosity::from_name("loud"), None);
+    }
+
+    #[test]
+    fn explicit_color_choice_wins() {
+        let output = |color| OutputContext {
+            color,
+            ..OutputContext::default()
+        };
+        assert!(output(ColorChoice::Always).use_color());
+        assert!(!output(ColorChoice::Never).use_color());
+        assert_eq!(ColorChoice::from_name("sometimes"), None);
+    }
+
+    #[test]
+    fn events_serialize_to_the_documented_format() {
+        let applied = Event::StitchApplied {
+            package: "foo",
+            stitch: "001-fix.patch",
+            kind: "patch",
+        };
+        assert_eq!(
+            serde_json::to_string(&applied).unwrap(),
+            r#"{"type":"stitch-applied","package":"foo","stitch":"001-fix.patch","kind":"patch"}"#
+        );
+
+        let error = Event::Error {
+            package: Some("foo"),
+            stitch: Some("002.patch"),
+            message: "patch failed".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"type":"error","package":"foo","stitch":"002.patch","message":"patch failed"}"#
+        );
+
+        let bare_error = Event::Error {
+            package: None,
+            stitch: None,
+            message: "no workspace".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&bare_error).unwrap(),
+            r#"{"type":"error","message":"no workspace"}"#
+        );
+
+        let status = Event::Status {
+            status: "Fresh",
+            message: "foo",
+        };
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"type":"status","status":"Fresh","message":"foo"}"#
+        );
+    }
+}
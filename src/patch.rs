@@ -0,0 +1,637 @@
+//! A built-in applier for unified diffs, which `.patch` stitches are applied with before
+//! falling back to the `patch` binary, so that `patch` need not be installed.
+//!
+//! It handles the common case: `---`/`+++` file headers, `@@` hunks of `+`, `-` and
+//! context lines, files created or removed with `/dev/null`, and `\ No newline at end of
+//! file`.  A hunk may have moved, as `patch` allows, but its lines must match exactly.
+//! Anything else (fuzz, context diffs, git renames, mode changes and binary patches) is
+//! an [`UnsupportedPatchFormat`], left to `patch`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use terrors::OneOf;
+
+use crate::error::{IoError, UnsupportedPatchFormat};
+
+/// Lines outside of hunks that only `patch` or `git apply` make sense of.
+const UNSUPPORTED_HEADERS: &[&str] = &[
+    "***************",
+    "GIT binary patch",
+    "Binary files ",
+    "rename from ",
+    "rename to ",
+    "copy from ",
+    "copy to ",
+    "old mode ",
+    "new mode ",
+];
+
+/// The header of a hunk: `@@ -<old_start>[,<old_count>] +<new_start>[,<new_count>] @@`,
+/// where a missing count means 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HunkHeader {
+    pub(crate) old_start: usize,
+    pub(crate) old_count: usize,
+    pub(crate) new_start: usize,
+    pub(crate) new_count: usize,
+}
+
+impl HunkHeader {
+    /// Parse `line`, which may go on after the closing `@@` (e.g. with the name of the
+    /// enclosing function), or return `None` if it is not a hunk header.
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let ranges = line.strip_prefix("@@ -")?;
+        let (ranges, _) = ranges.split_once(" @@")?;
+        let (old, new) = ranges.split_once(" +")?;
+        let range = |range: &str| match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        };
+        let (old_start, old_count) = range(old)?;
+        let (new_start, new_count) = range(new)?;
+        Some(Self {
+            old_start,
+            old_count,
+            new_start,
+            new_count,
+        })
+    }
+}
+
+/// One hunk: the lines it expects, and those it replaces them with, each with its line
+/// ending, if it has one.
+#[derive(Debug, PartialEq, Eq)]
+struct Hunk<'a> {
+    header: HunkHeader,
+    old: Vec<&'a str>,
+    new: Vec<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// The hunks for one file.
+#[derive(Debug, PartialEq, Eq)]
+struct FilePatch<'a> {
+    /// Relative to the directory the patch is applied to.
+    path: Utf8PathBuf,
+    change: Change,
+    hunks: Vec<Hunk<'a>>,
+}
+
+/// The lines one hunk changes, without the context lines around them.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct HunkChange<'a> {
+    /// Relative to the directory the patch is applied to.
+    pub(crate) path: Utf8PathBuf,
+    /// The lines changed, numbered as in the file the patch was made against.  A hunk
+    /// that only adds lines spans the lines on either side of them.
+    pub(crate) lines: RangeInclusive<usize>,
+    /// Every line the hunk expects, context included.
+    pub(crate) old: Vec<&'a str>,
+    pub(crate) removed: Vec<&'a str>,
+    pub(crate) added: Vec<&'a str>,
+}
+
+/// The changes made by each hunk of the unified diff `contents`, with `strip` leading
+/// components stripped from its paths, in the order of the diff.
+pub(crate) fn hunk_changes(
+    contents: &str,
+    strip: u32,
+) -> Result<Vec<HunkChange<'_>>, UnsupportedPatchFormat> {
+    let mut changes = Vec::new();
+    for file in parse(contents, strip)? {
+        for hunk in file.hunks {
+            let before = hunk
+                .old
+                .iter()
+                .zip(&hunk.new)
+                .take_while(|(o, n)| o == n)
+                .count();
+            let after = hunk.old[before..]
+                .iter()
+                .rev()
+                .zip(hunk.new[before..].iter().rev())
+                .take_while(|(o, n)| o == n)
+                .count();
+            let removed = hunk.old[before..hunk.old.len() - after].to_vec();
+            let added = hunk.new[before..hunk.new.len() - after].to_vec();
+
+            // A hunk that removes nothing goes after line `old_start`.
+            let first = match hunk.header.old_count {
+                0 => hunk.header.old_start + 1,
+                _ => hunk.header.old_start,
+            } + before;
+            let lines = match removed.len() {
+                0 => first.saturating_sub(1).max(1)..=first,
+                n => first..=first + n - 1,
+            };
+            changes.push(HunkChange {
+                path: file.path.clone(),
+                lines,
+                old: hunk.old,
+                removed,
+                added,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// Apply the unified diff in `patch_file` to `dir`, stripping `strip` leading components
+/// from the paths in it, as `patch -p<strip>` does.  Returns the files it changed, in
+/// path order.
+///
+/// Nothing is written unless every hunk applies, so that `patch` can be run on `dir` as
+/// it was when this returns an [`UnsupportedPatchFormat`].
+pub(crate) fn apply(
+    patch_file: &Utf8Path,
+    dir: &Utf8Path,
+    strip: u32,
+) -> Result<Vec<Utf8PathBuf>, OneOf<(IoError, UnsupportedPatchFormat)>> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let contents = fs::read_to_string(patch_file).map_err(io)?;
+    let files = parse(&contents, strip).map_err(OneOf::new)?;
+
+    let mut results: BTreeMap<&Utf8Path, Option<String>> = BTreeMap::new();
+    for file in &files {
+        let current = match results.get(file.path.as_path()) {
+            Some(result) => result.clone(),
+            None => read_target(&dir.join(&file.path)).map_err(OneOf::broaden)?,
+        };
+        let result = apply_file(file, current).map_err(OneOf::new)?;
+        results.insert(&file.path, result);
+    }
+
+    for (path, result) in &results {
+        let target = dir.join(path);
+        match result {
+            Some(text) => {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(io)?;
+                }
+                replace_file(&target, text).map_err(io)?;
+            }
+            None => match fs::remove_file(&target) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(io(e)),
+                _ => {}
+            },
+        }
+    }
+
+    Ok(results.into_keys().map(ToOwned::to_owned).collect())
+}
+
+/// Split the unified diff `contents` into the hunks for each file, with `strip` leading
+/// components stripped from its paths.  Lines outside of hunks that are not file headers
+/// are skipped, as `patch` skips them, unless they are [`UNSUPPORTED_HEADERS`].
+fn parse(contents: &str, strip: u32) -> Result<Vec<FilePatch<'_>>, UnsupportedPatchFormat> {
+    let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+    let mut files = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some(header) = UNSUPPORTED_HEADERS.iter().find(|h| line.starts_with(**h)) {
+            return Err(unsupported(format!("it has `{}` lines", header.trim_end())));
+        }
+        let (Some(old), Some(new)) = (
+            line.strip_prefix("--- "),
+            lines.get(i + 1).and_then(|next| next.strip_prefix("+++ ")),
+        ) else {
+            i += 1;
+            continue;
+        };
+        i += 2;
+
+        let old = header_path(old, strip)?;
+        let new = header_path(new, strip)?;
+        let (path, change) = match (old, new) {
+            (Some(old), Some(new)) if old == new => (new, Change::Modify),
+            (Some(old), Some(new)) => {
+                return Err(unsupported(format!("it renames `{old}` to `{new}`")));
+            }
+            (None, Some(new)) => (new, Change::Create),
+            (Some(old), None) => (old, Change::Delete),
+            (None, None) => return Err(unsupported("`/dev/null` is both its old and new file")),
+        };
+
+        let mut hunks = Vec::new();
+        while let Some(header) = lines.get(i).and_then(|line| HunkHeader::parse(line)) {
+            let (hunk, next) = parse_hunk(&lines, i + 1, header, &path)?;
+            hunks.push(hunk);
+            i = next;
+        }
+        if hunks.is_empty() {
+            return Err(unsupported(format!("it has no hunks for `{path}`")));
+        }
+        files.push(FilePatch {
+            path,
+            change,
+            hunks,
+        });
+    }
+
+    if files.is_empty() {
+        return Err(unsupported("it has no `---`/`+++` file headers"));
+    }
+    Ok(files)
+}
+
+/// Parse the lines of the hunk with `header`, starting at `lines[start]`, returning it and
+/// the index of the line after it.
+fn parse_hunk<'a>(
+    lines: &[&'a str],
+    start: usize,
+    header: HunkHeader,
+    path: &Utf8Path,
+) -> Result<(Hunk<'a>, usize), UnsupportedPatchFormat> {
+    let malformed = || unsupported(format!("a hunk for `{path}` is malformed"));
+
+    let mut hunk = Hunk {
+        header,
+        old: Vec::new(),
+        new: Vec::new(),
+    };
+    let (mut old_left, mut new_left) = (header.old_count, header.new_count);
+    // Which sides the last line went to, for a `\ No newline at end of file` after it.
+    let mut last = (false, false);
+    let mut i = start;
+
+    while let Some(&line) = lines.get(i) {
+        if line.starts_with('\\') {
+            // Drop the line ending of the line before.
+            let trim = |side: &mut Vec<&'a str>| {
+                if let Some(text) = side.last_mut() {
+                    *text = text.strip_suffix('\n').unwrap_or(text);
+                }
+            };
+            if last.0 {
+                trim(&mut hunk.old);
+            }
+            if last.1 {
+                trim(&mut hunk.new);
+            }
+            i += 1;
+            continue;
+        }
+        if (old_left, new_left) == (0, 0) {
+            break;
+        }
+
+        // An empty line is a context line whose leading space was stripped.
+        let (text, sides) = match line.as_bytes().first() {
+            Some(b' ') => (&line[1..], (true, true)),
+            Some(b'\n') => (line, (true, true)),
+            Some(b'-') => (&line[1..], (true, false)),
+            Some(b'+') => (&line[1..], (false, true)),
+            _ => return Err(malformed()),
+        };
+        if sides.0 {
+            old_left = old_left.checked_sub(1).ok_or_else(malformed)?;
+            hunk.old.push(text);
+        }
+        if sides.1 {
+            new_left = new_left.checked_sub(1).ok_or_else(malformed)?;
+            hunk.new.push(text);
+        }
+        last = sides;
+        i += 1;
+    }
+
+    if (old_left, new_left) != (0, 0) {
+        return Err(malformed());
+    }
+    Ok((hunk, i))
+}
+
+/// The path in a `---` or `+++` header line, without its timestamp and with `strip`
+/// leading components stripped, or `None` for `/dev/null`.
+fn header_path(line: &str, strip: u32) -> Result<Option<Utf8PathBuf>, UnsupportedPatchFormat> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let path = line.split_once('\t').map_or(line, |(path, _)| path);
+    if path == "/dev/null" {
+        return Ok(None);
+    }
+    if path.starts_with('"') {
+        return Err(unsupported(format!("the path {path} is quoted")));
+    }
+
+    let mut components = path.split('/');
+    for _ in 0..strip {
+        if components.next().is_none() {
+            return Err(unsupported(format!(
+                "the path `{path}` has fewer than {strip} components to strip"
+            )));
+        }
+    }
+    let stripped: Vec<&str> = components.filter(|c| !c.is_empty() && *c != ".").collect();
+    if stripped.is_empty() || stripped.contains(&"..") || (strip == 0 && path.starts_with('/')) {
+        return Err(unsupported(format!("the path `{path}` leaves the crate")));
+    }
+    Ok(Some(stripped.join("/").into()))
+}
+
+/// The contents of `target`, or `None` if it does not exist.  A symlink is left to
+/// `patch`, which refuses to change a file through it.
+fn read_target(
+    target: &Utf8Path,
+) -> Result<Option<String>, OneOf<(IoError, UnsupportedPatchFormat)>> {
+    match fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            return Err(OneOf::new(unsupported(format!("`{target}` is a symlink"))));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        _ => {}
+    }
+    match fs::read(target) {
+        Ok(bytes) => String::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| OneOf::new(unsupported(format!("`{target}` is not UTF-8")))),
+        Err(e) => Err(OneOf::new(IoError(e))),
+    }
+}
+
+/// Apply the hunks of `file` to its `current` contents (`None` if it does not exist),
+/// returning its new contents, or `None` if the patch removes it.
+fn apply_file(
+    file: &FilePatch<'_>,
+    current: Option<String>,
+) -> Result<Option<String>, UnsupportedPatchFormat> {
+    let path = &file.path;
+    let current = match (file.change, current) {
+        (Change::Create, None) => String::new(),
+        (Change::Create, Some(_)) => {
+            return Err(unsupported(format!(
+                "it creates `{path}`, which already exists"
+            )));
+        }
+        (_, None) => {
+            return Err(unsupported(format!(
+                "it changes `{path}`, which does not exist"
+            )));
+        }
+        (_, Some(current)) => current,
+    };
+
+    let lines: Vec<&str> = current.split_inclusive('\n').collect();
+    let mut out: Vec<&str> = Vec::with_capacity(lines.len());
+    let mut pos = 0;
+    let mut offset: isize = 0;
+
+    for (n, hunk) in file.hunks.iter().enumerate() {
+        // A hunk that removes nothing goes after line `old_start`, any other starts at it.
+        let start = match hunk.header.old_count {
+            0 => hunk.header.old_start,
+            _ => hunk.header.old_start.saturating_sub(1),
+        };
+        let expected = start.saturating_add_signed(offset);
+        let found = find_hunk(&lines, &hunk.old, pos, expected).ok_or_else(|| {
+            unsupported(format!(
+                "hunk #{} of `{path}` does not match without fuzz",
+                n + 1
+            ))
+        })?;
+
+        out.extend_from_slice(&lines[pos..found]);
+        out.extend_from_slice(&hunk.new);
+        pos = found + hunk.old.len();
+        offset = found as isize - start as isize;
+    }
+    out.extend_from_slice(&lines[pos..]);
+
+    let result = out.concat();
+    match file.change {
+        Change::Delete if result.is_empty() => Ok(None),
+        Change::Delete => Err(unsupported(format!(
+            "it removes `{path}`, which has more lines than the patch removes"
+        ))),
+        Change::Create | Change::Modify => Ok(Some(result)),
+    }
+}
+
+/// The index of the first of `old` in `lines`, at or after `from`, closest to `expected`.
+fn find_hunk(lines: &[&str], old: &[&str], from: usize, expected: usize) -> Option<usize> {
+    let last = lines.len().checked_sub(old.len())?;
+    if from > last {
+        return None;
+    }
+    if old.is_empty() {
+        // Nothing to match: the hunk goes exactly where it says.
+        return (from..=last).contains(&expected).then_some(expected);
+    }
+
+    let expected = expected.clamp(from, last);
+    let matches = |at: usize| lines[at..at + old.len()] == *old;
+    (0..=last - from).find_map(|distance| {
+        [
+            expected.checked_add(distance),
+            expected.checked_sub(distance),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|&at| (from..=last).contains(&at) && matches(at))
+    })
+}
+
+/// Replace `target` with a new file holding `contents`, with the permissions of the file
+/// it replaces, if any.  Like `patch`, this never writes through a hard link, which the
+/// patched copy of a crate may be made of.
+fn replace_file(target: &Utf8Path, contents: &str) -> io::Result<()> {
+    let name = target.file_name().unwrap_or_default();
+    let tmp = target.with_file_name(format!(".{name}.stitch-tmp"));
+    fs::write(&tmp, contents)?;
+    if let Ok(metadata) = fs::metadata(target) {
+        fs::set_permissions(&tmp, metadata.permissions())?;
+    }
+    fs::rename(&tmp, target)
+}
+
+fn unsupported(what: impl Into<String>) -> UnsupportedPatchFormat {
+    UnsupportedPatchFormat(what.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "# A comment, skipped.\n\
+                         --- a/src/lib.rs\t2024-03-01 12:00:00\n\
+                         +++ b/src/lib.rs\t2024-03-01 12:00:00\n\
+                         @@ -1,3 +1,3 @@ fn main() {\n \
+                         one\n\
+                         -two\n\
+                         +deux\n \
+                         three\n\
+                         @@ -6,2 +6,3 @@\n \
+                         six\n \
+                         seven\n\
+                         +eight\n";
+
+    fn apply_str(
+        contents: &str,
+        files: &[(&str, &str)],
+    ) -> Result<BTreeMap<String, String>, String> {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        let patch_file = dir.join("stitch.patch");
+        fs::write(&patch_file, contents).unwrap();
+
+        let changed = apply(&patch_file, dir, 1).map_err(|e| {
+            match e.narrow::<UnsupportedPatchFormat, _>() {
+                Ok(unsupported) => unsupported.0,
+                Err(e) => panic!("{e:?}"),
+            }
+        })?;
+        Ok(changed
+            .into_iter()
+            .map(|path| {
+                let text =
+                    fs::read_to_string(dir.join(&path)).unwrap_or_else(|_| "<removed>".to_string());
+                (path.into_string(), text)
+            })
+            .collect())
+    }
+
+    #[test]
+    fn hunk_changes_leave_out_context() {
+        let changes = hunk_changes(PATCH, 1).unwrap();
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|c| {
+                (
+                    c.path.as_str(),
+                    c.lines.clone(),
+                    c.removed.clone(),
+                    c.added.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("src/lib.rs", 2..=2, vec!["two\n"], vec!["deux\n"]),
+                ("src/lib.rs", 7..=8, vec![], vec!["eight\n"]),
+            ]
+        );
+        assert_eq!(changes[0].old, ["one\n", "two\n", "three\n"]);
+    }
+
+    #[test]
+    fn parse_hunk_headers() {
+        assert_eq!(
+            HunkHeader::parse("@@ -1,3 +1,4 @@ fn main() {"),
+            Some(HunkHeader {
+                old_start: 1,
+                old_count: 3,
+                new_start: 1,
+                new_count: 4
+            })
+        );
+        assert_eq!(
+            HunkHeader::parse("@@ -5 +5 @@").map(|h| (h.old_count, h.new_count)),
+            Some((1, 1))
+        );
+        assert_eq!(HunkHeader::parse("@@ bogus @@"), None);
+        assert_eq!(HunkHeader::parse("@@ -1,x +1 @@"), None);
+    }
+
+    #[test]
+    fn header_paths_are_stripped() {
+        assert_eq!(
+            header_path("a/src/lib.rs\t2024-03-01\n", 1).unwrap(),
+            Some("src/lib.rs".into())
+        );
+        assert_eq!(
+            header_path("src/lib.rs\n", 0).unwrap(),
+            Some("src/lib.rs".into())
+        );
+        assert_eq!(header_path("/dev/null\n", 1).unwrap(), None);
+        assert!(header_path("lib.rs\n", 2).is_err());
+        assert!(header_path("a/../escape.rs\n", 1).is_err());
+        assert!(header_path("/etc/passwd\n", 0).is_err());
+        assert!(header_path("\"a/with space.rs\"\n", 1).is_err());
+    }
+
+    #[test]
+    fn applies_hunks_that_moved() {
+        // Two lines were added at the top since the patch was made.
+        let original = "zero\nzero\none\ntwo\nthree\nfour\nfive\nsix\nseven\n";
+        let result = apply_str(PATCH, &[("src/lib.rs", original)]).unwrap();
+        assert_eq!(
+            result["src/lib.rs"],
+            "zero\nzero\none\ndeux\nthree\nfour\nfive\nsix\nseven\neight\n"
+        );
+    }
+
+    #[test]
+    fn creates_and_removes_files() {
+        let patch = "--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,2 @@\n+a\n+b\n\
+                     --- a/old.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-gone\n";
+        let result = apply_str(patch, &[("old.txt", "gone\n")]).unwrap();
+        assert_eq!(result["new.txt"], "a\nb\n");
+        assert_eq!(result["old.txt"], "<removed>");
+    }
+
+    #[test]
+    fn no_newline_at_end_of_file() {
+        let patch = "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n";
+        let result = apply_str(patch, &[("x", "a\nb")]).unwrap();
+        assert_eq!(result["x"], "a\nc\n");
+
+        let patch = "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+a\n\\ No newline at end of file\n";
+        let result = apply_str(patch, &[("x", "a\n")]).unwrap();
+        assert_eq!(result["x"], "a");
+    }
+
+    #[test]
+    fn leaves_what_it_cannot_apply_untouched() {
+        let original = "one\n2\nthree\nfour\nfive\nsix\nseven\n";
+        assert_eq!(
+            apply_str(PATCH, &[("src/lib.rs", original)]).unwrap_err(),
+            "hunk #1 of `src/lib.rs` does not match without fuzz"
+        );
+
+        let rename = "diff --git a/x b/y\nsimilarity index 100%\nrename from x\nrename to y\n";
+        assert_eq!(
+            apply_str(rename, &[]).unwrap_err(),
+            "it has `rename from` lines"
+        );
+        assert_eq!(
+            apply_str("*** a/x\n--- b/x\n", &[]).unwrap_err(),
+            "it has no `---`/`+++` file headers"
+        );
+        assert_eq!(
+            apply_str("--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n-a\n", &[("x", "a\n")]).unwrap_err(),
+            "a hunk for `x` is malformed"
+        );
+    }
+
+    #[test]
+    fn nothing_is_written_unless_every_file_applies() {
+        let patch =
+            "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n--- a/y\n+++ b/y\n@@ -1 +1 @@\n-nope\n+c\n";
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join("x"), "a\n").unwrap();
+        fs::write(dir.join("y"), "y\n").unwrap();
+        fs::write(dir.join("stitch.patch"), patch).unwrap();
+
+        assert!(apply(&dir.join("stitch.patch"), dir, 1).is_err());
+        assert_eq!(fs::read_to_string(dir.join("x")).unwrap(), "a\n");
+    }
+}
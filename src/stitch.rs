@@ -1,27 +1,152 @@
-use std::collections::HashMap;
-use std::process::Command;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Output};
+use std::sync::OnceLock;
+use std::time::Duration;
 
 use camino::{Utf8Path, Utf8PathBuf};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use terrors::OneOf;
+use tempfile::TempDir;
+use terrors::{E4, OneOf};
+use tracing::{debug, debug_span, error, info};
 
-use crate::error::{AstGrepFailed, IoError, PatchFailed};
+use crate::WORKSPACE_ROOT_OVERRIDE_ENV;
+use crate::config::{
+    CONFIG_FILE, PackageMetadata, PatchBackend, PatchConfig, StitchConfig, ToolsConfig,
+    WorkspaceMetadata,
+};
+use crate::error::{
+    AstGrepFailed, ChecksumMismatch, IoError, PatchFailed, ScriptFailed, UnsupportedPatchFormat,
+};
+use crate::fs::{
+    CopyOptions, compare_dirs, copy_dir_recursive, copy_jobs, copy_sources, rust_files, sync_files,
+    temp_dir,
+};
+use crate::hash::sha256_hex;
+use crate::output::OutputContext;
+use crate::patch;
+use crate::tools::{self, Tool, output_with_timeout};
+use crate::wrapper::update_patched_dir;
 
-/// Print a cargo-style status line to stderr.
+/// Print a cargo-style status line to stderr, or a `status` event if `output` asks for
+/// JSON.  With `--quiet`, only errors are printed.
 ///
-/// Format: bold yellow `status` right-aligned to 12 characters, followed by the message.
-fn cargo_status(status: &str, message: &str) {
+/// Format: `status` right-aligned to 12 characters, in bold yellow when colour is on,
+/// followed by the message.
+pub(crate) fn cargo_status(output: &OutputContext, status: &str, message: &str) {
     use std::io::Write;
 
+    if output.is_quiet() && status != "Error" {
+        return;
+    }
+    if output.is_json() {
+        output.status_event(status, message);
+        return;
+    }
+
     let mut stderr = std::io::stderr().lock();
-    let _ = writeln!(stderr, "\x1b[1;33m{status:>12}\x1b[0m {message}");
+    if output.use_color() {
+        let _ = writeln!(stderr, "\x1b[1;33m{status:>12}\x1b[0m {message}");
+    } else {
+        let _ = writeln!(stderr, "{status:>12} {message}");
+    }
+}
+
+/// The ways applying stitches can fail.
+pub(crate) type ApplyError = OneOf<(IoError, PatchFailed, AstGrepFailed, ScriptFailed)>;
+
+/// The stitch file that caused `e`, if it was caused by one.
+pub(crate) fn failed_stitch(e: &ApplyError) -> Option<&Utf8Path> {
+    match e.as_enum() {
+        E4::A(_) => None,
+        E4::B(PatchFailed { file, .. })
+        | E4::C(AstGrepFailed { file, .. })
+        | E4::D(ScriptFailed { file, .. }) => Some(file),
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "path")]
 pub enum Stitch {
     Patch(Utf8PathBuf),
     SgRule(Utf8PathBuf),
+    /// A `sed -E` script, run over every `.rs` file of the crate.
+    SedRule(Utf8PathBuf),
+    /// A bash script, run in the crate directory, for changes that the other kinds of
+    /// stitch cannot express.
+    Script(Utf8PathBuf),
+}
+
+/// The kind of a [`Stitch`], which decides the tool that applies it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StitchKind {
+    /// A `.patch` file.
+    Patch,
+    /// An ast-grep rule, in a `.yaml`, `.yml` or `.toml` file.
+    SgRule,
+    /// A `sed -E` script, in a `.sed` file.
+    SedRule,
+    /// A bash script, in a `.sh` file.
+    Script,
+}
+
+impl StitchKind {
+    /// The name of the kind, as shown by `cargo stitch list` and in JSON diagnostics.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StitchKind::Patch => "patch",
+            StitchKind::SgRule => "sg-rule",
+            StitchKind::SedRule => "sed",
+            StitchKind::Script => "script",
+        }
+    }
+}
+
+impl std::fmt::Display for StitchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// The suffix `sed -i` gives its backup copies, which are removed once it is done.  GNU
+/// and BSD `sed` disagree on how to spell `-i` without a backup, but agree on this form.
+const SED_BACKUP_SUFFIX: &str = ".cargo-stitch-orig";
+
+/// How many files to pass to a single `sed` invocation, to stay well below `ARG_MAX`.
+const SED_FILES_PER_RUN: usize = 256;
+
+/// Write the TOML ast-grep rule `file` to a temporary file as YAML, which is all
+/// `ast-grep scan -r` reads.  The rule is written as JSON, which YAML parsers accept.
+fn toml_rule_as_yaml(file: &Utf8Path) -> Result<tempfile::NamedTempFile, ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let contents = std::fs::read_to_string(file).map_err(io)?;
+    let rule: serde_json::Value = toml::from_str(&contents).map_err(|e| {
+        OneOf::new(AstGrepFailed {
+            file: file.to_owned(),
+            output: format!("invalid TOML rule: {e}"),
+        })
+    })?;
+    let mut yaml = tempfile::Builder::new()
+        .suffix(".yml")
+        .tempfile()
+        .map_err(io)?;
+    serde_json::to_writer(&mut yaml, &rule).map_err(|e| io(e.into()))?;
+    Ok(yaml)
+}
+
+/// Whether `sed` supports `--sandbox` (GNU sed 4.3 and later), which rejects the `e`,
+/// `r` and `w` commands so that a script can only rewrite the files it is given.
+pub(crate) fn sed_has_sandbox() -> bool {
+    static SANDBOX: OnceLock<bool> = OnceLock::new();
+    *SANDBOX.get_or_init(|| {
+        Command::new("sed")
+            .args(["--sandbox", "-n", "p", "/dev/null"])
+            .output()
+            .is_ok_and(|output| output.status.success())
+    })
 }
 
 impl Stitch {
@@ -29,50 +154,238 @@ impl Stitch {
         match path.extension() {
             Some("patch") => Some(Stitch::Patch(path)),
             Some("yaml" | "yml") => Some(Stitch::SgRule(path)),
+            // `stitch.toml` holds the settings of the stitches next to it.
+            Some("toml") if path.file_name() != Some(CONFIG_FILE) => Some(Stitch::SgRule(path)),
+            Some("sed") => Some(Stitch::SedRule(path)),
+            Some("sh") => Some(Stitch::Script(path)),
             _ => None,
         }
     }
 
     pub fn path(&self) -> &Utf8Path {
         match self {
-            Stitch::Patch(p) | Stitch::SgRule(p) => p.as_path(),
+            Stitch::Patch(p) | Stitch::SgRule(p) | Stitch::SedRule(p) | Stitch::Script(p) => {
+                p.as_path()
+            }
+        }
+    }
+
+    /// The external tool that applies this stitch.
+    pub fn tool(&self) -> &'static Tool {
+        match self {
+            Stitch::Patch(_) => &tools::PATCH,
+            Stitch::SgRule(_) => &tools::AST_GREP,
+            Stitch::SedRule(_) => &tools::SED,
+            Stitch::Script(_) => &tools::BASH,
+        }
+    }
+
+    /// The metadata in the front matter of this stitch, which only `.patch` files have,
+    /// completed with what the mail header of a patch from `git format-patch` says.
+    pub fn read_meta(&self) -> std::io::Result<StitchMeta> {
+        match self {
+            Stitch::Patch(file) => {
+                let contents = std::fs::read_to_string(file)?;
+                let (front_matter, body) = split_front_matter(&contents);
+                let meta = front_matter.map(StitchMeta::parse).unwrap_or_default();
+                Ok(match split_mail_header(body).0 {
+                    Some(mail) => meta.or(StitchMeta::from_mail(mail)),
+                    None => meta,
+                })
+            }
+            Stitch::SgRule(_) | Stitch::SedRule(_) | Stitch::Script(_) => Ok(StitchMeta::default()),
+        }
+    }
+
+    /// The kind of stitch.
+    pub fn kind(&self) -> StitchKind {
+        match self {
+            Stitch::Patch(_) => StitchKind::Patch,
+            Stitch::SgRule(_) => StitchKind::SgRule,
+            Stitch::SedRule(_) => StitchKind::SedRule,
+            Stitch::Script(_) => StitchKind::Script,
+        }
+    }
+
+    /// A one-line summary of this stitch for status output: its kind and file name, and
+    /// the first line of the description from its metadata if it has one, e.g.
+    /// `[patch] 001-fix.patch: "Fix segfault in parser (see issue #42)"`.
+    pub fn describe(&self) -> String {
+        let filename = self.path().file_name().unwrap_or(self.path().as_str());
+        let meta = self.read_meta().unwrap_or_default();
+        let description = meta.description.as_deref().and_then(|d| d.lines().next());
+        match description {
+            Some(description) => format!("[{}] {filename}: \"{description}\"", self.kind()),
+            None => format!("[{}] {filename}", self.kind()),
+        }
+    }
+
+    /// Print that this stitch, of `set`, was applied, along with the `output` of its tool
+    /// where that says more.
+    fn report_applied(&self, set: &StitchSet, output: &Output, out: &OutputContext) {
+        if out.is_json() {
+            out.stitch_applied(&set.pkg_name, self);
+            return;
+        }
+        match self {
+            Stitch::Patch(_) => cargo_status(out, "Patching", &self.describe()),
+            Stitch::SedRule(_) => cargo_status(out, "Substituting", &self.describe()),
+            Stitch::Script(_) => cargo_status(out, "Running", &self.describe()),
+            Stitch::SgRule(_) if out.is_verbose() => {
+                // ast-grep's own lines follow below, as it printed them
+                cargo_status(out, "Stitching", &self.describe());
+            }
+            Stitch::SgRule(_) => {
+                // Reformat ast-grep's stderr lines in cargo style
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                for line in stderr.lines() {
+                    if line.starts_with("Applied") {
+                        cargo_status(out, "Applied", line.trim_start_matches("Applied").trim());
+                    } else if !line.is_empty() {
+                        cargo_status(out, "Stitching", line.trim());
+                    }
+                }
+            }
+        }
+        if out.is_verbose() {
+            print_tool_output(output);
         }
     }
 
-    pub fn apply(
+    /// Apply this stitch to `dir` without printing any status output.
+    pub fn apply_quietly(&self, dir: &Utf8Path, set: &StitchSet) -> Result<(), ApplyError> {
+        self.run_tool(dir, set, false).map(|_| ())
+    }
+
+    /// Apply this stitch, with the options of `set`, to a scratch copy of `src_dir` and
+    /// report which files it changes, leaving `src_dir` itself alone.
+    pub fn validate(
+        &self,
+        src_dir: &Utf8Path,
+        set: &StitchSet,
+    ) -> Result<ValidationResult, ApplyError> {
+        let io = |e| OneOf::new(IoError(e));
+        let (_guard, scratch) = temp_dir().map_err(io)?;
+        copy_dir_recursive(src_dir, &scratch, &CopyOptions::default()).map_err(io)?;
+
+        self.apply_quietly(&scratch, set)?;
+
+        // The copy leaves out `target` and `.git`, which must not count as removed.
+        let changed_files = compare_dirs(src_dir, &scratch, &["target", ".git"]).map_err(io)?;
+        Ok(ValidationResult {
+            applied: !changed_files.is_empty(),
+            changed_files: changed_files
+                .into_iter()
+                .map(Utf8PathBuf::into_std_path_buf)
+                .collect(),
+        })
+    }
+
+    /// Run the tool for this stitch against `dir`, returning its output on success, or
+    /// `None` if there was nothing to apply (a patch with nothing but comments).
+    /// `verbose` asks the tool to say more than it does by default, where it can.
+    fn run_tool(
         &self,
         dir: &Utf8Path,
-    ) -> Result<(), OneOf<(IoError, PatchFailed, AstGrepFailed)>> {
+        set: &StitchSet,
+        verbose: bool,
+    ) -> Result<Option<Output>, ApplyError> {
+        let timeout = tools::stitch_timeout();
         match self {
             Stitch::Patch(file) => {
-                let output = Command::new("patch")
-                    .args(["-s", "-p1"])
-                    .arg("-i")
-                    .arg(file.as_str())
-                    .arg("-d")
-                    .arg(dir.as_str())
-                    .output()
-                    .map_err(|e| OneOf::new(IoError(e)))?;
+                let strip = read_strip_header(file).map_err(|e| OneOf::new(IoError(e)))?;
+                let prepared = prepare_patch(file).map_err(|e| OneOf::new(IoError(e)))?;
+                let input = match &prepared {
+                    PatchInput::File => file.as_path(),
+                    PatchInput::Copy { path, .. } => path,
+                    PatchInput::Empty => return Ok(None),
+                };
+
+                // The built-in applier goes first, leaving to `patch` the patches it does
+                // not support, and any `extra_args`, which are options for `patch`.
+                let mut unsupported = None;
+                if set.patch.backend() == PatchBackend::Patch && set.patch.extra_args.is_empty() {
+                    match patch::apply(input, dir, set.patch.strip_level(strip)) {
+                        Ok(changed) => return Ok(Some(builtin_patch_output(&changed, verbose))),
+                        Err(e) => {
+                            unsupported = Some(
+                                e.narrow::<UnsupportedPatchFormat, _>()
+                                    .map_err(OneOf::broaden)?,
+                            );
+                        }
+                    }
+                }
+
+                let output = match set.patch.backend() {
+                    PatchBackend::Patch => {
+                        let mut patch = Command::new("patch");
+                        if !verbose {
+                            patch.arg("-s");
+                        }
+                        patch
+                            .args(set.patch.args(strip))
+                            .arg("-i")
+                            .arg(input.as_str())
+                            .arg("-d")
+                            .arg(dir.as_str());
+                        output_with_timeout(&mut patch, tools::PATCH.name, file, timeout)
+                    }
+                    PatchBackend::Git => {
+                        let mut git = Command::new(tools::GIT.name);
+                        git.arg("apply");
+                        if verbose {
+                            git.arg("-v");
+                        }
+                        git.args(set.patch.args(strip))
+                            .arg(input.as_str())
+                            .current_dir(dir);
+                        // Keep git from finding a repository around `dir` (the patched
+                        // copy is usually inside the workspace's), which would make it
+                        // apply paths relative to that repository's root.
+                        if let Some(parent) = dir.parent() {
+                            git.env("GIT_CEILING_DIRECTORIES", parent);
+                        }
+                        output_with_timeout(&mut git, tools::GIT.name, file, timeout)
+                    }
+                }
+                .map_err(|e| match &unsupported {
+                    // Without `patch`, say why it was needed.
+                    Some(unsupported) if e.kind() == std::io::ErrorKind::NotFound => {
+                        OneOf::new(PatchFailed::new(
+                            file.clone(),
+                            format!("{unsupported}, and `patch` is not installed\n"),
+                        ))
+                    }
+                    _ => OneOf::new(IoError(e)),
+                })?;
 
                 if !output.status.success() {
                     let tool_output = [output.stdout, output.stderr].concat();
                     let output = String::from_utf8_lossy(&tool_output).into_owned();
-                    return Err(OneOf::new(PatchFailed {
-                        file: file.clone(),
-                        output,
-                    }));
+                    let failed = PatchFailed::new(file.clone(), output);
+                    // The patched directory may be gone by the time the error is
+                    // reported, so show what was rejected now.
+                    if verbose {
+                        print_first_reject(dir, &failed.reject_files);
+                    }
+                    return Err(OneOf::new(failed));
                 }
 
-                let filename = file.file_name().unwrap_or_default();
-                cargo_status("Patching", filename);
+                Ok(Some(output))
             }
             Stitch::SgRule(file) => {
-                let output = Command::new("ast-grep")
-                    .args(["scan", "-r"])
-                    .arg(file.as_str())
+                let yaml_rule = match file.extension() {
+                    Some("toml") => Some(toml_rule_as_yaml(file)?),
+                    _ => None,
+                };
+                let rule = yaml_rule.as_ref().map_or(file.as_std_path(), |f| f.path());
+                let mut sg = Command::new(tools::AST_GREP.name);
+                sg.args(["scan", "-r"])
+                    .arg(rule)
                     .arg("--update-all")
-                    .arg(dir.as_str())
-                    .output()
+                    .args(&set.sg_args)
+                    .arg(dir.as_str());
+                let output = output_with_timeout(&mut sg, tools::AST_GREP.name, file, timeout)
                     .map_err(|e| OneOf::new(IoError(e)))?;
 
                 if !output.status.success() {
@@ -84,223 +397,2712 @@ impl Stitch {
                     }));
                 }
 
-                // Reformat ast-grep's stderr lines in cargo style
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                for line in stderr.lines() {
-                    if line.starts_with("Applied") {
-                        cargo_status("Applied", line.trim_start_matches("Applied").trim());
-                    } else if !line.is_empty() {
-                        cargo_status("Stitching", line.trim());
-                    }
-                }
+                Ok(Some(output))
             }
+            Stitch::SedRule(file) => run_sed(file, dir, timeout).map(Some),
+            Stitch::Script(file) => run_script(file, dir, set, timeout).map(Some),
         }
-        Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct StitchSet {
-    stitches: Vec<Stitch>,
+/// What `patch` would have printed for the `changed` files, in place of the output of
+/// the built-in applier, which prints nothing.
+fn builtin_patch_output(changed: &[Utf8PathBuf], verbose: bool) -> Output {
+    let mut output = empty_output();
+    if verbose {
+        for path in changed {
+            output
+                .stdout
+                .extend_from_slice(format!("patching file {path}\n").as_bytes());
+        }
+    }
+    output
 }
 
-impl StitchSet {
-    /// Scan all `stitches/*/` subdirectories at once and return a map of `pkg_name` to `StitchSet`.
-    pub fn discover_all(
-        stitches_dir: &Utf8Path,
-    ) -> Result<HashMap<String, StitchSet>, OneOf<(IoError,)>> {
-        if !stitches_dir.is_dir() {
-            return Ok(HashMap::new());
-        }
+/// The output of a tool that was not run.
+fn empty_output() -> Output {
+    Output {
+        status: ExitStatus::default(),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    }
+}
 
-        let io = |e| OneOf::new(IoError(e));
+/// What applying a stitch did, as reported by [`StitchSet::apply_with_progress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StitchResult {
+    /// The stitch was applied.
+    Applied,
+    /// There was nothing to apply: a patch with nothing but comments.
+    Skipped,
+    /// The stitch could not be applied, with what its tool printed (stdout, then
+    /// stderr), or the I/O error that kept it from running.
+    Failed(String),
+}
 
-        let mut pkg_dirs: Vec<_> = stitches_dir
-            .read_dir_utf8()
-            .map_err(io)?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(io)?;
+impl StitchResult {
+    fn from_error(e: &ApplyError) -> Self {
+        StitchResult::Failed(match e.as_enum() {
+            E4::A(e) => e.to_string(),
+            E4::B(PatchFailed { output, .. })
+            | E4::C(AstGrepFailed { output, .. })
+            | E4::D(ScriptFailed { output, .. }) => output.clone(),
+        })
+    }
+}
 
-        pkg_dirs.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+/// What applying a stitch to a scratch copy of a source directory did, as reported by
+/// [`Stitch::validate`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationResult {
+    /// Whether the stitch changed anything: `false` for a stitch that applies without
+    /// changes, like an ast-grep rule that matches nothing.
+    pub applied: bool,
+    /// The files the stitch modified, created or removed, relative to the source
+    /// directory, in path order.
+    pub changed_files: Vec<PathBuf>,
+}
 
-        pkg_dirs
-            .into_iter()
-            .filter(|e| e.file_type().is_ok_and(|ft| ft.is_dir()))
-            .map(|entry| {
-                let pkg_name = entry.file_name().to_string();
-                let stitches = Self::discover_in(entry.path())?;
-                Ok((pkg_name, StitchSet { stitches }))
-            })
-            .filter(|result| match result {
-                Ok((_, set)) => !set.stitches.is_empty(),
-                Err(_) => true,
-            })
-            .collect()
+/// Two patches of a [`StitchSet`] that change the same lines of a file, as reported by
+/// [`StitchSet::conflict_check`].
+#[derive(Debug, Clone)]
+pub struct StitchConflict<'a> {
+    /// The patch applied first.
+    pub stitch_a: &'a Stitch,
+    /// The patch applied after it, which will likely fail to apply.
+    pub stitch_b: &'a Stitch,
+    /// The file both change, relative to the crate directory.
+    pub file: PathBuf,
+    /// The lines both change, as numbered in the hunk headers of the patches.
+    pub overlapping_lines: RangeInclusive<usize>,
+}
+
+/// Whether the hunk `later` was made against the result of the hunk `earlier`, rather than
+/// the sources `earlier` changes: it expects a line that `earlier` adds or, if that adds
+/// nothing, none of those it removes.
+fn builds_on(later: &patch::HunkChange<'_>, earlier: &patch::HunkChange<'_>) -> bool {
+    if earlier.added.is_empty() {
+        !later.old.iter().any(|line| earlier.removed.contains(line))
+    } else {
+        later.old.iter().any(|line| earlier.added.contains(line))
     }
+}
 
-    fn discover_in(dir: &Utf8Path) -> Result<Vec<Stitch>, OneOf<(IoError,)>> {
-        let io = |e| OneOf::new(IoError(e));
+/// The comment that sets the strip level of a single `.patch` file, overriding the
+/// crate's `[patch] strip`, e.g. `# stitch-strip: 0` for `git diff --no-prefix` output.
+const STRIP_HEADER: &str = "# stitch-strip:";
 
-        let mut paths: Vec<_> = dir
-            .read_dir_utf8()
-            .map_err(io)?
-            .map(|e| e.map(|e| e.path().to_owned()))
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(io)?;
+/// The strip level set by a `# stitch-strip: N` line among the comment lines at the top of
+/// the patch `file`, after its front matter.  Both `patch` and `git apply` skip such lines
+/// as leading garbage.
+fn read_strip_header(file: &Utf8Path) -> std::io::Result<Option<u32>> {
+    let contents = std::fs::read_to_string(file)?;
+    let (_, body) = split_front_matter(&contents);
+    for line in body.lines() {
+        let line = line.trim_end();
+        if let Some(value) = line.strip_prefix(STRIP_HEADER) {
+            return value.trim().parse().map(Some).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{file}: invalid strip level in `{line}`"),
+                )
+            });
+        }
+        if !line.is_empty() && !line.starts_with('#') {
+            break;
+        }
+    }
+    Ok(None)
+}
 
-        paths.sort();
+/// The line that opens and closes the YAML front matter of a `.patch` file.
+const FRONT_MATTER_DELIMITER: &str = "---";
 
-        Ok(paths.into_iter().filter_map(Stitch::from_path).collect())
-    }
+/// What a `.patch` file says about itself in its YAML front matter, e.g.
+///
+/// ```text
+/// ---
+/// id: greeting
+/// description: Make the greeting louder
+/// upstream_pr: https://github.com/example/crate-a/pull/12
+/// features: [simd]
+/// ---
+/// --- a/src/lib.rs
+/// ...
+/// ```
+///
+/// Every field is optional, and unknown keys are ignored.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct StitchMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issue_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_pr: Option<String>,
+    /// The Cargo features that must all be active for the stitch to be applied, as a
+    /// flow sequence (`[simd, std]`) or a single name.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+}
 
-    pub fn file_paths(&self) -> impl Iterator<Item = &Utf8Path> {
-        self.stitches.iter().map(|s| s.path())
+impl StitchMeta {
+    /// Read the scalar fields of the front matter `yaml`, unquoting their values.
+    fn parse(yaml: &str) -> Self {
+        let mut meta = Self::default();
+        for (key, value) in yaml_top_level_keys(yaml) {
+            if key == "features" {
+                meta.features = parse_feature_list(value);
+                continue;
+            }
+            let field = match key {
+                "id" => &mut meta.id,
+                "description" => &mut meta.description,
+                "author" => &mut meta.author,
+                "issue_url" => &mut meta.issue_url,
+                "upstream_pr" => &mut meta.upstream_pr,
+                _ => continue,
+            };
+            let value = value.trim_matches(['"', '\'']);
+            if !value.is_empty() {
+                *field = Some(value.to_string());
+            }
+        }
+        meta
     }
 
-    pub fn needs_patch(&self) -> bool {
-        self.stitches.iter().any(|s| matches!(s, Stitch::Patch(_)))
-    }
+    /// Read the author and the commit message of the mail header `mail` of a patch from
+    /// `git format-patch`: the `From:` header, and the `Subject:` header (without its
+    /// `[PATCH]` prefix) followed by the body of the message as the description.
+    fn from_mail(mail: &str) -> Self {
+        let mail = mail.replace("\r\n", "\n");
+        let (headers, message) = mail.split_once("\n\n").unwrap_or((&mail, ""));
 
-    pub fn needs_sg(&self) -> bool {
-        self.stitches.iter().any(|s| matches!(s, Stitch::SgRule(_)))
+        // Long headers are folded onto lines starting with whitespace.
+        let mut unfolded: Vec<String> = Vec::new();
+        for line in headers.lines().skip(1) {
+            match unfolded.last_mut() {
+                Some(last) if line.starts_with([' ', '\t']) => {
+                    last.push(' ');
+                    last.push_str(line.trim());
+                }
+                _ => unfolded.push(line.trim_end().to_string()),
+            }
+        }
+        let header = |name: &str| {
+            unfolded.iter().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                let value = value.trim();
+                (key.eq_ignore_ascii_case(name) && !value.is_empty()).then(|| value.to_string())
+            })
+        };
+
+        let subject = header("Subject").map(|subject| {
+            match subject
+                .strip_prefix('[')
+                .and_then(|rest| rest.split_once(']'))
+            {
+                Some((prefix, rest)) if prefix.contains("PATCH") => rest.trim().to_string(),
+                _ => subject,
+            }
+        });
+        let message = message.trim();
+        let description = match subject {
+            Some(subject) if !message.is_empty() => Some(format!("{subject}\n\n{message}")),
+            Some(subject) => Some(subject),
+            None => (!message.is_empty()).then(|| message.to_string()),
+        };
+
+        Self {
+            description,
+            author: header("From"),
+            ..Self::default()
+        }
     }
 
-    pub fn apply(
-        &self,
-        dir: &Utf8Path,
-    ) -> Result<(), OneOf<(IoError, PatchFailed, AstGrepFailed)>> {
-        for stitch in &self.stitches {
-            stitch.apply(dir)?;
+    /// Each field of `self`, or of `other` where `self` does not set it.
+    fn or(self, other: Self) -> Self {
+        Self {
+            id: self.id.or(other.id),
+            description: self.description.or(other.description),
+            author: self.author.or(other.author),
+            issue_url: self.issue_url.or(other.issue_url),
+            upstream_pr: self.upstream_pr.or(other.upstream_pr),
+            features: if self.features.is_empty() {
+                other.features
+            } else {
+                self.features
+            },
         }
-        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
+/// The feature names in `value`, a YAML flow sequence such as `[simd, "std"]` or a single
+/// name.
+fn parse_feature_list(value: &str) -> Vec<String> {
+    let value = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .unwrap_or(value);
+    value
+        .split(',')
+        .map(|name| name.trim().trim_matches(['"', '\'']))
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
 
-    #[test]
-    fn stitch_from_path_patch() {
-        let s = Stitch::from_path(Utf8PathBuf::from("fix.patch"));
-        assert!(matches!(s, Some(Stitch::Patch(_))));
+/// Split the YAML front matter, between a `---` line at the very top of `contents` and
+/// the next `---` line, from the rest.  Without a closing line there is no front matter.
+pub(crate) fn split_front_matter(contents: &str) -> (Option<&str>, &str) {
+    let Some(rest) = contents
+        .strip_prefix(FRONT_MATTER_DELIMITER)
+        .and_then(|rest| {
+            rest.strip_prefix('\n')
+                .or_else(|| rest.strip_prefix("\r\n"))
+        })
+    else {
+        return (None, contents);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == FRONT_MATTER_DELIMITER {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
     }
+    (None, contents)
+}
 
-    #[test]
-    fn stitch_from_path_yaml() {
-        let s = Stitch::from_path(Utf8PathBuf::from("rule.yaml"));
-        assert!(matches!(s, Some(Stitch::SgRule(_))));
+/// Split the mail header `git format-patch` starts a patch with, from its
+/// `From <commit> <date>` line to the `---` line that ends the commit message, from the
+/// rest: the diffstat and the diff.  Without both lines there is no mail header.
+pub(crate) fn split_mail_header(body: &str) -> (Option<&str>, &str) {
+    let is_mail = body
+        .strip_prefix("From ")
+        .and_then(|rest| rest.split_once(' '))
+        .is_some_and(|(commit, _)| {
+            commit.len() >= 40 && commit.bytes().all(|b| b.is_ascii_hexdigit())
+        });
+    if !is_mail {
+        return (None, body);
     }
 
-    #[test]
-    fn stitch_from_path_yml() {
-        let s = Stitch::from_path(Utf8PathBuf::from("rule.yml"));
-        assert!(matches!(s, Some(Stitch::SgRule(_))));
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (Some(&body[..offset]), &body[offset + line.len()..]);
+        }
+        offset += line.len();
     }
+    (None, body)
+}
 
-    #[test]
-    fn stitch_from_path_txt_returns_none() {
-        assert!(Stitch::from_path(Utf8PathBuf::from("readme.txt")).is_none());
+/// How to hand a patch file to `patch` or `git apply`.
+enum PatchInput {
+    /// As it is.
+    File,
+    /// As a copy in `_dir`, which is removed when dropped.
+    Copy { path: Utf8PathBuf, _dir: TempDir },
+    /// Not at all: it has nothing but comments, like a template from `cargo stitch new`,
+    /// which both tools would reject as garbage.
+    Empty,
+}
+
+/// How the patch `file` should be handed to `patch` and `git apply`: as a copy without its
+/// front matter or the mail header of `git format-patch`, which they would otherwise have
+/// to skip, and with `\n` line endings if it was saved with `\r\n` ones (e.g. on
+/// Windows), which they reject.
+fn prepare_patch(file: &Utf8Path) -> std::io::Result<PatchInput> {
+    let contents = std::fs::read_to_string(file)?;
+    let (front_matter, body) = split_front_matter(&contents);
+    let (mail, body) = split_mail_header(body);
+    if body
+        .lines()
+        .all(|line| line.trim().is_empty() || line.starts_with('#'))
+    {
+        return Ok(PatchInput::Empty);
+    }
+    let crlf = has_crlf_headers(body);
+    if front_matter.is_none() && mail.is_none() && !crlf {
+        return Ok(PatchInput::File);
     }
 
-    #[test]
-    fn stitch_from_path_no_extension_returns_none() {
-        assert!(Stitch::from_path(Utf8PathBuf::from("Makefile")).is_none());
+    let (tmp, dir) = temp_dir()?;
+    let prepared = dir.join(file.file_name().unwrap_or("stitch.patch"));
+    if crlf {
+        std::fs::write(&prepared, body.replace("\r\n", "\n"))?;
+    } else {
+        std::fs::write(&prepared, body)?;
     }
+    Ok(PatchInput::Copy {
+        path: prepared,
+        _dir: tmp,
+    })
+}
 
-    #[test]
-    fn stitch_path_returns_inner() {
-        let p = Utf8PathBuf::from("stitches/default/crate-a/001.patch");
-        let s = Stitch::Patch(p.clone());
-        assert_eq!(s.path(), p.as_path());
+/// Whether the patch `body` was saved with `\r\n` line endings, as told by its `---`,
+/// `+++` and `@@` lines.  Those are `\n`-terminated in a patch of files that themselves
+/// have `\r\n` line endings, whose other lines must then be left alone.
+pub(crate) fn has_crlf_headers(body: &str) -> bool {
+    body.split_inclusive('\n')
+        .filter(|line| {
+            line.starts_with("--- ") || line.starts_with("+++ ") || line.starts_with("@@ ")
+        })
+        .any(|line| line.ends_with("\r\n"))
+}
 
-        let p2 = Utf8PathBuf::from("stitches/default/crate-a/002.yaml");
-        let s2 = Stitch::SgRule(p2.clone());
-        assert_eq!(s2.path(), p2.as_path());
-    }
+/// Iterate over the `key: value` pairs at the top level of a YAML document.
+///
+/// This is a line-based scan rather than a full YAML parse, which is enough to find
+/// the scalar fields of an ast-grep rule or of a patch's front matter.
+pub(crate) fn yaml_top_level_keys(contents: &str) -> impl Iterator<Item = (&str, &str)> {
+    contents.lines().filter_map(|line| {
+        if line.starts_with([' ', '\t', '#', '-']) {
+            return None;
+        }
+        let (key, value) = line.split_once(':')?;
+        Some((key.trim(), value.trim()))
+    })
+}
 
-    #[test]
-    fn discover_all_nonexistent_dir() {
-        let result =
-            StitchSet::discover_all(Utf8Path::new("/nonexistent/stitches/default")).unwrap();
-        assert!(result.is_empty());
-    }
+/// Copy what a tool printed to stderr, stdout first.
+fn print_tool_output(output: &Output) {
+    use std::io::Write;
 
-    #[test]
-    fn discover_all_with_stitch_files() {
-        let tmp = tempfile::tempdir().unwrap();
-        let base = Utf8Path::from_path(tmp.path()).unwrap();
-        let stitches_dir = base.join("stitches");
+    let mut stderr = std::io::stderr().lock();
+    let _ = stderr.write_all(&output.stdout);
+    let _ = stderr.write_all(&output.stderr);
+}
 
-        let pkg_dir = stitches_dir.join("crate-a");
-        fs::create_dir_all(&pkg_dir).unwrap();
-        fs::write(pkg_dir.join("001.patch"), "").unwrap();
-        fs::write(pkg_dir.join("002.yaml"), "").unwrap();
+/// Copy the `.rej` files that `patch` saved in `from`, when `e` is a patch that failed, to
+/// `to`, which outlives it, and point `e` to them there.
+pub(crate) fn keep_rejects(e: ApplyError, from: &Utf8Path, to: &Utf8Path) -> ApplyError {
+    let mut failed = match e.narrow::<PatchFailed, _>() {
+        Ok(failed) => failed,
+        Err(e) => return e.broaden(),
+    };
 
-        let result = StitchSet::discover_all(&stitches_dir).unwrap();
-        assert!(result.contains_key("crate-a"));
-        assert_eq!(result["crate-a"].stitches.len(), 2);
-    }
+    let copied = failed.reject_files.iter().try_for_each(|reject| {
+        let target = to.join(reject);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(from.join(reject), target).map(drop)
+    });
+    failed.reject_files = match copied {
+        Ok(()) => failed
+            .reject_files
+            .iter()
+            .map(|reject| to.join(reject))
+            .collect(),
+        // Not saying where they are beats pointing into a directory that is about to go.
+        Err(_) => Vec::new(),
+    };
+    OneOf::new(failed)
+}
 
-    #[test]
-    fn discover_all_empty_subdir_filtered_out() {
-        let tmp = tempfile::tempdir().unwrap();
-        let base = Utf8Path::from_path(tmp.path()).unwrap();
-        let stitches_dir = base.join("stitches");
+/// Print the first of the `.rej` files `rejects`, relative to `dir`, that `patch` saved
+/// the hunks it could not apply to.
+fn print_first_reject(dir: &Utf8Path, rejects: &[Utf8PathBuf]) {
+    use std::io::Write;
 
-        let pkg_dir = stitches_dir.join("empty-crate");
-        fs::create_dir_all(&pkg_dir).unwrap();
-        // No stitch files, just a non-stitch file
-        fs::write(pkg_dir.join("readme.txt"), "").unwrap();
+    let Some(reject) = rejects.first() else {
+        return;
+    };
+    let Ok(contents) = std::fs::read_to_string(dir.join(reject)) else {
+        return;
+    };
+    let mut stderr = std::io::stderr().lock();
+    let _ = writeln!(stderr, "{reject}:");
+    let _ = stderr.write_all(contents.as_bytes());
+}
 
-        let result = StitchSet::discover_all(&stitches_dir).unwrap();
-        assert!(result.is_empty());
-    }
+/// Run the `sed -E` script `file` over every `.rs` file in `dir`, in place.
+///
+/// `sed -i` replaces each file rather than writing through it, so this is safe on a
+/// hard-linked copy of the sources.
+fn run_sed(
+    file: &Utf8Path,
+    dir: &Utf8Path,
+    timeout: Option<Duration>,
+) -> Result<Output, ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
 
-    #[test]
-    fn discover_in_returns_sorted_and_filters() {
-        let tmp = tempfile::tempdir().unwrap();
-        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+    let files: Vec<Utf8PathBuf> = rust_files(dir)
+        .map_err(io)?
+        .into_iter()
+        .map(|f| dir.join(f))
+        .collect();
 
-        fs::write(dir.join("002.yaml"), "").unwrap();
-        fs::write(dir.join("001.patch"), "").unwrap();
-        fs::write(dir.join("readme.txt"), "").unwrap();
+    let mut output = Output {
+        status: Default::default(),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+    for chunk in files.chunks(SED_FILES_PER_RUN) {
+        let mut sed = Command::new("sed");
+        if sed_has_sandbox() {
+            sed.arg("--sandbox");
+        }
+        sed.arg("-E")
+            .arg(format!("-i{SED_BACKUP_SUFFIX}"))
+            .arg("-f")
+            .arg(file.as_str())
+            .args(chunk);
+        output = output_with_timeout(&mut sed, tools::SED.name, file, timeout).map_err(io)?;
 
-        let stitches = StitchSet::discover_in(dir).unwrap();
-        assert_eq!(stitches.len(), 2);
-        assert!(matches!(&stitches[0], Stitch::Patch(p) if p.file_name() == Some("001.patch")));
-        assert!(matches!(&stitches[1], Stitch::SgRule(p) if p.file_name() == Some("002.yaml")));
+        for f in chunk {
+            match std::fs::remove_file(format!("{f}{SED_BACKUP_SUFFIX}")) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(io(e)),
+                _ => {}
+            }
+        }
+
+        if !output.status.success() {
+            let tool_output = [output.stdout, output.stderr].concat();
+            return Err(OneOf::new(ScriptFailed {
+                file: file.to_owned(),
+                interpreter: tools::SED.name,
+                output: String::from_utf8_lossy(&tool_output).into_owned(),
+            }));
+        }
+    }
+
+    Ok(output)
+}
+
+/// Run the bash script `file` in `dir`.
+///
+/// The script is told which crate it is patching through `STITCH_PKG_NAME`,
+/// `STITCH_PATCHED_DIR` and `STITCH_WORKSPACE_ROOT`, and gets an empty temporary `HOME`
+/// so that tools it runs do not read or write the user's configuration.
+fn run_script(
+    file: &Utf8Path,
+    dir: &Utf8Path,
+    set: &StitchSet,
+    timeout: Option<Duration>,
+) -> Result<Output, ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let (_home_guard, home) = temp_dir().map_err(io)?;
+
+    let mut script = Command::new(tools::BASH.name);
+    script
+        .arg(file.as_str())
+        .current_dir(dir)
+        .env("HOME", &home)
+        .env("STITCH_PKG_NAME", &set.pkg_name)
+        .env("STITCH_PATCHED_DIR", dir);
+    // An explicit `STITCH_WORKSPACE_ROOT` (see `WORKSPACE_ROOT_OVERRIDE_ENV`) is the
+    // workspace root as seen from here, so it is passed on as is.
+    if let Some(root) = &set.workspace_root
+        && std::env::var_os(WORKSPACE_ROOT_OVERRIDE_ENV).is_none()
+    {
+        script.env(WORKSPACE_ROOT_OVERRIDE_ENV, root);
+    }
+    let output = output_with_timeout(&mut script, tools::BASH.name, file, timeout).map_err(io)?;
+
+    if !output.status.success() {
+        let tool_output = [output.stdout, output.stderr].concat();
+        return Err(OneOf::new(ScriptFailed {
+            file: file.to_owned(),
+            interpreter: tools::BASH.name,
+            output: String::from_utf8_lossy(&tool_output).into_owned(),
+        }));
+    }
+
+    Ok(output)
+}
+
+/// Name of the optional file in `stitches/<crate>/` listing stitches in the order they
+/// are applied, by path relative to that directory.
+pub const ORDER_FILE: &str = ".stitch-order";
+
+/// Name of the optional file in `stitches/<crate>/` listing, one gitignore-style pattern
+/// per line, the stitch files in it that are not applied.
+pub const IGNORE_FILE: &str = ".stitch-ignore";
+
+/// Name of the optional file in `stitches/<crate>/` listing the SHA-256 of its stitch
+/// files, in the format of `sha256sum`: `<hex digest>  <path relative to that directory>`.
+pub const CHECKSUMS_FILE: &str = ".checksums";
+
+/// The settings a stitch directory, e.g. `stitches/default/`, gives every crate in it,
+/// from `stitches/stitch.toml` and `[workspace.metadata.stitch]`.
+struct SetDefaults {
+    hardlink: bool,
+    backend: Option<PatchBackend>,
+    tools: ToolsConfig,
+    strip: Option<u32>,
+    /// Whether `strip` comes from `default_strip` in `[workspace.metadata.stitch]`.
+    from_metadata: bool,
+    workspace_root: Option<Utf8PathBuf>,
+}
+
+impl SetDefaults {
+    fn load(
+        stitches_dir: &Utf8Path,
+        metadata: &WorkspaceMetadata,
+        output: &OutputContext,
+    ) -> Result<Self, OneOf<(IoError, ChecksumMismatch)>> {
+        // `stitches/stitch.toml` holds defaults shared by every set and crate.
+        let global = match stitches_dir.parent() {
+            Some(parent) => StitchConfig::load(parent, output).map_err(OneOf::broaden)?,
+            None => None,
+        };
+        let hardlink = global
+            .as_ref()
+            .and_then(|(_, c)| c.copy.hardlink)
+            .unwrap_or(false);
+        let backend = global.as_ref().and_then(|(_, c)| c.patch.backend);
+        let tools = global
+            .as_ref()
+            .map(|(_, c)| c.tools.clone())
+            .unwrap_or_default();
+        let strip = match (&global, metadata.default_strip) {
+            (Some((path, config)), Some(default_strip)) if config.patch.strip.is_some() => {
+                cargo_status(
+                    output,
+                    "Warning",
+                    &format!(
+                        "{path} sets `[patch] strip`, which takes precedence over \
+                         `default_strip = {default_strip}` in [workspace.metadata.stitch]"
+                    ),
+                );
+                config.patch.strip
+            }
+            _ => global
+                .as_ref()
+                .and_then(|(_, c)| c.patch.strip)
+                .or(metadata.default_strip),
+        };
+        let from_metadata = global.as_ref().is_none_or(|(_, c)| c.patch.strip.is_none())
+            && metadata.default_strip.is_some();
+
+        Ok(Self {
+            hardlink,
+            backend,
+            tools,
+            strip,
+            from_metadata,
+            workspace_root: stitches_dir
+                .parent()
+                .and_then(Utf8Path::parent)
+                .map(ToOwned::to_owned),
+        })
+    }
+
+    /// Discover the stitches of `pkg_name` in `dir`, with these defaults.
+    fn discover(
+        &self,
+        dir: &Utf8Path,
+        pkg_name: &str,
+        metadata: &WorkspaceMetadata,
+        output: &OutputContext,
+    ) -> Result<StitchSet, OneOf<(IoError, ChecksumMismatch)>> {
+        let package = metadata.packages.get(pkg_name);
+        let mut set = StitchSet::discover_in(dir, self.hardlink, package, output)?;
+        set.pkg_name = pkg_name.to_string();
+        set.patch.backend = set.patch.backend.or(self.backend);
+        if set.patch.strip.is_none() && self.from_metadata {
+            set.manifest = metadata.manifest.clone();
+        }
+        set.patch.strip = set.patch.strip.or(self.strip);
+        set.tools.inherit(&self.tools);
+        set.workspace_root = self.workspace_root.clone();
+        Ok(set)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StitchSet {
+    /// The crate the stitches are for, as named by their directory.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pkg_name: String,
+    /// The root of the workspace the stitches were found in, for `.sh` stitches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    workspace_root: Option<Utf8PathBuf>,
+    stitches: Vec<Stitch>,
+    /// Path to the crate's `stitch.toml`, if any.  Tracked so that edits to it
+    /// (e.g. a new `[order]`) invalidate the patched directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<Utf8PathBuf>,
+    /// Whether `stitch.toml` asks for the patched directory to be kept between builds.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    keep_patched: bool,
+    /// Whether to hard-link the crate sources instead of copying them, from `[copy]` in
+    /// the crate's `stitch.toml` or, failing that, the workspace-wide one.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    hardlink: bool,
+    /// How to run `patch`, from `[patch]` in the crate's `stitch.toml`.
+    #[serde(default, skip_serializing_if = "PatchConfig::is_default")]
+    patch: PatchConfig,
+    /// The oldest tool versions the stitches work with, from `[tools]` in the crate's
+    /// `stitch.toml` and the workspace-wide one.
+    #[serde(default, skip_serializing_if = "ToolsConfig::is_default")]
+    tools: ToolsConfig,
+    /// Whether `stitch.toml` asks for a vendored crate to be stitched in place.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    no_copy: bool,
+    /// Whether `stitch.toml` says the stitches are for a crates.io dependency.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    external: bool,
+    /// Extra arguments for `ast-grep scan`, from `[package.metadata.stitch]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sg_args: Vec<String>,
+    /// The Cargo features each stitch that needs some must be built with, from `[features]`
+    /// in the crate's `stitch.toml` or the stitch's front matter.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    features: BTreeMap<Utf8PathBuf, Vec<String>>,
+    /// Path to the crate's `.stitch-order`, if it sets the order.  Tracked like `config`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    order_file: Option<Utf8PathBuf>,
+    /// Path to the crate's `.stitch-ignore`, if any.  Tracked like `config`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ignore_file: Option<Utf8PathBuf>,
+    /// The workspace's `Cargo.toml`, if `[workspace.metadata.stitch]` changes how the
+    /// stitches are applied.  Tracked like `config`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    manifest: Option<Utf8PathBuf>,
+}
+
+impl StitchSet {
+    /// Scan the `*/` subdirectories of each of `stitches_dirs` and return a map of
+    /// `pkg_name` to `StitchSet`.
+    ///
+    /// Earlier directories take priority: a crate's stitches from every directory are
+    /// [merged](Self::merge), so a stitch file replaces those with the same name in later
+    /// directories, and the crate's settings come from the first directory that has it.
+    ///
+    /// `metadata` holds the settings from `[workspace.metadata.stitch]` and
+    /// `[package.metadata.stitch]`, which the `stitch.toml` files take precedence over.
+    /// Warnings about the `stitch.toml` files are printed as `output` says.
+    pub(crate) fn discover_all(
+        stitches_dirs: &[&Utf8Path],
+        metadata: &WorkspaceMetadata,
+        output: &OutputContext,
+    ) -> Result<HashMap<String, StitchSet>, OneOf<(IoError, ChecksumMismatch)>> {
+        let mut all: HashMap<String, StitchSet> = HashMap::new();
+        for stitches_dir in stitches_dirs {
+            for (pkg_name, set) in Self::discover_dir(stitches_dir, metadata, output)? {
+                let set = match all.remove(&pkg_name) {
+                    Some(earlier) => earlier.merge(set),
+                    None => set,
+                };
+                all.insert(pkg_name, set);
+            }
+        }
+        Ok(all)
+    }
+
+    /// The stitches for `pkg_name` alone in each of `stitches_dirs`, merged as
+    /// [`discover_all`](Self::discover_all) does, or `None` if there are none.
+    ///
+    /// Only the `<pkg_name>/` subdirectory of each directory is read, so this is cheaper
+    /// than `discover_all` when one crate's stitches are needed.
+    pub(crate) fn discover_for_package(
+        stitches_dirs: &[&Utf8Path],
+        pkg_name: &str,
+        metadata: &WorkspaceMetadata,
+        output: &OutputContext,
+    ) -> Result<Option<StitchSet>, OneOf<(IoError, ChecksumMismatch)>> {
+        if !metadata.is_enabled(pkg_name) {
+            return Ok(None);
+        }
+
+        let mut found: Option<StitchSet> = None;
+        for stitches_dir in stitches_dirs {
+            let dir = stitches_dir.join(pkg_name);
+            if !dir.is_dir() {
+                continue;
+            }
+            let defaults = SetDefaults::load(stitches_dir, metadata, output)?;
+            let set = defaults.discover(&dir, pkg_name, metadata, output)?;
+            if set.stitches.is_empty() {
+                continue;
+            }
+            found = Some(match found {
+                Some(earlier) => earlier.merge(set),
+                None => set,
+            });
+        }
+        Ok(found)
+    }
+
+    /// Scan all `stitches/*/` subdirectories at once and return a map of `pkg_name` to `StitchSet`.
+    fn discover_dir(
+        stitches_dir: &Utf8Path,
+        metadata: &WorkspaceMetadata,
+        output: &OutputContext,
+    ) -> Result<HashMap<String, StitchSet>, OneOf<(IoError, ChecksumMismatch)>> {
+        if !stitches_dir.is_dir() {
+            return Ok(HashMap::new());
+        }
+
+        let io = |e| OneOf::new(IoError(e));
+
+        let mut pkg_dirs: Vec<_> = stitches_dir
+            .read_dir_utf8()
+            .map_err(io)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io)?;
+
+        pkg_dirs.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+
+        let defaults = SetDefaults::load(stitches_dir, metadata, output)?;
+
+        pkg_dirs
+            .into_iter()
+            .filter(|e| e.file_type().is_ok_and(|ft| ft.is_dir()))
+            .filter(|e| metadata.is_enabled(e.file_name()))
+            .map(|entry| {
+                let pkg_name = entry.file_name().to_string();
+                let set = defaults.discover(entry.path(), &pkg_name, metadata, output)?;
+                Ok((pkg_name, set))
+            })
+            .filter(|result| match result {
+                Ok((_, set)) => !set.stitches.is_empty(),
+                Err(_) => true,
+            })
+            .collect()
+    }
+
+    /// Discover the stitch files in `dir`, in application order, along with the
+    /// settings from its `stitch.toml` and those from `package`, the crate's
+    /// `[package.metadata.stitch]`.
+    ///
+    /// Files are sorted by name, unless `stitch.toml` has an `[order]` section, in which
+    /// case the listed files come first (in the listed order), followed by the rest.
+    ///
+    /// If `dir` has a `.checksums` file, every stitch file listed in it must match its
+    /// checksum.
+    fn discover_in(
+        dir: &Utf8Path,
+        hardlink: bool,
+        package: Option<&PackageMetadata>,
+        output: &OutputContext,
+    ) -> Result<Self, OneOf<(IoError, ChecksumMismatch)>> {
+        let io = |e| OneOf::new(IoError(e));
+
+        let mut paths = stitch_dir_files(dir).map_err(io)?;
+        let order_file = dir.join(ORDER_FILE);
+        let order = match std::fs::read_to_string(&order_file) {
+            Ok(contents) => Some(parse_order_file(&contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(io(e)),
+        };
+
+        let mut set = StitchSet {
+            hardlink,
+            ..Default::default()
+        };
+        let mut enabled = package.and_then(|p| p.enabled);
+
+        let config = StitchConfig::load(dir, output).map_err(OneOf::broaden)?;
+        let explicit_order = config
+            .as_ref()
+            .filter(|(_, config)| !config.order.stitches.is_empty());
+        match (explicit_order, order) {
+            (Some((config_path, config)), order) => {
+                if order.is_some() {
+                    cargo_status(
+                        output,
+                        "Warning",
+                        &format!(
+                            "{config_path} sets [order], which takes precedence over {order_file}"
+                        ),
+                    );
+                }
+                paths = apply_order(dir, paths, &config.order.stitches).map_err(|name| {
+                    io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("{config_path}: stitch listed in [order] not found: {name}"),
+                    ))
+                })?;
+            }
+            (None, Some(order)) => {
+                paths = apply_order(dir, paths, &order).map_err(|name| {
+                    io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("{order_file}: stitch not found: {name}"),
+                    ))
+                })?;
+                set.order_file = Some(order_file);
+            }
+            (None, None) => {}
+        }
+
+        // Ignored files are left out after ordering, so that `[order]` may still list them.
+        let ignore_file = dir.join(IGNORE_FILE);
+        match std::fs::read_to_string(&ignore_file) {
+            Ok(contents) => {
+                let patterns = parse_ignore_file(&contents);
+                paths.retain(|path| {
+                    path.strip_prefix(dir)
+                        .is_ok_and(|relative| !is_ignored(relative.as_str(), &patterns))
+                });
+                set.ignore_file = Some(ignore_file);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io(e)),
+        }
+
+        let feature_config = config
+            .as_ref()
+            .map(|(config_path, config)| (config_path.clone(), config.features.clone()));
+
+        if let Some((config_path, config)) = config {
+            set.keep_patched = config.keep_patched;
+            set.no_copy = config.no_copy;
+            set.hardlink = config.copy.hardlink.unwrap_or(hardlink);
+            set.patch = config.patch.clone();
+            set.tools = config.tools.clone();
+            set.external = config.external;
+
+            if let Some(package) = package {
+                let overridden = [
+                    (
+                        "enabled",
+                        config.enabled.is_some() && package.enabled.is_some(),
+                    ),
+                    (
+                        "strip",
+                        config.patch.strip.is_some() && package.strip.is_some(),
+                    ),
+                ];
+                for (key, _) in overridden.iter().filter(|(_, both)| *both) {
+                    cargo_status(
+                        output,
+                        "Warning",
+                        &format!(
+                            "{config_path} sets `{key}`, which takes precedence over \
+                             [package.metadata.stitch] in {}",
+                            package.manifest
+                        ),
+                    );
+                }
+            }
+            enabled = config.enabled.or(enabled);
+            set.config = Some(config_path);
+        }
+
+        if let Some(package) = package {
+            set.patch.strip = set.patch.strip.or(package.strip);
+            set.patch
+                .extra_args
+                .extend(package.extra_patch_args.iter().cloned());
+            set.sg_args = package.extra_sg_args.clone();
+        }
+
+        // A disabled crate has no stitches, so `discover_all` leaves it out.
+        if !enabled.unwrap_or(true) {
+            debug!(dir = %dir, "stitches disabled");
+            return Ok(set);
+        }
+
+        set.stitches = paths.into_iter().filter_map(Stitch::from_path).collect();
+        verify_checksums(dir, &set.stitches, output)?;
+
+        for stitch in &set.stitches {
+            let features = stitch.read_meta().map_err(io)?.features;
+            if !features.is_empty() {
+                set.features.insert(stitch.path().to_owned(), features);
+            }
+        }
+        if let Some((config_path, features)) = feature_config {
+            for (name, features) in features {
+                let path = dir.join(&name);
+                if !set.stitches.iter().any(|s| s.path() == path) {
+                    return Err(io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("{config_path}: stitch listed in [features] not found: {name}"),
+                    )));
+                }
+                set.features.insert(path, features);
+            }
+        }
+
+        debug!(dir = %dir, stitches = set.stitches.len(), "discovered stitches");
+        Ok(set)
+    }
+
+    /// The stitches in application order.
+    pub fn iter(&self) -> impl Iterator<Item = &Stitch> {
+        self.stitches.iter()
+    }
+
+    /// Add the stitches of `other`, e.g. from a user-wide stitch directory, to these, except
+    /// those with the same file name as one of these, and sort them all by file name.
+    ///
+    /// The other settings (`stitch.toml`, patch options, ...) are those of `self`.
+    pub fn merge(mut self, other: StitchSet) -> StitchSet {
+        self.features.extend(other.features);
+        for stitch in other.stitches {
+            if !self
+                .stitches
+                .iter()
+                .any(|s| s.path().file_name() == stitch.path().file_name())
+            {
+                self.stitches.push(stitch);
+            }
+        }
+        self.sort_by_file_name();
+        self
+    }
+
+    /// Like [`merge`](Self::merge), but a stitch of `other` replaces the one of `self` with
+    /// the same file name.
+    pub fn merge_override(mut self, other: StitchSet) -> StitchSet {
+        self.features.extend(other.features);
+        for stitch in other.stitches {
+            match self
+                .stitches
+                .iter_mut()
+                .find(|s| s.path().file_name() == stitch.path().file_name())
+            {
+                Some(existing) => *existing = stitch,
+                None => self.stitches.push(stitch),
+            }
+        }
+        self.sort_by_file_name();
+        self
+    }
+
+    fn sort_by_file_name(&mut self) {
+        self.stitches
+            .sort_by(|a, b| a.path().file_name().cmp(&b.path().file_name()));
+    }
+
+    /// The Cargo features `stitch`, one of this set's stitches, needs to be applied: none
+    /// if it is always applied.
+    pub fn features_of(&self, stitch: &Stitch) -> &[String] {
+        self.features.get(stitch.path()).map_or(&[], Vec::as_slice)
+    }
+
+    /// Leave out the stitches that need a Cargo feature for which `is_active` is false.
+    pub fn retain_features(&mut self, is_active: impl Fn(&str) -> bool) {
+        let features = &self.features;
+        self.stitches.retain(|stitch| {
+            features
+                .get(stitch.path())
+                .is_none_or(|needed| needed.iter().all(|feature| is_active(feature)))
+        });
+    }
+
+    /// The number of stitches.
+    pub fn len(&self) -> usize {
+        self.stitches.len()
+    }
+
+    /// Whether there are no stitches, e.g. because the crate's `stitch.toml` disables them.
+    pub fn is_empty(&self) -> bool {
+        self.stitches.is_empty()
+    }
+
+    pub fn file_paths(&self) -> impl Iterator<Item = &Utf8Path> {
+        self.stitches.iter().map(|s| s.path())
+    }
+
+    pub fn config_path(&self) -> Option<&Utf8Path> {
+        self.config.as_deref()
+    }
+
+    /// The files the patched directory is made from besides the crate's sources: the
+    /// stitch files, `stitch.toml`, `.stitch-order`, `.stitch-ignore`, and the workspace's
+    /// `Cargo.toml` if its `[workspace.metadata.stitch]` is used.
+    pub fn input_paths(&self) -> impl Iterator<Item = &Utf8Path> {
+        self.file_paths()
+            .chain(self.config_path())
+            .chain(self.order_file.as_deref())
+            .chain(self.ignore_file.as_deref())
+            .chain(self.manifest.as_deref())
+    }
+
+    /// Whether the patched directory should be updated in place rather than recreated.
+    pub fn keep_patched(&self) -> bool {
+        self.keep_patched
+    }
+
+    /// Whether a vendored crate's sources should be stitched in place, without a copy.
+    pub fn no_copy(&self) -> bool {
+        self.no_copy
+    }
+
+    /// Whether the stitches are for a crates.io dependency, in `stitches/<crate>-<version>/`.
+    pub fn is_external(&self) -> bool {
+        self.external
+    }
+
+    /// How to copy the crate sources before applying the stitches.
+    ///
+    /// ast-grep rewrites files in place, which would modify the original sources through a
+    /// hard link, and so may a script, so hard links are only used for crates with neither
+    /// ast-grep rules nor scripts.
+    pub fn copy_options(&self) -> CopyOptions {
+        CopyOptions {
+            hardlink: self.hardlink && !self.needs_sg() && !self.needs(&tools::BASH),
+        }
+    }
+
+    pub fn needs_sg(&self) -> bool {
+        self.needs(&tools::AST_GREP)
+    }
+
+    /// Whether any of the stitches is applied with `tool`.
+    pub fn needs(&self, tool: &Tool) -> bool {
+        self.stitches
+            .iter()
+            .any(|s| self.tool_for(s).name == tool.name)
+    }
+
+    /// The oldest version of `tool` the stitches work with, as `[tools]` sets it.
+    pub(crate) fn min_version<'a>(&'a self, tool: &'a Tool) -> &'a str {
+        self.tools.min_version(tool)
+    }
+
+    /// The external tool that applies `stitch`, one of this set's stitches, given the
+    /// set's `[patch] backend`.
+    pub fn tool_for(&self, stitch: &Stitch) -> &'static Tool {
+        match (stitch, self.patch.backend()) {
+            (Stitch::Patch(_), PatchBackend::Git) => &tools::GIT,
+            _ => stitch.tool(),
+        }
+    }
+
+    /// The pairs of `.patch` stitches that change the same lines of the same file, in
+    /// application order, found from their hunks without applying them.
+    ///
+    /// A patch made on top of an earlier one, which expects the lines that one adds, does
+    /// not conflict with it; one made against the same sources, which still expects the
+    /// lines the earlier one removes, does.  Patches that cannot be read or parsed are
+    /// left out, and so are the other kinds of stitch.
+    pub fn conflict_check(&self) -> Vec<StitchConflict<'_>> {
+        let contents: Vec<(&Stitch, u32, String)> = self
+            .stitches
+            .iter()
+            .filter_map(|stitch| {
+                let Stitch::Patch(file) = stitch else {
+                    return None;
+                };
+                let strip = self.patch.strip_level(read_strip_header(file).ok()?);
+                Some((stitch, strip, std::fs::read_to_string(file).ok()?))
+            })
+            .collect();
+        let changes: Vec<(&Stitch, Vec<patch::HunkChange<'_>>)> = contents
+            .iter()
+            .filter_map(|(stitch, strip, contents)| {
+                let (_, body) = split_front_matter(contents);
+                Some((*stitch, patch::hunk_changes(body, *strip).ok()?))
+            })
+            .collect();
+
+        let mut conflicts = Vec::new();
+        for (i, (stitch_a, hunks_a)) in changes.iter().enumerate() {
+            for (stitch_b, hunks_b) in &changes[i + 1..] {
+                for a in hunks_a {
+                    for b in hunks_b.iter().filter(|b| b.path == a.path) {
+                        let start = *a.lines.start().max(b.lines.start());
+                        let end = *a.lines.end().min(b.lines.end());
+                        if start <= end && !builds_on(b, a) {
+                            conflicts.push(StitchConflict {
+                                stitch_a,
+                                stitch_b,
+                                file: a.path.clone().into(),
+                                overlapping_lines: start..=end,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Apply all stitches to `dir`, in order, or none of them: they are applied to a
+    /// scratch copy of `dir`, and the files they change are only written back to `dir`
+    /// once they all have.  The `.rej` files of a patch that fails are copied to `dir`.
+    pub(crate) fn apply(&self, dir: &Utf8Path, output: &OutputContext) -> Result<(), ApplyError> {
+        let io = |e| OneOf::new(IoError(e));
+
+        let (_guard, scratch) = temp_dir().map_err(io)?;
+        copy_sources(dir, &scratch, &self.copy_options()).map_err(io)?;
+        self.apply_to_scratch(&scratch, output)
+            .map_err(|e| keep_rejects(e, &scratch, dir))?;
+
+        // The copy leaves out `target/` and `.git/`, which the stitches do not touch.
+        let changed = compare_dirs(dir, &scratch, &["target", ".git"]).map_err(io)?;
+        sync_files(&scratch, dir, &changed).map_err(io)
+    }
+
+    /// Apply all stitches to `dir`, in order, as [`StitchSet::apply`] does but without a
+    /// scratch copy, for a directory that is thrown away if one of them fails.
+    pub(crate) fn apply_to_scratch(
+        &self,
+        dir: &Utf8Path,
+        output: &OutputContext,
+    ) -> Result<(), ApplyError> {
+        self.apply_each(dir, output.is_verbose(), |stitch, result| {
+            if let Ok(tool_output) = result {
+                let empty = empty_output();
+                stitch.report_applied(self, tool_output.unwrap_or(&empty), output);
+            }
+        })
+    }
+
+    /// Apply all stitches to `dir`, in order, calling `on_stitch` with each stitch and
+    /// what applying it did, rather than printing any status output.  Stops at the first
+    /// stitch that fails, once `on_stitch` has been told.
+    pub fn apply_with_progress<F>(&self, dir: &Utf8Path, mut on_stitch: F) -> Result<(), ApplyError>
+    where
+        F: FnMut(&Stitch, StitchResult),
+    {
+        self.apply_each(dir, false, |stitch, result| {
+            let result = match result {
+                Ok(Some(_)) => StitchResult::Applied,
+                Ok(None) => StitchResult::Skipped,
+                Err(e) => StitchResult::from_error(e),
+            };
+            on_stitch(stitch, result);
+        })
+    }
+
+    /// Run the tool of each stitch against `dir`, in order, passing its output (`None`
+    /// if there was nothing to apply) or its error to `on_stitch`.
+    fn apply_each(
+        &self,
+        dir: &Utf8Path,
+        verbose: bool,
+        mut on_stitch: impl FnMut(&Stitch, Result<Option<&Output>, &ApplyError>),
+    ) -> Result<(), ApplyError> {
+        let _span = debug_span!("apply", pkg_name = %self.pkg_name, dir = %dir).entered();
+        for stitch in &self.stitches {
+            let tool = self.tool_for(stitch).name;
+            debug!(stitch = %stitch.path(), tool, "applying stitch");
+            match stitch.run_tool(dir, self, verbose) {
+                Ok(output) => {
+                    info!(stitch = %stitch.path(), applied = output.is_some(), "applied stitch");
+                    on_stitch(stitch, Ok(output.as_ref()));
+                }
+                Err(e) => {
+                    error!(stitch = %stitch.path(), error = %e, "failed to apply stitch");
+                    on_stitch(stitch, Err(&e));
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply all stitches to `dir` without printing any status output.
+    pub fn apply_quietly(&self, dir: &Utf8Path) -> Result<(), ApplyError> {
+        for stitch in &self.stitches {
+            stitch.apply_quietly(dir, self)?;
+        }
+        Ok(())
+    }
+
+    /// Bring the patched directory under `patched_root` of every crate in `manifest` up to
+    /// date, applying the stitches of different crates concurrently, on `CARGO_STITCH_JOBS`
+    /// threads.  `sources` gives the source directory of each crate.
+    ///
+    /// Every crate is processed even if some fail; the failures are returned together,
+    /// sorted by crate name.
+    pub(crate) fn apply_all_parallel(
+        manifest: &HashMap<String, StitchSet>,
+        sources: &HashMap<String, Utf8PathBuf>,
+        patched_root: &Utf8Path,
+        keep_patched: bool,
+        output: &OutputContext,
+    ) -> Result<(), Vec<(String, ApplyError)>> {
+        let update = |(pkg_name, stitch_set): (&String, &StitchSet)| {
+            let result = match sources.get(pkg_name) {
+                Some(manifest_dir) => update_patched_dir(
+                    pkg_name,
+                    patched_root,
+                    manifest_dir,
+                    stitch_set,
+                    keep_patched,
+                    output,
+                ),
+                None => Err(OneOf::new(IoError(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("crate `{pkg_name}` is not a member of the workspace"),
+                )))),
+            };
+            match result {
+                Ok(true) => cargo_status(output, "Stitched", pkg_name),
+                Ok(false) => cargo_status(output, "Fresh", pkg_name),
+                Err(_) => {}
+            }
+            result.err().map(|e| (pkg_name.clone(), e))
+        };
+
+        // On as many threads as `CARGO_STITCH_JOBS` allows, or one crate after another if
+        // there are none to be had.
+        let mut failures: Vec<_> = match rayon::ThreadPoolBuilder::new()
+            .num_threads(copy_jobs())
+            .build()
+        {
+            Ok(pool) => pool.install(|| manifest.par_iter().filter_map(update).collect()),
+            Err(_) => manifest.iter().filter_map(update).collect(),
+        };
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            failures.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Err(failures)
+        }
+    }
+}
+
+/// The files in a crate's stitch directory `dir` and, recursively, its subdirectories,
+/// sorted by path.  Hidden subdirectories are skipped.
+pub(crate) fn stitch_dir_files(dir: &Utf8Path) -> std::io::Result<Vec<Utf8PathBuf>> {
+    fn walk(dir: &Utf8Path, files: &mut Vec<Utf8PathBuf>) -> std::io::Result<()> {
+        for entry in dir.read_dir_utf8()? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                files.push(entry.into_path());
+            } else if !entry.file_name().starts_with('.') {
+                walk(entry.path(), files)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(dir, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Check `stitches`, found in `dir`, against the checksums in its `.checksums` file, if it
+/// has one.  A stitch it does not list is only warned about, but one it lists must exist.
+fn verify_checksums(
+    dir: &Utf8Path,
+    stitches: &[Stitch],
+    output: &OutputContext,
+) -> Result<(), OneOf<(IoError, ChecksumMismatch)>> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let checksums_file = dir.join(CHECKSUMS_FILE);
+    let contents = match std::fs::read_to_string(&checksums_file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(io(e)),
+    };
+    let checksums = parse_checksums(&contents).map_err(|line| {
+        io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{checksums_file}: expected `<sha256>  <file>`, got `{line}`"),
+        ))
+    })?;
+
+    for (name, expected) in &checksums {
+        let file = dir.join(name);
+        let contents = std::fs::read(&file).map_err(|e| {
+            io(std::io::Error::new(
+                e.kind(),
+                format!("{checksums_file}: cannot read {name}: {e}"),
+            ))
+        })?;
+        let got = sha256_hex(&contents);
+        if !got.eq_ignore_ascii_case(expected) {
+            return Err(OneOf::new(ChecksumMismatch {
+                file,
+                expected: expected.to_string(),
+                got,
+            }));
+        }
+    }
+
+    for stitch in stitches {
+        let listed = stitch
+            .path()
+            .strip_prefix(dir)
+            .is_ok_and(|name| checksums.iter().any(|(listed, _)| name == *listed));
+        if !listed {
+            cargo_status(
+                output,
+                "Warning",
+                &format!("{} has no checksum in {checksums_file}", stitch.path()),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The `(file, digest)` pairs of a `sha256sum`-style checksums file, skipping blank lines
+/// and `#` comments.  Returns the offending line if one is malformed.
+fn parse_checksums(contents: &str) -> Result<Vec<(&str, &str)>, &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (digest, name) = line.split_once(char::is_whitespace).ok_or(line)?;
+            // `sha256sum --binary` marks the file name with a `*`.
+            let name = name.trim_start().trim_start_matches('*');
+            let valid = digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit());
+            if !valid || name.is_empty() {
+                return Err(line);
+            }
+            Ok((name, digest))
+        })
+        .collect()
+}
+
+/// The paths listed in a `.stitch-order` file, one per line, skipping blank lines and
+/// `#` comments.
+fn parse_order_file(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// A line of a `.stitch-ignore` file.
+#[derive(Debug, PartialEq, Eq)]
+struct IgnorePattern {
+    glob: String,
+    /// Whether the line starts with `!`, which applies the stitches it matches after all.
+    negated: bool,
+    /// Whether the glob is matched against the whole path relative to the stitch
+    /// directory, because it has a `/` other than at the end, rather than against each
+    /// file or directory name.
+    anchored: bool,
+    /// Whether the line ends with `/`, so that it only matches directories.
+    dir_only: bool,
+}
+
+/// The patterns of a `.stitch-ignore` file, skipping blank lines and `#` comments.
+fn parse_ignore_file(contents: &str) -> Vec<IgnorePattern> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            IgnorePattern {
+                glob: line.trim_start_matches('/').to_string(),
+                negated,
+                anchored: line.contains('/'),
+                dir_only,
+            }
+        })
+        .collect()
+}
+
+/// Whether the file at `path`, relative to the stitch directory, is ignored by
+/// `patterns`.  As in a `.gitignore`, the last pattern that matches it, or one of its
+/// directories, decides.
+fn is_ignored(path: &str, patterns: &[IgnorePattern]) -> bool {
+    let mut ignored = false;
+    for pattern in patterns {
+        if pattern.matches(path) {
+            ignored = !pattern.negated;
+        }
+    }
+    ignored
+}
+
+impl IgnorePattern {
+    fn matches(&self, path: &str) -> bool {
+        // The file itself, then each of its directories, e.g. `a/b/c.patch`, `a/b`, `a`.
+        let mut prefixes = path
+            .char_indices()
+            .filter(|&(_, c)| c == '/')
+            .map(|(i, _)| &path[..i])
+            .collect::<Vec<_>>();
+        if !self.dir_only {
+            prefixes.push(path);
+        }
+
+        prefixes.into_iter().any(|prefix| {
+            if self.anchored {
+                glob_match(&self.glob, prefix)
+            } else {
+                let name = prefix.rsplit('/').next().unwrap_or(prefix);
+                glob_match(&self.glob, name)
+            }
+        })
+    }
+}
+
+/// Whether `text` matches `glob`, where `*` matches any run of characters but `/`, `?`
+/// any one character but `/`, and `**` any run of characters, across directories.
+fn glob_match(glob: &str, text: &str) -> bool {
+    if let Some(rest) = glob.strip_prefix("**") {
+        // `**/` matches any number of whole directories, including none.
+        if let Some(rest) = rest.strip_prefix('/') {
+            return glob_match(rest, text)
+                || text
+                    .match_indices('/')
+                    .any(|(i, _)| glob_match(rest, &text[i + 1..]));
+        }
+        return text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([text.len()])
+            .any(|i| glob_match(rest, &text[i..]));
+    }
+
+    let mut chars = glob.chars();
+    match chars.next() {
+        None => text.is_empty(),
+        Some('*') => {
+            let rest = chars.as_str();
+            let end = text.find('/').unwrap_or(text.len());
+            text[..end]
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain([end])
+                .any(|i| glob_match(rest, &text[i..]))
+        }
+        Some('?') => {
+            let mut text_chars = text.chars();
+            matches!(text_chars.next(), Some(c) if c != '/')
+                && glob_match(chars.as_str(), text_chars.as_str())
+        }
+        Some(c) => text
+            .strip_prefix(c)
+            .is_some_and(|text| glob_match(chars.as_str(), text)),
+    }
+}
+
+/// Reorder `paths` (sorted by path) so the paths listed in `order`, relative to `dir`,
+/// come first, in the listed order, followed by the remaining paths in their existing
+/// order.
+///
+/// Returns the offending name if `order` lists a file that is not in `paths`.
+fn apply_order(
+    dir: &Utf8Path,
+    mut paths: Vec<Utf8PathBuf>,
+    order: &[String],
+) -> Result<Vec<Utf8PathBuf>, String> {
+    let mut ordered = Vec::with_capacity(paths.len());
+
+    for name in order {
+        let relative = |p: &Utf8PathBuf| p.strip_prefix(dir).is_ok_and(|p| p == name);
+        let Some(index) = paths.iter().position(relative) else {
+            return Err(name.clone());
+        };
+        ordered.push(paths.remove(index));
+    }
+
+    ordered.extend(paths);
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CONFIG_FILE;
+    use std::fs;
+
+    #[test]
+    fn stitch_from_path_patch() {
+        let s = Stitch::from_path(Utf8PathBuf::from("fix.patch"));
+        assert!(matches!(s, Some(Stitch::Patch(_))));
+    }
+
+    #[test]
+    fn stitch_from_path_yaml() {
+        let s = Stitch::from_path(Utf8PathBuf::from("rule.yaml"));
+        assert!(matches!(s, Some(Stitch::SgRule(_))));
+    }
+
+    #[test]
+    fn stitch_from_path_yml() {
+        let s = Stitch::from_path(Utf8PathBuf::from("rule.yml"));
+        assert!(matches!(s, Some(Stitch::SgRule(_))));
+    }
+
+    #[test]
+    fn stitch_from_path_toml() {
+        let s = Stitch::from_path(Utf8PathBuf::from("rule.toml"));
+        assert!(matches!(s, Some(Stitch::SgRule(_))));
+        assert!(Stitch::from_path(Utf8PathBuf::from("sub/stitch.toml")).is_none());
+    }
+
+    #[test]
+    fn toml_rule_is_passed_to_ast_grep_as_yaml() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        let file = dir.join("001-rule.toml");
+        std::fs::write(
+            &file,
+            "id = \"rename\"\nlanguage = \"rust\"\nfix = \"bar\"\n\n[rule]\npattern = \"foo\"\n",
+        )
+        .unwrap();
+
+        let Ok(yaml) = toml_rule_as_yaml(&file) else {
+            panic!("valid TOML rule rejected");
+        };
+        let rule: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(yaml.path()).unwrap()).unwrap();
+        assert_eq!(
+            rule,
+            serde_json::json!({
+                "id": "rename",
+                "language": "rust",
+                "fix": "bar",
+                "rule": { "pattern": "foo" },
+            })
+        );
+
+        std::fs::write(&file, "id = \n").unwrap();
+        let Err(err) = toml_rule_as_yaml(&file) else {
+            panic!("invalid TOML rule accepted");
+        };
+        assert!(err.to_string().contains("invalid TOML rule"), "{err}");
+    }
+
+    #[test]
+    fn stitch_from_path_sed() {
+        let s = Stitch::from_path(Utf8PathBuf::from("rename.sed"));
+        assert!(matches!(s, Some(Stitch::SedRule(_))));
+    }
+
+    #[test]
+    fn stitch_from_path_sh() {
+        let s = Stitch::from_path(Utf8PathBuf::from("add-dep.sh"));
+        assert!(matches!(s, Some(Stitch::Script(_))));
+    }
+
+    #[test]
+    fn stitch_from_path_txt_returns_none() {
+        assert!(Stitch::from_path(Utf8PathBuf::from("readme.txt")).is_none());
+    }
+
+    #[test]
+    fn stitch_from_path_no_extension_returns_none() {
+        assert!(Stitch::from_path(Utf8PathBuf::from("Makefile")).is_none());
+    }
+
+    #[test]
+    fn stitch_path_returns_inner() {
+        let p = Utf8PathBuf::from("stitches/default/crate-a/001.patch");
+        let s = Stitch::Patch(p.clone());
+        assert_eq!(s.path(), p.as_path());
+
+        let p2 = Utf8PathBuf::from("stitches/default/crate-a/002.yaml");
+        let s2 = Stitch::SgRule(p2.clone());
+        assert_eq!(s2.path(), p2.as_path());
+    }
+
+    #[test]
+    fn discover_all_nonexistent_dir() {
+        let result = StitchSet::discover_all(
+            &[Utf8Path::new("/nonexistent/stitches/default")],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn discover_all_with_stitch_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let stitches_dir = base.join("stitches");
+
+        let pkg_dir = stitches_dir.join("crate-a");
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(pkg_dir.join("001.patch"), "").unwrap();
+        fs::write(pkg_dir.join("002.yaml"), "").unwrap();
+
+        let result = StitchSet::discover_all(
+            &[stitches_dir.as_path()],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap();
+        assert!(result.contains_key("crate-a"));
+        assert_eq!(result["crate-a"].stitches.len(), 2);
+    }
+
+    #[test]
+    fn discover_all_empty_subdir_filtered_out() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let stitches_dir = base.join("stitches");
+
+        let pkg_dir = stitches_dir.join("empty-crate");
+        fs::create_dir_all(&pkg_dir).unwrap();
+        // No stitch files, just a non-stitch file
+        fs::write(pkg_dir.join("readme.txt"), "").unwrap();
+
+        let result = StitchSet::discover_all(
+            &[stitches_dir.as_path()],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn discover_all_earlier_dirs_take_priority() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let user = base.join("user/default");
+        let local = base.join("ws/stitches/default");
+
+        fs::create_dir_all(user.join("crate-a")).unwrap();
+        fs::write(user.join("crate-a/001-fix.patch"), "").unwrap();
+        fs::create_dir_all(local.join("crate-a")).unwrap();
+        fs::write(local.join("crate-a/001-fix.patch"), "").unwrap();
+        fs::write(local.join("crate-a/002-more.patch"), "").unwrap();
+        fs::create_dir_all(local.join("crate-b")).unwrap();
+        fs::write(local.join("crate-b/001-b.yaml"), "").unwrap();
+
+        let result = StitchSet::discover_all(
+            &[user.as_path(), local.as_path()],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            result["crate-a"].file_paths().collect::<Vec<_>>(),
+            [
+                user.join("crate-a/001-fix.patch"),
+                local.join("crate-a/002-more.patch")
+            ]
+        );
+        assert_eq!(result["crate-b"].len(), 1);
+    }
+
+    #[test]
+    fn discover_for_package_matches_discover_all() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let user = base.join("user/default");
+        let local = base.join("ws/stitches/default");
+
+        fs::create_dir_all(user.join("crate-a")).unwrap();
+        fs::write(user.join("crate-a/001-fix.patch"), "").unwrap();
+        fs::create_dir_all(local.join("crate-a")).unwrap();
+        fs::write(local.join("crate-a/001-fix.patch"), "").unwrap();
+        fs::write(local.join("crate-a/002-more.patch"), "").unwrap();
+        // A sibling that would fail to load, were it read.
+        fs::create_dir_all(local.join("crate-b")).unwrap();
+        fs::write(local.join("crate-b/stitch.toml"), "not toml [").unwrap();
+
+        let dirs = [user.as_path(), local.as_path()];
+        let metadata = WorkspaceMetadata::default();
+        let output = OutputContext::default();
+        let set = StitchSet::discover_for_package(&dirs, "crate-a", &metadata, &output)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            set.file_paths().collect::<Vec<_>>(),
+            [
+                user.join("crate-a/001-fix.patch"),
+                local.join("crate-a/002-more.patch")
+            ]
+        );
+
+        assert!(
+            StitchSet::discover_for_package(&dirs, "crate-c", &metadata, &output)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn discover_in_returns_sorted_and_filters() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        fs::write(dir.join("002.yaml"), "").unwrap();
+        fs::write(dir.join("001.patch"), "").unwrap();
+        fs::write(dir.join("readme.txt"), "").unwrap();
+
+        let stitches = StitchSet::discover_in(dir, false, None, &OutputContext::default())
+            .unwrap()
+            .stitches;
+        assert_eq!(stitches.len(), 2);
+        assert!(matches!(&stitches[0], Stitch::Patch(p) if p.file_name() == Some("001.patch")));
+        assert!(matches!(&stitches[1], Stitch::SgRule(p) if p.file_name() == Some("002.yaml")));
+    }
+
+    #[test]
+    fn discover_in_applies_stitch_toml_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        fs::write(dir.join("001-types.patch"), "").unwrap();
+        fs::write(dir.join("002-impl.patch"), "").unwrap();
+        fs::write(dir.join("003-cleanup.yaml"), "").unwrap();
+        fs::write(dir.join("004-extra.patch"), "").unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "[order]\nstitches = [\"003-cleanup.yaml\", \"002-impl.patch\"]\n",
+        )
+        .unwrap();
+
+        let names: Vec<_> = StitchSet::discover_in(dir, false, None, &OutputContext::default())
+            .unwrap()
+            .stitches
+            .iter()
+            .map(|s| s.path().file_name().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "003-cleanup.yaml",
+                "002-impl.patch",
+                "001-types.patch",
+                "004-extra.patch"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_checksums_like_sha256sum() {
+        let digest = "a".repeat(64);
+        let contents = format!("# crate-a\n{digest}  001.patch\n\n{digest} *sub/002.sed\n");
+        assert_eq!(
+            parse_checksums(&contents).unwrap(),
+            [
+                ("001.patch", digest.as_str()),
+                ("sub/002.sed", digest.as_str())
+            ]
+        );
+        assert_eq!(parse_checksums("abc  001.patch\n"), Err("abc  001.patch"));
+        assert_eq!(parse_checksums(&digest), Err(digest.as_str()));
+    }
+
+    #[test]
+    fn discover_in_verifies_checksums() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join("001.patch"), "one").unwrap();
+        fs::write(dir.join("002.patch"), "two").unwrap();
+        let discover = || StitchSet::discover_in(dir, false, None, &OutputContext::default());
+
+        // Unlisted files are only warned about.
+        fs::write(
+            dir.join(CHECKSUMS_FILE),
+            format!("{}  001.patch\n", sha256_hex(b"one")),
+        )
+        .unwrap();
+        assert_eq!(discover().unwrap().stitches.len(), 2);
+
+        fs::write(dir.join("001.patch"), "tampered").unwrap();
+        let Err(e) = discover() else {
+            panic!("a tampered stitch should fail");
+        };
+        let mismatch = e.narrow::<ChecksumMismatch, _>().unwrap();
+        assert_eq!(mismatch.file, dir.join("001.patch"));
+        assert_eq!(mismatch.expected, sha256_hex(b"one"));
+        assert_eq!(mismatch.got, sha256_hex(b"tampered"));
+
+        // A listed file must exist.
+        fs::write(
+            dir.join(CHECKSUMS_FILE),
+            format!("{}  003.patch\n", sha256_hex(b"three")),
+        )
+        .unwrap();
+        assert!(discover().is_err());
+    }
+
+    #[test]
+    fn discover_in_walks_subdirectories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        for sub in ["security", "features", ".hidden"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("security/001-cve.patch"), "").unwrap();
+        fs::write(dir.join("features/002-api.yaml"), "").unwrap();
+        fs::write(dir.join("features/001-flag.sed"), "").unwrap();
+        fs::write(dir.join(".hidden/001.patch"), "").unwrap();
+        fs::write(dir.join("000-first.patch"), "").unwrap();
+
+        let names = |set: StitchSet| -> Vec<String> {
+            set.stitches
+                .iter()
+                .map(|s| s.path().strip_prefix(dir).unwrap().to_string())
+                .collect()
+        };
+
+        let set = StitchSet::discover_in(dir, false, None, &OutputContext::default()).unwrap();
+        assert_eq!(
+            names(set),
+            [
+                "000-first.patch",
+                "features/001-flag.sed",
+                "features/002-api.yaml",
+                "security/001-cve.patch"
+            ]
+        );
+
+        fs::write(
+            dir.join(ORDER_FILE),
+            "# Security fixes first.\nsecurity/001-cve.patch\n\nfeatures/002-api.yaml\n",
+        )
+        .unwrap();
+        let set = StitchSet::discover_in(dir, false, None, &OutputContext::default()).unwrap();
+        assert_eq!(
+            set.input_paths().last(),
+            Some(dir.join(ORDER_FILE).as_path())
+        );
+        assert_eq!(
+            names(set),
+            [
+                "security/001-cve.patch",
+                "features/002-api.yaml",
+                "000-first.patch",
+                "features/001-flag.sed"
+            ]
+        );
+
+        // `[order]` in `stitch.toml` takes precedence.
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "[order]\nstitches = [\"features/001-flag.sed\"]\n",
+        )
+        .unwrap();
+        let set = StitchSet::discover_in(dir, false, None, &OutputContext::default()).unwrap();
+        assert_eq!(names(set)[0], "features/001-flag.sed");
+
+        fs::remove_file(dir.join(CONFIG_FILE)).unwrap();
+        fs::write(dir.join(ORDER_FILE), "security/missing.patch\n").unwrap();
+        let err = StitchSet::discover_in(dir, false, None, &OutputContext::default()).unwrap_err();
+        assert!(err.to_string().contains("security/missing.patch"));
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("*.patch", "001-fix.patch"));
+        assert!(!glob_match("*.patch", "perf/001-fix.patch"));
+        assert!(glob_match("00?-fix.patch", "001-fix.patch"));
+        assert!(!glob_match("?", "/"));
+        assert!(glob_match("perf/**", "perf/a/001.patch"));
+        assert!(glob_match("**/001.patch", "001.patch"));
+        assert!(glob_match("**/001.patch", "a/b/001.patch"));
+        assert!(!glob_match("**/001.patch", "a/b001.patch"));
+        assert!(glob_match("a/**/b.sed", "a/b.sed"));
+        assert!(glob_match("a/**/b.sed", "a/x/y/b.sed"));
+        assert!(!glob_match("002-*", "001-fix.patch"));
+    }
+
+    #[test]
+    fn ignore_patterns_like_gitignore() {
+        let patterns = parse_ignore_file(
+            "# Not ready yet.\n\
+             *-experiment.patch\n\
+             perf/\n\
+             !perf/001-keep.patch\n\
+             /top.sed\n",
+        );
+        assert_eq!(patterns.len(), 4);
+        assert!(patterns[2].negated && patterns[2].anchored);
+
+        for ignored in [
+            "003-experiment.patch",
+            "nested/003-experiment.patch",
+            "perf/002-fast.patch",
+            "top.sed",
+        ] {
+            assert!(is_ignored(ignored, &patterns), "{ignored}");
+        }
+        for applied in [
+            "001-fix.patch",
+            "perf/001-keep.patch",
+            "nested/top.sed",
+            "perf",
+        ] {
+            assert!(!is_ignored(applied, &patterns), "{applied}");
+        }
+    }
+
+    #[test]
+    fn discover_in_leaves_out_ignored_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        fs::write(dir.join("001-fix.patch"), "").unwrap();
+        fs::write(dir.join("002-experiment.patch"), "").unwrap();
+        fs::write(dir.join(IGNORE_FILE), "*-experiment.patch\n").unwrap();
+        // An ignored file may still be listed in the order.
+        fs::write(dir.join(ORDER_FILE), "002-experiment.patch\n").unwrap();
+
+        let set = StitchSet::discover_in(dir, false, None, &OutputContext::default()).unwrap();
+        let names: Vec<_> = set
+            .file_paths()
+            .map(|p| p.file_name().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["001-fix.patch"]);
+        assert!(set.input_paths().any(|p| p == dir.join(IGNORE_FILE)));
+    }
+
+    #[test]
+    fn discover_in_order_with_unknown_file_fails() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        fs::write(dir.join("001.patch"), "").unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "[order]\nstitches = [\"missing.patch\"]\n",
+        )
+        .unwrap();
+
+        let err = StitchSet::discover_in(dir, false, None, &OutputContext::default()).unwrap_err();
+        assert!(err.to_string().contains("missing.patch"));
+    }
+
+    #[test]
+    fn discover_all_records_config_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stitches_dir = Utf8Path::from_path(tmp.path()).unwrap().join("stitches");
+
+        let with_config = stitches_dir.join("crate-a");
+        fs::create_dir_all(&with_config).unwrap();
+        fs::write(with_config.join("001.patch"), "").unwrap();
+        fs::write(with_config.join(CONFIG_FILE), "").unwrap();
+
+        let without_config = stitches_dir.join("crate-b");
+        fs::create_dir_all(&without_config).unwrap();
+        fs::write(without_config.join("001.patch"), "").unwrap();
+
+        let result = StitchSet::discover_all(
+            &[stitches_dir.as_path()],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            result["crate-a"].config_path(),
+            Some(with_config.join(CONFIG_FILE).as_path())
+        );
+        assert_eq!(result["crate-b"].config_path(), None);
+    }
+
+    #[test]
+    fn discover_in_reads_keep_patched() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        fs::write(dir.join("001.patch"), "").unwrap();
+        assert!(
+            !StitchSet::discover_in(dir, false, None, &OutputContext::default())
+                .unwrap()
+                .keep_patched()
+        );
+
+        fs::write(dir.join(CONFIG_FILE), "keep_patched = true\n").unwrap();
+        assert!(
+            StitchSet::discover_in(dir, false, None, &OutputContext::default())
+                .unwrap()
+                .keep_patched()
+        );
+    }
+
+    #[test]
+    fn discover_in_reads_no_copy() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        fs::write(dir.join("001.patch"), "").unwrap();
+        fs::write(dir.join(CONFIG_FILE), "no_copy = true\n").unwrap();
+        assert!(
+            StitchSet::discover_in(dir, false, None, &OutputContext::default())
+                .unwrap()
+                .no_copy()
+        );
+    }
+
+    #[test]
+    fn discover_all_resolves_hardlink_setting() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(tmp.path()).unwrap().join("stitches");
+        let stitches_dir = root.join("default");
+
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join(CONFIG_FILE), "[copy]\nhardlink = true\n").unwrap();
+
+        let inherits = stitches_dir.join("crate-a");
+        fs::create_dir_all(&inherits).unwrap();
+        fs::write(inherits.join("001.patch"), "").unwrap();
+
+        let opts_out = stitches_dir.join("crate-b");
+        fs::create_dir_all(&opts_out).unwrap();
+        fs::write(opts_out.join("001.patch"), "").unwrap();
+        fs::write(opts_out.join(CONFIG_FILE), "[copy]\nhardlink = false\n").unwrap();
+
+        let with_sg = stitches_dir.join("crate-c");
+        fs::create_dir_all(&with_sg).unwrap();
+        fs::write(with_sg.join("001.yaml"), "").unwrap();
+
+        let result = StitchSet::discover_all(
+            &[stitches_dir.as_path()],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap();
+        assert!(result["crate-a"].copy_options().hardlink);
+        assert!(!result["crate-b"].copy_options().hardlink);
+        assert!(!result["crate-c"].copy_options().hardlink);
+    }
+
+    #[test]
+    fn read_strip_header_from_leading_comments() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        let read = |contents: &str| {
+            let file = dir.join("001.patch");
+            fs::write(&file, contents).unwrap();
+            read_strip_header(&file)
+        };
+
+        assert_eq!(
+            read("# Fix the greeting\n# stitch-strip: 0\n--- src/lib.rs\n").unwrap(),
+            Some(0)
+        );
+        assert_eq!(read("--- a/src/lib.rs\n+++ b/src/lib.rs\n").unwrap(), None);
+        // Only the comments before the diff count.
+        assert_eq!(
+            read("--- a/src/lib.rs\n+++ b/src/lib.rs\n# stitch-strip: 0\n").unwrap(),
+            None
+        );
+        assert!(read("# stitch-strip: zero\n").is_err());
+    }
+
+    #[test]
+    fn read_strip_header_after_front_matter() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = Utf8Path::from_path(tmp.path()).unwrap().join("001.patch");
+        fs::write(
+            &file,
+            "---\nid: greeting\n---\n# stitch-strip: 0\n--- src/lib.rs\n",
+        )
+        .unwrap();
+        assert_eq!(read_strip_header(&file).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn yaml_top_level_keys_skips_nested() {
+        let yaml = "id: foo\nlanguage: Rust\nrule:\n  pattern: $A\n# id: nope\n";
+        let keys: Vec<_> = yaml_top_level_keys(yaml).collect();
+        assert_eq!(
+            keys,
+            vec![("id", "foo"), ("language", "Rust"), ("rule", "")]
+        );
+    }
+
+    #[test]
+    fn split_front_matter_needs_both_delimiters() {
+        assert_eq!(
+            split_front_matter("---\nid: a\n---\n--- a/x\n"),
+            (Some("id: a\n"), "--- a/x\n")
+        );
+        assert_eq!(
+            split_front_matter("--- a/x\n+++ b/x\n"),
+            (None, "--- a/x\n+++ b/x\n")
+        );
+        assert_eq!(split_front_matter("---\nid: a\n"), (None, "---\nid: a\n"));
+    }
+
+    const FORMAT_PATCH: &str = "From 0123456789abcdef0123456789abcdef01234567 Mon Sep 17 00:00:00 2001\n\
+                                From: Jane Doe <jane@example.com>\n\
+                                Date: Tue, 5 Mar 2024 10:00:00 +0100\n\
+                                Subject: [PATCH 2/3] Make the greeting louder, as the\n \
+                                users asked\n\
+                                \n\
+                                Upstream prefers to keep it quiet.\n\
+                                ---\n \
+                                src/lib.rs | 2 +-\n \
+                                1 file changed, 1 insertion(+), 1 deletion(-)\n\
+                                \n\
+                                diff --git a/src/lib.rs b/src/lib.rs\n\
+                                --- a/src/lib.rs\n";
+
+    #[test]
+    fn split_mail_header_of_format_patch() {
+        let (mail, rest) = split_mail_header(FORMAT_PATCH);
+        assert!(mail.unwrap().ends_with("quiet.\n"));
+        assert!(rest.starts_with(" src/lib.rs | 2 +-\n"));
+        assert!(rest.ends_with("diff --git a/src/lib.rs b/src/lib.rs\n--- a/src/lib.rs\n"));
+
+        // Not a commit, or no end to the commit message.
+        let not_mail = "From the upstream repository\n---\n--- a/x\n";
+        assert_eq!(split_mail_header(not_mail), (None, not_mail));
+        let unterminated = &FORMAT_PATCH[..FORMAT_PATCH.find("---").unwrap()];
+        assert_eq!(split_mail_header(unterminated), (None, unterminated));
+    }
+
+    #[test]
+    fn read_meta_from_mail_header() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = Utf8Path::from_path(tmp.path()).unwrap().join("001.patch");
+        fs::write(&file, FORMAT_PATCH).unwrap();
+
+        assert_eq!(
+            Stitch::Patch(file.clone()).read_meta().unwrap(),
+            StitchMeta {
+                description: Some(
+                    "Make the greeting louder, as the users asked\n\n\
+                     Upstream prefers to keep it quiet."
+                        .to_string()
+                ),
+                author: Some("Jane Doe <jane@example.com>".to_string()),
+                ..StitchMeta::default()
+            }
+        );
+
+        // The front matter takes precedence.
+        fs::write(
+            &file,
+            format!("---\nid: louder\ndescription: Louder\n---\n{FORMAT_PATCH}"),
+        )
+        .unwrap();
+        let meta = Stitch::Patch(file).read_meta().unwrap();
+        assert_eq!(meta.id.as_deref(), Some("louder"));
+        assert_eq!(meta.description.as_deref(), Some("Louder"));
+        assert_eq!(meta.author.as_deref(), Some("Jane Doe <jane@example.com>"));
+    }
+
+    #[test]
+    fn has_crlf_headers_only_for_converted_patches() {
+        assert!(has_crlf_headers(
+            "--- a/x\r\n+++ b/x\r\n@@ -1 +1 @@\r\n-a\r\n+b\r\n"
+        ));
+        assert!(!has_crlf_headers("--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n"));
+        // A patch of a file with `\r\n` line endings.
+        assert!(!has_crlf_headers(
+            "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\r\n+b\r\n"
+        ));
+    }
+
+    #[test]
+    fn read_meta_from_front_matter() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = Utf8Path::from_path(tmp.path()).unwrap().join("001.patch");
+        fs::write(
+            &file,
+            "---\n\
+             id: greeting\n\
+             description: \"Make the greeting louder\"\n\
+             upstream_pr: https://example.com/pull/12\n\
+             reviewer: nobody\n\
+             ---\n\
+             --- a/src/lib.rs\n",
+        )
+        .unwrap();
+
+        let meta = Stitch::Patch(file.clone()).read_meta().unwrap();
+        assert_eq!(
+            meta,
+            StitchMeta {
+                id: Some("greeting".to_string()),
+                description: Some("Make the greeting louder".to_string()),
+                upstream_pr: Some("https://example.com/pull/12".to_string()),
+                ..StitchMeta::default()
+            }
+        );
+
+        fs::write(&file, "--- a/src/lib.rs\n").unwrap();
+        assert!(Stitch::Patch(file).read_meta().unwrap().is_empty());
+    }
+
+    #[test]
+    fn describe_shows_kind_and_description() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        let patch = dir.join("001-fix.patch");
+        fs::write(
+            &patch,
+            "---\ndescription: Fix segfault in parser (see issue #42)\n---\n--- a/src/lib.rs\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Stitch::Patch(patch.clone()).describe(),
+            "[patch] 001-fix.patch: \"Fix segfault in parser (see issue #42)\""
+        );
+
+        fs::write(&patch, "--- a/src/lib.rs\n").unwrap();
+        assert_eq!(Stitch::Patch(patch).describe(), "[patch] 001-fix.patch");
+        assert_eq!(
+            Stitch::SgRule(dir.join("002-rename.yaml")).describe(),
+            "[sg-rule] 002-rename.yaml"
+        );
+    }
+
+    #[test]
+    fn read_features_from_front_matter() {
+        assert_eq!(parse_feature_list("[simd, \"std\"]"), ["simd", "std"]);
+        assert_eq!(parse_feature_list("simd"), ["simd"]);
+        assert!(parse_feature_list("[]").is_empty());
+
+        let tmp = tempfile::tempdir().unwrap();
+        let file = Utf8Path::from_path(tmp.path()).unwrap().join("001.patch");
+        fs::write(&file, "---\nfeatures: [simd]\n---\n--- a/src/lib.rs\n").unwrap();
+        assert_eq!(Stitch::Patch(file).read_meta().unwrap().features, ["simd"]);
+    }
+
+    #[test]
+    fn discover_all_reads_features() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stitches_dir = Utf8Path::from_path(tmp.path()).unwrap().join("default");
+        let dir = stitches_dir.join("crate-a");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("001-always.patch"), "").unwrap();
+        fs::write(dir.join("002-simd.patch"), "---\nfeatures: [simd]\n---\n").unwrap();
+        fs::write(dir.join("003-std.sed"), "").unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "[features]\n\"003-std.sed\" = [\"std\", \"simd\"]\n",
+        )
+        .unwrap();
+
+        let mut all = StitchSet::discover_all(
+            &[&stitches_dir],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap();
+        let set = all.remove("crate-a").unwrap();
+        let features: Vec<_> = set.iter().map(|s| set.features_of(s).to_vec()).collect();
+        assert_eq!(
+            features,
+            [
+                vec![],
+                vec!["simd".to_string()],
+                vec!["std".to_string(), "simd".to_string()]
+            ]
+        );
+
+        let names = |set: &StitchSet| -> Vec<String> {
+            set.file_paths()
+                .map(|p| p.file_name().unwrap().to_string())
+                .collect()
+        };
+        let retained = |active: &[&str]| {
+            let mut set = StitchSet::discover_all(
+                &[&stitches_dir],
+                &WorkspaceMetadata::default(),
+                &OutputContext::default(),
+            )
+            .unwrap()
+            .remove("crate-a")
+            .unwrap();
+            set.retain_features(|feature| active.contains(&feature));
+            names(&set)
+        };
+        assert_eq!(retained(&[]), ["001-always.patch"]);
+        assert_eq!(retained(&["simd"]), ["001-always.patch", "002-simd.patch"]);
+        assert_eq!(
+            retained(&["simd", "std"]),
+            ["001-always.patch", "002-simd.patch", "003-std.sed"]
+        );
+
+        fs::write(
+            dir.join(CONFIG_FILE),
+            "[features]\n\"004-missing.patch\" = [\"std\"]\n",
+        )
+        .unwrap();
+        let err = StitchSet::discover_all(
+            &[&stitches_dir],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("stitch listed in [features] not found: 004-missing.patch"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn discover_all_inherits_patch_backend() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(tmp.path()).unwrap().join("stitches");
+        let stitches_dir = root.join("default");
+
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join(CONFIG_FILE), "[patch]\nbackend = \"git\"\n").unwrap();
+
+        let inherits = stitches_dir.join("crate-a");
+        fs::create_dir_all(&inherits).unwrap();
+        fs::write(inherits.join("001.patch"), "").unwrap();
+
+        let overrides = stitches_dir.join("crate-b");
+        fs::create_dir_all(&overrides).unwrap();
+        fs::write(overrides.join("001.patch"), "").unwrap();
+        fs::write(
+            overrides.join(CONFIG_FILE),
+            "[patch]\nbackend = \"patch\"\n",
+        )
+        .unwrap();
+
+        let result = StitchSet::discover_all(
+            &[stitches_dir.as_path()],
+            &WorkspaceMetadata::default(),
+            &OutputContext::default(),
+        )
+        .unwrap();
+        assert!(result["crate-a"].needs(&tools::GIT));
+        assert!(!result["crate-a"].needs(&tools::PATCH));
+        assert!(result["crate-b"].needs(&tools::PATCH));
+        assert!(!result["crate-b"].needs(&tools::GIT));
+    }
+
+    #[test]
+    fn discover_all_inherits_strip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(tmp.path()).unwrap().join("stitches");
+        let stitches_dir = root.join("default");
+
+        let inherits = stitches_dir.join("crate-a");
+        fs::create_dir_all(&inherits).unwrap();
+        fs::write(inherits.join("001.patch"), "").unwrap();
+
+        let overrides = stitches_dir.join("crate-b");
+        fs::create_dir_all(&overrides).unwrap();
+        fs::write(overrides.join("001.patch"), "").unwrap();
+        fs::write(overrides.join(CONFIG_FILE), "[patch]\nstrip = 2\n").unwrap();
+
+        let strip = |default_strip| {
+            let mut metadata = WorkspaceMetadata::default();
+            metadata.default_strip = default_strip;
+            let result = StitchSet::discover_all(
+                &[stitches_dir.as_path()],
+                &metadata,
+                &OutputContext::default(),
+            )
+            .unwrap();
+            (result["crate-a"].patch.strip, result["crate-b"].patch.strip)
+        };
+        assert_eq!(strip(None), (None, Some(2)));
+        assert_eq!(strip(Some(0)), (Some(0), Some(2)));
+
+        // `stitches/stitch.toml` takes precedence over `[workspace.metadata.stitch]`.
+        fs::write(root.join(CONFIG_FILE), "[patch]\nstrip = 3\n").unwrap();
+        assert_eq!(strip(None), (Some(3), Some(2)));
+        assert_eq!(strip(Some(0)), (Some(3), Some(2)));
+    }
+
+    #[test]
+    fn discover_all_applies_package_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stitches_dir = Utf8Path::from_path(tmp.path()).unwrap().join("stitches");
+
+        for krate in ["crate-a", "crate-b", "crate-c"] {
+            fs::create_dir_all(stitches_dir.join(krate)).unwrap();
+            fs::write(stitches_dir.join(krate).join("001.patch"), "").unwrap();
+        }
+        // `stitch.toml` takes precedence over `[package.metadata.stitch]`.
+        fs::write(
+            stitches_dir.join("crate-b").join(CONFIG_FILE),
+            "enabled = true
+
+[patch]
+strip = 2
+extra_args = [\"--posix\"]
+",
+        )
+        .unwrap();
+
+        let package = |enabled| {
+            let mut package = PackageMetadata::default();
+            package.enabled = enabled;
+            package.extra_patch_args = vec!["--fuzz=0".to_string()];
+            package.extra_sg_args = vec!["--no-ignore=hidden".to_string()];
+            package.strip = Some(0);
+            package
+        };
+        let mut metadata = WorkspaceMetadata::default();
+        metadata.default_strip = Some(3);
+        metadata.manifest = Some(Utf8PathBuf::from("/ws/Cargo.toml"));
+        metadata.packages = HashMap::from([
+            ("crate-a".to_string(), package(None)),
+            ("crate-b".to_string(), package(Some(false))),
+            ("crate-c".to_string(), package(Some(false))),
+        ]);
+
+        let result = StitchSet::discover_all(
+            &[stitches_dir.as_path()],
+            &metadata,
+            &OutputContext::default(),
+        )
+        .unwrap();
+        let a = &result["crate-a"];
+        assert_eq!(a.patch.args(None), vec!["-p0", "--fuzz=0"]);
+        assert_eq!(a.sg_args, vec!["--no-ignore=hidden"]);
+        let b = &result["crate-b"];
+        assert_eq!(b.patch.args(None), vec!["-p2", "--posix", "--fuzz=0"]);
+        assert!(!result.contains_key("crate-c"));
+
+        // Only `default_strip` makes the workspace's `Cargo.toml` an input.
+        assert_eq!(a.input_paths().count(), 1);
+
+        metadata.enabled_packages = Some(vec!["crate-a".to_string()]);
+        let result = StitchSet::discover_all(
+            &[stitches_dir.as_path()],
+            &metadata,
+            &OutputContext::default(),
+        )
+        .unwrap();
+        assert_eq!(result.keys().collect::<Vec<_>>(), ["crate-a"]);
     }
 
     #[test]
-    fn needs_patch_and_needs_sg() {
+    fn needs_tools_of_its_stitches() {
         let set = StitchSet {
             stitches: vec![
                 Stitch::Patch(Utf8PathBuf::from("a.patch")),
                 Stitch::SgRule(Utf8PathBuf::from("b.yaml")),
             ],
+            ..Default::default()
         };
-        assert!(set.needs_patch());
+        assert!(set.needs(&tools::PATCH));
         assert!(set.needs_sg());
+        assert!(!set.needs(&tools::SED));
 
         let patch_only = StitchSet {
             stitches: vec![Stitch::Patch(Utf8PathBuf::from("a.patch"))],
+            ..Default::default()
         };
-        assert!(patch_only.needs_patch());
+        assert!(patch_only.needs(&tools::PATCH));
         assert!(!patch_only.needs_sg());
 
         let sg_only = StitchSet {
             stitches: vec![Stitch::SgRule(Utf8PathBuf::from("b.yml"))],
+            ..Default::default()
         };
-        assert!(!sg_only.needs_patch());
+        assert!(!sg_only.needs(&tools::PATCH));
         assert!(sg_only.needs_sg());
 
-        let empty = StitchSet { stitches: vec![] };
-        assert!(!empty.needs_patch());
+        let sed_only = StitchSet {
+            stitches: vec![Stitch::SedRule(Utf8PathBuf::from("c.sed"))],
+            ..Default::default()
+        };
+        assert!(sed_only.needs(&tools::SED));
+        assert!(!sed_only.needs_sg());
+
+        let empty = StitchSet {
+            stitches: vec![],
+            ..Default::default()
+        };
+        assert!(!empty.needs(&tools::PATCH));
         assert!(!empty.needs_sg());
     }
 
+    #[test]
+    fn apply_with_progress_reports_each_stitch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("lib.rs"), "fn hello() {}\n").unwrap();
+
+        let stitches = base.join("stitches");
+        fs::create_dir_all(&stitches).unwrap();
+        fs::write(stitches.join("001-todo.patch"), "# Nothing yet.\n").unwrap();
+        fs::write(
+            stitches.join("002-rename.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1 +1 @@\n-fn hello() {}\n+fn hi() {}\n",
+        )
+        .unwrap();
+        fs::write(
+            stitches.join("003-stale.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1 +1 @@\n-fn gone() {}\n+fn bye() {}\n",
+        )
+        .unwrap();
+        fs::write(stitches.join("004-never.sed"), "s/hi/hey/\n").unwrap();
+
+        let set = StitchSet {
+            stitches: stitch_dir_files(&stitches)
+                .unwrap()
+                .into_iter()
+                .filter_map(Stitch::from_path)
+                .collect(),
+            ..StitchSet::default()
+        };
+        let mut results = Vec::new();
+        let result = set.apply_with_progress(base, |stitch, result| {
+            results.push((stitch.path().file_name().unwrap().to_string(), result));
+        });
+
+        assert!(result.is_err());
+        assert_eq!(results.len(), 3, "{results:?}");
+        assert_eq!(
+            results[..2],
+            [
+                ("001-todo.patch".to_string(), StitchResult::Skipped),
+                ("002-rename.patch".to_string(), StitchResult::Applied),
+            ]
+        );
+        let (name, StitchResult::Failed(output)) = &results[2] else {
+            panic!("{results:?}");
+        };
+        assert_eq!(name, "003-stale.patch");
+        assert!(!output.is_empty());
+        assert_eq!(
+            fs::read_to_string(src.join("lib.rs")).unwrap(),
+            "fn hi() {}\n"
+        );
+    }
+
+    #[test]
+    fn merge_skips_or_overrides_stitches_with_the_same_file_name() {
+        let set = |paths: &[&str]| StitchSet {
+            stitches: paths
+                .iter()
+                .map(|path| Stitch::from_path(Utf8PathBuf::from(path)).unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        let paths =
+            |set: StitchSet| -> Vec<String> { set.file_paths().map(ToString::to_string).collect() };
+
+        // Empty sets.
+        assert!(set(&[]).merge(set(&[])).is_empty());
+        assert!(set(&[]).merge_override(set(&[])).is_empty());
+        assert_eq!(
+            paths(set(&[]).merge(set(&["user/001-a.patch"]))),
+            ["user/001-a.patch"]
+        );
+        assert_eq!(
+            paths(set(&["ws/001-a.patch"]).merge_override(set(&[]))),
+            ["ws/001-a.patch"]
+        );
+
+        // Fully overlapping.
+        let ws = || set(&["ws/001-a.patch", "ws/002-b.sed"]);
+        let user = || set(&["user/002-b.sed", "user/001-a.patch"]);
+        assert_eq!(
+            paths(ws().merge(user())),
+            ["ws/001-a.patch", "ws/002-b.sed"]
+        );
+        assert_eq!(
+            paths(ws().merge_override(user())),
+            ["user/001-a.patch", "user/002-b.sed"]
+        );
+
+        // Partially overlapping, sorted by file name.
+        let ws = || set(&["ws/001-a.patch", "ws/003-c.patch"]);
+        let user = || set(&["user/003-c.patch", "user/002-b.yaml"]);
+        assert_eq!(
+            paths(ws().merge(user())),
+            ["ws/001-a.patch", "user/002-b.yaml", "ws/003-c.patch"]
+        );
+        assert_eq!(
+            paths(ws().merge_override(user())),
+            ["ws/001-a.patch", "user/002-b.yaml", "user/003-c.patch"]
+        );
+    }
+
+    #[test]
+    fn conflict_check_finds_patches_made_against_the_same_lines() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        let patch = |name: &str, from: &str, to: &str| {
+            let path = dir.join(name);
+            fs::write(
+                &path,
+                format!(
+                    "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n fn greeting() {{\n\
+                     -    {from}\n+    {to}\n }}\n"
+                ),
+            )
+            .unwrap();
+            Stitch::from_path(path).unwrap()
+        };
+        let set = |stitches| StitchSet {
+            stitches,
+            ..Default::default()
+        };
+
+        // The second patch changes the line as the first one left it.
+        let stacked = set(vec![
+            patch("001-a.patch", "\"hello\"", "\"patched\""),
+            patch("002-b.patch", "\"patched\"", "\"patched!\""),
+        ]);
+        assert!(stacked.conflict_check().is_empty());
+
+        // Both patches were made against the original line.
+        let clashing = set(vec![
+            patch("001-a.patch", "\"hello\"", "\"patched\""),
+            patch("002-b.patch", "\"hello\"", "\"bonjour\""),
+            patch("003-c.sed", "", ""),
+        ]);
+        let conflicts = clashing.conflict_check();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].stitch_a.path().file_name(),
+            Some("001-a.patch")
+        );
+        assert_eq!(
+            conflicts[0].stitch_b.path().file_name(),
+            Some("002-b.patch")
+        );
+        assert_eq!(conflicts[0].file, PathBuf::from("src/lib.rs"));
+        assert_eq!(conflicts[0].overlapping_lines, 2..=2);
+    }
+
+    #[test]
+    fn validate_reports_changed_files_and_leaves_source_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Utf8Path::from_path(tmp.path()).unwrap();
+        let src = root.join("crate-a");
+        fs::create_dir_all(src.join("src")).unwrap();
+        fs::write(src.join("src/lib.rs"), "fn hello() {}\n").unwrap();
+        fs::write(src.join("src/other.rs"), "fn other() {}\n").unwrap();
+
+        let rename = root.join("001-rename.sed");
+        fs::write(&rename, "s/hello/patched/\n").unwrap();
+        let noop = root.join("002-noop.sed");
+        fs::write(&noop, "s/missing/nothing/\n").unwrap();
+        let set = StitchSet::default();
+
+        let Ok(result) = Stitch::SedRule(rename).validate(&src, &set) else {
+            panic!("the rename should apply");
+        };
+        assert_eq!(
+            result,
+            ValidationResult {
+                applied: true,
+                changed_files: vec![PathBuf::from("src/lib.rs")],
+            }
+        );
+        assert_eq!(
+            fs::read_to_string(src.join("src/lib.rs")).unwrap(),
+            "fn hello() {}\n"
+        );
+
+        let Ok(result) = Stitch::SedRule(noop).validate(&src, &set) else {
+            panic!("a script without matches should still apply");
+        };
+        assert_eq!(result, ValidationResult::default());
+    }
+
     #[test]
     fn file_paths_returns_all() {
         let set = StitchSet {
@@ -308,6 +3110,7 @@ mod tests {
                 Stitch::Patch(Utf8PathBuf::from("a.patch")),
                 Stitch::SgRule(Utf8PathBuf::from("b.yaml")),
             ],
+            ..Default::default()
         };
         let paths: Vec<_> = set.file_paths().collect();
         assert_eq!(
@@ -336,6 +3139,7 @@ mod tests {
                 Stitch::Patch(Utf8PathBuf::from("a.patch")),
                 Stitch::SgRule(Utf8PathBuf::from("b.yaml")),
             ],
+            ..Default::default()
         };
         let json = serde_json::to_string(&set).unwrap();
         let deser: StitchSet = serde_json::from_str(&json).unwrap();
@@ -1,14 +1,67 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::process::Command;
+use std::io::{self, BufRead, Write};
+use std::process::{Command, ExitCode};
 
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::Package;
 use terrors::OneOf;
 
-use crate::error::{CargoFailed, IoError, MissingStitchSet, MissingTool, MissingWorkspaceRoot};
-use crate::fs::find_workspace_root;
-use crate::stitch::StitchSet;
-use crate::{STITCH_MANIFEST_ENV, WORKSPACE_ROOT_ENV, WRAPPER_ENV, check_required_tools};
+use crate::config::WorkspaceMetadata;
+use crate::error::{
+    AstGrepFailed, CargoFailed, ChecksumMismatch, IoError, MissingStitchSet, MissingTool,
+    MissingWorkspaceRoot, PatchFailed, ScriptFailed,
+};
+use crate::fs::{
+    cargo_home, dependency_packages, find_workspace_root, temp_dir, workspace_packages,
+};
+use crate::hash::{STATE_FILE, StitchState};
+use crate::output::{self, ColorChoice, OutputContext, Verbosity};
+use crate::stitch::{StitchSet, cargo_status};
+use crate::vendor::{VENDOR_DIR, is_stitched_in_place, vendored_dirs};
+use crate::wrapper::{PATCHED_CRATES_DIR, patched_dir, registry_key};
+use crate::{
+    COLOR_ENV, EVENTS_FILE_ENV, JSON_ENV, KEEP_PATCHED_ENV, PACKAGES_ENV, PATCHED_DIR_ENV,
+    REGISTRY_SRC_ENV, STITCH_MANIFEST_ENV, VENDOR_DIR_ENV, VERBOSITY_ENV, WORKSPACE_ROOT_ENV,
+    WRAPPER_ENV, check_required_tools,
+};
+
+mod apply;
+mod apply_to;
+mod bench;
+mod capture;
+mod check;
+mod check_ordering;
+mod check_tools;
+mod clean;
+mod completions;
+mod diff;
+mod diff_snapshot;
+mod environment;
+mod export;
+mod fetch;
+mod fmt;
+mod help;
+mod init;
+mod install;
+mod lint;
+mod list;
+mod log;
+mod new;
+mod registry;
+mod reorder;
+mod reset;
+mod snapshot;
+mod status;
+mod summarize;
+mod trace;
+mod uninstall;
+mod upgrade;
+mod vendor;
+mod verify;
+mod verify_applied;
 
 /// FNV-1a 64-bit hash of `data`.
 fn fnv1a_64(data: &[u8]) -> u64 {
@@ -26,11 +79,30 @@ type SubcommandError = OneOf<(
     MissingWorkspaceRoot,
     MissingStitchSet,
     MissingTool,
+    PatchFailed,
+    AstGrepFailed,
+    ScriptFailed,
+    ChecksumMismatch,
 )>;
 
 struct CargoStitchArgs {
     set_name: String,
     set_explicit: bool,
+    keep_patched: bool,
+    /// `--external`: stitch crates.io dependencies too.
+    external: bool,
+    /// `--json`, which must come before the command: after it, `--json` is the
+    /// command's own flag (e.g. `cargo stitch list --json`).
+    json: bool,
+    /// `--quiet`/`-q` or `--verbose`/`-v`, also only before the command: after it, they
+    /// are cargo's own.
+    verbosity: Option<Verbosity>,
+    /// `--color <when>` or `--color=<when>`, only before the command: after it, `--color`
+    /// is cargo's own.  Checked by `color`.
+    color: Option<String>,
+    /// `--strict`, only before the command: after it, it is the command's own flag (e.g.
+    /// `cargo stitch check --strict`).
+    strict: bool,
     cargo_args: Vec<String>,
 }
 
@@ -49,15 +121,41 @@ impl CargoStitchArgs {
         Self::parse(raw_args)
     }
 
-    /// Parse `--set <name>` out of args, returning the set name and the remaining cargo args.
+    /// Parse `--set <name>`, `--keep-patched`, `--external`, `--json`, `--quiet`, `--verbose`,
+    /// `--color <when>` and `--strict` out of args, returning them along with the remaining
+    /// cargo args.
     fn parse(args: &[String]) -> Self {
         let mut set_name = None;
+        let mut keep_patched = false;
+        let mut external = false;
+        let mut json = false;
+        let mut verbosity = None;
+        let mut color = None;
+        let mut strict = false;
         let mut cargo_args = Vec::new();
         let mut args = args.iter();
 
         while let Some(arg) = args.next() {
             if arg == "--set" {
                 set_name = args.next().cloned();
+            } else if arg == "--keep-patched" {
+                keep_patched = true;
+            } else if arg == "--external" {
+                external = true;
+            } else if arg == "--json" && cargo_args.is_empty() {
+                json = true;
+            } else if (arg == "--quiet" || arg == "-q") && cargo_args.is_empty() {
+                verbosity = Some(Verbosity::Quiet);
+            } else if (arg == "--verbose" || arg == "-v") && cargo_args.is_empty() {
+                verbosity = Some(Verbosity::Verbose);
+            } else if arg == "--color" && cargo_args.is_empty() {
+                color = Some(args.next().cloned().unwrap_or_default());
+            } else if let Some(when) = arg.strip_prefix("--color=")
+                && cargo_args.is_empty()
+            {
+                color = Some(when.to_string());
+            } else if arg == "--strict" && cargo_args.is_empty() {
+                strict = true;
             } else {
                 cargo_args.push(arg.clone());
             }
@@ -66,71 +164,617 @@ impl CargoStitchArgs {
         Self {
             set_explicit: set_name.is_some(),
             set_name: set_name.unwrap_or_else(|| "default".to_string()),
+            keep_patched,
+            external,
+            json,
+            verbosity,
+            color,
+            strict,
             cargo_args,
         }
     }
+
+    /// The colour choice given with `--color`, if any.
+    fn color(&self) -> Result<Option<ColorChoice>, IoError> {
+        self.color
+            .as_deref()
+            .map(|when| {
+                ColorChoice::from_name(when).ok_or_else(|| {
+                    usage_error(format!(
+                        "invalid value for `--color`: `{when}` (expected `auto`, `always` or `never`)"
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    /// How to print diagnostics: as asked for by the flags, or else by
+    /// `CARGO_STITCH_JSON` and `CARGO_STITCH_VERBOSITY`.
+    fn output(&self) -> OutputContext {
+        let mut output = OutputContext::from_env();
+        if self.json {
+            output = OutputContext {
+                verbosity: output.verbosity,
+                ..OutputContext::JSON
+            };
+        }
+        if let Some(verbosity) = self.verbosity {
+            output.verbosity = verbosity;
+        }
+        if let Ok(Some(color)) = self.color() {
+            output.color = color;
+        }
+        output
+    }
+}
+
+/// Paths shared by all subcommands, resolved from the current directory.
+struct Workspace {
+    root: Utf8PathBuf,
+    stitches_dir: Utf8PathBuf,
+    /// The directories the stitch set is discovered in, in priority order: those of
+    /// `CARGO_STITCH_PATCHES_PATH`, then `stitches_dir`.
+    set_dirs: Vec<Utf8PathBuf>,
+    /// Cargo's target directory: `$CARGO_TARGET_DIR` if set, `<root>/target` otherwise.
+    target_dir: Utf8PathBuf,
+    /// Where the patched copies of the crates go: `<target_dir>/cargo-stitch`, unless
+    /// `[workspace.metadata.stitch]` says otherwise.
+    patched_root: Utf8PathBuf,
+    /// The settings from `[workspace.metadata.stitch]`.
+    metadata: WorkspaceMetadata,
+    keep_patched: bool,
+    /// Whether `--external` asks for crates.io dependencies to be stitched.
+    external: bool,
+    /// Whether `--strict` turns warnings about the stitches into errors.
+    strict: bool,
+    output: OutputContext,
+}
+
+impl Workspace {
+    fn resolve(
+        args: &CargoStitchArgs,
+    ) -> Result<Self, OneOf<(IoError, MissingWorkspaceRoot, MissingStitchSet)>> {
+        args.color().map_err(OneOf::new)?;
+
+        let cwd =
+            Utf8PathBuf::from_path_buf(env::current_dir().map_err(|e| OneOf::new(IoError(e)))?)
+                .map_err(|p| {
+                    OneOf::new(IoError(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("current directory is not valid UTF-8: {}", p.display()),
+                    )))
+                })?;
+
+        let root = find_workspace_root(&cwd)
+            .ok_or_else(|| OneOf::new(MissingWorkspaceRoot(cwd.clone())))?;
+
+        let output = args.output();
+        let metadata = WorkspaceMetadata::load(&root, &output).map_err(OneOf::broaden)?;
+        let stitches_dir = metadata.stitches_dir(&root).join(&args.set_name);
+        let set_dirs: Vec<_> = metadata
+            .stitches_dirs(&root)
+            .into_iter()
+            .map(|dir| dir.join(&args.set_name))
+            .collect();
+
+        if !set_dirs.iter().any(|dir| dir.is_dir()) && args.set_explicit {
+            return Err(OneOf::new(MissingStitchSet(args.set_name.clone())));
+        }
+
+        // A relative CARGO_TARGET_DIR is relative to the directory cargo is run from.
+        let custom_target_dir = env::var("CARGO_TARGET_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| cwd.join(dir));
+
+        let target_dir = custom_target_dir.unwrap_or_else(|| root.join("target"));
+
+        Ok(Self {
+            patched_root: metadata.patched_dir(&root, &target_dir),
+            target_dir,
+            metadata,
+            root,
+            stitches_dir,
+            set_dirs,
+            keep_patched: args.keep_patched,
+            external: args.external,
+            strict: args.strict,
+            output,
+        })
+    }
+
+    fn discover(&self) -> Result<HashMap<String, StitchSet>, OneOf<(IoError, ChecksumMismatch)>> {
+        let dirs: Vec<_> = self.set_dirs.iter().map(Utf8PathBuf::as_path).collect();
+        StitchSet::discover_all(&dirs, &self.metadata, &self.output)
+    }
+
+    /// The stitches for `krate`, failing if there are none.
+    fn stitch_set(&self, krate: &str) -> Result<StitchSet, OneOf<(IoError, ChecksumMismatch)>> {
+        let dirs: Vec<_> = self.set_dirs.iter().map(Utf8PathBuf::as_path).collect();
+        StitchSet::discover_for_package(&dirs, krate, &self.metadata, &self.output)?.ok_or_else(
+            || {
+                OneOf::new(usage_error(format!(
+                    "no stitches found for crate `{krate}` in {}",
+                    self.stitches_dir
+                )))
+            },
+        )
+    }
+
+    /// The source directory of the workspace member `krate`.
+    fn member_dir(&self, krate: &str) -> Result<Utf8PathBuf, IoError> {
+        workspace_packages(&self.root)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|p| p.name.as_str() == krate)
+            .and_then(|p| p.manifest_path.parent().map(ToOwned::to_owned))
+            .ok_or_else(|| usage_error(format!("crate `{krate}` is not a member of the workspace")))
+    }
+}
+
+/// An error for malformed subcommand arguments.
+fn usage_error(message: impl Into<String>) -> IoError {
+    IoError(io::Error::new(io::ErrorKind::InvalidInput, message.into()))
+}
+
+/// Ask `question` on stderr and read the answer from stdin.  Anything but `y` or `yes`,
+/// including end of input, is a no.
+fn confirm(question: &str) -> io::Result<bool> {
+    let mut stderr = io::stderr().lock();
+    write!(stderr, "{question} [y/N] ")?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
 }
 
-pub fn run_subcommand() -> Result<(), SubcommandError> {
+pub fn run_subcommand() -> Result<ExitCode, SubcommandError> {
+    output::init_tracing();
+
     let args = CargoStitchArgs::from_env();
+    let output = args.output();
+
+    match dispatch(&args) {
+        // With JSON diagnostics, the error is an event like any other, rather than the
+        // plain line `main` would print.
+        Err(e) if output.is_json() => {
+            output.error(None, None, &e);
+            Ok(ExitCode::FAILURE)
+        }
+        result => result,
+    }
+}
+
+fn dispatch(args: &CargoStitchArgs) -> Result<ExitCode, SubcommandError> {
+    // Help and completions need no workspace, and so work outside of one.
+    match args.cargo_args.first().map(String::as_str) {
+        Some(command) if help::wants_help(command, &args.cargo_args[1..]) => {
+            help::run(&args.cargo_args[..1])
+        }
+        Some("completions") => completions::run(&args.cargo_args[1..]),
+        Some("help") if help::has_topic(&args.cargo_args[1..]) => help::run(&args.cargo_args[1..]),
+        _ => dispatch_in_workspace(args),
+    }
+}
+
+fn dispatch_in_workspace(args: &CargoStitchArgs) -> Result<ExitCode, SubcommandError> {
+    let workspace = Workspace::resolve(args).map_err(OneOf::broaden)?;
+
+    match args.cargo_args.first().map(String::as_str) {
+        Some("apply") => apply::run(&workspace, &args.cargo_args[1..]),
+        Some("apply-to") => apply_to::run(&workspace, &args.cargo_args[1..]),
+        Some("bench") if bench::names_stitched_crate(&workspace, &args.cargo_args[1..]) => {
+            bench::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("capture") => capture::run(&workspace, &args.cargo_args[1..]),
+        Some("check") if check::wants_report(&args.cargo_args[1..]) => {
+            check::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("check-ordering") => check_ordering::run(&workspace, &args.cargo_args[1..]),
+        Some("check-tools") => check_tools::run(&workspace, &args.cargo_args[1..]),
+        Some("check-unique-ids") => lint::run_check_unique_ids(&workspace, &args.cargo_args[1..]),
+        Some("clean") if clean::is_stitch_clean(&args.cargo_args[1..]) => {
+            clean::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("diff") => diff::run(&workspace, &args.cargo_args[1..]),
+        Some("diff-snapshot") => diff_snapshot::run(&workspace, &args.cargo_args[1..]),
+        Some("env") => environment::run(&workspace, &args.cargo_args[1..]),
+        Some("export") => export::run(&workspace, &args.cargo_args[1..]),
+        Some("fetch") if fetch::is_crate_fetch(&args.cargo_args[1..]) => {
+            fetch::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("fetch-stitches") => registry::run_fetch(&workspace, &args.cargo_args[1..]),
+        Some("fmt") if fmt::names_stitched_crates(&workspace, &args.cargo_args[1..]) => {
+            fmt::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("init") if init::names_member(&workspace, &args.cargo_args[1..]) => {
+            init::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("install") if install::is_self_source(&workspace) => {
+            install::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("lint") => lint::run(&workspace, &args.cargo_args[1..]),
+        Some("list") => list::run(&workspace, &args.cargo_args[1..]),
+        Some("log") => log::run(&workspace, &args.cargo_args[1..]),
+        Some("new") if new::names_member(&workspace, &args.cargo_args[1..]) => {
+            new::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("publish-stitches") => registry::run_publish(&workspace, &args.cargo_args[1..]),
+        Some("reorder") => reorder::run(&workspace, &args.cargo_args[1..]),
+        Some("reset") => reset::run(&workspace, &args.cargo_args[1..]),
+        Some("snapshot") => snapshot::run(&workspace, &args.cargo_args[1..]),
+        Some("status") => status::run(&workspace, &args.cargo_args[1..]),
+        Some("summarize") => summarize::run(&workspace, &args.cargo_args[1..]),
+        Some("trace") => trace::run(&workspace, &args.cargo_args[1..]),
+        Some("uninstall") if uninstall::names_stitched_crate(&workspace, &args.cargo_args[1..]) => {
+            uninstall::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("upgrade") if upgrade::names_stitched_crate(&workspace, &args.cargo_args[1..]) => {
+            upgrade::run(&workspace, &args.cargo_args[1..])
+        }
+        Some("vendor") => vendor::run(&workspace, &args.cargo_args[1..]),
+        Some("verify") => verify::run(&workspace, &args.cargo_args[1..]),
+        Some("verify-applied") => verify_applied::run(&workspace, &args.cargo_args[1..]),
+        _ => run_cargo(&workspace, &args.cargo_args).map(|()| ExitCode::SUCCESS),
+    }
+}
+
+/// Run `cargo` with `cargo_args`, with cargo-stitch installed as the rustc wrapper.
+fn run_cargo(workspace: &Workspace, cargo_args: &[String]) -> Result<(), SubcommandError> {
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+
+    check_required_tools(manifest.values(), &workspace.output).map_err(OneOf::broaden)?;
+    check_orphaned_stitches(workspace, &manifest).map_err(OneOf::new)?;
+    clean_stale_dependencies(workspace, &manifest).map_err(OneOf::new)?;
+
+    let mut cargo = Command::new("cargo");
+    cargo
+        .args(cargo_args)
+        .envs(stitch_env(workspace, &manifest).map_err(OneOf::new)?);
+
+    // With `--package`, only stitch the selected packages, not their dependencies.
+    let packages = selected_packages(cargo_args);
+    if !packages.is_empty() {
+        cargo.env(PACKAGES_ENV, packages.join(","));
+    }
+
+    // Off a terminal, cargo strips the colour from what the wrapper prints unless it uses
+    // colour itself.
+    if workspace.output.color == ColorChoice::Always && env::var_os("CARGO_TERM_COLOR").is_none() {
+        cargo.env("CARGO_TERM_COLOR", "always");
+    }
+
+    // Cargo would swallow the JSON the wrapper prints, so it is collected in a file.
+    let (_guard, scratch) = temp_dir().map_err(|e| OneOf::new(IoError(e)))?;
+    let events_file = scratch.join("events.jsonl");
+    if workspace.output.is_json() {
+        cargo.env(EVENTS_FILE_ENV, &events_file);
+    }
+
+    let status = cargo.status().map_err(|e| OneOf::new(IoError(e)))?;
+
+    if let Ok(events) = fs::read(&events_file) {
+        let _ = io::stderr().write_all(&events);
+    }
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(OneOf::new(CargoFailed(status.code().unwrap_or(1))))
+    }
+}
+
+/// Warn about each directory of the stitch set whose stitches are for a crate that is not
+/// a member of the workspace, e.g. one that was renamed, and so are never applied.  With
+/// `--strict`, fail instead.
+///
+/// Only the workspace's own stitch directory is looked at: those of
+/// `CARGO_STITCH_PATCHES_PATH` may hold stitches for other workspaces.  Stitches for
+/// crates.io dependencies and vendored crates are not for members either, and are left
+/// alone.
+fn check_orphaned_stitches(
+    workspace: &Workspace,
+    manifest: &HashMap<String, StitchSet>,
+) -> Result<(), IoError> {
+    // Without `cargo metadata`, there is no telling what the members are.
+    let Ok(packages) = workspace_packages(&workspace.root) else {
+        return Ok(());
+    };
+
+    let mut orphaned: Vec<_> = manifest
+        .iter()
+        .filter(|(name, set)| {
+            !is_applied(workspace, &packages, name, set)
+                && workspace.stitches_dir.join(name).is_dir()
+        })
+        .map(|(name, _)| {
+            let dir = workspace.stitches_dir.join(name);
+            dir.strip_prefix(&workspace.root).unwrap_or(&dir).to_owned()
+        })
+        .collect();
+    orphaned.sort();
+
+    if workspace.strict && !orphaned.is_empty() {
+        let dirs: Vec<_> = orphaned.iter().map(ToString::to_string).collect();
+        return Err(IoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "stitches for crates that are not members of the workspace: {}",
+                dirs.join(", ")
+            ),
+        )));
+    }
+    for dir in orphaned {
+        cargo_status(
+            &workspace.output,
+            "Warning",
+            &format!(
+                "{dir} has stitches for `{}`, which is not a member of the workspace",
+                dir.file_name().unwrap_or_default()
+            ),
+        );
+    }
+    Ok(())
+}
+
+/// Run `cargo clean --package <name>@<version>` for each crates.io dependency or vendored
+/// crate whose patched copy was made from other stitches than those in `manifest`.
+///
+/// Cargo fingerprints such crates by their version alone: after one of their stitches
+/// changes, it would neither run the wrapper for them again nor rebuild them, and keep
+/// using what it compiled from the old stitches.
+fn clean_stale_dependencies(
+    workspace: &Workspace,
+    manifest: &HashMap<String, StitchSet>,
+) -> Result<(), IoError> {
+    // Finding the dependencies resolves the whole dependency graph, so only do it if one
+    // of them was stitched by an earlier build.
+    let state_file = |key: &str| patched_dir(key, &workspace.patched_root).join(STATE_FILE);
+    let stitched_before = manifest
+        .iter()
+        .any(|(key, set)| is_dependency_key(workspace, key, set) && state_file(key).is_file());
+    if !stitched_before {
+        return Ok(());
+    }
+
+    let registry_src = cargo_home().map_err(IoError)?.join("registry").join("src");
+    for package in dependency_packages(&workspace.root).map_err(IoError)? {
+        let (Some(source_dir), Some(key)) = (
+            package.manifest_path.parent(),
+            dependency_key(workspace, &package, &registry_src),
+        ) else {
+            continue;
+        };
+        if is_stitched_in_place(source_dir) {
+            continue;
+        }
+        let (Some(stitch_set), Some(stored)) =
+            (manifest.get(&key), StitchState::load(&state_file(&key)))
+        else {
+            continue;
+        };
+        if !is_dependency_key(workspace, &key, stitch_set)
+            || !is_stale(&stored, source_dir, stitch_set).map_err(IoError)?
+        {
+            continue;
+        }
 
-    let self_exe = env::current_exe().map_err(|e| OneOf::new(IoError(e)))?;
+        let spec = format!("{}@{}", package.name, package.version);
+        cargo_status(
+            &workspace.output,
+            "Cleaning",
+            &format!("{spec}, whose stitches changed"),
+        );
+        let status = Command::new("cargo")
+            .args(["clean", "--package", &spec])
+            .current_dir(&workspace.root)
+            .status()
+            .map_err(IoError)?;
+        if !status.success() {
+            return Err(IoError(io::Error::other(format!(
+                "`cargo clean --package {spec}` failed ({status})"
+            ))));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the stitches in `set`, found under `key`, are for a crates.io dependency or a
+/// vendored crate that the build stitches in a copy.
+fn is_dependency_key(workspace: &Workspace, key: &str, set: &StitchSet) -> bool {
+    ((workspace.external || set.is_external()) && is_registry_key(key))
+        || vendored_dirs(&workspace.root.join(VENDOR_DIR), key)
+            .iter()
+            .any(|dir| !is_stitched_in_place(dir))
+}
+
+/// Whether the patched copy of the crate at `source_dir`, last made as `stored` says, is
+/// out of date with `stitch_set`.
+fn is_stale(
+    stored: &StitchState,
+    source_dir: &Utf8Path,
+    stitch_set: &StitchSet,
+) -> io::Result<bool> {
+    let mut current = StitchState::compute(source_dir, stitch_set)?;
+
+    // The wrapper leaves out the stitches for the features the build does not enable, so
+    // the feature-gated stitches that were left out last time do not count.
+    let applied: HashSet<_> = stored
+        .stitches
+        .iter()
+        .filter_map(|s| s.path.file_name())
+        .collect();
+    let gated: HashSet<_> = stitch_set
+        .iter()
+        .filter(|stitch| !stitch_set.features_of(stitch).is_empty())
+        .map(|stitch| stitch.path())
+        .collect();
+    current.stitches.retain(|s| {
+        !gated.contains(s.path.as_path())
+            || s.path
+                .file_name()
+                .is_some_and(|name| applied.contains(name))
+    });
+
+    Ok(!current.matches(stored))
+}
+
+/// Whether the stitches in `set`, found under `name`, are applied by builds: whether they
+/// are for a member of the workspace among `packages`, a crates.io dependency or a vendored crate.
+pub(super) fn is_applied(
+    workspace: &Workspace,
+    packages: &[Package],
+    name: &str,
+    set: &StitchSet,
+) -> bool {
+    packages.iter().any(|p| p.name.as_str() == name)
+        || set.is_external()
+        || (workspace.external && is_registry_key(name))
+        || !vendored_dirs(&workspace.root.join(VENDOR_DIR), name).is_empty()
+}
+
+/// The key under which the stitches of `package`, a crates.io dependency or a vendored
+/// crate of `workspace`, are found: `<crate>-<version>` or `<crate>`.
+pub(super) fn dependency_key(
+    workspace: &Workspace,
+    package: &Package,
+    registry_src: &Utf8Path,
+) -> Option<String> {
+    if package.manifest_path.starts_with(registry_src) {
+        Some(registry_key(
+            package.name.as_str(),
+            &package.version.to_string(),
+        ))
+    } else if package
+        .manifest_path
+        .starts_with(workspace.root.join(VENDOR_DIR))
+    {
+        Some(package.name.to_string())
+    } else {
+        None
+    }
+}
+
+/// Whether `name` looks like the `<crate>-<version>` directory of a crates.io dependency's
+/// stitches.
+fn is_registry_key(name: &str) -> bool {
+    name.match_indices('-')
+        .any(|(i, _)| i > 0 && name[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// The names of the packages selected with `--package <spec>` or `-p <spec>` in
+/// `cargo_args`, which are passed on to cargo as they are.  Arguments after `--` are the
+/// program's, not cargo's.
+fn selected_packages(cargo_args: &[String]) -> Vec<String> {
+    let mut packages = Vec::new();
+    let mut args = cargo_args.iter().take_while(|arg| *arg != "--");
+
+    while let Some(arg) = args.next() {
+        let spec = match arg.as_str() {
+            "--package" | "-p" => args.next().map(String::as_str),
+            other => other
+                .strip_prefix("--package=")
+                .or_else(|| other.strip_prefix("-p").filter(|spec| !spec.is_empty())),
+        };
+        // A package spec may name a version too, as in `serde@1.0.200`.
+        if let Some(name) = spec.and_then(|spec| spec.split('@').next()) {
+            packages.push(name.to_string());
+        }
+    }
 
-    let cwd = Utf8PathBuf::from_path_buf(env::current_dir().map_err(|e| OneOf::new(IoError(e)))?)
-        .map_err(|p| {
-        OneOf::new(IoError(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            format!("current directory is not valid UTF-8: {}", p.display()),
-        )))
-    })?;
+    packages
+}
 
-    let workspace_root =
-        find_workspace_root(&cwd).ok_or_else(|| OneOf::new(MissingWorkspaceRoot(cwd.clone())))?;
+/// The environment variables that install cargo-stitch as the rustc wrapper for a build
+/// with the stitches in `manifest`, in the order `cargo stitch env` prints them.
+///
+/// This writes the manifest file that `STITCH_MANIFEST_ENV` points to.
+fn stitch_env(
+    workspace: &Workspace,
+    manifest: &HashMap<String, StitchSet>,
+) -> Result<Vec<(&'static str, OsString)>, IoError> {
+    let self_exe = env::current_exe().map_err(IoError)?;
+    let mut vars = Vec::new();
+
+    // Vendored crates are not workspace members, so stitching them needs the wrapper to
+    // run for every crate.  Those already stitched in place by `cargo stitch vendor` do
+    // not count.
+    let vendor_dir = workspace.root.join(VENDOR_DIR);
+    let stitch_vendored = manifest
+        .keys()
+        .flat_map(|krate| vendored_dirs(&vendor_dir, krate))
+        .any(|dir| !is_stitched_in_place(&dir));
+
+    // Likewise for crates.io dependencies, with `--external` or when a crate's
+    // `stitch.toml` says its stitches are for one.
+    let stitch_external = workspace.external || manifest.values().any(StitchSet::is_external);
+
+    if stitch_vendored || stitch_external {
+        vars.push(("RUSTC_WRAPPER", self_exe.into_os_string()));
+    } else {
+        vars.push(("RUSTC_WORKSPACE_WRAPPER", self_exe.into_os_string()));
+    }
+    if stitch_vendored {
+        vars.push((VENDOR_DIR_ENV, vendor_dir.into_string().into()));
+    }
+    if stitch_external {
+        let registry_src = cargo_home().map_err(IoError)?.join("registry").join("src");
+        vars.push((REGISTRY_SRC_ENV, registry_src.into_string().into()));
+    }
+    vars.push((WRAPPER_ENV, "1".into()));
+    vars.push((WORKSPACE_ROOT_ENV, workspace.root.as_str().into()));
 
-    let stitches_dir = workspace_root.join("stitches").join(&args.set_name);
+    if workspace.keep_patched {
+        vars.push((KEEP_PATCHED_ENV, "1".into()));
+    }
 
-    if !stitches_dir.is_dir() && args.set_explicit {
-        return Err(OneOf::new(MissingStitchSet(args.set_name)));
+    if workspace.output.is_json() {
+        vars.push((JSON_ENV, "1".into()));
     }
 
-    let manifest = StitchSet::discover_all(&stitches_dir).map_err(OneOf::broaden)?;
+    // The wrapper's stderr is a pipe to cargo, so left to itself it never uses colour.
+    // Tell it what to do when it should, or when it would inherit something else.
+    let use_color = workspace.output.use_color();
+    if use_color || env::var_os(COLOR_ENV).is_some() {
+        let color = if use_color { "always" } else { "never" };
+        vars.push((COLOR_ENV, color.into()));
+    }
 
-    let need_patch = manifest.values().any(StitchSet::needs_patch);
-    let need_sg = manifest.values().any(StitchSet::needs_sg);
-    check_required_tools(need_patch, need_sg).map_err(OneOf::broaden)?;
+    if workspace.output.verbosity != Verbosity::Normal {
+        vars.push((VERBOSITY_ENV, workspace.output.verbosity.name().into()));
+    }
 
-    let manifest_json =
-        serde_json::to_string(&manifest).map_err(|e| OneOf::new(IoError(e.into())))?;
+    if workspace.patched_root != workspace.root.join("target").join(PATCHED_CRATES_DIR) {
+        vars.push((PATCHED_DIR_ENV, workspace.patched_root.as_str().into()));
+    }
 
-    // Write the manifest to target/cargo-stitch/ using a content hash as the filename.
+    // Write the manifest to <target dir>/cargo-stitch/ using a content hash as the filename.
     // This makes the file content-addressable: same manifest → same file, so concurrent
     // builds with identical manifests converge naturally.  The file is intentionally
     // persistent — cargo clean removes it with the rest of target/.
     // Per the critical invariant: write nothing (and create no directory) when the manifest
     // is empty, so `target/cargo-stitch/` does not exist for crates with no stitch files.
-    let mut cargo_cmd = Command::new("cargo");
-    cargo_cmd
-        .args(&args.cargo_args)
-        .env("RUSTC_WORKSPACE_WRAPPER", &self_exe)
-        .env(WRAPPER_ENV, "1")
-        .env(WORKSPACE_ROOT_ENV, workspace_root.as_str());
-
     if !manifest.is_empty() {
+        let manifest_json = serde_json::to_string(manifest).map_err(|e| IoError(e.into()))?;
         let hash = fnv1a_64(manifest_json.as_bytes());
-        let stitch_dir = workspace_root.join("target").join("cargo-stitch");
-        fs::create_dir_all(&stitch_dir).map_err(|e| OneOf::new(IoError(e)))?;
+        let stitch_dir = workspace.target_dir.join("cargo-stitch");
+        fs::create_dir_all(&stitch_dir).map_err(IoError)?;
         let manifest_file = stitch_dir.join(format!(".manifest-{hash:016x}.json"));
-        fs::write(&manifest_file, &manifest_json).map_err(|e| OneOf::new(IoError(e)))?;
-        cargo_cmd.env(STITCH_MANIFEST_ENV, manifest_file.as_os_str());
+        fs::write(&manifest_file, &manifest_json).map_err(IoError)?;
+        vars.push((STITCH_MANIFEST_ENV, manifest_file.into_string().into()));
     }
 
-    let status = cargo_cmd.status().map_err(|e| OneOf::new(IoError(e)))?;
+    Ok(vars)
+}
 
-    if status.success() {
-        Ok(())
-    } else {
-        Err(OneOf::new(CargoFailed(status.code().unwrap_or(1))))
-    }
+/// `args` as the `Vec<String>` a subcommand is given, for tests.
+#[cfg(test)]
+pub(crate) fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(ToString::to_string).collect()
 }
 
 #[cfg(test)]
@@ -170,6 +814,105 @@ mod tests {
         assert_eq!(args.cargo_args, vec!["build", "--release"]);
     }
 
+    #[test]
+    fn parse_keep_patched() {
+        let args = CargoStitchArgs::parse(&["build".to_string(), "--keep-patched".to_string()]);
+        assert!(args.keep_patched);
+        assert_eq!(args.cargo_args, vec!["build"]);
+
+        let args = CargoStitchArgs::parse(&["build".to_string()]);
+        assert!(!args.keep_patched);
+    }
+
+    #[test]
+    fn parse_external() {
+        let args = CargoStitchArgs::parse(&["--external".to_string(), "build".to_string()]);
+        assert!(args.external);
+        assert_eq!(args.cargo_args, vec!["build"]);
+
+        let args = CargoStitchArgs::parse(&["build".to_string()]);
+        assert!(!args.external);
+    }
+
+    #[test]
+    fn parse_json_only_before_command() {
+        let args = CargoStitchArgs::parse(&["--json".to_string(), "build".to_string()]);
+        assert!(args.json);
+        assert_eq!(args.cargo_args, vec!["build"]);
+
+        let args = CargoStitchArgs::parse(&["list".to_string(), "--json".to_string()]);
+        assert!(!args.json);
+        assert_eq!(args.cargo_args, vec!["list", "--json"]);
+    }
+
+    #[test]
+    fn parse_verbosity_only_before_command() {
+        let args = CargoStitchArgs::parse(&["-q".to_string(), "build".to_string()]);
+        assert_eq!(args.verbosity, Some(Verbosity::Quiet));
+        assert_eq!(args.cargo_args, vec!["build"]);
+
+        let args = CargoStitchArgs::parse(&["--verbose".to_string(), "build".to_string()]);
+        assert_eq!(args.verbosity, Some(Verbosity::Verbose));
+
+        let args = CargoStitchArgs::parse(&["build".to_string(), "-v".to_string()]);
+        assert_eq!(args.verbosity, None);
+        assert_eq!(args.cargo_args, vec!["build", "-v"]);
+    }
+
+    #[test]
+    fn parse_color_only_before_command() {
+        let args = CargoStitchArgs::parse(&["--color=never".to_string(), "build".to_string()]);
+        assert!(matches!(args.color(), Ok(Some(ColorChoice::Never))));
+        assert_eq!(args.cargo_args, vec!["build"]);
+
+        let args = CargoStitchArgs::parse(&[
+            "--color".to_string(),
+            "always".to_string(),
+            "build".to_string(),
+        ]);
+        assert!(matches!(args.color(), Ok(Some(ColorChoice::Always))));
+
+        let args = CargoStitchArgs::parse(&["build".to_string(), "--color=never".to_string()]);
+        assert!(matches!(args.color(), Ok(None)));
+        assert_eq!(args.cargo_args, vec!["build", "--color=never"]);
+
+        let args = CargoStitchArgs::parse(&["--color=sometimes".to_string()]);
+        assert!(args.color().is_err());
+    }
+
+    #[test]
+    fn selected_packages_in_every_form() {
+        assert_eq!(
+            selected_packages(&args(&["build", "-p", "a", "--package", "b", "--release"])),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            selected_packages(&args(&["test", "--package=a@0.1.0", "-pb"])),
+            vec!["a", "b"]
+        );
+        assert!(selected_packages(&args(&["build", "--profile", "dev"])).is_empty());
+        assert!(selected_packages(&args(&["run", "--", "-p", "a"])).is_empty());
+    }
+
+    #[test]
+    fn parse_strict_only_before_the_command() {
+        let parsed = CargoStitchArgs::parse(&args(&["--strict", "build"]));
+        assert!(parsed.strict);
+        assert_eq!(parsed.cargo_args, ["build"]);
+
+        let parsed = CargoStitchArgs::parse(&args(&["check", "--strict"]));
+        assert!(!parsed.strict);
+        assert_eq!(parsed.cargo_args, ["check", "--strict"]);
+    }
+
+    #[test]
+    fn registry_keys_end_with_a_version() {
+        assert!(is_registry_key("serde-1.0.200"));
+        assert!(is_registry_key("serde-json-1.0.0-alpha"));
+        assert!(!is_registry_key("crate-a"));
+        assert!(!is_registry_key("sha2"));
+    }
+
     #[test]
     fn parse_set_without_value_defaults() {
         let args = CargoStitchArgs::parse(&["--set".to_string()]);
@@ -0,0 +1,52 @@
+//! `cargo stitch apply`: bring the patched directory of every crate with stitches up to
+//! date, without building anything.
+//!
+//! The crates are stitched concurrently, and a failing crate does not stop the others:
+//! every failure is reported at the end.
+
+use std::collections::HashMap;
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::fs::workspace_packages;
+use crate::stitch::{StitchSet, failed_stitch};
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    if let Some(arg) = args.first() {
+        return Err(OneOf::new(usage_error(format!(
+            "unexpected argument for `apply`: {arg}"
+        ))));
+    }
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+
+    check_required_tools(manifest.values(), &workspace.output).map_err(OneOf::broaden)?;
+
+    let sources: HashMap<_, _> = workspace_packages(&workspace.root)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|p| {
+            let manifest_dir = p.manifest_path.parent()?.to_owned();
+            Some((p.name.to_string(), manifest_dir))
+        })
+        .collect();
+
+    match StitchSet::apply_all_parallel(
+        &manifest,
+        &sources,
+        &workspace.patched_root,
+        workspace.keep_patched,
+        &workspace.output,
+    ) {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(failures) => {
+            for (pkg_name, e) in &failures {
+                workspace.output.error(Some(pkg_name), failed_stitch(e), e);
+            }
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! `cargo stitch apply-to <dir> <crate>`: apply a crate's stitches to a directory of the
+//! user's choosing, in place.
+
+use std::io;
+use std::process::ExitCode;
+
+use camino::Utf8PathBuf;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::error::IoError;
+use crate::stitch::cargo_status;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    dir: Utf8PathBuf,
+    krate: String,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        match args {
+            [dir, krate] if !dir.starts_with('-') && !krate.starts_with('-') => Ok(Self {
+                dir: Utf8PathBuf::from(dir),
+                krate: krate.clone(),
+            }),
+            _ => Err(usage_error("usage: cargo stitch apply-to <dir> <crate>")),
+        }
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    if !args.dir.is_dir() {
+        return Err(OneOf::new(IoError(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a directory", args.dir),
+        ))));
+    }
+
+    let stitch_set = workspace.stitch_set(&args.krate).map_err(OneOf::broaden)?;
+
+    check_required_tools([&stitch_set], &workspace.output).map_err(OneOf::broaden)?;
+
+    cargo_status(
+        &workspace.output,
+        "Applying",
+        &format!("the stitches of {} to {}", args.krate, args.dir),
+    );
+    stitch_set
+        .apply(&args.dir, &workspace.output)
+        .map_err(OneOf::broaden)?;
+    cargo_status(
+        &workspace.output,
+        "Stitched",
+        &format!("{} in {}", args.krate, args.dir),
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_dir_and_crate() {
+        assert_eq!(
+            Args::parse(&args(&["/tmp/copy", "crate-a"])).unwrap(),
+            Args {
+                dir: Utf8PathBuf::from("/tmp/copy"),
+                krate: "crate-a".to_string(),
+            }
+        );
+        assert!(Args::parse(&args(&["/tmp/copy"])).is_err());
+        assert!(Args::parse(&args(&["/tmp/copy", "crate-a", "extra"])).is_err());
+        assert!(Args::parse(&args(&["--dir", "crate-a"])).is_err());
+    }
+}
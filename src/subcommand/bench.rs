@@ -0,0 +1,223 @@
+//! `cargo stitch bench <crate> [--iterations <n>]`: time how long each stitch of a crate
+//! takes to apply, e.g. to find an ast-grep rule that dominates build time.
+//!
+//! This only takes over `bench` when it names a crate with stitches; anything else is
+//! forwarded to `cargo bench` as usual.
+
+use std::fmt::Write as _;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::error::IoError;
+use crate::fs::{copy_dir_recursive, temp_dir};
+use crate::stitch::{ApplyError, StitchSet};
+
+const USAGE: &str = "usage: cargo stitch bench <crate> [--iterations <n>]";
+
+/// How many times the stitches are applied by default.
+const DEFAULT_ITERATIONS: usize = 10;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    iterations: usize,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut krate = None;
+        let mut iterations = DEFAULT_ITERATIONS;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--iterations" => {
+                    iterations = args
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| {
+                            usage_error("`--iterations` requires a number greater than 0")
+                        })?;
+                }
+                other if other.starts_with('-') || krate.is_some() => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `bench`: {other}"
+                    )));
+                }
+                other => krate = Some(other.to_string()),
+            }
+        }
+
+        let krate = krate.ok_or_else(|| usage_error(USAGE))?;
+        Ok(Self { krate, iterations })
+    }
+}
+
+/// Returns `true` if `args` (following `bench`) name a crate with stitches, rather than
+/// options or a benchmark name for `cargo bench`.
+pub(super) fn names_stitched_crate(workspace: &Workspace, args: &[String]) -> bool {
+    Args::parse(args).is_ok_and(|args| workspace.stitches_dir.join(&args.krate).is_dir())
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let stitch_set = workspace.stitch_set(&args.krate).map_err(OneOf::broaden)?;
+    check_required_tools([&stitch_set], &workspace.output).map_err(OneOf::broaden)?;
+
+    let manifest_dir = workspace.member_dir(&args.krate).map_err(OneOf::new)?;
+
+    let timings = bench(&manifest_dir, &stitch_set, args.iterations).map_err(OneOf::broaden)?;
+    print!("{}", render(&timings, args.iterations));
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// How long one stitch, or the whole set, took to apply in each iteration.
+#[derive(Debug)]
+struct Timing {
+    name: String,
+    samples: Vec<Duration>,
+}
+
+impl Timing {
+    fn min(&self) -> Duration {
+        self.samples.iter().min().copied().unwrap_or_default()
+    }
+
+    fn max(&self) -> Duration {
+        self.samples.iter().max().copied().unwrap_or_default()
+    }
+
+    fn mean(&self) -> Duration {
+        let total: Duration = self.samples.iter().sum();
+        total / self.samples.len().max(1) as u32
+    }
+}
+
+/// Apply the stitches of `stitch_set` to `iterations` fresh copies of `manifest_dir`,
+/// timing each one, and return the timings of every stitch followed by those of the
+/// whole set.  Copying the sources is not timed.
+fn bench(
+    manifest_dir: &Utf8Path,
+    stitch_set: &StitchSet,
+    iterations: usize,
+) -> Result<Vec<Timing>, ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let mut timings: Vec<Timing> = stitch_set
+        .iter()
+        .map(|stitch| Timing {
+            name: stitch
+                .path()
+                .file_name()
+                .unwrap_or(stitch.path().as_str())
+                .to_string(),
+            samples: Vec::with_capacity(iterations),
+        })
+        .collect();
+    let mut total = Timing {
+        name: "total".to_string(),
+        samples: Vec::with_capacity(iterations),
+    };
+
+    for _ in 0..iterations {
+        let (_guard, scratch) = temp_dir().map_err(io)?;
+        copy_dir_recursive(manifest_dir, &scratch, &stitch_set.copy_options()).map_err(io)?;
+
+        let mut elapsed = Duration::ZERO;
+        for (stitch, timing) in stitch_set.iter().zip(&mut timings) {
+            let start = Instant::now();
+            stitch.apply_quietly(&scratch, stitch_set)?;
+            let sample = start.elapsed();
+            timing.samples.push(sample);
+            elapsed += sample;
+        }
+        total.samples.push(elapsed);
+    }
+
+    timings.push(total);
+    Ok(timings)
+}
+
+/// One line per stitch and one for the whole set, in the style of `cargo bench`:
+/// `bench <name> ... bench: min <ms> mean <ms> max <ms>`.
+fn render(timings: &[Timing], iterations: usize) -> String {
+    let ms = |d: Duration| format!("{:.3} ms", d.as_secs_f64() * 1000.0);
+    let width = timings.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    let stitches = timings.len().saturating_sub(1);
+
+    let mut out = String::new();
+    _ = writeln!(
+        out,
+        "running {stitches} stitches, {iterations} iterations\n"
+    );
+    for timing in timings {
+        _ = writeln!(
+            out,
+            "bench {:<width$} ... bench: min {:>10}  mean {:>10}  max {:>10}",
+            timing.name,
+            ms(timing.min()),
+            ms(timing.mean()),
+            ms(timing.max()),
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_crate_and_iterations() {
+        assert_eq!(
+            Args::parse(&args(&["crate-a"])).unwrap(),
+            Args {
+                krate: "crate-a".to_string(),
+                iterations: DEFAULT_ITERATIONS,
+            }
+        );
+        assert_eq!(
+            Args::parse(&args(&["--iterations", "3", "crate-a"])).unwrap(),
+            Args {
+                krate: "crate-a".to_string(),
+                iterations: 3,
+            }
+        );
+        assert!(Args::parse(&[]).is_err());
+        assert!(Args::parse(&args(&["crate-a", "--iterations", "0"])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "--iterations"])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "crate-b"])).is_err());
+    }
+
+    #[test]
+    fn render_shows_min_mean_and_max() {
+        let timing = |name: &str, samples: &[u64]| Timing {
+            name: name.to_string(),
+            samples: samples
+                .iter()
+                .map(|&us| Duration::from_micros(us))
+                .collect(),
+        };
+        let timings = [
+            timing("001-greeting.patch", &[1000, 2000, 3000]),
+            timing("total", &[1500, 2500, 5000]),
+        ];
+
+        assert_eq!(
+            render(&timings, 3),
+            "running 1 stitches, 3 iterations\n\
+             \n\
+             bench 001-greeting.patch ... bench: min   1.000 ms  mean   2.000 ms  max   3.000 ms\n\
+             bench total              ... bench: min   1.500 ms  mean   3.000 ms  max   5.000 ms\n"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! `cargo stitch capture <crate> [--name <name>] [--output <file>]`: turn edits made by hand
+//! to the patched copy in `target/cargo-stitch/<crate>/` into a new `.patch` stitch.
+
+use std::fs;
+use std::process::ExitCode;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use terrors::OneOf;
+
+use super::lint::split_number;
+use super::registry::require_tool;
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::config::StitchConfig;
+use crate::error::IoError;
+use crate::fs::{CopyOptions, copy_dir_recursive, diff_dirs, temp_dir};
+use crate::hash::{STAMP_FILE, STATE_FILE, StitchStamp};
+use crate::stitch::{ORDER_FILE, Stitch, cargo_status};
+use crate::wrapper::patched_dir;
+
+const USAGE: &str = "usage: cargo stitch capture <crate> [--name <name>] [--output <file>]";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    name: String,
+    output: Option<Utf8PathBuf>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut krate = None;
+        let mut name = None;
+        let mut output = None;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--name" => name = Some(args.next().cloned().ok_or_else(|| usage_error(USAGE))?),
+                "--output" | "-o" => {
+                    output = Some(
+                        args.next()
+                            .map(Utf8PathBuf::from)
+                            .ok_or_else(|| usage_error(USAGE))?,
+                    );
+                }
+                other if other.starts_with('-') || krate.is_some() => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `capture`: {other}"
+                    )));
+                }
+                other => krate = Some(other.to_string()),
+            }
+        }
+
+        Ok(Self {
+            krate: krate.ok_or_else(|| usage_error(USAGE))?,
+            name: name.unwrap_or_else(|| "captured".to_string()),
+            output,
+        })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let manifest_dir = workspace.member_dir(&args.krate).map_err(OneOf::new)?;
+    let patched_dir = patched_dir(&args.krate, &workspace.patched_root);
+    if !patched_dir.is_dir() {
+        return Err(OneOf::new(usage_error(format!(
+            "{patched_dir} does not exist; run `cargo stitch build` first, then edit the \
+             patched sources there"
+        ))));
+    }
+
+    let stitch_set = workspace.stitch_set(&args.krate).map_err(OneOf::broaden)?;
+    check_required_tools([&stitch_set], &workspace.output).map_err(OneOf::broaden)?;
+    require_tool("diff")?;
+
+    // Only the edits made by hand are wanted, not what the existing stitches already do.
+    // The patched copy is only made from the current stitches if they have not changed
+    // since it was, and rebuilding it would throw the edits away: say so, and go on.
+    let inputs: Vec<&Utf8Path> = stitch_set.input_paths().collect();
+    let stamp = StitchStamp::compute(&manifest_dir, &inputs).map_err(io)?;
+    if StitchStamp::load(&patched_dir.join(STAMP_FILE)) != Some(stamp) {
+        cargo_status(
+            &workspace.output,
+            "Warning",
+            &format!(
+                "the sources or stitches of `{}` changed since {patched_dir} was patched; \
+                 the captured patch may undo those changes",
+                args.krate
+            ),
+        );
+    }
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    let (stitched, edited) = (scratch.join("a"), scratch.join("b"));
+    copy_dir_recursive(&manifest_dir, &stitched, &CopyOptions::default()).map_err(io)?;
+    stitch_set
+        .apply_quietly(&stitched)
+        .map_err(OneOf::broaden)?;
+    copy_dir_recursive(&patched_dir, &edited, &CopyOptions::default()).map_err(io)?;
+    for bookkeeping in [STAMP_FILE, STATE_FILE] {
+        let _ = fs::remove_file(edited.join(bookkeeping));
+    }
+
+    let diff = diff_dirs(&stitched, &edited).map_err(io)?;
+    if diff.is_empty() {
+        cargo_status(
+            &workspace.output,
+            "Fresh",
+            &format!("no changes to capture in {patched_dir}"),
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let dir = workspace.stitches_dir.join(&args.krate);
+    let path = match args.output {
+        Some(output) => output,
+        None => dir.join(format!(
+            "{:03}-{}.patch",
+            next_number(&dir).map_err(io)?,
+            args.name
+        )),
+    };
+    if path.exists() {
+        return Err(OneOf::new(usage_error(format!("{path} already exists"))));
+    }
+    if let Some(parent) = path.parent().filter(|p| !p.as_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(io)?;
+    }
+    fs::write(&path, diff).map_err(io)?;
+
+    let display = path.strip_prefix(&workspace.root).unwrap_or(&path);
+    cargo_status(&workspace.output, "Created", display.as_str());
+
+    if StitchConfig::load(&dir, &workspace.output)
+        .map_err(OneOf::broaden)?
+        .is_some_and(|(_, config)| !config.order.stitches.is_empty())
+    {
+        cargo_status(
+            &workspace.output,
+            "Warning",
+            &format!("{dir}/stitch.toml sets an explicit [order]; add {display} to it"),
+        );
+    } else if dir.join(ORDER_FILE).is_file() {
+        cargo_status(
+            &workspace.output,
+            "Warning",
+            &format!("{dir}/{ORDER_FILE} sets an explicit order; add {display} to it"),
+        );
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The number following that of the last numbered stitch in `dir`, or 1 if there is none.
+pub(super) fn next_number(dir: &Utf8Path) -> std::io::Result<u32> {
+    if !dir.is_dir() {
+        return Ok(1);
+    }
+
+    let mut last = 0;
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        if Stitch::from_path(entry.path().to_owned()).is_some()
+            && let Some((number, _)) = split_number(entry.file_name())
+        {
+            last = last.max(number);
+        }
+    }
+    Ok(last + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_crate_name_and_output() {
+        assert_eq!(
+            Args::parse(&args(&["crate-a"])).unwrap(),
+            Args {
+                krate: "crate-a".to_string(),
+                name: "captured".to_string(),
+                output: None,
+            }
+        );
+        assert_eq!(
+            Args::parse(&args(&["--name", "fix-typo", "crate-a", "-o", "fix.patch"])).unwrap(),
+            Args {
+                krate: "crate-a".to_string(),
+                name: "fix-typo".to_string(),
+                output: Some(Utf8PathBuf::from("fix.patch")),
+            }
+        );
+        assert!(Args::parse(&args(&[])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "--name"])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "crate-b"])).is_err());
+    }
+
+    #[test]
+    fn next_number_follows_the_last_stitch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        assert_eq!(next_number(&dir.join("missing")).unwrap(), 1);
+
+        fs::write(dir.join("001-a.patch"), "").unwrap();
+        fs::write(dir.join("007-b.yaml"), "").unwrap();
+        fs::write(dir.join("010-notes.md"), "").unwrap();
+        fs::write(dir.join("012-example.patch.sample"), "").unwrap();
+        assert_eq!(next_number(dir).unwrap(), 8);
+    }
+}
@@ -0,0 +1,604 @@
+//! `cargo stitch check --report-format <format>`: verify that every stitch applies and
+//! write the result as a test report.
+//!
+//! Each stitch is a test case that passes if it applies cleanly on top of the stitches
+//! before it.  A stitch that applies but changes nothing (e.g. an ast-grep rule with no
+//! matches) passes with a warning; `--strict` makes warnings fail the run as well.
+//! With `--since <git-ref>`, only the crates whose stitches changed since
+//! that ref (according to `git diff --name-only`) are checked.  Without any `--report-*`
+//! or `--since` flag, `cargo stitch check` is forwarded to `cargo check` like any other
+//! cargo command.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::process::{Command, ExitCode};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use terrors::OneOf;
+
+use super::lint::conflict_message;
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::{IoError, MissingTool};
+use crate::fs::{CopyOptions, copy_dir_recursive, reject_files, temp_dir};
+use crate::hash::hash_dir;
+use crate::stitch::{Stitch, StitchSet};
+use crate::{check_required_tools, crate_source_dirs};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReportFormat {
+    Human,
+    Tap,
+    Junit,
+}
+
+impl ReportFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "human" => Some(Self::Human),
+            "tap" => Some(Self::Tap),
+            "junit" => Some(Self::Junit),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    format: ReportFormat,
+    output: Option<Utf8PathBuf>,
+    since: Option<String>,
+    strict: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut format = ReportFormat::Human;
+        let mut output = None;
+        let mut since = None;
+        let mut strict = false;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--report-format" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| usage_error("`--report-format` requires a value"))?;
+                    format = ReportFormat::parse(value).ok_or_else(|| {
+                        usage_error(format!(
+                            "unknown report format `{value}` (expected `human`, `tap` or `junit`)"
+                        ))
+                    })?;
+                }
+                "--report-output" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| usage_error("`--report-output` requires a path"))?;
+                    output = Some(Utf8PathBuf::from(value));
+                }
+                "--since" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| usage_error("`--since` requires a git ref"))?;
+                    since = Some(value.clone());
+                }
+                "--strict" => strict = true,
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `check`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self {
+            format,
+            output,
+            since,
+            strict,
+        })
+    }
+}
+
+/// Returns `true` if `args` (following `check`) ask for a stitch report rather than a
+/// plain `cargo check`.
+pub(super) fn wants_report(args: &[String]) -> bool {
+    args.iter().any(|a| {
+        a == "--report-format" || a == "--report-output" || a == "--since" || a == "--strict"
+    })
+}
+
+/// The outcome of applying one stitch.
+pub(super) struct TestCase {
+    pub(super) krate: String,
+    /// Path of the stitch file, relative to the workspace root.
+    pub(super) name: Utf8PathBuf,
+    pub(super) failure: Option<String>,
+    /// Set if the stitch applies but looks wrong, e.g. because it changes nothing.
+    pub(super) warning: Option<String>,
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+
+    let mut pkg_names: Vec<_> = manifest.keys().collect();
+    pkg_names.sort();
+
+    if let Some(since) = &args.since {
+        let changed = changed_crates(&workspace.stitches_dir, since)?;
+        pkg_names.retain(|name| changed.contains(name.as_str()));
+    }
+
+    let cases = check_packages(workspace, &manifest, &pkg_names)?;
+
+    let report = match args.format {
+        ReportFormat::Human => render_human(&cases),
+        ReportFormat::Tap => render_tap(&cases),
+        ReportFormat::Junit => render_junit(&cases),
+    };
+
+    match &args.output {
+        Some(path) => fs::write(path, report).map_err(|e| OneOf::new(IoError(e)))?,
+        None => print!("{report}"),
+    }
+
+    let failed = cases.iter().any(|case| case.failure.is_some());
+    let warned = cases.iter().any(|case| case.warning.is_some());
+    // Like `-D warnings`: with `--strict`, warnings fail the run too.
+    if failed || (args.strict && warned) {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Check the stitches of `pkg_names`, in that order, with one test case per stitch.
+pub(super) fn check_packages(
+    workspace: &Workspace,
+    manifest: &HashMap<String, StitchSet>,
+    pkg_names: &[&String],
+) -> Result<Vec<TestCase>, SubcommandError> {
+    check_required_tools(
+        pkg_names.iter().map(|name| &manifest[*name]),
+        &workspace.output,
+    )
+    .map_err(OneOf::broaden)?;
+
+    let sources = crate_source_dirs(&workspace.root, pkg_names.iter().copied())
+        .map_err(|e| OneOf::new(IoError(e)))?;
+
+    let mut cases = Vec::new();
+    for pkg_name in pkg_names {
+        let results = check_crate(
+            pkg_name,
+            sources.get(pkg_name.as_str()).map(Utf8PathBuf::as_path),
+            &manifest[*pkg_name],
+            &workspace.root,
+        )
+        .map_err(OneOf::broaden)?;
+        cases.extend(results);
+    }
+
+    Ok(cases)
+}
+
+/// The crates in `stitches_dir` with a stitch file that differs from `since`, according
+/// to `git diff --name-only`.
+fn changed_crates(
+    stitches_dir: &Utf8Path,
+    since: &str,
+) -> Result<BTreeSet<String>, SubcommandError> {
+    let output = Command::new("git")
+        .args(["diff", "--name-only", "--relative", since, "--"])
+        .current_dir(stitches_dir)
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => OneOf::new(MissingTool("git")),
+            _ => OneOf::new(IoError(e)),
+        })?;
+
+    if !output.status.success() {
+        return Err(OneOf::new(usage_error(format!(
+            "git diff against `{since}` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+
+    Ok(crates_in_diff(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The crate names in the output of `git diff --name-only --relative`, run from the
+/// stitch set directory: the first component of every path below a crate directory.
+fn crates_in_diff(names: &str) -> BTreeSet<String> {
+    names
+        .lines()
+        .filter_map(|line| line.split_once('/'))
+        .map(|(krate, _)| krate.to_string())
+        .collect()
+}
+
+/// Apply the stitches of one crate in order to a scratch copy of its source, recording
+/// whether each one applies.  A failing stitch is skipped and the next ones are tried
+/// on top of the stitches that did apply.
+fn check_crate(
+    pkg_name: &str,
+    manifest_dir: Option<&Utf8Path>,
+    stitch_set: &StitchSet,
+    workspace_root: &Utf8Path,
+) -> Result<Vec<TestCase>, OneOf<(IoError,)>> {
+    let name_of = |path: &Utf8Path| path.strip_prefix(workspace_root).unwrap_or(path).to_owned();
+
+    let Some(manifest_dir) = manifest_dir else {
+        let failure = format!(
+            "crate `{pkg_name}` is neither a member nor a crates.io dependency of the workspace"
+        );
+        return Ok(stitch_set
+            .file_paths()
+            .map(|path| TestCase {
+                krate: pkg_name.to_string(),
+                name: name_of(path),
+                failure: Some(failure.clone()),
+                warning: None,
+            })
+            .collect());
+    };
+
+    let io = |e| OneOf::new(IoError(e));
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    copy_dir_recursive(manifest_dir, &scratch, &CopyOptions::default()).map_err(io)?;
+
+    check_stitches_in(pkg_name, &scratch, stitch_set, workspace_root)
+}
+
+/// Apply the stitches of `pkg_name` in order to `scratch`, a copy of its source, as
+/// [`check_crate`] does.  `scratch` is left with the stitches that applied.
+pub(super) fn check_stitches_in(
+    pkg_name: &str,
+    scratch: &Utf8Path,
+    stitch_set: &StitchSet,
+    workspace_root: &Utf8Path,
+) -> Result<Vec<TestCase>, OneOf<(IoError,)>> {
+    let name_of = |path: &Utf8Path| path.strip_prefix(workspace_root).unwrap_or(path).to_owned();
+    let io = |e| OneOf::new(IoError(e));
+
+    let mut cases = Vec::new();
+    let mut digest = hash_dir(scratch).map_err(io)?;
+    for stitch in stitch_set.iter() {
+        let mut warning = None;
+        let failure = match stitch.apply_quietly(scratch, stitch_set) {
+            Ok(()) => {
+                let applied = hash_dir(scratch).map_err(io)?;
+                if applied == digest {
+                    warning = Some(no_change_warning(stitch));
+                }
+                digest = applied;
+                None
+            }
+            Err(e) => match e.narrow::<IoError, _>() {
+                Ok(e) => return Err(OneOf::new(e)),
+                Err(e) => Some(e.to_string() + &take_rejects(scratch).map_err(io)?),
+            },
+        };
+
+        cases.push(TestCase {
+            krate: pkg_name.to_string(),
+            name: name_of(stitch.path()),
+            failure,
+            warning,
+        });
+    }
+
+    for conflict in stitch_set.conflict_check() {
+        let name = name_of(conflict.stitch_b.path());
+        if let Some(case) = cases.iter_mut().find(|case| case.name == name) {
+            let message = conflict_message(&conflict);
+            case.warning = Some(match case.warning.take() {
+                Some(warning) => format!("{warning}; {message}"),
+                None => message,
+            });
+        }
+    }
+
+    Ok(cases)
+}
+
+/// The hunks `patch` rejected, as left in `.rej` files under `dir`, which are removed so
+/// that they are not mistaken for those of the next stitch.
+fn take_rejects(dir: &Utf8Path) -> std::io::Result<String> {
+    let mut rejects = String::new();
+    for file in reject_files(dir)? {
+        let path = dir.join(&file);
+        _ = write!(rejects, "\n{file}:\n{}", fs::read_to_string(&path)?);
+        fs::remove_file(path)?;
+    }
+    Ok(rejects.trim_end().to_string())
+}
+
+fn no_change_warning(stitch: &Stitch) -> String {
+    match stitch {
+        Stitch::Patch(_) => "stitch produces no changes".to_string(),
+        Stitch::SgRule(_) => "no matches found for rule".to_string(),
+        Stitch::SedRule(_) => "script makes no substitutions".to_string(),
+        Stitch::Script(_) => "script changes nothing".to_string(),
+    }
+}
+
+fn render_human(cases: &[TestCase]) -> String {
+    let mut out = String::new();
+    for case in cases {
+        match &case.failure {
+            None => _ = writeln!(out, "ok      {}", case.name),
+            Some(failure) => {
+                _ = writeln!(out, "FAILED  {}", case.name);
+                for line in failure.lines() {
+                    _ = writeln!(out, "        {line}");
+                }
+            }
+        }
+        if let Some(warning) = &case.warning {
+            _ = writeln!(out, "        warning: {warning}");
+        }
+    }
+
+    let failed = cases.iter().filter(|c| c.failure.is_some()).count();
+    let warned = cases.iter().filter(|c| c.warning.is_some()).count();
+    _ = write!(
+        out,
+        "\n{} stitches checked: {} passed, {failed} failed",
+        cases.len(),
+        cases.len() - failed
+    );
+    if warned > 0 {
+        _ = write!(out, ", {warned} with warnings");
+    }
+    out.push('\n');
+    out
+}
+
+/// Render `cases` in the Test Anything Protocol, version 13.
+fn render_tap(cases: &[TestCase]) -> String {
+    let mut out = format!("TAP version 13\n1..{}\n", cases.len());
+    for (i, case) in cases.iter().enumerate() {
+        match &case.failure {
+            None => _ = writeln!(out, "ok {} - {}", i + 1, case.name),
+            Some(failure) => {
+                _ = writeln!(out, "not ok {} - {}", i + 1, case.name);
+                out.push_str("  ---\n  message: |\n");
+                for line in failure.lines() {
+                    _ = writeln!(out, "    {line}");
+                }
+                out.push_str("  ...\n");
+            }
+        }
+        if let Some(warning) = &case.warning {
+            _ = writeln!(out, "# warning: {warning}");
+        }
+    }
+    out
+}
+
+/// Render `cases` as JUnit XML, with one `<testsuite>` per crate.
+fn render_junit(cases: &[TestCase]) -> String {
+    let failed = cases.iter().filter(|c| c.failure.is_some()).count();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    _ = writeln!(
+        out,
+        "<testsuites name=\"cargo-stitch\" tests=\"{}\" failures=\"{failed}\">",
+        cases.len()
+    );
+
+    for suite in cases.chunk_by(|a, b| a.krate == b.krate) {
+        let krate = xml_escape(&suite[0].krate);
+        let suite_failed = suite.iter().filter(|c| c.failure.is_some()).count();
+        _ = writeln!(
+            out,
+            "  <testsuite name=\"{krate}\" tests=\"{}\" failures=\"{suite_failed}\">",
+            suite.len()
+        );
+
+        for case in suite {
+            let name = xml_escape(case.name.as_str());
+            if case.failure.is_none() && case.warning.is_none() {
+                _ = writeln!(out, "    <testcase name=\"{name}\" classname=\"{krate}\"/>");
+                continue;
+            }
+
+            _ = writeln!(out, "    <testcase name=\"{name}\" classname=\"{krate}\">");
+            if let Some(failure) = &case.failure {
+                let message = xml_escape(failure.lines().next().unwrap_or_default());
+                _ = writeln!(
+                    out,
+                    "      <failure message=\"{message}\">{}</failure>",
+                    xml_escape(failure)
+                );
+            }
+            if let Some(warning) = &case.warning {
+                _ = writeln!(
+                    out,
+                    "      <system-out>warning: {}</system-out>",
+                    xml_escape(warning)
+                );
+            }
+            out.push_str("    </testcase>\n");
+        }
+
+        out.push_str("  </testsuite>\n");
+    }
+
+    out.push_str("</testsuites>\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cases() -> Vec<TestCase> {
+        vec![
+            TestCase {
+                krate: "config".to_string(),
+                name: Utf8PathBuf::from("stitches/default/config/001-a.patch"),
+                failure: None,
+                warning: None,
+            },
+            TestCase {
+                krate: "config".to_string(),
+                name: Utf8PathBuf::from("stitches/default/config/002-b.patch"),
+                failure: Some("failed to apply patch: 002-b.patch\nHunk #1 FAILED".to_string()),
+                warning: None,
+            },
+        ]
+    }
+
+    fn warned_case() -> TestCase {
+        TestCase {
+            krate: "config".to_string(),
+            name: Utf8PathBuf::from("stitches/default/config/003-c.yaml"),
+            failure: None,
+            warning: Some("no matches found for rule".to_string()),
+        }
+    }
+
+    #[test]
+    fn parse_defaults_to_human() {
+        let args = Args::parse(&[]).unwrap();
+        assert_eq!(args.format, ReportFormat::Human);
+        assert_eq!(args.output, None);
+    }
+
+    #[test]
+    fn parse_format_and_output() {
+        let args = Args::parse(&[
+            "--report-format".to_string(),
+            "junit".to_string(),
+            "--report-output".to_string(),
+            "report.xml".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(args.format, ReportFormat::Junit);
+        assert_eq!(args.output, Some(Utf8PathBuf::from("report.xml")));
+    }
+
+    #[test]
+    fn parse_since() {
+        let args = Args::parse(&["--since".to_string(), "HEAD~1".to_string()]).unwrap();
+        assert_eq!(args.since.as_deref(), Some("HEAD~1"));
+        assert!(Args::parse(&["--since".to_string()]).is_err());
+    }
+
+    #[test]
+    fn crates_in_diff_ignores_top_level_files() {
+        let crates =
+            crates_in_diff("config/001-a.patch\nconfig/002-b.yaml\nserde/stitch.toml\nREADME.md\n");
+        assert_eq!(
+            crates.into_iter().collect::<Vec<_>>(),
+            vec!["config".to_string(), "serde".to_string()]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unknown_format() {
+        let err = Args::parse(&["--report-format".to_string(), "xml".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("unknown report format `xml`"));
+    }
+
+    #[test]
+    fn wants_report_only_with_report_flags() {
+        assert!(!wants_report(&["--release".to_string()]));
+        assert!(wants_report(&[
+            "--report-format".to_string(),
+            "tap".to_string()
+        ]));
+        assert!(wants_report(&[
+            "--report-output".to_string(),
+            "out.txt".to_string()
+        ]));
+        assert!(wants_report(&["--since".to_string(), "main".to_string()]));
+    }
+
+    #[test]
+    fn render_tap_marks_failures() {
+        assert_eq!(
+            render_tap(&cases()),
+            "TAP version 13\n\
+             1..2\n\
+             ok 1 - stitches/default/config/001-a.patch\n\
+             not ok 2 - stitches/default/config/002-b.patch\n  \
+             ---\n  \
+             message: |\n    \
+             failed to apply patch: 002-b.patch\n    \
+             Hunk #1 FAILED\n  \
+             ...\n"
+        );
+    }
+
+    #[test]
+    fn render_junit_reports_failure_message() {
+        let xml = render_junit(&cases());
+        assert!(xml.contains("<testsuites name=\"cargo-stitch\" tests=\"2\" failures=\"1\">"));
+        assert!(xml.contains("<testsuite name=\"config\" tests=\"2\" failures=\"1\">"));
+        assert!(xml.contains(
+            "<testcase name=\"stitches/default/config/001-a.patch\" classname=\"config\"/>"
+        ));
+        assert!(xml.contains(
+            "<failure message=\"failed to apply patch: 002-b.patch\">\
+             failed to apply patch: 002-b.patch\nHunk #1 FAILED</failure>"
+        ));
+    }
+
+    #[test]
+    fn render_human_summarizes() {
+        let out = render_human(&cases());
+        assert!(out.contains("ok      stitches/default/config/001-a.patch"));
+        assert!(out.contains("FAILED  stitches/default/config/002-b.patch"));
+        assert!(out.ends_with("2 stitches checked: 1 passed, 1 failed\n"));
+    }
+
+    #[test]
+    fn parse_strict() {
+        assert!(!Args::parse(&[]).unwrap().strict);
+        assert!(Args::parse(&["--strict".to_string()]).unwrap().strict);
+        assert!(wants_report(&["--strict".to_string()]));
+    }
+
+    #[test]
+    fn renderers_include_warnings() {
+        let cases = [warned_case()];
+
+        let human = render_human(&cases);
+        assert!(human.contains("ok      stitches/default/config/003-c.yaml\n        warning: no matches found for rule\n"));
+        assert!(human.ends_with("1 stitches checked: 1 passed, 0 failed, 1 with warnings\n"));
+
+        assert!(render_tap(&cases).ends_with(
+            "ok 1 - stitches/default/config/003-c.yaml\n# warning: no matches found for rule\n"
+        ));
+
+        let xml = render_junit(&cases);
+        assert!(xml.contains("failures=\"0\""));
+        assert!(xml.contains("<system-out>warning: no matches found for rule</system-out>"));
+    }
+
+    #[test]
+    fn xml_escape_special_characters() {
+        assert_eq!(xml_escape("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+    }
+}
@@ -0,0 +1,166 @@
+//! `cargo stitch check-ordering <crate>`: find adjacent stitches whose order matters.
+//!
+//! The stitches are applied in their configured order, then once more for every pair of
+//! adjacent stitches with that pair swapped.  If a swapped order fails to apply or gives
+//! a different result, the two stitches depend on each other's order.
+
+use std::process::ExitCode;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::error::IoError;
+use crate::fs::{CopyOptions, compare_dirs, copy_dir_recursive, temp_dir};
+use crate::stitch::{ApplyError, Stitch, StitchSet, cargo_status};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    exit_code: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut krate = None;
+        let mut exit_code = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--exit-code" => exit_code = true,
+                other if other.starts_with('-') || krate.is_some() => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `check-ordering`: {other}"
+                    )));
+                }
+                other => krate = Some(other.to_string()),
+            }
+        }
+
+        let krate = krate.ok_or_else(|| {
+            usage_error("usage: cargo stitch check-ordering <crate> [--exit-code]")
+        })?;
+        Ok(Self { krate, exit_code })
+    }
+}
+
+/// How swapping two adjacent stitches affects the result.
+#[derive(Debug, PartialEq, Eq)]
+enum Swap {
+    /// Same output either way.
+    Independent,
+    /// The swapped order does not apply.
+    Fails,
+    /// The swapped order applies but changes these files.
+    Differs(Vec<Utf8PathBuf>),
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let stitch_set = workspace.stitch_set(&args.krate).map_err(OneOf::broaden)?;
+    let manifest_dir = workspace.member_dir(&args.krate).map_err(OneOf::new)?;
+
+    check_required_tools([&stitch_set], &workspace.output).map_err(OneOf::broaden)?;
+
+    let stitches: Vec<&Stitch> = stitch_set.iter().collect();
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    let baseline = scratch.join("baseline");
+    apply_in_order(&manifest_dir, &baseline, &stitches, &stitch_set).map_err(OneOf::broaden)?;
+
+    let mut dependent = 0;
+    for i in 1..stitches.len() {
+        let mut swapped = stitches.clone();
+        swapped.swap(i - 1, i);
+
+        let dir = scratch.join(format!("swap-{i}"));
+        let swap = match apply_in_order(&manifest_dir, &dir, &swapped, &stitch_set) {
+            Ok(()) => match compare_dirs(&baseline, &dir, &[]).map_err(io)? {
+                differing if differing.is_empty() => Swap::Independent,
+                differing => Swap::Differs(differing),
+            },
+            Err(e) => match e.narrow::<IoError, _>() {
+                Ok(e) => return Err(OneOf::new(e)),
+                Err(_) => Swap::Fails,
+            },
+        };
+
+        let pair = format!(
+            "{} <-> {}",
+            file_name(stitches[i - 1].path()),
+            file_name(stitches[i].path())
+        );
+        match swap {
+            Swap::Independent => cargo_status(&workspace.output, "Independent", &pair),
+            Swap::Fails => {
+                dependent += 1;
+                cargo_status(
+                    &workspace.output,
+                    "Dependent",
+                    &format!("{pair}: swapped order fails to apply"),
+                );
+            }
+            Swap::Differs(files) => {
+                dependent += 1;
+                let files: Vec<_> = files.iter().map(|f| f.as_str()).collect();
+                cargo_status(
+                    &workspace.output,
+                    "Dependent",
+                    &format!("{pair}: swapped order changes {}", files.join(", ")),
+                );
+            }
+        }
+    }
+
+    if args.exit_code && dependent > 0 {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn file_name(path: &Utf8Path) -> &str {
+    path.file_name().unwrap_or(path.as_str())
+}
+
+/// Copy `manifest_dir` to `dir` and apply `stitches`, from `stitch_set`, to it in the
+/// given order.
+fn apply_in_order(
+    manifest_dir: &Utf8Path,
+    dir: &Utf8Path,
+    stitches: &[&Stitch],
+    stitch_set: &StitchSet,
+) -> Result<(), ApplyError> {
+    copy_dir_recursive(manifest_dir, dir, &CopyOptions::default())
+        .map_err(|e| OneOf::new(IoError(e)))?;
+    for stitch in stitches {
+        stitch.apply_quietly(dir, stitch_set)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_crate_and_exit_code() {
+        let args = Args::parse(&["config".to_string(), "--exit-code".to_string()]).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                krate: "config".to_string(),
+                exit_code: true
+            }
+        );
+    }
+
+    #[test]
+    fn parse_requires_crate() {
+        let err = Args::parse(&["--exit-code".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("usage"));
+    }
+}
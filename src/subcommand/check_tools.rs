@@ -0,0 +1,212 @@
+//! `cargo stitch check-tools [--versions]`: show which of the external tools cargo-stitch
+//! runs are installed, where, and whether they are recent enough.
+
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+use crate::min_tool_version;
+use crate::tools::{
+    TOOLS, Tool, find_in_path, meets_minimum, parse_min_version, parse_version, version_string,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    versions: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut versions = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--versions" => versions = true,
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `check-tools`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { versions })
+    }
+}
+
+/// What was found for one tool.
+#[derive(Debug, PartialEq, Eq)]
+enum Found {
+    Missing,
+    Installed {
+        path: String,
+        version: Option<String>,
+        /// `None` if the version could not be determined.
+        recent_enough: Option<bool>,
+    },
+}
+
+impl Found {
+    /// Look for `tool`, which is recent enough from `min_version` on.
+    fn probe(tool: &Tool, min_version: &str) -> Self {
+        let Some(path) = find_in_path(tool.name) else {
+            return Found::Missing;
+        };
+        let version = version_string(path.as_str());
+        let recent_enough = version
+            .as_deref()
+            .and_then(parse_version)
+            .zip(parse_min_version(min_version))
+            .map(|(version, min)| meets_minimum(&version, &min));
+
+        Found::Installed {
+            path: path.into_string(),
+            version,
+            recent_enough,
+        }
+    }
+
+    /// Whether this rules the tool out for the current stitches.
+    fn is_problem(&self) -> bool {
+        matches!(
+            self,
+            Found::Missing
+                | Found::Installed {
+                    recent_enough: Some(false),
+                    ..
+                }
+        )
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+    let sets: Vec<_> = manifest.values().collect();
+    let tools = TOOLS.iter().map(|tool| {
+        let needed = sets.iter().any(|set| set.needs(tool));
+        (tool, needed, min_tool_version(tool, &sets))
+    });
+
+    let width = TOOLS.iter().map(|t| t.name.len()).max().unwrap_or_default();
+
+    println!("cargo-stitch {}", env!("CARGO_PKG_VERSION"));
+    println!();
+    println!("tools");
+    println!("-----");
+
+    let mut ok = true;
+    for (tool, needed, min_version) in tools {
+        let found = Found::probe(tool, min_version);
+        if needed && !tool.optional && found.is_problem() {
+            ok = false;
+        }
+        println!(
+            "{:width$}  {}",
+            tool.name,
+            describe(tool, min_version, &found, needed, args.versions)
+        );
+    }
+
+    Ok(if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// One line describing `found`, after the tool name.
+fn describe(tool: &Tool, min_version: &str, found: &Found, needed: bool, versions: bool) -> String {
+    let requirement = match (needed, tool.optional) {
+        (true, false) => format!("needed for {}", tool.needed_for),
+        (true, true) => format!("optional, used for {}", tool.needed_for),
+        (false, _) => "not needed by the current stitches".to_string(),
+    };
+
+    match found {
+        Found::Missing => format!("not found ({requirement})"),
+        Found::Installed {
+            path,
+            version,
+            recent_enough,
+        } => {
+            let verdict = match recent_enough {
+                Some(true) => "ok",
+                Some(false) => "too old",
+                None => "unknown version",
+            };
+            if versions {
+                format!(
+                    "{} ({path}), minimum {min_version}: {verdict}",
+                    version.as_deref().unwrap_or("no version information"),
+                )
+            } else {
+                format!("{verdict} ({path})")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{AST_GREP, PATCH};
+
+    #[test]
+    fn parse_versions_flag() {
+        assert!(!Args::parse(&[]).unwrap().versions);
+        assert!(Args::parse(&["--versions".to_string()]).unwrap().versions);
+        assert!(Args::parse(&["--all".to_string()]).is_err());
+    }
+
+    #[test]
+    fn describe_installed_tool() {
+        let found = Found::Installed {
+            path: "/usr/bin/patch".to_string(),
+            version: Some("GNU patch 2.7.6".to_string()),
+            recent_enough: Some(true),
+        };
+        assert_eq!(
+            describe(&PATCH, "2.0", &found, true, true),
+            "GNU patch 2.7.6 (/usr/bin/patch), minimum 2.0: ok"
+        );
+        assert_eq!(
+            describe(&PATCH, "2.0", &found, true, false),
+            "ok (/usr/bin/patch)"
+        );
+    }
+
+    #[test]
+    fn describe_missing_tool() {
+        assert_eq!(
+            describe(&AST_GREP, "0.20.0", &Found::Missing, false, true),
+            "not found (not needed by the current stitches)"
+        );
+        assert_eq!(
+            describe(&AST_GREP, "0.20.0", &Found::Missing, true, true),
+            "not found (needed for ast-grep rules)"
+        );
+    }
+
+    #[test]
+    fn describe_missing_optional_tool() {
+        assert_eq!(
+            describe(&PATCH, "2.0", &Found::Missing, true, false),
+            "not found (optional, used for `.patch` stitches the built-in applier cannot apply)"
+        );
+    }
+
+    #[test]
+    fn too_old_is_a_problem() {
+        let found = Found::Installed {
+            path: "/usr/bin/ast-grep".to_string(),
+            version: Some("ast-grep 0.1.0".to_string()),
+            recent_enough: Some(false),
+        };
+        assert!(found.is_problem());
+        assert!(Found::Missing.is_problem());
+    }
+}
@@ -0,0 +1,311 @@
+//! `cargo stitch clean [--package <name>] [--restore] [--including-history]`: remove the
+//! patched copies of crate sources under `target/cargo-stitch/`.
+//!
+//! The history of stitch applications in `target/cargo-stitch/.history/`, which
+//! `cargo stitch log` shows, is kept unless `--including-history` is given.
+//!
+//! With `--restore`, also vendor the crates with stitches again, undoing stitches applied
+//! to their vendored sources in place, with `no_copy = true` or by `cargo stitch vendor`.
+//!
+//! This only takes over `clean` when it is given no arguments, or only `--package`,
+//! `--restore` and `--including-history`; with any other argument (`--release`, `--doc`, ...) it is forwarded to
+//! `cargo clean` as usual.
+
+use std::fs;
+use std::process::{Command, ExitCode, Stdio};
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::{CargoFailed, IoError};
+use crate::hash::HISTORY_DIR;
+use crate::stitch::cargo_status;
+use crate::vendor::{VENDOR_DIR, vendored_dirs};
+use crate::wrapper::patched_dir;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    package: Option<String>,
+    restore: bool,
+    including_history: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let usage = || {
+            usage_error(
+                "usage: cargo stitch clean [--package <name>] [--restore] [--including-history]",
+            )
+        };
+
+        let restore = args.iter().any(|arg| arg == "--restore");
+        let including_history = args.iter().any(|arg| arg == "--including-history");
+        let args: Vec<_> = args
+            .iter()
+            .filter(|arg| *arg != "--restore" && *arg != "--including-history")
+            .cloned()
+            .collect();
+        let package = match args.as_slice() {
+            [] => None,
+            [flag, name] if (flag == "--package" || flag == "-p") && !name.starts_with('-') => {
+                Some(name.clone())
+            }
+            [flag] => match flag.strip_prefix("--package=") {
+                Some(name) if !name.is_empty() => Some(name.to_string()),
+                _ => return Err(usage()),
+            },
+            _ => return Err(usage()),
+        };
+
+        Ok(Self {
+            package,
+            restore,
+            including_history,
+        })
+    }
+}
+
+/// Returns `true` if `args` (following `clean`) are for `cargo stitch clean` rather than
+/// `cargo clean`.
+pub(super) fn is_stitch_clean(args: &[String]) -> bool {
+    Args::parse(args).is_ok()
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let dir = match &args.package {
+        Some(package) => patched_dir(package, &workspace.patched_root),
+        None => workspace.patched_root.clone(),
+    };
+    let display = dir.strip_prefix(&workspace.root).unwrap_or(&dir).to_owned();
+    let history = workspace.patched_root.join(HISTORY_DIR);
+
+    let removed = if !dir.is_dir() {
+        0
+    } else {
+        let crates = match &args.package {
+            Some(_) => 1,
+            None => patched_crates(&dir).map_err(io)?.len(),
+        };
+        if args.package.is_none() && !args.including_history && history.is_dir() {
+            remove_all_but(&dir, HISTORY_DIR).map_err(io)?;
+        } else {
+            fs::remove_dir_all(&dir).map_err(io)?;
+        }
+        crates
+    };
+    if let Some(package) = &args.package
+        && args.including_history
+    {
+        remove_history(&history, package).map_err(io)?;
+    }
+
+    cargo_status(
+        &workspace.output,
+        "Removed",
+        &format!(
+            "{removed} patched {} from {display}",
+            if removed == 1 { "crate" } else { "crates" }
+        ),
+    );
+
+    if args.restore {
+        restore_vendored(workspace, args.package.as_deref())?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Vendor the crates with stitches, or only `package`, again, by removing their vendored
+/// copies and running `cargo vendor`, which leaves alone those that are still there.
+fn restore_vendored(workspace: &Workspace, package: Option<&str>) -> Result<(), SubcommandError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let vendor_dir = workspace.root.join(VENDOR_DIR);
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+    let mut dirs: Vec<_> = manifest
+        .keys()
+        .filter(|krate| package.is_none_or(|package| package == krate.as_str()))
+        .flat_map(|krate| vendored_dirs(&vendor_dir, krate))
+        .collect();
+    dirs.sort();
+
+    for dir in &dirs {
+        fs::remove_dir_all(dir).map_err(io)?;
+    }
+
+    // `cargo vendor` prints the configuration to use the vendored sources, which is
+    // already in place.
+    let status = Command::new("cargo")
+        .args(["vendor", VENDOR_DIR])
+        .current_dir(&workspace.root)
+        .stdout(Stdio::null())
+        .status()
+        .map_err(io)?;
+    if !status.success() {
+        return Err(OneOf::new(CargoFailed(status.code().unwrap_or(1))));
+    }
+
+    cargo_status(
+        &workspace.output,
+        "Restored",
+        &format!(
+            "{} vendored {} in {VENDOR_DIR}",
+            dirs.len(),
+            if dirs.len() == 1 { "crate" } else { "crates" }
+        ),
+    );
+    Ok(())
+}
+
+/// Remove everything in `dir` but its entry `keep`.
+fn remove_all_but(dir: &Utf8Path, keep: &str) -> std::io::Result<()> {
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        if entry.file_name() == keep {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the records of `package` from `history_dir`, named `<applied_at>-<package>.json`.
+fn remove_history(history_dir: &Utf8Path, package: &str) -> std::io::Result<()> {
+    let entries = match history_dir.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let is_record = entry
+            .file_name()
+            .split_once('-')
+            .is_some_and(|(applied_at, rest)| {
+                applied_at.bytes().all(|b| b.is_ascii_digit())
+                    && rest.strip_suffix(".json") == Some(package)
+            });
+        if is_record {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// The names of the patched crate directories in `dir`.  Hidden entries, such as the
+/// stitch manifests and scratch directories, are not crates.
+pub(super) fn patched_crates(dir: &Utf8Path) -> std::io::Result<Vec<String>> {
+    let mut crates = Vec::new();
+    for entry in dir.read_dir_utf8()? {
+        let entry = entry?;
+        if !entry.file_name().starts_with('.') && entry.file_type()?.is_dir() {
+            crates.push(entry.file_name().to_string());
+        }
+    }
+    crates.sort();
+    Ok(crates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_package() {
+        assert_eq!(
+            Args::parse(&[]).unwrap(),
+            Args {
+                package: None,
+                restore: false,
+                including_history: false,
+            }
+        );
+        for given in [
+            &["--package", "config"][..],
+            &["-p", "config"],
+            &["--package=config"],
+        ] {
+            assert_eq!(
+                Args::parse(&args(given)).unwrap(),
+                Args {
+                    package: Some("config".to_string()),
+                    restore: false,
+                    including_history: false,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn parse_restore() {
+        assert_eq!(
+            Args::parse(&args(&["--restore"])).unwrap(),
+            Args {
+                package: None,
+                restore: true,
+                including_history: false,
+            }
+        );
+        assert_eq!(
+            Args::parse(&args(&["-p", "config", "--restore"])).unwrap(),
+            Args {
+                package: Some("config".to_string()),
+                restore: true,
+                including_history: false,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_including_history() {
+        assert_eq!(
+            Args::parse(&args(&["--including-history", "-p", "config"])).unwrap(),
+            Args {
+                package: Some("config".to_string()),
+                restore: false,
+                including_history: true,
+            }
+        );
+    }
+
+    #[test]
+    fn remove_history_of_one_package() {
+        let tmp = tempfile::tempdir().unwrap();
+        let history = Utf8Path::from_path(tmp.path()).unwrap();
+        for name in [
+            "10-crate-a.json",
+            "20-crate-a.json",
+            "30-crate-a-b.json",
+            "notes-crate-a.json",
+        ] {
+            fs::write(history.join(name), "{}").unwrap();
+        }
+
+        remove_history(history, "crate-a").unwrap();
+        let mut left: Vec<_> = history
+            .read_dir_utf8()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["30-crate-a-b.json", "notes-crate-a.json"]);
+    }
+
+    #[test]
+    fn cargo_clean_invocations_are_not_stitch_cleans() {
+        assert!(is_stitch_clean(&[]));
+        assert!(!is_stitch_clean(&args(&["--release"])));
+        assert!(!is_stitch_clean(&args(&["-p", "config", "--release"])));
+        assert!(!is_stitch_clean(&args(&["--package"])));
+        assert!(!is_stitch_clean(&args(&["--package="])));
+        assert!(!is_stitch_clean(&args(&["--doc"])));
+    }
+}
@@ -0,0 +1,302 @@
+//! `cargo stitch completions <shell>`: print a completion script for bash, zsh, fish,
+//! elvish or PowerShell.
+//!
+//! The scripts are generated by `clap_complete` from [`Cli`], a model of cargo-stitch's
+//! command line.  Arguments are still parsed by hand: the flags before the command and
+//! the arguments passed on to cargo do not fit clap, so [`Cli`] only describes them.
+
+use std::io;
+use std::process::ExitCode;
+
+use camino::Utf8PathBuf;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use terrors::OneOf;
+
+use super::{SubcommandError, help, usage_error};
+
+const USAGE: &str = "usage: cargo stitch completions <bash|zsh|fish|elvish|powershell>";
+
+// Only the shape of the model is used, to generate the scripts; its fields are never read.
+#[allow(dead_code)]
+#[derive(Parser)]
+#[command(name = "cargo-stitch", bin_name = "cargo-stitch")]
+enum Cli {
+    /// Apply source-level patches and ast-grep rules to workspace crates before compilation
+    Stitch(StitchCli),
+}
+
+#[allow(dead_code)]
+#[derive(Args)]
+#[command(disable_help_subcommand = true)]
+struct StitchCli {
+    /// Use the stitches in `stitches/<name>/` instead of `stitches/default/`
+    #[arg(long, value_name = "name")]
+    set: Option<String>,
+    /// Update the patched copies in place, rewriting only changed files
+    #[arg(long)]
+    keep_patched: bool,
+    /// Also stitch crates.io dependencies
+    #[arg(long)]
+    external: bool,
+    /// Print diagnostics as newline-delimited JSON
+    #[arg(long)]
+    json: bool,
+    /// Print nothing but errors
+    #[arg(short, long)]
+    quiet: bool,
+    /// Also print everything the tools applying the stitches print
+    #[arg(short, long)]
+    verbose: bool,
+    /// Colour status lines
+    #[arg(long, value_name = "when", value_parser = ["auto", "always", "never"])]
+    color: Option<String>,
+    /// Fail on stitches for crates that are not workspace members
+    #[arg(long)]
+    strict: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Arguments passed on to cargo unchanged.
+#[allow(dead_code)]
+#[derive(Args)]
+struct CargoArgs {
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+}
+
+/// The commands with a help topic take their descriptions from it; see [`command`].
+#[allow(dead_code)]
+#[derive(Subcommand)]
+enum Command {
+    Apply,
+    ApplyTo {
+        dir: Utf8PathBuf,
+        krate: String,
+    },
+    Bench {
+        krate: String,
+        #[arg(long, value_name = "n")]
+        iterations: Option<usize>,
+    },
+    Build(CargoArgs),
+    Capture {
+        krate: String,
+        #[arg(long, value_name = "name")]
+        name: Option<String>,
+        #[arg(short, long, value_name = "file")]
+        output: Option<Utf8PathBuf>,
+    },
+    Check {
+        #[arg(long, value_name = "format", value_parser = ["human", "tap", "junit"])]
+        report_format: Option<String>,
+        #[arg(long, value_name = "file")]
+        report_output: Option<Utf8PathBuf>,
+        #[arg(long, value_name = "git-ref")]
+        since: Option<String>,
+        #[arg(long)]
+        strict: bool,
+        #[command(flatten)]
+        cargo: CargoArgs,
+    },
+    CheckOrdering {
+        krate: String,
+        #[arg(long)]
+        exit_code: bool,
+    },
+    CheckTools {
+        #[arg(long)]
+        versions: bool,
+    },
+    CheckUniqueIds,
+    Clean {
+        #[arg(short, long, value_name = "name")]
+        package: Option<String>,
+        /// Vendor the stitched crates in `vendor/` again
+        #[arg(long)]
+        restore: bool,
+        #[arg(long)]
+        including_history: bool,
+    },
+    Completions {
+        shell: Shell,
+    },
+    Diff {
+        #[arg(short, long, value_name = "name")]
+        package: Option<String>,
+    },
+    DiffSnapshot {
+        label: String,
+    },
+    Env {
+        #[arg(long)]
+        export: bool,
+    },
+    Export {
+        krate: String,
+        output_dir: Utf8PathBuf,
+        #[arg(long)]
+        overwrite: bool,
+        #[arg(long)]
+        build: bool,
+    },
+    Fetch {
+        krate: String,
+        version: String,
+    },
+    FetchStitches {
+        krate: String,
+        version: String,
+        #[arg(long, value_name = "url")]
+        registry: String,
+    },
+    Fmt {
+        crates: Vec<String>,
+        #[arg(long)]
+        check: bool,
+    },
+    /// Show detailed help for a command
+    Help {
+        command: Option<String>,
+    },
+    Init {
+        krate: String,
+        #[arg(short, long)]
+        force: bool,
+    },
+    Install {
+        #[arg(short, long)]
+        release: bool,
+    },
+    Lint {
+        #[arg(long)]
+        fix: bool,
+    },
+    List {
+        #[arg(long, conflicts_with = "checksums")]
+        json: bool,
+        #[arg(long)]
+        checksums: bool,
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    Log {
+        #[arg(short, long, value_name = "name")]
+        package: Option<String>,
+        #[arg(long)]
+        json: bool,
+    },
+    New {
+        krate: String,
+        name: String,
+        #[arg(long = "type", value_name = "kind", value_parser = ["patch", "sg"])]
+        kind: Option<String>,
+        #[arg(long)]
+        with_metadata: bool,
+    },
+    PublishStitches {
+        krate: String,
+        #[arg(long, value_name = "url")]
+        registry: String,
+    },
+    Reorder {
+        krate: String,
+        old_number: u32,
+        new_number: u32,
+    },
+    Reset {
+        #[arg(short, long, value_name = "name")]
+        package: Option<String>,
+        #[arg(long)]
+        all: bool,
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Run a binary of the workspace with its stitches applied
+    Run(CargoArgs),
+    Snapshot {
+        krate: Option<String>,
+        #[arg(long, requires = "krate")]
+        check: bool,
+        #[arg(long, value_name = "label", conflicts_with = "krate")]
+        name: Option<String>,
+    },
+    Status {
+        #[arg(long)]
+        json: bool,
+    },
+    Summarize {
+        #[arg(long)]
+        exit_code: bool,
+    },
+    Test(CargoArgs),
+    Trace {
+        krate: String,
+    },
+    Uninstall {
+        krate: String,
+        #[arg(short, long)]
+        yes: bool,
+        #[arg(long)]
+        keep_patched: bool,
+    },
+    Upgrade {
+        krate: String,
+        version: Option<String>,
+        #[arg(long)]
+        apply: bool,
+    },
+    Vendor(CargoArgs),
+    Verify {
+        #[arg(short, long, value_name = "crate")]
+        package: Vec<String>,
+    },
+    VerifyApplied {
+        krate: String,
+    },
+}
+
+pub(super) fn run(args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let [shell] = args else {
+        return Err(OneOf::new(usage_error(USAGE)));
+    };
+    let shell: Shell = shell
+        .parse()
+        .map_err(|_| OneOf::new(usage_error(format!("unsupported shell `{shell}`; {USAGE}"))))?;
+
+    clap_complete::generate(shell, &mut command(), "cargo-stitch", &mut io::stdout());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The command line as clap sees it, with each command described by its help topic's summary.
+fn command() -> clap::Command {
+    Cli::command().mut_subcommand("stitch", |stitch| {
+        help::summaries().fold(stitch, |stitch, (name, summary)| {
+            stitch.mut_subcommand(name, |command| command.about(summary))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn cli_is_well_formed() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn cli_has_a_subcommand_for_every_help_topic() {
+        let cli = command();
+        let stitch = cli.find_subcommand("stitch").unwrap();
+        let commands: BTreeSet<_> = stitch.get_subcommands().map(|c| c.get_name()).collect();
+        let mut expected: BTreeSet<_> = help::summaries().map(|(name, _)| name).collect();
+        // Cargo commands without a help topic of their own.
+        expected.extend(["help", "run"]);
+        assert_eq!(commands, expected);
+    }
+}
@@ -0,0 +1,153 @@
+//! `cargo stitch diff [--package <name>]`: show the combined effect of the stitches as a
+//! unified diff, without building anything.
+//!
+//! Like `diff`, this exits with 0 when the stitches change nothing, 1 when they do, and 2
+//! on errors.
+
+use std::process::ExitCode;
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::registry::require_tool;
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::error::IoError;
+use crate::fs::{CopyOptions, copy_dir_recursive, diff_dirs, temp_dir};
+use crate::stitch::{ApplyError, StitchSet};
+
+/// The exit code for errors, as used by `diff`.
+const EXIT_ERROR: u8 = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    package: Option<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut package = None;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--package" | "-p" if package.is_none() => {
+                    package = Some(args.next().cloned().ok_or_else(|| {
+                        usage_error("usage: cargo stitch diff [--package <name>]")
+                    })?);
+                }
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `diff`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { package })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    // Report errors here rather than in `main`, which exits with 1: that would read as
+    // "the stitches change something".
+    match diff(workspace, args) {
+        Ok(true) => Ok(ExitCode::FAILURE),
+        Ok(false) => Ok(ExitCode::SUCCESS),
+        Err(e) if workspace.output.is_json() => {
+            workspace.output.error(None, None, &e);
+            Ok(ExitCode::from(EXIT_ERROR))
+        }
+        Err(e) => {
+            eprintln!("cargo-stitch: {e}");
+            Ok(ExitCode::from(EXIT_ERROR))
+        }
+    }
+}
+
+/// Print the diff of every selected crate, returning whether there were any changes.
+fn diff(workspace: &Workspace, args: &[String]) -> Result<bool, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let mut manifest: Vec<(String, StitchSet)> = match &args.package {
+        Some(package) => vec![(
+            package.clone(),
+            workspace.stitch_set(package).map_err(OneOf::broaden)?,
+        )],
+        None => workspace
+            .discover()
+            .map_err(OneOf::broaden)?
+            .into_iter()
+            .collect(),
+    };
+    manifest.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    check_required_tools(manifest.iter().map(|(_, set)| set), &workspace.output)
+        .map_err(OneOf::broaden)?;
+    require_tool("diff")?;
+
+    let mut changed = false;
+    for (krate, stitch_set) in &manifest {
+        let manifest_dir = workspace.member_dir(krate).map_err(OneOf::new)?;
+        let diff =
+            crate_diff(&workspace.root, &manifest_dir, stitch_set).map_err(OneOf::broaden)?;
+        changed |= !diff.is_empty();
+        print!("{diff}");
+    }
+
+    Ok(changed)
+}
+
+/// Apply `stitch_set` to a scratch copy of `manifest_dir` and diff it against another
+/// copy of the original.
+///
+/// Both copies keep the crate's path relative to `root`, so the diff reads like
+/// `git diff` run at the workspace root, and can be applied there with `patch -p1`.
+fn crate_diff(
+    root: &Utf8Path,
+    manifest_dir: &Utf8Path,
+    stitch_set: &StitchSet,
+) -> Result<String, ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let relative = manifest_dir.strip_prefix(root).unwrap_or(Utf8Path::new(""));
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    let (orig, patched) = (scratch.join("a"), scratch.join("b"));
+    for tree in [&orig, &patched] {
+        copy_dir_recursive(manifest_dir, &tree.join(relative), &CopyOptions::default())
+            .map_err(io)?;
+    }
+
+    stitch_set.apply_quietly(&patched.join(relative))?;
+
+    diff_dirs(&orig, &patched).map_err(io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_package() {
+        assert_eq!(Args::parse(&[]).unwrap(), Args { package: None });
+        assert_eq!(
+            Args::parse(&args(&["--package", "config"])).unwrap(),
+            Args {
+                package: Some("config".to_string())
+            }
+        );
+        assert_eq!(
+            Args::parse(&args(&["-p", "config"])).unwrap().package,
+            Some("config".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_arguments() {
+        assert!(Args::parse(&args(&["--package"])).is_err());
+        assert!(Args::parse(&args(&["-p", "a", "-p", "b"])).is_err());
+        assert!(Args::parse(&args(&["config"])).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! `cargo stitch diff-snapshot <label>`: compare the patched directory with a snapshot of
+//! it that `cargo stitch snapshot --name <label>` saved, with `diff -ru`.
+
+use std::process::{Command, ExitCode};
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::clean::patched_crates;
+use super::snapshot::{PATCHED_SNAPSHOTS_DIR, is_valid_label};
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::{IoError, MissingTool};
+use crate::hash::{HISTORY_DIR, STAMP_FILE, STATE_FILE};
+use crate::stitch::cargo_status;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    label: String,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        match args {
+            [label] if !label.starts_with('-') && is_valid_label(label) => Ok(Self {
+                label: label.clone(),
+            }),
+            _ => Err(usage_error("usage: cargo stitch diff-snapshot <label>")),
+        }
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    if Command::new("diff").arg("--version").output().is_err() {
+        return Err(OneOf::new(MissingTool("diff")));
+    }
+
+    let relative = |path: &Utf8Path| {
+        path.strip_prefix(&workspace.root)
+            .unwrap_or(path)
+            .to_owned()
+    };
+    let snapshot = relative(
+        &workspace
+            .target_dir
+            .join(PATCHED_SNAPSHOTS_DIR)
+            .join(&args.label),
+    );
+    let patched = relative(&workspace.patched_root);
+
+    if !workspace.root.join(&snapshot).is_dir() {
+        cargo_status(
+            &workspace.output,
+            "Error",
+            &format!(
+                "no snapshot in {snapshot}; run `cargo stitch snapshot --name {}` first",
+                args.label
+            ),
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let crates = if workspace.patched_root.is_dir() {
+        patched_crates(&workspace.patched_root).map_err(io)?
+    } else {
+        Vec::new()
+    };
+    let excludes = bookkeeping_patterns(&crates);
+    let status = Command::new("diff")
+        .arg("-ru")
+        .args(excludes.iter().flat_map(|pattern| ["-x", pattern.as_str()]))
+        .args([&snapshot, &patched])
+        .current_dir(&workspace.root)
+        .status()
+        .map_err(io)?;
+
+    // diff exits with 0 when the inputs are identical, 1 when they differ.
+    match status.code() {
+        Some(0) => {
+            cargo_status(
+                &workspace.output,
+                "Fresh",
+                &format!("{patched} matches the snapshot in {snapshot}"),
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(1) => Ok(ExitCode::FAILURE),
+        _ => Err(io(std::io::Error::other(format!(
+            "`diff -ru {snapshot} {patched}` failed: {status}"
+        )))),
+    }
+}
+
+/// The `diff -x` patterns for cargo-stitch's own bookkeeping, which differs from one build
+/// to the next: the stitch manifests, the history, and the scratch directories of `crates`
+/// and their locks next to the crates, and the state and stamp files inside them.
+fn bookkeeping_patterns(crates: &[String]) -> Vec<String> {
+    let mut patterns = vec![
+        STAMP_FILE.to_string(),
+        STATE_FILE.to_string(),
+        HISTORY_DIR.to_string(),
+        ".manifest-*.json".to_string(),
+    ];
+    patterns.extend(crates.iter().map(|krate| format!(".{krate}.[0-9]*")));
+    patterns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requires_one_label() {
+        assert_eq!(
+            Args::parse(&["before".to_string()]).unwrap(),
+            Args {
+                label: "before".to_string()
+            }
+        );
+        assert!(Args::parse(&[]).is_err());
+        assert!(Args::parse(&["a".to_string(), "b".to_string()]).is_err());
+        assert!(Args::parse(&["--name".to_string()]).is_err());
+        assert!(Args::parse(&["../x".to_string()]).is_err());
+    }
+
+    #[test]
+    fn bookkeeping_patterns_leave_other_dotfiles_alone() {
+        let patterns = bookkeeping_patterns(&["crate-a".to_string()]);
+        assert!(patterns.contains(&".stitch-state.json".to_string()));
+        assert!(patterns.contains(&".crate-a.[0-9]*".to_string()));
+        assert!(!patterns.iter().any(|p| p == ".*"));
+    }
+}
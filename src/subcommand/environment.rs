@@ -0,0 +1,84 @@
+//! `cargo stitch env [--export]`: print the environment variables `cargo stitch <command>`
+//! sets for cargo, to debug a build or to reproduce it without cargo-stitch.
+
+use std::borrow::Cow;
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, stitch_env, usage_error};
+use crate::error::IoError;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    export: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut export = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--export" => export = true,
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `env`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { export })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+    for (key, value) in stitch_env(workspace, &manifest).map_err(OneOf::new)? {
+        let value = value.to_string_lossy();
+        if args.export {
+            println!("export {key}={}", shell_quote(&value));
+        } else {
+            println!("{key}={value}");
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Quote `value` for a POSIX shell, if it needs it.
+fn shell_quote(value: &str) -> Cow<'_, str> {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c));
+    if plain {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(format!("'{}'", value.replace('\'', r"'\''")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_export_flag() {
+        assert!(!Args::parse(&[]).unwrap().export);
+        assert!(Args::parse(&["--export".to_string()]).unwrap().export);
+        assert!(Args::parse(&["--json".to_string()]).is_err());
+    }
+
+    #[test]
+    fn shell_quote_only_when_needed() {
+        assert_eq!(shell_quote("/home/me/ws"), "/home/me/ws");
+        assert_eq!(shell_quote("1"), "1");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("/my ws"), "'/my ws'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,138 @@
+//! `cargo stitch export <crate> <output-dir> [--overwrite] [--build]`: copy the patched
+//! copy of a crate in `target/cargo-stitch/<crate>/` to a directory of the user's choosing.
+
+use std::fs;
+use std::process::ExitCode;
+
+use camino::Utf8PathBuf;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, run_cargo, usage_error};
+use crate::error::IoError;
+use crate::fs::{CopyOptions, copy_dir_recursive};
+use crate::hash::{STAMP_FILE, STATE_FILE};
+use crate::stitch::cargo_status;
+use crate::wrapper::patched_dir;
+
+const USAGE: &str = "usage: cargo stitch export <crate> <output-dir> [--overwrite] [--build]";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    output_dir: Utf8PathBuf,
+    overwrite: bool,
+    build: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut positional = Vec::new();
+        let mut overwrite = false;
+        let mut build = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--overwrite" => overwrite = true,
+                "--build" => build = true,
+                other if other.starts_with('-') || positional.len() == 2 => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `export`: {other}"
+                    )));
+                }
+                other => positional.push(other.to_string()),
+            }
+        }
+
+        let [krate, output_dir] =
+            <[String; 2]>::try_from(positional).map_err(|_| usage_error(USAGE))?;
+
+        Ok(Self {
+            krate,
+            output_dir: Utf8PathBuf::from(output_dir),
+            overwrite,
+            build,
+        })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    if args.output_dir.exists() && !args.overwrite {
+        return Err(OneOf::new(usage_error(format!(
+            "{} already exists; pass `--overwrite` to replace it",
+            args.output_dir
+        ))));
+    }
+
+    if args.build {
+        run_cargo(
+            workspace,
+            &[
+                "build".to_string(),
+                "--package".to_string(),
+                args.krate.clone(),
+            ],
+        )?;
+    }
+
+    let patched_dir = patched_dir(&args.krate, &workspace.patched_root);
+    if !patched_dir.is_dir() {
+        return Err(OneOf::new(usage_error(format!(
+            "{patched_dir} does not exist; run `cargo stitch build` first, or pass `--build`"
+        ))));
+    }
+
+    if args.output_dir.is_dir() {
+        fs::remove_dir_all(&args.output_dir).map_err(io)?;
+    } else if args.output_dir.exists() {
+        fs::remove_file(&args.output_dir).map_err(io)?;
+    }
+
+    copy_dir_recursive(&patched_dir, &args.output_dir, &CopyOptions::default()).map_err(io)?;
+    // The files a build leaves to tell whether the patched copy is up to date are not part
+    // of the crate's sources.
+    for bookkeeping in [STAMP_FILE, STATE_FILE] {
+        let _ = fs::remove_file(args.output_dir.join(bookkeeping));
+    }
+
+    cargo_status(
+        &workspace.output,
+        "Exported",
+        &format!("{} to {}", args.krate, args.output_dir),
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_crate_and_output_dir() {
+        assert_eq!(
+            Args::parse(&args(&["crate-a", "/tmp/out"])).unwrap(),
+            Args {
+                krate: "crate-a".to_string(),
+                output_dir: Utf8PathBuf::from("/tmp/out"),
+                overwrite: false,
+                build: false,
+            }
+        );
+        assert_eq!(
+            Args::parse(&args(&["--build", "crate-a", "/tmp/out", "--overwrite"])).unwrap(),
+            Args {
+                krate: "crate-a".to_string(),
+                output_dir: Utf8PathBuf::from("/tmp/out"),
+                overwrite: true,
+                build: true,
+            }
+        );
+        assert!(Args::parse(&args(&["crate-a"])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "/tmp/out", "extra"])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "/tmp/out", "--force"])).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! `cargo stitch fetch <crate> <version>`: unpack a crate's published source under
+//! `target/cargo-stitch/.fetch/` so it can be inspected before writing stitches for it.
+//!
+//! The source is taken from cargo's registry cache when it is there, and downloaded from
+//! crates.io otherwise.  This only takes over `fetch` when it is given a crate and a
+//! version; `cargo fetch` takes no positional arguments, so anything else is forwarded to
+//! it as usual.
+
+use std::fs;
+use std::process::{Command, ExitCode};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use terrors::OneOf;
+
+use super::registry::{require_tool, run_tool};
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+use crate::fs::{CopyOptions, cargo_home, copy_dir_recursive, temp_dir};
+use crate::output::OutputContext;
+use crate::stitch::cargo_status;
+
+/// Where crates.io serves `.crate` files from.
+const CRATES_IO_DOWNLOAD: &str = "https://static.crates.io/crates";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    version: String,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        match args {
+            [krate, version] if !krate.starts_with('-') && !version.starts_with('-') => Ok(Self {
+                krate: krate.clone(),
+                version: version.clone(),
+            }),
+            _ => Err(usage_error("usage: cargo stitch fetch <crate> <version>")),
+        }
+    }
+
+    /// The `<crate>-<version>` directory name used by cargo and by `.crate` files.
+    fn dir_name(&self) -> String {
+        format!("{}-{}", self.krate, self.version)
+    }
+}
+
+/// Returns `true` if `args` (following `fetch`) name a crate and version, rather than
+/// options for `cargo fetch`.
+pub(super) fn is_crate_fetch(args: &[String]) -> bool {
+    Args::parse(args).is_ok()
+}
+
+/// Where cargo's registry cache keeps a crate's source, either already unpacked or as a
+/// `.crate` file.
+#[derive(Debug, PartialEq, Eq)]
+enum Cached {
+    Unpacked(Utf8PathBuf),
+    Archive(Utf8PathBuf),
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let dest = fetch_source(workspace, &args.krate, &args.version)?;
+    println!("{dest}");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Unpack the source of `krate` v`version` under `target/cargo-stitch/.fetch/`, unless it
+/// is already there, and return its path.
+pub(super) fn fetch_source(
+    workspace: &Workspace,
+    krate: &str,
+    version: &str,
+) -> Result<Utf8PathBuf, SubcommandError> {
+    let args = Args {
+        krate: krate.to_string(),
+        version: version.to_string(),
+    };
+    let io = |e| OneOf::new(IoError(e));
+
+    let fetch_dir = workspace.target_dir.join("cargo-stitch").join(".fetch");
+    let dest = fetch_dir.join(args.dir_name());
+
+    if dest.is_dir() {
+        cargo_status(
+            &workspace.output,
+            "Fresh",
+            &format!("{} v{}", args.krate, args.version),
+        );
+        return Ok(dest);
+    }
+
+    // Unpack into a scratch directory next to `dest` and move it into place at the end,
+    // so that an interrupted fetch never leaves a partial source tree behind.
+    fs::create_dir_all(&fetch_dir).map_err(io)?;
+    let scratch = fetch_dir.join(format!(".tmp.{}", std::process::id()));
+    let _ = fs::remove_dir_all(&scratch);
+    let unpacked = scratch.join(args.dir_name());
+
+    let result = unpack(&args, &scratch, &unpacked, &workspace.output).and_then(|()| {
+        fs::rename(&unpacked, &dest).map_err(io)?;
+        Ok(())
+    });
+    let _ = fs::remove_dir_all(&scratch);
+    result?;
+
+    Ok(dest)
+}
+
+/// Put the source of `args.krate` v`args.version` in `unpacked`, a subdirectory of
+/// `scratch`, reporting where it came from as `output` says.
+fn unpack(
+    args: &Args,
+    scratch: &Utf8Path,
+    unpacked: &Utf8Path,
+    output: &OutputContext,
+) -> Result<(), SubcommandError> {
+    let io = |e| OneOf::new(IoError(e));
+    let what = format!("{} v{}", args.krate, args.version);
+
+    let registry = cargo_home().map_err(io)?.join("registry");
+    match find_cached(&registry, &args.dir_name()).map_err(io)? {
+        Some(Cached::Unpacked(src)) => {
+            copy_dir_recursive(&src, unpacked, &CopyOptions::default()).map_err(io)?;
+            cargo_status(output, "Copied", &format!("{what} from {src}"));
+        }
+        Some(Cached::Archive(archive)) => {
+            extract(&archive, scratch)?;
+            cargo_status(output, "Extracted", &format!("{what} from {archive}"));
+        }
+        None => {
+            require_tool("curl")?;
+            let (_guard, download) = temp_dir().map_err(io)?;
+            let archive = download.join(format!("{}.crate", args.dir_name()));
+
+            let url = format!(
+                "{CRATES_IO_DOWNLOAD}/{}/{}.crate",
+                args.krate,
+                args.dir_name()
+            );
+            cargo_status(output, "Downloading", &url);
+            let mut curl = Command::new("curl");
+            curl.args(["-fsSL", "-o"]).arg(&archive).arg(&url);
+            run_tool(&mut curl, None).map_err(io)?;
+
+            extract(&archive, scratch)?;
+            cargo_status(output, "Downloaded", &what);
+        }
+    }
+
+    if !unpacked.is_dir() {
+        return Err(OneOf::new(IoError(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("the source of {what} does not contain {}/", args.dir_name()),
+        ))));
+    }
+    Ok(())
+}
+
+/// Extract the `.crate` file `archive` (a gzipped tarball) into `dir`.
+fn extract(archive: &Utf8Path, dir: &Utf8Path) -> Result<(), SubcommandError> {
+    require_tool("tar")?;
+    fs::create_dir_all(dir).map_err(|e| OneOf::new(IoError(e)))?;
+    run_tool(
+        Command::new("tar")
+            .arg("-xzf")
+            .arg(archive)
+            .arg("-C")
+            .arg(dir),
+        None,
+    )
+    .map_err(|e| OneOf::new(IoError(e)))
+}
+
+/// Look for `<dir_name>` in cargo's registry cache under `registry`, preferring an
+/// unpacked source directory over a `.crate` file.  Each registry index gets its own
+/// subdirectory of `src/` and `cache/`; these are searched in name order.
+fn find_cached(registry: &Utf8Path, dir_name: &str) -> std::io::Result<Option<Cached>> {
+    for (kind, file_name) in [
+        ("src", dir_name.to_string()),
+        ("cache", format!("{dir_name}.crate")),
+    ] {
+        let Ok(entries) = registry.join(kind).read_dir_utf8() else {
+            continue;
+        };
+
+        let mut indexes = Vec::new();
+        for entry in entries {
+            indexes.push(entry?.into_path());
+        }
+        indexes.sort();
+
+        for index in indexes {
+            let candidate = index.join(&file_name);
+            if kind == "src" && candidate.is_dir() {
+                return Ok(Some(Cached::Unpacked(candidate)));
+            }
+            if kind == "cache" && candidate.is_file() {
+                return Ok(Some(Cached::Archive(candidate)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_crate_and_version() {
+        let parsed = Args::parse(&args(&["serde", "1.0.195"])).unwrap();
+        assert_eq!(
+            parsed,
+            Args {
+                krate: "serde".to_string(),
+                version: "1.0.195".to_string()
+            }
+        );
+        assert_eq!(parsed.dir_name(), "serde-1.0.195");
+    }
+
+    #[test]
+    fn cargo_fetch_invocations_are_not_crate_fetches() {
+        assert!(!is_crate_fetch(&[]));
+        assert!(!is_crate_fetch(&args(&["--locked"])));
+        assert!(!is_crate_fetch(&args(&[
+            "--target",
+            "wasm32-unknown-unknown"
+        ])));
+        assert!(!is_crate_fetch(&args(&["serde"])));
+        assert!(is_crate_fetch(&args(&["serde", "1.0.195"])));
+    }
+
+    #[test]
+    fn find_cached_prefers_unpacked_source() {
+        let (_guard, registry) = temp_dir().unwrap();
+        let cache = registry.join("cache/index.crates.io-1949cf8c6b5b557f");
+        let src = registry.join("src/index.crates.io-1949cf8c6b5b557f/serde-1.0.195");
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(cache.join("serde-1.0.195.crate"), "").unwrap();
+
+        assert_eq!(
+            find_cached(&registry, "serde-1.0.195").unwrap(),
+            Some(Cached::Archive(cache.join("serde-1.0.195.crate")))
+        );
+
+        fs::create_dir_all(&src).unwrap();
+        assert_eq!(
+            find_cached(&registry, "serde-1.0.195").unwrap(),
+            Some(Cached::Unpacked(src))
+        );
+        assert_eq!(find_cached(&registry, "serde-1.0.196").unwrap(), None);
+    }
+}
@@ -0,0 +1,277 @@
+//! `cargo stitch fmt <crate>... [--check]`: normalize the formatting of the `.patch` files
+//! in `stitches/<crate>/`, in place.
+//!
+//! This only takes over `fmt` when every crate it names has stitches; anything else is
+//! forwarded to `cargo fmt` as usual.
+
+use std::fs;
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+use crate::patch::HunkHeader;
+use crate::stitch::{Stitch, cargo_status, has_crlf_headers, split_front_matter, stitch_dir_files};
+
+const USAGE: &str = "usage: cargo stitch fmt <crate>... [--check]";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krates: Vec<String>,
+    check: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut krates = Vec::new();
+        let mut check = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--check" => check = true,
+                other if other.starts_with('-') => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `fmt`: {other}"
+                    )));
+                }
+                other => krates.push(other.to_string()),
+            }
+        }
+
+        if krates.is_empty() {
+            return Err(usage_error(USAGE));
+        }
+        Ok(Self { krates, check })
+    }
+}
+
+/// Returns `true` if `args` (following `fmt`) name crates with stitches, rather than
+/// options for `cargo fmt`.
+pub(super) fn names_stitched_crates(workspace: &Workspace, args: &[String]) -> bool {
+    Args::parse(args).is_ok_and(|args| {
+        args.krates
+            .iter()
+            .all(|krate| workspace.stitches_dir.join(krate).is_dir())
+    })
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let mut unformatted = 0;
+    for krate in &args.krates {
+        let dir = workspace.stitches_dir.join(krate);
+        if !dir.is_dir() {
+            return Err(OneOf::new(usage_error(format!(
+                "no stitches found for crate `{krate}` in {}",
+                workspace.stitches_dir
+            ))));
+        }
+
+        for path in stitch_dir_files(&dir).map_err(io)? {
+            if !matches!(Stitch::from_path(path.clone()), Some(Stitch::Patch(_))) {
+                continue;
+            }
+            let contents = fs::read_to_string(&path).map_err(io)?;
+            let formatted = format_patch(&contents);
+            if formatted == contents {
+                continue;
+            }
+
+            let display = path.strip_prefix(&workspace.root).unwrap_or(&path);
+            if args.check {
+                unformatted += 1;
+                cargo_status(
+                    &workspace.output,
+                    "Warning",
+                    &format!("{display}: is not formatted"),
+                );
+            } else {
+                fs::write(&path, formatted).map_err(io)?;
+                cargo_status(&workspace.output, "Formatted", display.as_str());
+            }
+        }
+    }
+
+    if unformatted > 0 {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// The patch `contents`, formatted:
+///
+/// - with `\n` line endings, if it was saved with `\r\n` ones (as [`has_crlf_headers`]
+///   tells: the `\r` that ends the lines of a patch of a file with `\r\n` line endings
+///   is kept);
+/// - without trailing whitespace;
+/// - without the timestamps in the `---` and `+++` headers, as `git diff` writes them;
+/// - without blank lines at the end, but with a final newline.
+///
+/// The lines of each hunk are counted as its `@@` header says, so that a removed line
+/// starting with `--` is not mistaken for the header of the next file.
+fn format_patch(contents: &str) -> String {
+    let crlf = has_crlf_headers(contents);
+    let contents = if crlf {
+        contents.replace("\r\n", "\n")
+    } else {
+        contents.to_string()
+    };
+    let (front_matter, body) = split_front_matter(&contents);
+
+    let mut out = String::new();
+    if let Some(front_matter) = front_matter {
+        out.push_str("---\n");
+        for line in front_matter.lines() {
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out.push_str("---\n");
+    }
+
+    // The old and new lines left in the current hunk, and where the last hunk ended: an
+    // empty line before that is a context line, not a trailing blank line.
+    let mut remaining = (0, 0);
+    let mut hunk_end = 0;
+    // Not `lines()`, which would drop the `\r` of lines that end with `\r\n`.
+    for line in body.split_inclusive('\n') {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        if remaining != (0, 0) {
+            match line.as_bytes().first() {
+                Some(b'-') => remaining.0 -= 1,
+                Some(b'+') => remaining.1 -= 1,
+                Some(b'\\') => {}
+                // A context line, whose leading space may have been stripped already.
+                _ => {
+                    remaining.0 -= 1;
+                    remaining.1 -= 1;
+                }
+            }
+            remaining = (remaining.0.max(0), remaining.1.max(0));
+            let (line, cr) = match line.strip_suffix('\r') {
+                Some(line) => (line, "\r"),
+                None => (line, ""),
+            };
+            out.push_str(line.trim_end());
+            out.push_str(cr);
+            out.push('\n');
+            hunk_end = out.len();
+            continue;
+        }
+
+        let line = line.trim_end();
+        if let Some(counts) = hunk_counts(line) {
+            remaining = counts;
+            out.push_str(line);
+        } else if line.starts_with("--- ") || line.starts_with("+++ ") {
+            out.push_str(line.split_once('\t').map_or(line, |(path, _)| path));
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+
+    while out.len() > hunk_end && (out == "\n" || out.ends_with("\n\n")) {
+        out.pop();
+    }
+    out
+}
+
+/// The number of old and new lines in the hunk starting with `line`, if it is a hunk
+/// header.
+fn hunk_counts(line: &str) -> Option<(i64, i64)> {
+    let header = HunkHeader::parse(line)?;
+    Some((header.old_count as i64, header.new_count as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_crates_and_check_flag() {
+        assert_eq!(
+            Args::parse(&args(&["crate-a", "--check", "crate-b"])).unwrap(),
+            Args {
+                krates: vec!["crate-a".to_string(), "crate-b".to_string()],
+                check: true,
+            }
+        );
+        // `cargo fmt --check` names no crate.
+        assert!(Args::parse(&args(&["--check"])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "--all"])).is_err());
+    }
+
+    #[test]
+    fn hunk_counts_default_to_one() {
+        assert_eq!(hunk_counts("@@ -1,3 +1,4 @@ fn main() {"), Some((3, 4)));
+        assert_eq!(hunk_counts("@@ -5 +5 @@"), Some((1, 1)));
+        assert_eq!(hunk_counts("@@ -0,0 +1,2 @@"), Some((0, 2)));
+        assert_eq!(hunk_counts("@@ bogus @@"), None);
+    }
+
+    #[test]
+    fn format_strips_whitespace_timestamps_and_trailing_blank_lines() {
+        let patch = "--- a/src/lib.rs\t2024-03-01 12:00:00.000000000 +0100  \n\
+                     +++ b/src/lib.rs\t2024-03-02 08:30:00.000000000 +0100\n\
+                     @@ -1,3 +1,3 @@\n \
+                     fn a() {}  \n\
+                     -fn b() {}\t\n\
+                     +fn c() {}\n \n\
+                     \n\n";
+        assert_eq!(
+            format_patch(patch),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n fn a() {}\n-fn b() {}\n+fn c() {}\n\n"
+        );
+        assert_eq!(format_patch("--- a/x\n+++ b/x"), "--- a/x\n+++ b/x\n");
+        assert_eq!(format_patch(""), "");
+    }
+
+    #[test]
+    fn format_multi_file_patch_counts_hunk_lines() {
+        // The second hunk removes a line that looks like a header.
+        let patch = "--- a/one.rs\t2024-03-01\n\
+                     +++ b/one.rs\t2024-03-01\n\
+                     @@ -1 +1 @@\n\
+                     -one\n\
+                     +uno\n\
+                     --- a/two.txt\t2024-03-01\n\
+                     +++ b/two.txt\t2024-03-01\n\
+                     @@ -1,2 +1 @@\n\
+                     --- keep\t me\n \
+                     two\n";
+        assert_eq!(
+            format_patch(patch),
+            "--- a/one.rs\n+++ b/one.rs\n@@ -1 +1 @@\n-one\n+uno\n\
+             --- a/two.txt\n+++ b/two.txt\n@@ -1,2 +1 @@\n--- keep\t me\n two\n"
+        );
+    }
+
+    #[test]
+    fn format_line_endings() {
+        // Saved with `\r\n` line endings.
+        assert_eq!(
+            format_patch("--- a/x\r\n+++ b/x\r\n@@ -1 +1 @@\r\n-a\r\n+b\r\n"),
+            "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n"
+        );
+        // A patch of a file with `\r\n` line endings.
+        let patch = "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a \r\n+b\r\n";
+        assert_eq!(
+            format_patch(patch),
+            "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\r\n+b\r\n"
+        );
+    }
+
+    #[test]
+    fn format_keeps_front_matter() {
+        let patch = "---\nid: fix  \n---\n# A comment \n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n";
+        assert_eq!(
+            format_patch(patch),
+            "---\nid: fix\n---\n# A comment\n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n"
+        );
+    }
+}
@@ -0,0 +1,1075 @@
+//! `cargo stitch help [<command>]`: detailed help, with examples, for each command.
+//!
+//! The examples are written against the workspace created by `create_workspace` in
+//! `tests/integration.rs`, with the two stitches set up by the `help` tests there.  Those
+//! tests run every example that shows its output and check that the output still
+//! matches, so keep them in sync when changing a command's output.
+
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::{SubcommandError, usage_error};
+
+struct Topic {
+    name: &'static str,
+    summary: &'static str,
+    /// Whether `cargo <name>` is also a cargo command, so that `cargo stitch <name> --help`
+    /// shows cargo's help rather than this topic.
+    shadows_cargo: bool,
+    text: &'static str,
+}
+
+const TOPICS: &[Topic] = &[
+    Topic {
+        name: "apply",
+        summary: "Patch every crate with stitches, without building",
+        shadows_cargo: false,
+        text: r#"cargo stitch [--keep-patched] apply
+
+Bring the patched copy in `target/cargo-stitch/<crate>/` of every crate with stitches up
+to date, as `cargo stitch build` would, but without building anything.  Crates are
+stitched concurrently, and a crate whose stitches fail does not stop the others: every
+failure is reported at the end, and the command exits non-zero.
+
+OPTIONS
+    --keep-patched    Update the patched copies in place, rewriting only changed files
+
+EXAMPLES
+    Patch the workspace ahead of a build:
+
+        $ cargo stitch apply
+            Patching [patch] 001-greeting.patch
+            Patching [patch] 002-exclaim.patch
+            Stitched crate-a
+"#,
+    },
+    Topic {
+        name: "apply-to",
+        summary: "Apply a crate's stitches to any directory, in place",
+        shadows_cargo: false,
+        text: r#"cargo stitch apply-to <dir> <crate>
+
+Apply the stitches of `<crate>`, from `stitches/<set>/<crate>/`, to `<dir>` in place,
+without copying it to `target/cargo-stitch/`, e.g. to look at the result or feed it to
+another tool.  `<dir>` must already exist and hold the crate's sources.  Stops at the
+first stitch that fails, leaving `<dir>` as it was, apart from the `.rej` files of a
+patch.
+
+EXAMPLES
+    Stitch a copy of crate-a made by hand:
+
+        $ cp -r crate-a /tmp/crate-a
+        $ cargo stitch apply-to /tmp/crate-a crate-a
+"#,
+    },
+    Topic {
+        name: "bench",
+        summary: "Time how long each stitch of a crate takes to apply",
+        shadows_cargo: true,
+        text: r#"cargo stitch bench <crate> [--iterations <n>]
+
+Apply the crate's stitches to <n> fresh scratch copies of its source, timing each stitch,
+and print the fastest, mean and slowest time of each one and of the whole set, in
+milliseconds.  Copying the sources is not timed.  Handy to find the stitch, e.g. a heavy
+ast-grep rule, that dominates build time.
+
+When <crate> has no stitches, `cargo stitch bench` runs `cargo bench` as usual.
+
+OPTIONS
+    --iterations <n>    How many times to apply the stitches (default: 10)
+
+EXAMPLES
+    Time the stitches of crate-a over 50 runs:
+
+        $ cargo stitch bench crate-a --iterations 50
+"#,
+    },
+    Topic {
+        name: "build",
+        summary: "Build the workspace with its stitches applied",
+        shadows_cargo: true,
+        text: r#"cargo stitch [--set <name>] [--keep-patched] [--external] [--json] [-q|-v] [--color <when>] [--strict] build [<cargo build args>...]
+
+Copy every workspace crate that has stitches to `target/cargo-stitch/<crate>/`, apply
+the stitches from `stitches/<set>/<crate>/` there, and build the workspace against the
+patched copies.  A crate is only re-patched when its sources or stitches change.
+
+With `--package <name>` (`-p <name>`), only the selected packages are stitched; their
+dependencies are built from their original sources.
+
+Any other cargo command (`run`, `test`, `doc`, ...) works the same way.
+
+OPTIONS
+    --set <name>      Use the stitches in `stitches/<name>/` instead of `stitches/default/`
+    --keep-patched    Update the patched copy in place, rewriting only the files whose
+                      patched content changed, so incremental compilation can reuse them
+    --external        Also stitch crates.io dependencies, with the stitches in
+                      `stitches/<set>/<crate>-<version>/`; `external = true` in the
+                      `stitch.toml` of such a directory does the same
+    --json            Print cargo-stitch's diagnostics as newline-delimited JSON, as
+                      described in the README; `CARGO_STITCH_JSON=1` does the same
+    -q, --quiet       Print nothing but errors
+    -v, --verbose     Also print everything the tools applying the stitches print;
+                      `CARGO_STITCH_VERBOSITY=quiet|verbose` does the same as these two
+    --color <when>    Colour status lines: `auto` (the default: when stderr is a terminal
+                      and `NO_COLOR` is not set), `always` or `never`; the same as
+                      `CARGO_STITCH_COLOR=<when>`
+    --strict          Fail, rather than warn, when a stitch directory is for a crate
+                      that is not a member of the workspace
+    <cargo build args>...
+                      Passed on to `cargo build` unchanged
+
+EXAMPLES
+    Build the workspace, patching `crate-a` on the way:
+
+        $ cargo stitch build
+           Compiling crate-a v0.1.0 (...)
+            Patching [patch] 001-greeting.patch
+            Patching [patch] 002-exclaim.patch
+           Compiling crate-b v0.1.0 (...)
+            Finished `dev` profile [unoptimized + debuginfo] target(s) in ...
+
+    Build in release mode; the patched copy is shared with the previous build:
+
+        $ cargo stitch build --release
+           Compiling crate-a v0.1.0 (...)
+           Compiling crate-b v0.1.0 (...)
+            Finished `release` profile [optimized] target(s) in ...
+
+    Build a single crate with an explicitly chosen stitch set:
+
+        $ cargo stitch --set default build -p crate-a
+            Finished `dev` profile [unoptimized + debuginfo] target(s) in ...
+"#,
+    },
+    Topic {
+        name: "capture",
+        summary: "Save hand edits to a patched copy as a new stitch",
+        shadows_cargo: false,
+        text: r#"cargo stitch capture <crate> [--name <name>] [--output <file>]
+
+Turn the changes made by hand to `target/cargo-stitch/<crate>/` into a new `.patch` file
+in `stitches/<set>/<crate>/`, numbered after the last stitch there, so that they survive
+the next clean build.  Only the edits are captured: the diff is taken against the
+crate's sources with its current stitches applied.  Requires `diff`.
+
+Run `cargo stitch build` first, so that the patched copy exists, then edit it.  Capturing
+nothing, when the patched copy has not been edited, writes no file.
+
+OPTIONS
+    --name <name>           Name the patch `<NNN>-<name>.patch` rather than
+                            `<NNN>-captured.patch`
+    --output, -o <file>     Write the patch to <file> instead
+
+EXAMPLES
+    After editing `target/cargo-stitch/crate-a/src/lib.rs`, save the edit as
+    `stitches/default/crate-a/003-fix-greeting.patch`:
+
+        $ cargo stitch capture crate-a --name fix-greeting
+"#,
+    },
+    Topic {
+        name: "check",
+        summary: "Check that every stitch applies, and write a test report",
+        shadows_cargo: true,
+        text: r#"cargo stitch check [--report-format <format>] [--report-output <file>] [--since <git-ref>] [--strict]
+
+Apply each crate's stitches in order to a scratch copy of its source, and report which
+ones apply, with one test case per stitch file.  Exits non-zero if any stitch fails.
+A stitch that applies but changes nothing (e.g. an ast-grep rule with no matches), and a
+patch that changes the same lines as an earlier one made against the same sources, get
+a warning.
+
+Without any of the flags below, `cargo stitch check` runs `cargo check` as usual.
+
+OPTIONS
+    --report-format <format>    `human` (the default), `tap` or `junit`
+    --report-output <file>      Write the report to <file> instead of stdout
+    --since <git-ref>           Only check the crates whose stitches changed since
+                                <git-ref>, according to `git diff --name-only`
+    --strict                    Also exit non-zero if any stitch has a warning, like
+                                `-D warnings`; meant for CI, where any unexpected
+                                behaviour should block the build
+
+EXAMPLES
+    Check the stitches of every crate:
+
+        $ cargo stitch check --report-format human
+        ok      stitches/default/crate-a/001-greeting.patch
+        ok      stitches/default/crate-a/002-exclaim.patch
+        2 stitches checked: 2 passed, 0 failed
+
+    Print a TAP report:
+
+        $ cargo stitch check --report-format tap
+        TAP version 13
+        1..2
+        ok 1 - stitches/default/crate-a/001-greeting.patch
+        ok 2 - stitches/default/crate-a/002-exclaim.patch
+
+    In CI, write a JUnit report for the stitches changed by a pull request:
+
+        $ cargo stitch check --since origin/main --strict --report-format junit --report-output stitches.xml
+"#,
+    },
+    Topic {
+        name: "check-ordering",
+        summary: "Find adjacent stitches whose order matters",
+        shadows_cargo: false,
+        text: r#"cargo stitch check-ordering <crate> [--exit-code]
+
+Apply the crate's stitches in order, then once more for every pair of adjacent stitches
+with that pair swapped, and report whether the swapped order still applies and gives
+the same result.
+
+OPTIONS
+    --exit-code    Exit non-zero if any pair depends on its order
+
+EXAMPLES
+    `002-exclaim.patch` changes a line written by `001-greeting.patch`:
+
+        $ cargo stitch check-ordering crate-a
+           Dependent 001-greeting.patch <-> 002-exclaim.patch: swapped order fails to apply
+"#,
+    },
+    Topic {
+        name: "check-tools",
+        summary: "Show the external tools cargo-stitch uses and their versions",
+        shadows_cargo: false,
+        text: r#"cargo stitch check-tools [--versions]
+
+Show the version of cargo-stitch, and for each external tool it runs to apply stitches
+(`patch`, `ast-grep`, `sed`, `bash` and `git`) where it was found in `PATH` and whether it is
+recent enough. Exits non-zero if a tool needed by the current stitches is missing or too old.
+`patch` is optional: `.patch` files are applied by a built-in applier, which only falls back
+to `patch` for the patches it cannot apply.
+
+OPTIONS
+    --versions    Also show each tool's full version string and the minimum version
+                  cargo-stitch supports, or the one the stitches set in `[tools]`
+
+EXAMPLES
+    Check the tools needed by the stitches of the workspace:
+
+        $ cargo stitch check-tools
+        cargo-stitch ...
+        ...
+        patch     ok (...)
+
+    Show the versions in detail:
+
+        $ cargo stitch check-tools --versions
+        cargo-stitch ...
+        ...
+        patch     ... (...), minimum 2.0: ok
+"#,
+    },
+    Topic {
+        name: "check-unique-ids",
+        summary: "Check that ast-grep rule ids are unique",
+        shadows_cargo: false,
+        text: r#"cargo stitch check-unique-ids
+
+Read the `id` of every ast-grep rule in the stitch set, across all crates and all YAML
+documents in each file, and report the ids used more than once along with the files
+that use them.  Exits non-zero if there are any.  `cargo stitch lint` runs the same
+check along with its others.
+
+EXAMPLES
+    Check the rules of every crate:
+
+        $ cargo stitch check-unique-ids
+             Checked every ast-grep rule id is unique
+"#,
+    },
+    Topic {
+        name: "clean",
+        summary: "Remove the patched crate sources",
+        shadows_cargo: true,
+        text: r#"cargo stitch clean [--package <name>] [--restore] [--including-history]
+
+Remove `target/cargo-stitch/`, where the patched copies of the crates' sources are kept,
+and report how many crates were removed.  They are created again by the next build.  Does
+nothing if there is nothing to remove.
+
+With any other argument, `cargo stitch clean` runs `cargo clean` as usual.
+
+OPTIONS
+    --package, -p <name>    Only remove the patched copy of crate <name>
+    --restore               Also remove the vendored copies of the crates with stitches
+                            from `vendor/` and run `cargo vendor` again, undoing stitches
+                            applied in place with `no_copy = true` or `cargo stitch vendor`
+    --including-history     Also remove the history of stitch applications that
+                            `cargo stitch log` shows, which is kept otherwise
+
+EXAMPLES
+    Remove every patched crate:
+
+        $ cargo stitch clean
+             Removed 0 patched crates from target/cargo-stitch
+
+    Remove the patched copy of `crate-a` only:
+
+        $ cargo stitch clean --package crate-a
+"#,
+    },
+    Topic {
+        name: "completions",
+        summary: "Print a shell completion script",
+        shadows_cargo: false,
+        text: r#"cargo stitch completions <bash|zsh|fish|elvish|powershell>
+
+Print a script completing cargo-stitch's commands and their options in the given shell.
+The script completes `cargo-stitch stitch ...`; load it from the shell's startup file.
+
+EXAMPLES
+    Complete cargo-stitch in bash:
+
+        $ cargo stitch completions bash > ~/.local/share/bash-completion/completions/cargo-stitch
+
+    Complete cargo-stitch in zsh:
+
+        $ cargo stitch completions zsh > ~/.zfunc/_cargo-stitch
+"#,
+    },
+    Topic {
+        name: "diff",
+        summary: "Show the combined effect of the stitches as a diff",
+        shadows_cargo: false,
+        text: r#"cargo stitch diff [--package <name>]
+
+Apply every crate's stitches to a scratch copy of its source and print a unified diff
+against the original, without building anything.  Paths are relative to the workspace
+root, so the output can be applied there with `patch -p1`.  Requires `diff`.
+
+Like `diff`, exits with 0 if the stitches change nothing, 1 if they change something,
+and 2 if something went wrong, e.g. a stitch does not apply.
+
+OPTIONS
+    --package, -p <name>    Only show the changes to crate <name>
+
+EXAMPLES
+    Preview the changes made to `crate-a`:
+
+        $ cargo stitch diff --package crate-a
+        diff -ruN a/crate-a/src/lib.rs b/crate-a/src/lib.rs
+        ...
+        -    "hello"
+        +    "patched!"
+"#,
+    },
+    Topic {
+        name: "diff-snapshot",
+        summary: "Compare the patched crates with a snapshot of them",
+        shadows_cargo: false,
+        text: r#"cargo stitch diff-snapshot <label>
+
+Run `diff -ru` between `target/cargo-stitch-snapshots/<label>/`, saved by
+`cargo stitch snapshot --name <label>`, and the patched crates in `target/cargo-stitch/`,
+to see how the patched sources changed since.  Hidden files, which include the state
+cargo-stitch keeps in each patched crate, are left out.  Exits non-zero if they differ.
+
+EXAMPLES
+    Save the patched crates, change a stitch, build, and see what changed:
+
+        $ cargo stitch snapshot --name before
+        $ cargo stitch build
+        $ cargo stitch diff-snapshot before
+"#,
+    },
+    Topic {
+        name: "env",
+        summary: "Print the environment variables set for cargo",
+        shadows_cargo: false,
+        text: r#"cargo stitch env [--export]
+
+Print the environment variables that `cargo stitch <command>` sets when it runs cargo,
+as `KEY=VALUE` lines: the rustc wrapper, and the variables that tell it where the
+workspace and the stitch manifest are.  Writes the stitch manifest they refer to, so
+that the variables can be used to run cargo directly.
+
+OPTIONS
+    --export    Prefix each line with `export ` and quote values for the shell
+
+EXAMPLES
+    Show the variables for a build of the workspace:
+
+        $ cargo stitch env
+        RUSTC_WORKSPACE_WRAPPER=...cargo-stitch
+        __CARGO_STITCH_WRAP=1
+        __CARGO_STITCH_WORKSPACE_ROOT=...
+        __CARGO_STITCH_MANIFEST=...target/cargo-stitch/.manifest-....json
+
+    Build with them in a shell script, without cargo-stitch:
+
+        $ eval "$(cargo stitch env --export)"
+"#,
+    },
+    Topic {
+        name: "export",
+        summary: "Copy a crate's patched sources to another directory",
+        shadows_cargo: false,
+        text: r#"cargo stitch export <crate> <output-dir> [--overwrite] [--build]
+
+Copy the patched copy of `<crate>` in `target/cargo-stitch/<crate>/` to `<output-dir>`,
+e.g. to send the stitched sources for review or open a pull request upstream.  The
+files a build leaves there to track the stitches are left out.  Fails if there is no
+patched copy yet; run `cargo stitch build` first.
+
+OPTIONS
+    --overwrite     Replace <output-dir> if it already exists
+    --build         Run `cargo stitch build --package <crate>` first
+
+EXAMPLES
+    Build crate-a and export its stitched sources:
+
+        $ cargo stitch export crate-a /tmp/crate-a --build
+"#,
+    },
+    Topic {
+        name: "fetch",
+        summary: "Unpack a crate's published source for inspection",
+        shadows_cargo: true,
+        text: r#"cargo stitch fetch <crate> <version>
+
+Unpack the source of `<crate>` v`<version>` into
+`target/cargo-stitch/.fetch/<crate>-<version>/` and print its path, to look at before
+writing stitches for it.  The source is taken from cargo's registry cache in
+`$CARGO_HOME/registry` if it is there, and downloaded from crates.io otherwise, which
+requires `curl` and `tar`.  A crate that has already been fetched is not fetched again.
+
+Without a crate and a version, `cargo stitch fetch` runs `cargo fetch` as usual.
+
+EXAMPLES
+    Unpack serde 1.0.195 and print where it went:
+
+        $ cargo stitch fetch serde 1.0.195
+"#,
+    },
+    Topic {
+        name: "fetch-stitches",
+        summary: "Download a crate's stitches from a registry",
+        shadows_cargo: false,
+        text: r#"cargo stitch fetch-stitches <crate> <version> --registry <url>
+
+Download `<url>/<crate>/<version>.tar.gz` and unpack it into `stitches/<set>/<crate>/`,
+which must not already contain stitches.  Requires `tar` and `curl`.
+
+If `CARGO_STITCH_REGISTRY_TOKEN` is set, it is sent as a bearer token.
+
+EXAMPLES
+    Fetch the stitches published for version 0.1.0 of `crate-a`:
+
+        $ cargo stitch fetch-stitches crate-a 0.1.0 --registry https://stitches.example.com
+"#,
+    },
+    Topic {
+        name: "fmt",
+        summary: "Normalize the formatting of a crate's patch files",
+        shadows_cargo: true,
+        text: r#"cargo stitch [--set <name>] fmt <crate>... [--check]
+
+Rewrite each `.patch` file in `stitches/<set>/<crate>/` in place, normalized: `\n` line
+endings, no trailing whitespace, no timestamps in the `---` and `+++` headers, no blank
+lines at the end, and a final newline.  Multi-file patches are handled, and so are patches
+of files with `\r\n` line endings, whose `\r`s are kept.
+
+When a <crate> has no stitches, `cargo stitch fmt` runs `cargo fmt` as usual.
+
+OPTIONS
+    --check    Change nothing, but list the files that are not formatted and exit
+               with a non-zero status if there are any, e.g. in CI
+
+EXAMPLES
+    Check that the patches of `crate-a` are formatted:
+
+        $ cargo stitch fmt crate-a --check
+"#,
+    },
+    Topic {
+        name: "init",
+        summary: "Start stitching a crate",
+        shadows_cargo: true,
+        text: r#"cargo stitch [--set <name>] init <crate> [--force]
+
+Create `stitches/<set>/<crate>/` for the workspace member <crate>, with a README.md
+describing how stitch files are named and applied, and an example patch,
+`001-example.patch.sample`, which is applied once renamed to `.patch`.  Refuses to touch
+an existing directory without `--force`.
+
+When <crate> is not a workspace member, `cargo stitch init` runs `cargo init` as usual.
+
+OPTIONS
+    --force, -f    Write the README and example into an existing directory, overwriting
+                   files of the same name; other stitches are left alone
+
+EXAMPLES
+    Start stitching `crate-b`:
+
+        $ cargo stitch init crate-b
+             Created stitches/default/crate-b/README.md
+             Created stitches/default/crate-b/001-example.patch.sample
+"#,
+    },
+    Topic {
+        name: "install",
+        summary: "Install a local build of cargo-stitch",
+        shadows_cargo: true,
+        text: r#"cargo stitch install [--release]
+
+When run from the cargo-stitch source tree, copy the cargo-stitch binary built there to
+`$CARGO_HOME/bin` (`~/.cargo/bin` by default).  Anywhere else, `cargo stitch install`
+runs `cargo install` as usual.
+
+OPTIONS
+    --release, -r    Install the release build, rebuilding it first if it is stale
+
+EXAMPLES
+    Install the debug build from `cargo build`:
+
+        $ cargo stitch install
+
+    Build and install the release build:
+
+        $ cargo stitch install --release
+"#,
+    },
+    Topic {
+        name: "lint",
+        summary: "Check stitch files for common problems",
+        shadows_cargo: false,
+        text: r#"cargo stitch lint [--fix]
+
+Check stitch files for common problems: names without a `NNN-` prefix, duplicate
+numbers, files that are not UTF-8, ast-grep rules without an `id`, a `language` or a
+`rule`, TOML rules that do not parse, or rules with an `id` used elsewhere (see
+`check-unique-ids`), patches without a `---`
+and a `+++` header for each file, trailing whitespace in patches, patches that change
+the same lines as an earlier patch made against the same sources, and sed scripts that
+`sed` cannot parse.  Every problem is reported, and the command fails if any is left.
+Prints nothing if every stitch file is fine.
+
+OPTIONS
+    --fix    Fix the problems that are safe to fix automatically: names, missing rule
+             ids, missing `---` headers and trailing whitespace
+
+EXAMPLES
+    Lint every stitch file, fixing what can be fixed:
+
+        $ cargo stitch lint --fix
+"#,
+    },
+    Topic {
+        name: "list",
+        summary: "List every stitch file and the crate it applies to",
+        shadows_cargo: false,
+        text: r#"cargo stitch list [--json | --checksums] [--verbose]
+
+Print one line per stitch file: the crate it applies to, its position in the order the
+crate's stitches are applied, its kind (`patch` or `sg-rule`) and its file name.  Crates
+are listed by name, and each crate's stitches in application order, which takes any
+`[order]` in its `stitch.toml` into account.
+
+OPTIONS
+    --json          Print the stitches discovered for each crate as JSON instead, with
+                    the front matter of each patch as its `meta`
+    --checksums     Print the SHA-256 of each stitch file instead, under a `# <crate>`
+                    line, in the format of `stitches/<set>/<crate>/.checksums`
+    -v, --verbose   Print the `description` from each patch's front matter under it
+
+EXAMPLES
+    List the stitches of the workspace:
+
+        $ cargo stitch list
+        crate-a  1  patch  001-greeting.patch
+        crate-a  2  patch  002-exclaim.patch
+"#,
+    },
+    Topic {
+        name: "log",
+        summary: "Show when stitches were applied to each crate",
+        shadows_cargo: false,
+        text: r#"cargo stitch log [--package <name>] [--json]
+
+Print one line per time the stitches of a crate were applied to its patched copy, most
+recent first: when, in UTC, the crate, and how many stitches were applied.  Builds keep
+these records in `target/cargo-stitch/.history/`, which `cargo stitch clean` leaves
+alone unless given `--including-history`.
+
+OPTIONS
+    --package, -p <name>    Only show the history of crate <name>
+    --json                  Print the records as JSON instead, with the hashes of the
+                            sources and stitch files they were applied to
+
+EXAMPLES
+    Show when the stitches of `crate-a` were applied:
+
+        $ cargo stitch log --package crate-a
+"#,
+    },
+    Topic {
+        name: "new",
+        summary: "Create the next numbered stitch file for a crate",
+        shadows_cargo: true,
+        text: r#"cargo stitch [--set <name>] new <crate> <name> [--type patch|sg] [--with-metadata]
+
+Create `stitches/<set>/<crate>/<NNN>-<name>.patch` for the workspace member <crate>,
+numbered after its last stitch, with <name> in lowercase words joined by `-`.  The file
+holds comments saying what to replace them with, and changes nothing until then.  An
+existing file is never overwritten.
+
+When <crate> is not a workspace member, `cargo stitch new` runs `cargo new` as usual.
+
+OPTIONS
+    --type <kind>      `patch` (the default), or `sg` for an ast-grep rule,
+                       `<NNN>-<name>.yaml`
+    --with-metadata    Start the patch with front matter to fill in: `id`,
+                       `description`, `author`, `issue_url` and `upstream_pr`
+
+EXAMPLES
+    Add a patch after the two stitches of `crate-a`:
+
+        $ cargo stitch new crate-a fix-overflow
+             Created stitches/default/crate-a/003-fix-overflow.patch
+"#,
+    },
+    Topic {
+        name: "publish-stitches",
+        summary: "Upload a crate's stitches to a registry",
+        shadows_cargo: false,
+        text: r#"cargo stitch publish-stitches <crate> --registry <url>
+
+Upload `stitches/<set>/<crate>/` as a tarball to `<url>/<crate>/<version>.tar.gz`, where
+<version> is the crate's version, with an HTTP `PUT`.  Requires `tar` and `curl`.
+
+If `CARGO_STITCH_REGISTRY_TOKEN` is set, it is sent as a bearer token.
+
+EXAMPLES
+    Publish the stitches of `crate-a`:
+
+        $ cargo stitch publish-stitches crate-a --registry https://stitches.example.com
+"#,
+    },
+    Topic {
+        name: "reorder",
+        summary: "Renumber a stitch file",
+        shadows_cargo: false,
+        text: r#"cargo stitch reorder <crate> <old-number> <new-number>
+
+Give the stitch numbered <old-number> the number <new-number>, shifting any stitch
+already at that number (and so on, until there is a gap) up by one.  Not available for
+crates with an explicit `[order]` in their `stitch.toml`.
+
+EXAMPLES
+    Move `002-exclaim.patch` first:
+
+        $ cargo stitch reorder crate-a 2 1
+             Renamed 002-exclaim.patch -> 001-exclaim.patch
+             Renamed 001-greeting.patch -> 002-greeting.patch
+
+    Renumbering a stitch to its own number does nothing:
+
+        $ cargo stitch reorder crate-a 1 1
+               Fresh no change needed
+"#,
+    },
+    Topic {
+        name: "reset",
+        summary: "Remove the patched crate sources and Cargo's build of them",
+        shadows_cargo: false,
+        text: r#"cargo stitch reset (--package <name> | --all) [--yes]
+
+Remove the patched copy of crate <name>, or of every crate with one, from
+`target/cargo-stitch/`, as `cargo stitch clean` does, and run `cargo clean --package` for
+each of them, so that the next build stitches and compiles them again rather than
+reusing what Cargo built from the patched sources.  Asks for confirmation first.
+
+The patched copy of a crates.io dependency, `serde-1.0.200` say, is found by the crate's
+name too, and cleaned with `cargo clean --package serde@1.0.200`.
+
+OPTIONS
+    --package, -p <name>    Reset crate <name>, whether or not it has a patched copy
+    --all                   Reset every crate with a patched copy
+    --yes, -y               Do not ask for confirmation
+
+EXAMPLES
+    Reset every patched crate without asking:
+
+        $ cargo stitch reset --all --yes
+               Reset 0 patched crates
+
+    Reset `crate-a` only:
+
+        $ cargo stitch reset --package crate-a
+"#,
+    },
+    Topic {
+        name: "snapshot",
+        summary: "Save the stitched source of a crate, or check it is unchanged",
+        shadows_cargo: false,
+        text: r#"cargo stitch snapshot [--check] <crate>
+cargo stitch snapshot [--name <label>]
+
+Apply the crate's stitches to a scratch copy of its source and save the resulting tree
+to `stitches/<set>/<crate>/.snapshots/`, replacing any previous snapshot.  Commit the
+snapshot with the stitches: with `--check`, the result is compared against it instead,
+so the stitches can be reworked with confidence that they still produce the same source.
+
+Without a crate, copy every patched crate in `target/cargo-stitch/` to
+`target/cargo-stitch-snapshots/<label>/` instead, for `cargo stitch diff-snapshot
+<label>` to compare the patched crates against later, e.g. while working on a stitch.
+
+OPTIONS
+    --check           Compare against the saved snapshot instead of saving it, listing
+                      the files that differ; exits non-zero if any do or there is no
+                      snapshot
+    --name <label>    The name of the snapshot of every patched crate; the current Unix
+                      time by default
+
+EXAMPLES
+    Save a snapshot of what the stitches of `crate-a` produce:
+
+        $ cargo stitch snapshot crate-a
+               Saved crate-a: snapshot in stitches/default/crate-a/.snapshots
+
+    Check that the stitches still produce it:
+
+        $ cargo stitch snapshot --check crate-a
+               Fresh crate-a: stitches produce the snapshot in stitches/default/crate-a/.snapshots
+"#,
+    },
+    Topic {
+        name: "status",
+        summary: "Show which crates have an up-to-date patched copy",
+        shadows_cargo: false,
+        text: r#"cargo stitch status [--json]
+
+Print one line per crate with stitches or a patched copy in `target/cargo-stitch/`,
+without building anything, in the style of `git status --short`:
+
+    ✓  the patched copy was made from the current sources and stitches
+    M  the sources or stitches changed since: the next build re-applies them
+    ?  there is no patched copy yet
+    !  there is a patched copy, but the crate no longer has any stitches
+
+Each line also says when the patched copy was last updated.
+
+OPTIONS
+    --json    Print the status of each crate as JSON instead, with the time it was
+              last updated in seconds since the Unix epoch
+
+EXAMPLES
+    Show the status of a workspace that has not been built yet:
+
+        $ cargo stitch status
+        ? crate-a  not built yet
+"#,
+    },
+    Topic {
+        name: "summarize",
+        summary: "Show the health of every patched crate",
+        shadows_cargo: false,
+        text: r#"cargo stitch summarize [--exit-code]
+
+Show a table of the crates with stitches, with their version, number of stitches and
+status: `ok`, `outdated` (the stitches apply but the crate needs re-patching), `failing`
+(a stitch no longer applies) or `orphaned` (no workspace member has that name).
+
+OPTIONS
+    --exit-code    Exit non-zero unless every crate is `ok`
+
+EXAMPLES
+    Before the first build, the patched copy of `crate-a` is missing:
+
+        $ cargo stitch summarize
+        Crate    Version  Stitches  Status
+        crate-a  0.1.0           2  outdated
+
+    After building, it is up to date:
+
+        $ cargo stitch build
+        ...
+        $ cargo stitch summarize --exit-code
+        Crate    Version  Stitches  Status
+        crate-a  0.1.0           2  ok
+"#,
+    },
+    Topic {
+        name: "test",
+        summary: "Run the tests of the workspace with its stitches applied",
+        shadows_cargo: true,
+        text: r#"cargo stitch [--set <name>] [--external] [-q|-v] test [<cargo test args>...]
+
+Run `cargo test` against the patched copies of the crates with stitches, as
+`cargo stitch build` builds them.  Every target of a stitched crate is compiled from
+`target/cargo-stitch/<crate>/`: unit tests in `src/`, and the targets in `tests/`,
+`benches/` and `examples/`, so a patch may fix or change the crate's tests too.
+
+The options before `test` are those of `cargo stitch build`; see
+`cargo stitch help build`.  The arguments after it are passed on to `cargo test`
+unchanged.
+
+EXAMPLES
+    Run the tests of crate-a, including those in `benches/`, with its stitches:
+
+        $ cargo stitch test -p crate-a --all-targets
+"#,
+    },
+    Topic {
+        name: "trace",
+        summary: "Show the diff introduced by each stitch",
+        shadows_cargo: false,
+        text: r#"cargo stitch trace <crate>
+
+Apply the crate's stitches one at a time to a scratch copy of its source, and print the
+diff introduced by each step.  Requires `diff`.
+
+EXAMPLES
+    Trace the stitches of `crate-a`:
+
+        $ cargo stitch trace crate-a
+        ==> [1/2] 001-greeting.patch
+        ...
+        -    "hello"
+        +    "patched"
+        ...
+        ==> [2/2] 002-exclaim.patch
+        ...
+        -    "patched"
+        +    "patched!"
+"#,
+    },
+    Topic {
+        name: "uninstall",
+        summary: "Remove all stitches for a crate",
+        shadows_cargo: true,
+        text: r#"cargo stitch [--keep-patched] uninstall <crate> [--yes]
+
+Delete `stitches/<set>/<crate>/` and the crate's patched copy in
+`target/cargo-stitch/<crate>/`, e.g. once the crate includes the fix a stitch was
+working around.  Asks for confirmation first.
+
+When <crate> has no stitches, `cargo stitch uninstall` runs `cargo uninstall` as usual.
+
+OPTIONS
+    --yes, -y         Do not ask for confirmation
+    --keep-patched    Keep the patched copy for inspection
+
+EXAMPLES
+    Remove the stitches of `crate-a` without asking:
+
+        $ cargo stitch uninstall crate-a --yes
+             Removed stitches/default/crate-a
+"#,
+    },
+    Topic {
+        name: "upgrade",
+        summary: "Check a crate's stitches against a newer version of it",
+        shadows_cargo: true,
+        text: r#"cargo stitch upgrade <crate> [<version>] [--apply]
+
+Fetch the published source of <crate> v<version>, as `cargo stitch fetch` does, and
+apply the crate's stitches to a scratch copy of it one at a time, to see which still
+apply after a dependency upgrade.  A stitch that is rejected is skipped, and listed with
+the hunks that did not apply.  Exits non-zero if any stitch is rejected.  Stitch files
+are never changed.
+
+Without <version>, the version of <crate> in `Cargo.lock` is used, e.g. the one
+`cargo update` just picked.
+
+When <crate> has no stitches, `cargo stitch upgrade` runs `cargo upgrade` as usual.
+
+OPTIONS
+    --apply    Keep the new version with the stitches that apply in
+               `target/cargo-stitch/.upgrade/<crate>-<version>/`
+
+EXAMPLES
+    Check the stitches of serde against the version in Cargo.lock:
+
+        $ cargo stitch upgrade serde
+
+    Apply the stitches that still apply to serde 1.0.200:
+
+        $ cargo stitch upgrade serde 1.0.200 --apply
+"#,
+    },
+    Topic {
+        name: "vendor",
+        summary: "Vendor dependencies and stitch the vendored copies in place",
+        shadows_cargo: true,
+        text: r#"cargo stitch vendor [<cargo vendor args>...]
+
+Run `cargo vendor`, then apply the stitches of every vendored crate that has some
+directly to its copy in the vendor directory, updating `.cargo-checksum.json` so that
+cargo still accepts it.  Builds then use the stitched sources as they are.
+
+Without `cargo stitch vendor`, `cargo stitch build` stitches crates vendored to
+`vendor/` like workspace members, in a patched copy under `target/cargo-stitch/`.
+
+OPTIONS
+    <cargo vendor args>...    Passed on to `cargo vendor` unchanged
+
+EXAMPLES
+    Vendor the dependencies of the workspace to `vendor/` and stitch them:
+
+        $ cargo stitch vendor
+"#,
+    },
+    Topic {
+        name: "verify",
+        summary: "Check that every stitch still applies, without building",
+        shadows_cargo: false,
+        text: r#"cargo stitch verify [--package <crate>]...
+
+Apply each crate's stitches in order to a scratch copy of its current source, and list
+the ones that no longer apply with their crate and the hunks that were rejected.  Meant
+for after updating a dependency; nothing is built.  Exits with 1 if any stitch fails.
+
+OPTIONS
+    -p, --package <crate>    Only verify the stitches of <crate>; may be repeated
+
+EXAMPLES
+    Verify the stitches of every crate:
+
+        $ cargo stitch verify
+        ok      crate-a  001-greeting.patch
+        ok      crate-a  002-exclaim.patch
+        2 stitches verified: 2 apply, 0 rejected
+"#,
+    },
+    Topic {
+        name: "verify-applied",
+        summary: "Check the patched copy of a crate against its stitches",
+        shadows_cargo: false,
+        text: r#"cargo stitch verify-applied <crate>
+
+Apply the crate's stitches to a fresh copy of its source and compare the result byte
+for byte with `target/cargo-stitch/<crate>/`.  Lists the differing files and exits
+non-zero if they don't match.
+
+EXAMPLES
+    The patched copy only exists once the crate has been built:
+
+        $ cargo stitch verify-applied crate-a
+               Error crate-a: ... does not exist; build first
+        $ cargo stitch build
+        ...
+        $ cargo stitch verify-applied crate-a
+            Verified crate-a: patched directory matches its stitches
+"#,
+    },
+];
+
+fn topic(name: &str) -> Option<&'static Topic> {
+    TOPICS.iter().find(|topic| topic.name == name)
+}
+
+/// The name and summary of every command with a help topic.
+pub(super) fn summaries() -> impl Iterator<Item = (&'static str, &'static str)> {
+    TOPICS.iter().map(|topic| (topic.name, topic.summary))
+}
+
+/// Returns `true` if `args` (following `help`) ask for one of our topics, or for none
+/// at all.  `cargo stitch help <other>` is forwarded to `cargo help <other>`.
+pub(super) fn has_topic(args: &[String]) -> bool {
+    args.first().is_none_or(|name| topic(name).is_some())
+}
+
+/// Returns `true` if `args` (following `command`) ask for the help of one of our own
+/// commands with `--help` or `-h`.
+pub(super) fn wants_help(command: &str, args: &[String]) -> bool {
+    topic(command).is_some_and(|topic| !topic.shadows_cargo)
+        && args.iter().any(|a| a == "--help" || a == "-h")
+}
+
+pub(super) fn run(args: &[String]) -> Result<ExitCode, SubcommandError> {
+    match args {
+        [] => print!("{}", overview()),
+        [name] => match topic(name) {
+            Some(topic) => print!("{}", topic.text),
+            None => {
+                return Err(OneOf::new(usage_error(format!(
+                    "no help for `{name}`; see `cargo stitch help`"
+                ))));
+            }
+        },
+        _ => {
+            return Err(OneOf::new(usage_error(
+                "usage: cargo stitch help [<command>]",
+            )));
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn overview() -> String {
+    let width = TOPICS
+        .iter()
+        .map(|t| t.name.len())
+        .max()
+        .unwrap_or_default();
+    let mut out = String::from(
+        "Usage: cargo stitch [--set <name>] [--keep-patched] [--external] [--json] [-q|-v] [--color <when>] <command> [<args>...]\n\nCommands:\n",
+    );
+    for topic in TOPICS {
+        out.push_str(&format!("    {:width$}    {}\n", topic.name, topic.summary));
+    }
+    out.push_str(
+        "\nAny other command is passed on to cargo with the stitches applied.\n\
+         See `cargo stitch help <command>` for details and examples.\n",
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_start_with_their_usage() {
+        for topic in TOPICS {
+            assert!(
+                topic.text.starts_with("cargo stitch ") && topic.text.contains(topic.name),
+                "help for `{}` should start with its usage line",
+                topic.name
+            );
+            assert!(
+                topic.text.contains("\nEXAMPLES\n"),
+                "help for `{}` should have examples",
+                topic.name
+            );
+        }
+    }
+
+    #[test]
+    fn topics_are_sorted() {
+        let names: Vec<_> = TOPICS.iter().map(|t| t.name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn has_topic_for_known_commands_only() {
+        assert!(has_topic(&[]));
+        assert!(has_topic(&["trace".to_string()]));
+        assert!(!has_topic(&["doc".to_string()]));
+    }
+
+    #[test]
+    fn wants_help_leaves_cargo_commands_alone() {
+        let help = ["--help".to_string()];
+        assert!(wants_help("trace", &help));
+        assert!(!wants_help("build", &help));
+        assert!(!wants_help("trace", &["crate-a".to_string()]));
+    }
+
+    #[test]
+    fn overview_lists_every_topic() {
+        let overview = overview();
+        for topic in TOPICS {
+            assert!(overview.contains(topic.summary));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! `cargo stitch init <crate> [--force]`: create `stitches/<set>/<crate>/` with a README
+//! describing the layout and an example patch to start from.
+//!
+//! This only takes over `init` when `<crate>` is a workspace member; anything else is
+//! forwarded to `cargo init` as usual.
+
+use std::fs;
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+use crate::stitch::cargo_status;
+
+/// The example patch, named so that it is not applied until it is renamed to `.patch`.
+const EXAMPLE_PATCH: &str = "001-example.patch.sample";
+
+const README: &str = r#"# Stitches for `{krate}`
+
+cargo-stitch applies the files in this directory to a copy of `{krate}`'s sources in
+`target/cargo-stitch/{krate}/` before compiling it.  The sources themselves are never
+modified.
+
+## Naming
+
+Files are applied in filename order, so prefix them with a number:
+`001-fix-overflow.patch`, `002-rename-api.yaml`, ...  Other files, like this README, are
+ignored.
+
+- `.patch`: a unified diff, as `git diff` or `diff -u` write it, with paths relative to
+  the crate root and an `a/` and `b/` prefix (see `001-example.patch.sample`).
+- `.yaml` or `.yml`: an ast-grep rule, applied with `ast-grep scan --update-all`.
+- `.sed`: a `sed -E` script, run over every `.rs` file of the crate.
+- `.sh`: a bash script, run in the crate's patched copy.
+
+A `stitch.toml` here can change the order, or how patches are applied; see the
+cargo-stitch README.
+
+## Next steps
+
+1. Rename `001-example.patch.sample` to `001-<what-it-does>.patch` and replace its
+   contents with your change, e.g. the output of `git diff --relative` run in the
+   crate's directory.
+2. Run `cargo stitch diff --package {krate}` to check what the stitches change.
+3. Run `cargo stitch build`.
+"#;
+
+const EXAMPLE: &str = r#"# Rename this file to `001-<what-it-does>.patch` for cargo-stitch to apply it.
+# Lines starting with `#` before the diff are ignored.
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn example() -> u32 {
+-    1
++    2
+ }
+"#;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    force: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut krate = None;
+        let mut force = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--force" | "-f" => force = true,
+                other if other.starts_with('-') || krate.is_some() => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `init`: {other}"
+                    )));
+                }
+                other => krate = Some(other.to_string()),
+            }
+        }
+
+        let krate =
+            krate.ok_or_else(|| usage_error("usage: cargo stitch init <crate> [--force]"))?;
+        Ok(Self { krate, force })
+    }
+}
+
+/// Returns `true` if `args` (following `init`) name a workspace member, rather than a
+/// path for `cargo init`.
+pub(super) fn names_member(workspace: &Workspace, args: &[String]) -> bool {
+    Args::parse(args).is_ok_and(|args| workspace.member_dir(&args.krate).is_ok())
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    workspace.member_dir(&args.krate).map_err(OneOf::new)?;
+
+    let dir = workspace.stitches_dir.join(&args.krate);
+    let display = |name: &str| {
+        let path = dir.join(name);
+        path.strip_prefix(&workspace.root)
+            .unwrap_or(&path)
+            .to_string()
+    };
+
+    if dir.exists() && !args.force {
+        return Err(OneOf::new(usage_error(format!(
+            "{} already exists; pass `--force` to write the README and example into it anyway",
+            display("")
+        ))));
+    }
+
+    fs::create_dir_all(&dir).map_err(io)?;
+    let files = [
+        ("README.md", README.replace("{krate}", &args.krate)),
+        (EXAMPLE_PATCH, EXAMPLE.to_string()),
+    ];
+    for (name, contents) in files {
+        fs::write(dir.join(name), contents).map_err(io)?;
+        cargo_status(&workspace.output, "Created", &display(name));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_crate_and_force() {
+        let args = Args::parse(&["crate-a".to_string(), "--force".to_string()]).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                krate: "crate-a".to_string(),
+                force: true
+            }
+        );
+        assert!(!Args::parse(&["crate-a".to_string()]).unwrap().force);
+        assert!(Args::parse(&[]).is_err());
+        assert!(Args::parse(&["a".to_string(), "b".to_string()]).is_err());
+        assert!(Args::parse(&["--vcs".to_string(), "git".to_string()]).is_err());
+    }
+
+    #[test]
+    fn example_is_not_a_stitch_until_renamed() {
+        use camino::Utf8PathBuf;
+
+        use crate::stitch::Stitch;
+
+        assert!(Stitch::from_path(Utf8PathBuf::from(EXAMPLE_PATCH)).is_none());
+        assert!(Stitch::from_path(Utf8PathBuf::from("001-example.patch")).is_some());
+    }
+}
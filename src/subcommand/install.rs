@@ -0,0 +1,142 @@
+//! `cargo stitch install`: copy a locally built cargo-stitch into `$CARGO_HOME/bin`.
+//!
+//! This only takes over `install` when run from within the cargo-stitch source tree;
+//! anywhere else, `cargo stitch install` is forwarded to `cargo install` as usual.
+
+use std::fs;
+use std::io;
+use std::process::{Command, ExitCode};
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::{CargoFailed, IoError};
+use crate::fs::{any_file_newer_than, cargo_home, workspace_packages};
+use crate::stitch::cargo_status;
+
+const BIN_NAME: &str = "cargo-stitch";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    release: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut release = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--release" | "-r" => release = true,
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `install`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { release })
+    }
+}
+
+/// Returns `true` if the workspace is the cargo-stitch source tree itself.
+pub(super) fn is_self_source(workspace: &Workspace) -> bool {
+    workspace_packages(&workspace.root)
+        .is_ok_and(|packages| packages.iter().any(|p| p.name.as_str() == BIN_NAME))
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let profile = if args.release { "release" } else { "debug" };
+    let binary = workspace.target_dir.join(profile).join(BIN_NAME);
+
+    if args.release && !is_fresh(&binary, &workspace.root) {
+        let status = Command::new("cargo")
+            .args(["build", "--release", "--bin", BIN_NAME])
+            .current_dir(&workspace.root)
+            .status()
+            .map_err(io)?;
+        if !status.success() {
+            return Err(OneOf::new(CargoFailed(status.code().unwrap_or(1))));
+        }
+    }
+
+    if !binary.is_file() {
+        return Err(OneOf::new(usage_error(format!(
+            "{binary} does not exist; run `cargo build` first or pass `--release`"
+        ))));
+    }
+
+    let dest_dir = cargo_home().map_err(io)?.join("bin");
+    let dest = dest_dir.join(BIN_NAME);
+
+    if fs::read(&dest).ok() == Some(fs::read(&binary).map_err(io)?) {
+        cargo_status(
+            &workspace.output,
+            "Fresh",
+            &format!("{dest} is already up to date"),
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    install_file(&binary, &dest_dir, &dest).map_err(io)?;
+    cargo_status(
+        &workspace.output,
+        "Installed",
+        &format!("{binary} to {dest}"),
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Returns `true` if `binary` exists and is newer than every source file in `root`.
+fn is_fresh(binary: &Utf8Path, root: &Utf8Path) -> bool {
+    fs::metadata(binary)
+        .and_then(|m| m.modified())
+        .is_ok_and(|mtime| !any_file_newer_than(root, mtime))
+}
+
+/// Copy `src` to `dest` via a temporary file in `dest_dir`, so that replacing a binary
+/// that is currently running (e.g. the installed cargo-stitch itself) works.
+fn install_file(src: &Utf8Path, dest_dir: &Utf8Path, dest: &Utf8Path) -> io::Result<()> {
+    fs::create_dir_all(dest_dir)?;
+    let tmp = dest_dir.join(format!(".{BIN_NAME}.{}", std::process::id()));
+    fs::copy(src, &tmp)?;
+    fs::rename(&tmp, dest).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_release() {
+        assert!(!Args::parse(&[]).unwrap().release);
+        assert!(Args::parse(&["--release".to_string()]).unwrap().release);
+        assert!(Args::parse(&["--path".to_string()]).is_err());
+    }
+
+    #[test]
+    fn install_file_replaces_existing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = Utf8Path::from_path(tmp.path()).unwrap();
+        let src = base.join("built");
+        let dest_dir = base.join("bin");
+        let dest = dest_dir.join(BIN_NAME);
+
+        fs::write(&src, "new").unwrap();
+        fs::create_dir_all(&dest_dir).unwrap();
+        fs::write(&dest, "old").unwrap();
+
+        install_file(&src, &dest_dir, &dest).unwrap();
+
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dest_dir).unwrap().count(), 1);
+    }
+}
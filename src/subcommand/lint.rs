@@ -0,0 +1,912 @@
+//! `cargo stitch lint`: validate stitch files, optionally fixing the issues that are safe
+//! to fix automatically.
+//!
+//! Also `cargo stitch check-unique-ids`, which only runs the check that ast-grep rule ids
+//! are unique across all crates.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::{Command, ExitCode};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::config::{CONFIG_FILE, StitchConfig};
+use crate::error::IoError;
+use crate::output::{OutputContext, Verbosity};
+use crate::stitch::{
+    ORDER_FILE, Stitch, StitchConflict, StitchSet, cargo_status, sed_has_sandbox,
+    split_front_matter, split_mail_header, stitch_dir_files,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    fix: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut fix = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--fix" => fix = true,
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `lint`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { fix })
+    }
+}
+
+/// A problem found in a stitch file.
+#[derive(Debug, PartialEq, Eq)]
+struct Issue {
+    file: Utf8PathBuf,
+    message: String,
+    /// How to fix the issue, if it can be fixed without human judgment.
+    fix: Option<Fix>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Fix {
+    /// Rename the file to the given name, in the same directory.
+    Rename(String),
+    /// Insert an `id: <id>` line (`id = "<id>"` in TOML) at the top of an ast-grep rule.
+    InsertId(String),
+    /// Strip trailing whitespace and make sure the patch ends with a newline.
+    NormalizePatch,
+    /// Insert the `--- a/<path>` header missing before each `+++ b/<path>` one.
+    InsertOldHeaders,
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let pkg_dirs = pkg_dirs(workspace).map_err(io)?;
+
+    let mut issues = Vec::new();
+    for pkg_dir in &pkg_dirs {
+        issues.extend(lint_dir(pkg_dir, &workspace.output).map_err(OneOf::broaden)?);
+    }
+    issues.extend(duplicate_rule_ids(&workspace.stitches_dir, &pkg_dirs).map_err(io)?);
+    issues.extend(patch_conflicts(workspace));
+
+    let mut remaining = 0;
+    for issue in issues {
+        match issue.fix {
+            Some(fix) if args.fix => {
+                apply_fix(&issue.file, &fix).map_err(io)?;
+                cargo_status(
+                    &workspace.output,
+                    "Fixed",
+                    &format!("{}: {}", issue.file, issue.message),
+                );
+            }
+            _ => {
+                remaining += 1;
+                cargo_status(
+                    &workspace.output,
+                    "Warning",
+                    &format!("{}: {}", issue.file, issue.message),
+                );
+            }
+        }
+    }
+
+    if remaining > 0 {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+pub(super) fn run_check_unique_ids(
+    workspace: &Workspace,
+    args: &[String],
+) -> Result<ExitCode, SubcommandError> {
+    if let Some(arg) = args.first() {
+        return Err(OneOf::new(usage_error(format!(
+            "unexpected argument for `check-unique-ids`: {arg}"
+        ))));
+    }
+    let io = |e| OneOf::new(IoError(e));
+
+    let pkg_dirs = pkg_dirs(workspace).map_err(io)?;
+    let issues = duplicate_rule_ids(&workspace.stitches_dir, &pkg_dirs).map_err(io)?;
+
+    if issues.is_empty() {
+        cargo_status(
+            &workspace.output,
+            "Checked",
+            "every ast-grep rule id is unique",
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    for issue in issues {
+        cargo_status(
+            &workspace.output,
+            "Warning",
+            &format!("{}: {}", issue.file, issue.message),
+        );
+    }
+    Ok(ExitCode::FAILURE)
+}
+
+/// The per-crate directories of the current stitch set, sorted.
+fn pkg_dirs(workspace: &Workspace) -> std::io::Result<Vec<Utf8PathBuf>> {
+    let mut pkg_dirs = Vec::new();
+    if workspace.stitches_dir.is_dir() {
+        for entry in workspace.stitches_dir.read_dir_utf8()? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pkg_dirs.push(entry.into_path());
+            }
+        }
+    }
+    pkg_dirs.sort();
+    Ok(pkg_dirs)
+}
+
+/// The patches that change the same lines as an earlier patch of the same crate.  Problems
+/// with the stitch sets themselves are reported by [`lint_dir`], and so are left out.
+fn patch_conflicts(workspace: &Workspace) -> Vec<Issue> {
+    // Quietly, since `lint_dir` already warns about the `stitch.toml` files.
+    let quiet = OutputContext {
+        verbosity: Verbosity::Quiet,
+        ..OutputContext::default()
+    };
+    let dirs: Vec<_> = workspace
+        .set_dirs
+        .iter()
+        .map(Utf8PathBuf::as_path)
+        .collect();
+    let Ok(manifest) = StitchSet::discover_all(&dirs, &workspace.metadata, &quiet) else {
+        return Vec::new();
+    };
+
+    let mut issues: Vec<Issue> = manifest
+        .values()
+        .flat_map(StitchSet::conflict_check)
+        .map(|conflict| Issue {
+            file: conflict.stitch_b.path().to_owned(),
+            message: conflict_message(&conflict),
+            fix: None,
+        })
+        .collect();
+    issues.sort_by(|a, b| (&a.file, &a.message).cmp(&(&b.file, &b.message)));
+    issues
+}
+
+/// Why `conflict.stitch_b` will likely fail to apply, to follow its path.
+pub(super) fn conflict_message(conflict: &StitchConflict<'_>) -> String {
+    let (start, end) = conflict.overlapping_lines.clone().into_inner();
+    let lines = if start == end {
+        format!("line {start}")
+    } else {
+        format!("lines {start}-{end}")
+    };
+    format!(
+        "changes {lines} of {}, which {} changes too; it will likely fail to apply",
+        conflict.file.display(),
+        conflict.stitch_a.path().file_name().unwrap_or_default()
+    )
+}
+
+/// Lint every file in one package's stitch directory.
+fn lint_dir(dir: &Utf8Path, output: &OutputContext) -> Result<Vec<Issue>, OneOf<(IoError,)>> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let paths = stitch_dir_files(dir).map_err(io)?;
+
+    let config = StitchConfig::load(dir, output)?.map(|(_, config)| config);
+    let ordered =
+        config.is_some_and(|c| !c.order.stitches.is_empty()) || dir.join(ORDER_FILE).is_file();
+
+    let mut issues = Vec::new();
+    let mut stitches = Vec::new();
+
+    for path in paths {
+        let name = path.file_name().unwrap_or_default();
+        // Besides READMEs, `.sample` files are templates, like the one `cargo stitch init`
+        // writes, which are not applied until renamed.
+        if name == CONFIG_FILE
+            || name.starts_with('.')
+            || name.starts_with("README")
+            || name.ends_with(".sample")
+        {
+            continue;
+        }
+
+        match Stitch::from_path(path.clone()) {
+            Some(stitch) => stitches.push(stitch),
+            None if path.is_file() => issues.push(Issue {
+                file: path,
+                message: "not a recognized stitch file and will be ignored".to_string(),
+                fix: None,
+            }),
+            None => {}
+        }
+    }
+
+    for stitch in &stitches {
+        let Ok(contents) = String::from_utf8(fs::read(stitch.path()).map_err(io)?) else {
+            issues.push(Issue {
+                file: stitch.path().to_owned(),
+                message: "is not valid UTF-8".to_string(),
+                fix: None,
+            });
+            continue;
+        };
+        match stitch {
+            Stitch::Patch(path) => {
+                issues.extend(lint_patch_headers(path, &contents));
+                if needs_patch_normalization(&contents) {
+                    issues.push(Issue {
+                        file: path.clone(),
+                        message: "has trailing whitespace or no final newline".to_string(),
+                        fix: Some(Fix::NormalizePatch),
+                    });
+                }
+            }
+            Stitch::SgRule(path) => issues.extend(lint_rule_keys(path, &contents)),
+            Stitch::SedRule(path) => issues.extend(lint_sed_script(path).map_err(io)?),
+            Stitch::Script(_) => {}
+        }
+    }
+
+    // Renames come last so that content fixes still find the file at its old path.
+    issues.extend(lint_filenames(&stitches, ordered));
+
+    Ok(issues)
+}
+
+/// Check that the patch `contents` has a `---` and a `+++` header for each file it
+/// changes.  A patch with nothing but comments changes nothing, and a git diff may
+/// rename a file without changing its contents, so neither needs them.
+fn lint_patch_headers(path: &Utf8Path, contents: &str) -> Vec<Issue> {
+    let (_, body) = split_front_matter(contents);
+    let (_, body) = split_mail_header(body);
+    let lines: Vec<&str> = body.lines().collect();
+    if lines
+        .iter()
+        .all(|line| line.trim().is_empty() || line.starts_with('#'))
+    {
+        return Vec::new();
+    }
+
+    if !(0..lines.len()).any(|i| is_new_header(&lines, i)) {
+        if lines.iter().any(|line| line.starts_with("diff --git ")) {
+            return Vec::new();
+        }
+        return vec![Issue {
+            file: path.to_owned(),
+            message: "has no `---`/`+++` file headers followed by a hunk".to_string(),
+            fix: None,
+        }];
+    }
+
+    (0..lines.len())
+        .filter(|&i| is_new_header(&lines, i) && !has_old_header(&lines, i))
+        .map(|i| Issue {
+            file: path.to_owned(),
+            message: format!("`{}` has no `---` header before it", lines[i].trim_end()),
+            fix: Some(Fix::InsertOldHeaders),
+        })
+        .collect()
+}
+
+/// Whether `lines[i]` is a `+++` header, which the first `@@` line of a hunk follows.
+fn is_new_header(lines: &[&str], i: usize) -> bool {
+    lines[i].starts_with("+++ ") && lines.get(i + 1).is_some_and(|next| next.starts_with("@@ "))
+}
+
+/// Whether the `+++` header `lines[i]` follows a `---` one.
+fn has_old_header(lines: &[&str], i: usize) -> bool {
+    i > 0 && lines[i - 1].starts_with("--- ")
+}
+
+/// Check that each rule in the ast-grep rule file `contents` has the keys ast-grep
+/// requires: `id`, `language` and `rule`.
+fn lint_rule_keys(path: &Utf8Path, contents: &str) -> Vec<Issue> {
+    if path.extension() == Some("toml") {
+        return lint_toml_rule_keys(path, contents);
+    }
+
+    let rules = match yaml_rules(contents) {
+        Ok(rules) => rules,
+        Err(e) => {
+            return vec![Issue {
+                file: path.to_owned(),
+                message: format!("is not valid YAML: {e}"),
+                fix: None,
+            }];
+        }
+    };
+    let mut issues = Vec::new();
+
+    for (index, rule) in rules.iter().enumerate() {
+        let which = if rules.len() > 1 {
+            format!(" (document {})", index + 1)
+        } else {
+            String::new()
+        };
+        // A document with nothing but comments is not a rule.
+        let rule = match rule {
+            serde_yaml_ng::Value::Null => continue,
+            serde_yaml_ng::Value::Mapping(rule) => rule,
+            _ => {
+                issues.push(Issue {
+                    file: path.to_owned(),
+                    message: format!("ast-grep rule is not a mapping{which}"),
+                    fix: None,
+                });
+                continue;
+            }
+        };
+        for key in ["id", "language", "rule"] {
+            if rule.contains_key(key) {
+                continue;
+            }
+            // An `id` can only be inserted at the top of the file for its first rule.
+            let fix =
+                (key == "id" && index == 0).then(|| Fix::InsertId(rule_id_from_filename(path)));
+            issues.push(Issue {
+                file: path.to_owned(),
+                message: format!("ast-grep rule has no `{key}`{which}"),
+                fix,
+            });
+        }
+    }
+
+    issues
+}
+
+/// Check that the TOML ast-grep rule `contents` parses and has the keys ast-grep requires.
+fn lint_toml_rule_keys(path: &Utf8Path, contents: &str) -> Vec<Issue> {
+    let rule = match toml::from_str::<toml::Table>(contents) {
+        Ok(rule) => rule,
+        Err(e) => {
+            return vec![Issue {
+                file: path.to_owned(),
+                message: format!("is not valid TOML: {}", e.message()),
+                fix: None,
+            }];
+        }
+    };
+
+    ["id", "language", "rule"]
+        .into_iter()
+        .filter(|key| !rule.contains_key(*key))
+        .map(|key| Issue {
+            file: path.to_owned(),
+            message: format!("ast-grep rule has no `{key}`"),
+            fix: (key == "id").then(|| Fix::InsertId(rule_id_from_filename(path))),
+        })
+        .collect()
+}
+
+/// The rules in the YAML rule file `contents`, one for each of its documents.
+fn yaml_rules(contents: &str) -> Result<Vec<serde_yaml_ng::Value>, serde_yaml_ng::Error> {
+    serde_yaml_ng::Deserializer::from_str(contents)
+        .map(serde_yaml_ng::Value::deserialize)
+        .collect()
+}
+
+/// Check that the `sed -E` script `path` parses, by running it over no input.  Errors
+/// are only reported if `sed` can be run at all: a missing `sed` is reported when the
+/// stitches are applied.
+fn lint_sed_script(path: &Utf8Path) -> std::io::Result<Option<Issue>> {
+    let mut sed = Command::new("sed");
+    if sed_has_sandbox() {
+        sed.arg("--sandbox");
+    }
+    let output = match sed
+        .args(["-E", "-n", "-f", path.as_str(), "/dev/null"])
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if output.status.success() {
+        return Ok(None);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error = stderr.trim();
+    Ok(Some(Issue {
+        file: path.to_owned(),
+        message: format!(
+            "is not a valid sed script: {}",
+            error.strip_prefix("sed: ").unwrap_or(error)
+        ),
+        fix: None,
+    }))
+}
+
+/// Find ast-grep rule ids that are used more than once, within a file or across the
+/// crates in `pkg_dirs`, reporting one issue per file that uses each of them.
+fn duplicate_rule_ids(
+    stitches_dir: &Utf8Path,
+    pkg_dirs: &[Utf8PathBuf],
+) -> std::io::Result<Vec<Issue>> {
+    let mut rules = Vec::new();
+    for pkg_dir in pkg_dirs {
+        for path in stitch_dir_files(pkg_dir)? {
+            // `lint` reports the rules that are not UTF-8 on their own.
+            if let Some(Stitch::SgRule(path)) = Stitch::from_path(path)
+                && let Ok(contents) = String::from_utf8(fs::read(&path)?)
+            {
+                rules.push((path, contents));
+            }
+        }
+    }
+
+    // Every use of each id, in file order.
+    let mut uses: BTreeMap<String, Vec<&Utf8Path>> = BTreeMap::new();
+    for (path, contents) in &rules {
+        for id in rule_ids(path, contents) {
+            uses.entry(id).or_default().push(path);
+        }
+    }
+
+    let mut issues = Vec::new();
+    for (id, paths) in uses.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        let mut files = paths.clone();
+        files.dedup();
+
+        for file in &files {
+            let others: Vec<_> = files
+                .iter()
+                .filter(|other| *other != file)
+                .map(|other| other.strip_prefix(stitches_dir).unwrap_or(other).as_str())
+                .collect();
+            let message = if others.is_empty() {
+                format!("ast-grep rule id `{id}` is used more than once in this file")
+            } else {
+                format!(
+                    "ast-grep rule id `{id}` is also used in {}",
+                    others.join(", ")
+                )
+            };
+            issues.push(Issue {
+                file: file.to_path_buf(),
+                message,
+                fix: None,
+            });
+        }
+    }
+
+    Ok(issues)
+}
+
+/// The ids of the ast-grep rules in `contents`, the rule file `path`, which may hold
+/// several YAML documents.  A rule file that does not parse has none.
+fn rule_ids(path: &Utf8Path, contents: &str) -> Vec<String> {
+    if path.extension() == Some("toml") {
+        return toml::from_str::<toml::Table>(contents)
+            .ok()
+            .and_then(|rule| rule.get("id")?.as_str().map(str::to_owned))
+            .into_iter()
+            .collect();
+    }
+
+    yaml_rules(contents)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|rule| rule.get("id")?.as_str().map(str::to_owned))
+        .collect()
+}
+
+/// Check that every stitch follows the `NNN-description.ext` naming convention.
+///
+/// Renames are only offered as fixes if they keep the application order intact: no
+/// explicit `[order]` in `stitch.toml`, no name collisions, and the same sort order.
+fn lint_filenames(stitches: &[Stitch], ordered: bool) -> Vec<Issue> {
+    let names: Vec<&str> = stitches
+        .iter()
+        .map(|s| s.path().file_name().unwrap_or_default())
+        .collect();
+
+    let mut next_number = names
+        .iter()
+        .filter_map(|name| split_number(name).map(|(n, _)| n))
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    let normalized: Vec<String> = names
+        .iter()
+        .map(|name| {
+            normalize_filename(name).unwrap_or_else(|| {
+                let number = next_number;
+                next_number += 1;
+                format!("{number:03}-{}", normalize_description(name))
+            })
+        })
+        .collect();
+
+    let mut sorted = normalized.clone();
+    sorted.sort();
+    sorted.dedup();
+    let order_preserved = !ordered && sorted == normalized;
+
+    stitches
+        .iter()
+        .zip(&names)
+        .zip(normalized)
+        .filter(|((_, name), normalized)| **name != normalized)
+        .map(|((stitch, _), normalized)| Issue {
+            file: stitch.path().to_owned(),
+            message: format!("should be named `{normalized}`"),
+            fix: order_preserved.then_some(Fix::Rename(normalized)),
+        })
+        .collect()
+}
+
+/// Split a leading `NNN-` (or `NNN_`) prefix off `name`.
+pub(super) fn split_number(name: &str) -> Option<(u32, &str)> {
+    let digits = name.find(|c: char| !c.is_ascii_digit())?;
+    let rest = name[digits..].strip_prefix(['-', '_'])?;
+    Some((name[..digits].parse().ok()?, rest))
+}
+
+/// Normalize a numbered filename to `NNN-description.ext`, or `None` if it has no number.
+fn normalize_filename(name: &str) -> Option<String> {
+    let (number, rest) = split_number(name)?;
+    Some(format!("{number:03}-{}", normalize_description(rest)))
+}
+
+/// Lowercase the description and collapse runs of separators into single dashes,
+/// keeping the extension as-is.
+pub(super) fn normalize_description(name: &str) -> String {
+    let (stem, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    let description = stem
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+
+    if ext.is_empty() {
+        description
+    } else {
+        format!("{description}.{ext}")
+    }
+}
+
+/// Derive an ast-grep rule id from a stitch filename: `002-Unwrap_To_Expect.yaml`
+/// becomes `unwrap-to-expect`.
+fn rule_id_from_filename(path: &Utf8Path) -> String {
+    let name = path.file_stem().unwrap_or_default();
+    let description = split_number(name).map_or(name, |(_, rest)| rest);
+    normalize_description(description)
+}
+
+fn needs_patch_normalization(contents: &str) -> bool {
+    normalize_patch(contents) != contents
+}
+
+fn normalize_patch(contents: &str) -> String {
+    let mut out: String = contents
+        .lines()
+        .map(|line| format!("{}\n", line.trim_end()))
+        .collect();
+    if out.is_empty() && !contents.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// `contents` with a `--- a/<path>` line before each `+++ b/<path>` one that has none,
+/// as [`lint_patch_headers`] reports.
+fn insert_old_headers(contents: &str) -> String {
+    let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(contents.len());
+    for (i, line) in lines.iter().enumerate() {
+        if is_new_header(&lines, i)
+            && !has_old_header(&lines, i)
+            && let Some(new) = line.strip_prefix("+++ ")
+        {
+            let old = new
+                .strip_prefix("b/")
+                .map_or(new.to_string(), |path| format!("a/{path}"));
+            out.push_str("--- ");
+            out.push_str(&old);
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+fn apply_fix(file: &Utf8Path, fix: &Fix) -> std::io::Result<()> {
+    match fix {
+        Fix::Rename(name) => fs::rename(file, file.with_file_name(name)),
+        Fix::InsertId(id) => {
+            let contents = fs::read_to_string(file)?;
+            let line = match file.extension() {
+                Some("toml") => format!("id = \"{id}\""),
+                _ => format!("id: {id}"),
+            };
+            fs::write(file, format!("{line}\n{contents}"))
+        }
+        Fix::NormalizePatch => {
+            let contents = fs::read_to_string(file)?;
+            fs::write(file, normalize_patch(&contents))
+        }
+        Fix::InsertOldHeaders => {
+            let contents = fs::read_to_string(file)?;
+            fs::write(file, insert_old_headers(&contents))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(name: &str) -> Stitch {
+        Stitch::Patch(Utf8PathBuf::from(name))
+    }
+
+    #[test]
+    fn parse_fix_flag() {
+        assert_eq!(Args::parse(&[]).unwrap(), Args { fix: false });
+        assert_eq!(
+            Args::parse(&["--fix".to_string()]).unwrap(),
+            Args { fix: true }
+        );
+        assert!(Args::parse(&["--nope".to_string()]).is_err());
+    }
+
+    #[test]
+    fn normalize_filename_cases() {
+        assert_eq!(
+            normalize_filename("001-fix-thing.patch").as_deref(),
+            Some("001-fix-thing.patch")
+        );
+        assert_eq!(
+            normalize_filename("2_Fix_Thing.patch").as_deref(),
+            Some("002-fix-thing.patch")
+        );
+        assert_eq!(
+            normalize_filename("003-rename  fn.yaml").as_deref(),
+            Some("003-rename-fn.yaml")
+        );
+        assert_eq!(normalize_filename("fix.patch"), None);
+    }
+
+    #[test]
+    fn rule_id_from_filename_strips_number() {
+        assert_eq!(
+            rule_id_from_filename(Utf8Path::new("stitches/x/002-Unwrap_To_Expect.yaml")),
+            "unwrap-to-expect"
+        );
+        assert_eq!(rule_id_from_filename(Utf8Path::new("rule.yml")), "rule");
+    }
+
+    #[test]
+    fn rule_ids_of_every_document() {
+        let yaml = "id: first\nrule:\n  pattern: $A\n---\nid: \"second\"\nrule:\n  pattern: $B\n";
+        assert_eq!(
+            rule_ids(Utf8Path::new("rule.yaml"), yaml),
+            vec!["first", "second"]
+        );
+        assert_eq!(
+            rule_ids(Utf8Path::new("rule.toml"), "id = \"third\"\n"),
+            vec!["third"]
+        );
+        assert!(rule_ids(Utf8Path::new("rule.toml"), "id = ").is_empty());
+        assert_eq!(
+            rule_ids(Utf8Path::new("rule.yaml"), "id: kept # a comment\n"),
+            vec!["kept"]
+        );
+    }
+
+    #[test]
+    fn lint_rule_keys_reports_where_yaml_stops_parsing() {
+        let issues = lint_rule_keys(
+            Utf8Path::new("001-rule.yaml"),
+            "id: rule\nlanguage: Rust\nrule: [unclosed\n",
+        );
+        let [issue] = issues.as_slice() else {
+            panic!("expected one issue, got {issues:?}");
+        };
+        assert!(
+            issue.message.starts_with("is not valid YAML: ") && issue.message.contains("line "),
+            "got: {}",
+            issue.message
+        );
+    }
+
+    #[test]
+    fn duplicate_rule_ids_across_crates_and_within_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let stitches = Utf8Path::from_path(tmp.path()).unwrap();
+        let a = stitches.join("crate-a");
+        let b = stitches.join("crate-b");
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+
+        fs::write(a.join("001-x.yaml"), "id: shared\n").unwrap();
+        fs::write(a.join("002-y.yaml"), "id: twice\n---\nid: twice\n").unwrap();
+        fs::write(a.join("003-z.patch"), "id: shared\n").unwrap();
+        fs::write(b.join("001-x.yaml"), "id: 'shared'\n").unwrap();
+        fs::write(b.join("002-unique.yaml"), "id: unique\n").unwrap();
+
+        let issues = duplicate_rule_ids(stitches, &[a.clone(), b.clone()]).unwrap();
+        let found: Vec<_> = issues
+            .iter()
+            .map(|issue| (issue.file.clone(), issue.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    a.join("001-x.yaml"),
+                    "ast-grep rule id `shared` is also used in crate-b/001-x.yaml"
+                ),
+                (
+                    b.join("001-x.yaml"),
+                    "ast-grep rule id `shared` is also used in crate-a/001-x.yaml"
+                ),
+                (
+                    a.join("002-y.yaml"),
+                    "ast-grep rule id `twice` is used more than once in this file"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_patch_strips_whitespace_and_adds_newline() {
+        assert_eq!(normalize_patch("a  \n b\t\n+c"), "a\n b\n+c\n");
+        assert_eq!(normalize_patch("a\n"), "a\n");
+        assert!(!needs_patch_normalization("--- a/x\n+++ b/x\n"));
+    }
+
+    #[test]
+    fn lint_patch_headers_and_fix() {
+        let path = Utf8Path::new("001-fix.patch");
+        let messages = |contents| {
+            lint_patch_headers(path, contents)
+                .into_iter()
+                .map(|issue| issue.message)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(messages("--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n").is_empty());
+        assert!(messages("# Changes nothing yet.\n").is_empty());
+        assert!(messages("diff --git a/x b/y\nrename from x\nrename to y\n").is_empty());
+        assert_eq!(
+            messages("-a\n+b\n"),
+            ["has no `---`/`+++` file headers followed by a hunk"]
+        );
+
+        let patch = "+++ b/x\n@@ -1 +1 @@\n-a\n+++ added\n--- a/y\n+++ b/y\n@@ -1 +1 @@\n-c\n+d\n";
+        assert_eq!(messages(patch), ["`+++ b/x` has no `---` header before it"]);
+        assert_eq!(insert_old_headers(patch), format!("--- a/x\n{patch}"));
+    }
+
+    #[test]
+    fn lint_rule_keys_of_every_document() {
+        let path = Utf8Path::new("002-rename.yaml");
+        let issues = lint_rule_keys(
+            path,
+            "rule:\n  pattern: $A\n---\nid: second\nlanguage: Rust\nrule:\n  pattern: $B\n---\nid: third\n",
+        );
+        let found: Vec<_> = issues
+            .iter()
+            .map(|issue| (issue.message.as_str(), issue.fix.is_some()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("ast-grep rule has no `id` (document 1)", true),
+                ("ast-grep rule has no `language` (document 1)", false),
+                ("ast-grep rule has no `language` (document 3)", false),
+                ("ast-grep rule has no `rule` (document 3)", false),
+            ]
+        );
+        assert!(lint_rule_keys(path, "---\nid: a\nlanguage: Rust\nrule: {}\n").is_empty());
+    }
+
+    #[test]
+    fn lint_sed_script_reports_syntax_errors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        fs::write(dir.join("001-ok.sed"), "s/foo/bar/g\n/^$/d\n").unwrap();
+        fs::write(dir.join("002-bad.sed"), "s/foo/bar/g\ns/unterminated\n").unwrap();
+
+        assert_eq!(lint_sed_script(&dir.join("001-ok.sed")).unwrap(), None);
+        let issue = lint_sed_script(&dir.join("002-bad.sed")).unwrap().unwrap();
+        assert!(
+            issue.message.starts_with("is not a valid sed script: "),
+            "{issue:?}"
+        );
+    }
+
+    #[test]
+    fn lint_filenames_offers_renames_that_keep_order() {
+        let stitches = [patch("001-a.patch"), patch("2_B.patch"), patch("fix.patch")];
+        let issues = lint_filenames(&stitches, false);
+
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].fix, Some(Fix::Rename("002-b.patch".to_string())));
+        assert_eq!(
+            issues[1].fix,
+            Some(Fix::Rename("003-fix.patch".to_string()))
+        );
+    }
+
+    #[test]
+    fn lint_filenames_refuses_renames_that_change_order() {
+        // `1-late.patch` currently sorts after `010-early.patch`; padding it would not.
+        let stitches = [patch("010-early.patch"), patch("1-late.patch")];
+        let issues = lint_filenames(&stitches, false);
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].fix, None);
+    }
+
+    #[test]
+    fn lint_filenames_refuses_renames_with_explicit_order() {
+        let stitches = [patch("fix.patch")];
+        let issues = lint_filenames(&stitches, true);
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].fix, None);
+    }
+
+    #[test]
+    fn lint_dir_and_fix() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+
+        fs::write(
+            dir.join("001-fix.patch"),
+            "--- a/x  \n+++ b/x\n@@ -1 +1 @@\n-a\n+b",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("002-Rule.yaml"),
+            "language: Rust\nrule:\n  pattern: $A\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let issues = lint_dir(dir, &OutputContext::default()).unwrap();
+        assert_eq!(issues.len(), 4, "{issues:#?}");
+
+        for issue in &issues {
+            if let Some(fix) = &issue.fix {
+                apply_fix(&issue.file, fix).unwrap();
+            }
+        }
+
+        assert_eq!(
+            fs::read_to_string(dir.join("001-fix.patch")).unwrap(),
+            "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n"
+        );
+        assert!(
+            fs::read_to_string(dir.join("002-rule.yaml"))
+                .unwrap()
+                .starts_with("id: rule\n")
+        );
+
+        // Only the unrecognized file remains.
+        let issues = lint_dir(dir, &OutputContext::default()).unwrap();
+        assert_eq!(issues.len(), 1, "{issues:#?}");
+        assert_eq!(issues[0].file, dir.join("notes.txt"));
+    }
+}
@@ -0,0 +1,186 @@
+//! `cargo stitch list [--json | --checksums] [--verbose]`: list every stitch file, with the
+//! crate it applies to, in application order.
+
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+use crate::hash::sha256_hex;
+use crate::stitch::StitchSet;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    json: bool,
+    verbose: bool,
+    checksums: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut json = false;
+        let mut verbose = false;
+        let mut checksums = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--json" => json = true,
+                "--verbose" | "-v" => verbose = true,
+                "--checksums" => checksums = true,
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `list`: {other}"
+                    )));
+                }
+            }
+        }
+
+        if json && checksums {
+            return Err(usage_error(
+                "`--json` and `--checksums` cannot be used together",
+            ));
+        }
+
+        Ok(Self {
+            json,
+            verbose,
+            checksums,
+        })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+    let manifest: BTreeMap<_, _> = manifest.iter().collect();
+
+    if args.json {
+        let json = to_json(&manifest).map_err(OneOf::new)?;
+        let json =
+            serde_json::to_string_pretty(&json).map_err(|e| OneOf::new(IoError(e.into())))?;
+        println!("{json}");
+    } else if args.checksums {
+        let out = render_checksums(&manifest, &workspace.stitches_dir)
+            .map_err(|e| OneOf::new(IoError(e)))?;
+        print!("{out}");
+    } else {
+        let verbose = args.verbose || workspace.output.is_verbose();
+        let out = render(&manifest, &workspace.stitches_dir, verbose)
+            .map_err(|e| OneOf::new(IoError(e)))?;
+        print!("{out}");
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The manifest as JSON, with a `meta` object next to the `type` and `path` of each
+/// stitch whose front matter says anything about it.
+fn to_json(manifest: &BTreeMap<&String, &StitchSet>) -> Result<serde_json::Value, IoError> {
+    let mut json = serde_json::to_value(manifest).map_err(|e| IoError(e.into()))?;
+    for (krate, stitch_set) in manifest {
+        let Some(entries) = json[krate.as_str()]["stitches"].as_array_mut() else {
+            continue;
+        };
+        for (entry, stitch) in entries.iter_mut().zip(stitch_set.iter()) {
+            let meta = stitch.read_meta().map_err(IoError)?;
+            if !meta.is_empty() {
+                entry["meta"] = serde_json::to_value(meta).map_err(|e| IoError(e.into()))?;
+            }
+        }
+    }
+    Ok(json)
+}
+
+/// One line per stitch: `<crate>  <index>  <kind>  <path>`, with the crates in name
+/// order and the stitches of each in application order, numbered from 1.  The path is
+/// relative to the crate's directory in `stitches_dir`.  With `verbose`, the description
+/// in a stitch's front matter (or its commit message) follows on lines of its own, under
+/// the path.
+fn render(
+    manifest: &BTreeMap<&String, &StitchSet>,
+    stitches_dir: &Utf8Path,
+    verbose: bool,
+) -> std::io::Result<String> {
+    let crate_width = manifest.keys().map(|k| k.len()).max().unwrap_or(0);
+    let index_width = manifest
+        .values()
+        .map(|set| set.len().to_string().len())
+        .max()
+        .unwrap_or(0);
+    let path_column = crate_width + 2 + index_width + 2 + 7 + 2;
+
+    let mut out = String::new();
+    for (krate, stitch_set) in manifest {
+        let dir = stitches_dir.join(krate);
+        for (index, stitch) in stitch_set.iter().enumerate() {
+            let kind = stitch.kind();
+            let path = stitch.path();
+            out.push_str(&format!(
+                "{krate:<crate_width$}  {:>index_width$}  {kind:<7}  {}\n",
+                index + 1,
+                path.strip_prefix(&dir)
+                    .map_or(path.file_name().unwrap_or_default(), Utf8Path::as_str)
+            ));
+            if !verbose {
+                continue;
+            }
+            if let Some(description) = stitch.read_meta()?.description {
+                for line in description.lines() {
+                    out.push_str(format!("{:path_column$}{line}", "").trim_end());
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// For each crate, a `# <crate>` line followed by the SHA-256 of each of its stitch
+/// files, in the format of its `.checksums` file: `<hex digest>  <path>`, with the path
+/// relative to the crate's directory in `stitches_dir`.
+fn render_checksums(
+    manifest: &BTreeMap<&String, &StitchSet>,
+    stitches_dir: &Utf8Path,
+) -> std::io::Result<String> {
+    let mut out = String::new();
+    for (krate, stitch_set) in manifest {
+        let dir = stitches_dir.join(krate);
+        out.push_str(&format!("# {krate}\n"));
+        for path in stitch_set.file_paths() {
+            let digest = sha256_hex(&std::fs::read(path)?);
+            let relative = path.strip_prefix(&dir).unwrap_or(path);
+            out.push_str(&format!("{digest}  {relative}\n"));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_json_flag() {
+        assert!(!Args::parse(&[]).unwrap().json);
+        assert!(Args::parse(&["--json".to_string()]).unwrap().json);
+        assert!(Args::parse(&["--bogus".to_string()]).is_err());
+    }
+
+    #[test]
+    fn parse_checksums_flag() {
+        assert!(!Args::parse(&[]).unwrap().checksums);
+        assert!(Args::parse(&["--checksums".to_string()]).unwrap().checksums);
+        assert!(Args::parse(&["--checksums".to_string(), "--json".to_string()]).is_err());
+    }
+
+    #[test]
+    fn parse_verbose_flag() {
+        assert!(!Args::parse(&[]).unwrap().verbose);
+        assert!(Args::parse(&["--verbose".to_string()]).unwrap().verbose);
+        assert!(Args::parse(&["-v".to_string()]).unwrap().verbose);
+    }
+}
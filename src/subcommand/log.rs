@@ -0,0 +1,171 @@
+//! `cargo stitch log [--package <name>] [--json]`: show when stitches were applied to the
+//! crates' patched copies, most recent first, from the records the wrapper keeps in
+//! `target/cargo-stitch/.history/`.
+
+use std::fmt::Write as _;
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+use crate::hash::{HISTORY_DIR, StitchRecord};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    package: Option<String>,
+    json: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut package = None;
+        let mut json = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => json = true,
+                "--package" | "-p" => match args.next() {
+                    Some(name) if !name.starts_with('-') => package = Some(name.clone()),
+                    _ => {
+                        return Err(usage_error(
+                            "usage: cargo stitch log [--package <name>] [--json]",
+                        ));
+                    }
+                },
+                other => match other.strip_prefix("--package=") {
+                    Some(name) if !name.is_empty() => package = Some(name.to_string()),
+                    _ => {
+                        return Err(usage_error(format!(
+                            "unexpected argument for `log`: {other}"
+                        )));
+                    }
+                },
+            }
+        }
+
+        Ok(Self { package, json })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let mut records =
+        StitchRecord::load_all(&workspace.patched_root.join(HISTORY_DIR)).map_err(io)?;
+    records.retain(|record| {
+        args.package
+            .as_ref()
+            .is_none_or(|package| *package == record.package)
+    });
+    records.reverse();
+
+    if args.json {
+        let json =
+            serde_json::to_string_pretty(&records).map_err(|e| OneOf::new(IoError(e.into())))?;
+        println!("{json}");
+    } else {
+        print!("{}", render(&records));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// One line per record, `<date> <time>  <crate>  <n> stitches applied`, in UTC.
+fn render(records: &[StitchRecord]) -> String {
+    let width = records.iter().map(|r| r.package.len()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    for record in records {
+        _ = writeln!(
+            out,
+            "{}  {:<width$}  {} {} applied",
+            format_timestamp(record.state.applied_at),
+            record.package,
+            record.applied,
+            if record.applied == 1 {
+                "stitch"
+            } else {
+                "stitches"
+            }
+        );
+    }
+    out
+}
+
+/// `secs` since the Unix epoch as `YYYY-MM-DD HH:MM:SS`, in UTC.
+fn format_timestamp(secs: u64) -> String {
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+
+    // Howard Hinnant's `civil_from_days`, for days since 1970-01-01.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::StitchState;
+
+    #[test]
+    fn parse_package_and_json() {
+        assert_eq!(
+            Args::parse(&[]).unwrap(),
+            Args {
+                package: None,
+                json: false,
+            }
+        );
+        let args: Vec<_> = ["-p", "crate-a", "--json"].map(String::from).into();
+        assert_eq!(
+            Args::parse(&args).unwrap(),
+            Args {
+                package: Some("crate-a".to_string()),
+                json: true,
+            }
+        );
+        assert!(Args::parse(&["--package".to_string()]).is_err());
+        assert!(Args::parse(&["--oneline".to_string()]).is_err());
+    }
+
+    #[test]
+    fn format_timestamp_in_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1_705_329_121), "2024-01-15 14:32:01");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00");
+    }
+
+    #[test]
+    fn render_one_line_per_record() {
+        let record = |package: &str, applied| StitchRecord {
+            package: package.to_string(),
+            applied,
+            state: StitchState {
+                applied_at: 1_705_329_121,
+                stitches: Vec::new(),
+                source_hash: String::new(),
+            },
+        };
+        assert_eq!(
+            render(&[record("crate-foo", 3), record("a", 1)]),
+            "2024-01-15 14:32:01  crate-foo  3 stitches applied\n\
+             2024-01-15 14:32:01  a          1 stitch applied\n"
+        );
+    }
+}
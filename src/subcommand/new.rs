@@ -0,0 +1,259 @@
+//! `cargo stitch new <crate> <name> [--type patch|sg] [--with-metadata]`: create the next
+//! numbered stitch file for a crate, from a template.
+//!
+//! This only takes over `new` when `<crate>` is a workspace member; anything else is
+//! forwarded to `cargo new` as usual.
+
+use std::fs;
+use std::io::Write as _;
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::capture::next_number;
+use super::lint::normalize_description;
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+use crate::stitch::cargo_status;
+
+const USAGE: &str = "usage: cargo stitch new <crate> <name> [--type patch|sg] [--with-metadata]";
+
+/// The front matter `--with-metadata` starts a patch with, for `{id}` to be filled in.
+const FRONT_MATTER: &str = "---
+id: {id}
+description:
+author:
+issue_url:
+upstream_pr:
+---
+";
+
+const PATCH: &str = "# {file}: a patch for `{krate}`.
+#
+# Replace these comments with a unified diff, with paths relative to the crate root and
+# an `a/` and `b/` prefix, e.g. the output of `git diff --relative` run in the crate's
+# directory.  Until then, this stitch changes nothing.
+";
+
+const SG_RULE: &str = "# {file}: an ast-grep rule for `{krate}`, applied with
+# `ast-grep scan --update-all`.  Until the pattern is filled in, it matches nothing.
+id: {id}
+language: Rust
+rule:
+  # The code to rewrite, e.g. `foo($A)`.
+  pattern: TODO_PATTERN
+# What to rewrite it to, e.g. `bar($A)`.
+fix: TODO_FIX
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Patch,
+    SgRule,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    name: String,
+    kind: Kind,
+    with_metadata: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut positional = Vec::new();
+        let mut kind = Kind::Patch;
+        let mut with_metadata = false;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--type" => {
+                    kind = match args.next().map(String::as_str) {
+                        Some("patch") => Kind::Patch,
+                        Some("sg") => Kind::SgRule,
+                        Some(other) => {
+                            return Err(usage_error(format!(
+                                "invalid value for `--type`: `{other}` (expected `patch` or `sg`)"
+                            )));
+                        }
+                        None => return Err(usage_error(USAGE)),
+                    };
+                }
+                "--with-metadata" => with_metadata = true,
+                other if other.starts_with('-') || positional.len() == 2 => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `new`: {other}"
+                    )));
+                }
+                other => positional.push(other.to_string()),
+            }
+        }
+
+        let [krate, name] = <[String; 2]>::try_from(positional).map_err(|_| usage_error(USAGE))?;
+        if with_metadata && kind != Kind::Patch {
+            return Err(usage_error(
+                "`--with-metadata` only applies to patches, which have front matter",
+            ));
+        }
+
+        Ok(Self {
+            krate,
+            name,
+            kind,
+            with_metadata,
+        })
+    }
+
+    /// The file name for stitch number `number`, e.g. `003-fix-overflow.patch`.
+    fn file_name(&self, number: u32) -> String {
+        let extension = match self.kind {
+            Kind::Patch => "patch",
+            Kind::SgRule => "yaml",
+        };
+        format!(
+            "{number:03}-{}",
+            normalize_description(&format!("{}.{extension}", self.name))
+        )
+    }
+
+    /// The template for the stitch `file`.
+    fn contents(&self, file: &str) -> String {
+        let id = file
+            .split_once('-')
+            .map_or(file, |(_, rest)| rest)
+            .rsplit_once('.')
+            .map_or(file, |(stem, _)| stem);
+        let template = match (self.kind, self.with_metadata) {
+            (Kind::Patch, false) => PATCH.to_string(),
+            (Kind::Patch, true) => format!("{FRONT_MATTER}{PATCH}"),
+            (Kind::SgRule, _) => SG_RULE.to_string(),
+        };
+        template
+            .replace("{file}", file)
+            .replace("{krate}", &self.krate)
+            .replace("{id}", id)
+    }
+}
+
+/// Returns `true` if `args` (following `new`) name a workspace member and a stitch, rather
+/// than a path for `cargo new`.
+pub(super) fn names_member(workspace: &Workspace, args: &[String]) -> bool {
+    Args::parse(args).is_ok_and(|args| workspace.member_dir(&args.krate).is_ok())
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    workspace.member_dir(&args.krate).map_err(OneOf::new)?;
+
+    if normalize_description(&args.name).is_empty() {
+        return Err(OneOf::new(usage_error(format!(
+            "stitch name must contain a letter or a digit: `{}`",
+            args.name
+        ))));
+    }
+
+    let dir = workspace.stitches_dir.join(&args.krate);
+    fs::create_dir_all(&dir).map_err(io)?;
+    let file_name = args.file_name(next_number(&dir).map_err(io)?);
+    let path = dir.join(&file_name);
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                OneOf::new(usage_error(format!("{path} already exists")))
+            }
+            _ => io(e),
+        })?;
+    file.write_all(args.contents(&file_name).as_bytes())
+        .map_err(io)?;
+
+    let display = path.strip_prefix(&workspace.root).unwrap_or(&path);
+    cargo_status(&workspace.output, "Created", display.as_str());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+
+    use super::*;
+    use crate::stitch::{Stitch, StitchMeta, StitchSet};
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_crate_name_and_options() {
+        assert_eq!(
+            Args::parse(&args(&["crate-a", "fix-overflow"])).unwrap(),
+            Args {
+                krate: "crate-a".to_string(),
+                name: "fix-overflow".to_string(),
+                kind: Kind::Patch,
+                with_metadata: false,
+            }
+        );
+        assert_eq!(
+            Args::parse(&args(&["--type", "sg", "crate-a", "rename"])).unwrap(),
+            Args {
+                krate: "crate-a".to_string(),
+                name: "rename".to_string(),
+                kind: Kind::SgRule,
+                with_metadata: false,
+            }
+        );
+        assert!(
+            Args::parse(&args(&["crate-a", "fix", "--with-metadata"]))
+                .unwrap()
+                .with_metadata
+        );
+        // `cargo new <path>` has a single path.
+        assert!(Args::parse(&args(&["my-project"])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "fix", "extra"])).is_err());
+        assert!(Args::parse(&args(&["crate-a", "fix", "--type", "sed"])).is_err());
+        assert!(Args::parse(&args(&["--type", "sg", "--with-metadata", "a", "b"])).is_err());
+    }
+
+    #[test]
+    fn file_name_is_numbered_and_normalized() {
+        let args = Args::parse(&args(&["crate-a", "Fix Overflow"])).unwrap();
+        assert_eq!(args.file_name(3), "003-fix-overflow.patch");
+
+        let args =
+            Args::parse(&["--type", "sg", "crate-a", "rename_api"].map(String::from)).unwrap();
+        assert_eq!(args.file_name(12), "012-rename-api.yaml");
+    }
+
+    #[test]
+    fn patch_template_applies_and_changes_nothing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(tmp.path()).unwrap();
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("lib.rs"), "fn hello() {}\n").unwrap();
+
+        let args = Args::parse(&args(&["crate-a", "fix", "--with-metadata"])).unwrap();
+        let file = args.file_name(1);
+        let path = dir.join(&file);
+        fs::write(&path, args.contents(&file)).unwrap();
+
+        let stitch = Stitch::from_path(path).unwrap();
+        assert_eq!(
+            stitch.read_meta().unwrap(),
+            StitchMeta {
+                id: Some("fix".to_string()),
+                ..StitchMeta::default()
+            }
+        );
+        let Ok(result) = stitch.validate(dir, &StitchSet::default()) else {
+            panic!("the template should apply");
+        };
+        assert!(!result.applied);
+    }
+}
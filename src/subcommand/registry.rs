@@ -0,0 +1,262 @@
+//! `cargo stitch publish-stitches` and `cargo stitch fetch-stitches`: share a crate's
+//! stitches through a simple HTTP registry.
+//!
+//! The registry is a flat file layout: the stitches for version `<version>` of crate
+//! `<crate>` live at `<registry>/<crate>/<version>.tar.gz`, a gzipped tarball of the
+//! `stitches/<set>/<crate>/` directory.  Publishing uploads the tarball with an HTTP `PUT`;
+//! fetching downloads it with a `GET`.  If `CARGO_STITCH_REGISTRY_TOKEN` is set, it is
+//! sent as a bearer token with both requests.
+
+use std::env;
+use std::io::Write;
+use std::process::{Command, ExitCode, Stdio};
+
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::{IoError, MissingTool};
+use crate::fs::{temp_dir, workspace_packages};
+use crate::stitch::cargo_status;
+
+/// Environment variable holding the bearer token sent to the registry.
+const REGISTRY_TOKEN_ENV: &str = "CARGO_STITCH_REGISTRY_TOKEN";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    positional: Vec<String>,
+    registry: String,
+}
+
+impl Args {
+    /// Parse `<positional>... --registry <url>`, requiring exactly `expected.len()`
+    /// positional arguments (named by `expected` in the usage message).
+    fn parse(command: &str, expected: &[&str], args: &[String]) -> Result<Self, IoError> {
+        let usage = || {
+            let names: Vec<_> = expected.iter().map(|n| format!("<{n}>")).collect();
+            usage_error(format!(
+                "usage: cargo stitch {command} {} --registry <url>",
+                names.join(" ")
+            ))
+        };
+
+        let mut positional = Vec::new();
+        let mut registry = None;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--registry" => registry = Some(args.next().ok_or_else(usage)?.clone()),
+                other if other.starts_with('-') => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `{command}`: {other}"
+                    )));
+                }
+                other => positional.push(other.to_string()),
+            }
+        }
+
+        if positional.len() != expected.len() {
+            return Err(usage());
+        }
+
+        Ok(Self {
+            positional,
+            registry: registry.ok_or_else(usage)?,
+        })
+    }
+}
+
+/// The URL of the tarball for version `version` of `krate` in `registry`.
+fn tarball_url(registry: &str, krate: &str, version: &str) -> String {
+    format!(
+        "{}/{krate}/{version}.tar.gz",
+        registry.trim_end_matches('/')
+    )
+}
+
+pub(super) fn run_publish(
+    workspace: &Workspace,
+    args: &[String],
+) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse("publish-stitches", &["crate"], args).map_err(OneOf::new)?;
+    let krate = &args.positional[0];
+    let io = |e| OneOf::new(IoError(e));
+
+    let crate_dir = workspace.stitches_dir.join(krate);
+    if !crate_dir.is_dir() {
+        return Err(OneOf::new(usage_error(format!(
+            "no stitches to publish: {crate_dir} does not exist"
+        ))));
+    }
+
+    let packages = workspace_packages(&workspace.root).unwrap_or_default();
+    let Some(package) = packages.iter().find(|p| p.name.as_str() == krate) else {
+        return Err(OneOf::new(usage_error(format!(
+            "crate `{krate}` is not a member of the workspace"
+        ))));
+    };
+    let version = package.version.to_string();
+
+    require_tool("tar")?;
+    require_tool("curl")?;
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    let tarball = scratch.join("stitches.tar.gz");
+    run_tool(
+        Command::new("tar")
+            .arg("-czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&crate_dir)
+            .arg("."),
+        None,
+    )
+    .map_err(io)?;
+
+    let url = tarball_url(&args.registry, krate, &version);
+    let mut curl = Command::new("curl");
+    curl.args(["-fsS", "-T"]).arg(&tarball).arg(&url);
+    run_tool(&mut curl, auth_header().as_deref()).map_err(io)?;
+
+    cargo_status(
+        &workspace.output,
+        "Published",
+        &format!("stitches for {krate} v{version} to {url}"),
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+pub(super) fn run_fetch(
+    workspace: &Workspace,
+    args: &[String],
+) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse("fetch-stitches", &["crate", "version"], args).map_err(OneOf::new)?;
+    let (krate, version) = (&args.positional[0], &args.positional[1]);
+    let io = |e| OneOf::new(IoError(e));
+
+    let crate_dir = workspace.stitches_dir.join(krate);
+    if crate_dir
+        .read_dir_utf8()
+        .is_ok_and(|mut d| d.next().is_some())
+    {
+        return Err(OneOf::new(usage_error(format!(
+            "{crate_dir} already contains stitches; remove it first"
+        ))));
+    }
+
+    require_tool("tar")?;
+    require_tool("curl")?;
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    let tarball = scratch.join("stitches.tar.gz");
+
+    let url = tarball_url(&args.registry, krate, version);
+    let mut curl = Command::new("curl");
+    curl.args(["-fsSL", "-o"]).arg(&tarball).arg(&url);
+    run_tool(&mut curl, auth_header().as_deref()).map_err(io)?;
+
+    std::fs::create_dir_all(&crate_dir).map_err(io)?;
+    run_tool(
+        Command::new("tar")
+            .arg("-xzf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&crate_dir),
+        None,
+    )
+    .map_err(io)?;
+
+    cargo_status(
+        &workspace.output,
+        "Fetched",
+        &format!("stitches for {krate} v{version} into {crate_dir}"),
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+pub(super) fn require_tool(tool: &'static str) -> Result<(), SubcommandError> {
+    if Command::new(tool).arg("--version").output().is_err() {
+        return Err(OneOf::new(MissingTool(tool)));
+    }
+    Ok(())
+}
+
+/// The `Authorization` header to send to the registry, if a token is configured.
+fn auth_header() -> Option<String> {
+    env::var(REGISTRY_TOKEN_ENV)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(|token| format!("Authorization: Bearer {token}"))
+}
+
+/// Run `cmd`, failing with its stderr if it exits unsuccessfully.
+///
+/// If `header` is given, it is passed to curl on stdin (`-H @-`) rather than on the
+/// command line, so the token does not show up in the process list.
+pub(super) fn run_tool(cmd: &mut Command, header: Option<&str>) -> std::io::Result<()> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+
+    if header.is_some() {
+        cmd.args(["-H", "@-"]).stdin(Stdio::piped());
+    }
+    let mut child = cmd.stdout(Stdio::null()).stderr(Stdio::piped()).spawn()?;
+    if let (Some(header), Some(mut stdin)) = (header, child.stdin.take()) {
+        writeln!(stdin, "{header}")?;
+    }
+
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_publish() {
+        let parsed = Args::parse(
+            "publish-stitches",
+            &["crate"],
+            &args(&["config", "--registry", "https://example.com/stitches"]),
+        )
+        .unwrap();
+        assert_eq!(parsed.positional, vec!["config"]);
+        assert_eq!(parsed.registry, "https://example.com/stitches");
+    }
+
+    #[test]
+    fn parse_fetch_requires_version() {
+        let err = Args::parse(
+            "fetch-stitches",
+            &["crate", "version"],
+            &args(&["config", "--registry", "https://example.com"]),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("usage: cargo stitch fetch-stitches <crate> <version>")
+        );
+    }
+
+    #[test]
+    fn parse_requires_registry() {
+        let err = Args::parse("publish-stitches", &["crate"], &args(&["config"])).unwrap_err();
+        assert!(err.to_string().contains("--registry <url>"));
+    }
+
+    #[test]
+    fn tarball_url_layout() {
+        assert_eq!(
+            tarball_url("https://example.com/stitches/", "config", "0.15.2"),
+            "https://example.com/stitches/config/0.15.2.tar.gz"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! `cargo stitch reorder <crate> <old-number> <new-number>`: renumber a stitch file,
+//! shifting any files it collides with.
+
+use std::fs;
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::lint::split_number;
+use super::{SubcommandError, Workspace, usage_error};
+use crate::config::StitchConfig;
+use crate::error::IoError;
+use crate::stitch::{ORDER_FILE, Stitch, cargo_status};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    old: u32,
+    new: u32,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let usage = || usage_error("usage: cargo stitch reorder <crate> <old-number> <new-number>");
+
+        let [krate, old, new] = args else {
+            return Err(usage());
+        };
+        let number = |s: &str| {
+            s.parse::<u32>()
+                .map_err(|_| usage_error(format!("not a stitch number: {s}")))
+        };
+
+        Ok(Self {
+            krate: krate.clone(),
+            old: number(old)?,
+            new: number(new)?,
+        })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let dir = workspace.stitches_dir.join(&args.krate);
+    if !dir.is_dir() {
+        return Err(OneOf::new(usage_error(format!(
+            "no stitches found for crate `{}` in {}",
+            args.krate, workspace.stitches_dir
+        ))));
+    }
+
+    if StitchConfig::load(&dir, &workspace.output)
+        .map_err(OneOf::broaden)?
+        .is_some_and(|(_, config)| !config.order.stitches.is_empty())
+    {
+        return Err(OneOf::new(usage_error(format!(
+            "{dir}/stitch.toml sets an explicit [order]; edit it instead of renumbering files"
+        ))));
+    }
+    if dir.join(ORDER_FILE).is_file() {
+        return Err(OneOf::new(usage_error(format!(
+            "{dir}/{ORDER_FILE} sets an explicit order; edit it instead of renumbering files"
+        ))));
+    }
+
+    let mut names = Vec::new();
+    for entry in dir.read_dir_utf8().map_err(io)? {
+        let entry = entry.map_err(io)?;
+        if Stitch::from_path(entry.path().to_owned()).is_some() {
+            names.push(entry.file_name().to_string());
+        }
+    }
+    names.sort();
+
+    let renames =
+        plan_renames(&names, args.old, args.new).map_err(|e| OneOf::new(usage_error(e)))?;
+    if renames.is_empty() {
+        cargo_status(&workspace.output, "Fresh", "no change needed");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Move the reordered file out of the way first, then shift the others starting from
+    // the highest number so that no rename overwrites a file that has yet to move.
+    let (moved_from, moved_to) = &renames[0];
+    let parked = dir.join(format!(".reorder.{}", std::process::id()));
+    fs::rename(dir.join(moved_from), &parked).map_err(io)?;
+    for (from, to) in renames[1..].iter().rev() {
+        fs::rename(dir.join(from), dir.join(to)).map_err(io)?;
+    }
+    fs::rename(&parked, dir.join(moved_to)).map_err(io)?;
+
+    for (from, to) in &renames {
+        cargo_status(&workspace.output, "Renamed", &format!("{from} -> {to}"));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Work out the renames needed to give the stitch numbered `old` the number `new`.
+///
+/// The first rename is the reordered file itself; the rest cascade from `new` upwards,
+/// bumping each file that would collide with the previous one by one, in ascending order.
+/// Returns an empty list if `old == new`.
+fn plan_renames(names: &[String], old: u32, new: u32) -> Result<Vec<(String, String)>, String> {
+    let numbered: Vec<(u32, &str, &str)> = names
+        .iter()
+        .filter_map(|name| split_number(name).map(|(n, rest)| (n, name.as_str(), rest)))
+        .collect();
+
+    let matching: Vec<_> = numbered.iter().filter(|(n, _, _)| *n == old).collect();
+    let moving = match matching.as_slice() {
+        [] => return Err(format!("no stitch numbered {old:03}")),
+        [moving] => **moving,
+        _ => {
+            let names: Vec<_> = matching.iter().map(|(_, name, _)| *name).collect();
+            return Err(format!(
+                "more than one stitch numbered {old:03}: {}",
+                names.join(", ")
+            ));
+        }
+    };
+
+    if old == new {
+        return Ok(Vec::new());
+    }
+
+    let rename = |number: u32, rest: &str| format!("{number:03}-{rest}");
+    let mut renames = vec![(moving.1.to_string(), rename(new, moving.2))];
+
+    let others: Vec<_> = numbered.iter().filter(|(n, _, _)| *n != old).collect();
+    let mut target = new;
+    loop {
+        let colliding: Vec<_> = others.iter().filter(|(n, _, _)| *n == target).collect();
+        if colliding.is_empty() {
+            break;
+        }
+        for (_, name, rest) in colliding {
+            renames.push((name.to_string(), rename(target + 1, rest)));
+        }
+        target += 1;
+    }
+
+    Ok(renames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_numbers() {
+        let args = Args::parse(&names(&["config", "003", "1"])).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                krate: "config".to_string(),
+                old: 3,
+                new: 1
+            }
+        );
+        assert!(Args::parse(&names(&["config", "three", "1"])).is_err());
+        assert!(Args::parse(&names(&["config", "3"])).is_err());
+    }
+
+    #[test]
+    fn move_earlier_cascades() {
+        let renames = plan_renames(&names(&["001-a.patch", "002-b.patch", "003-c.yaml"]), 3, 1);
+        assert_eq!(
+            renames.unwrap(),
+            pairs(&[
+                ("003-c.yaml", "001-c.yaml"),
+                ("001-a.patch", "002-a.patch"),
+                ("002-b.patch", "003-b.patch"),
+            ])
+        );
+    }
+
+    #[test]
+    fn move_later_stops_at_gap() {
+        let renames = plan_renames(&names(&["001-a.patch", "003-c.patch", "005-e.patch"]), 1, 3);
+        assert_eq!(
+            renames.unwrap(),
+            pairs(&[
+                ("001-a.patch", "003-a.patch"),
+                ("003-c.patch", "004-c.patch"),
+            ])
+        );
+    }
+
+    #[test]
+    fn move_to_free_number() {
+        let renames = plan_renames(&names(&["001-a.patch", "002-b.patch"]), 2, 7);
+        assert_eq!(renames.unwrap(), pairs(&[("002-b.patch", "007-b.patch")]));
+    }
+
+    #[test]
+    fn same_number_is_no_change() {
+        let renames = plan_renames(&names(&["001-a.patch"]), 1, 1);
+        assert_eq!(renames.unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn unknown_or_ambiguous_number_fails() {
+        let err = plan_renames(&names(&["001-a.patch"]), 2, 1).unwrap_err();
+        assert_eq!(err, "no stitch numbered 002");
+
+        let err = plan_renames(&names(&["001-a.patch", "001-b.patch"]), 1, 2).unwrap_err();
+        assert!(err.contains("more than one stitch numbered 001"));
+    }
+}
@@ -0,0 +1,181 @@
+//! `cargo stitch reset (--package <name> | --all) [--yes]`: remove the patched copies of
+//! crates, as `cargo stitch clean` does, and run `cargo clean --package` for each of them,
+//! so that the next build stitches and compiles them again instead of reusing what Cargo
+//! cached from the patched sources.
+
+use std::fs;
+use std::process::{Command, ExitCode};
+
+use terrors::OneOf;
+
+use super::clean::patched_crates;
+use super::{SubcommandError, Workspace, confirm, usage_error};
+use crate::error::{CargoFailed, IoError};
+use crate::stitch::cargo_status;
+use crate::wrapper::{patched_dir, split_registry_key};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    /// The crate to reset, or `None` for every patched crate (`--all`).
+    package: Option<String>,
+    yes: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let usage = || usage_error("usage: cargo stitch reset (--package <name> | --all) [--yes]");
+
+        let mut package = None;
+        let mut all = false;
+        let mut yes = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--yes" | "-y" => yes = true,
+                "--all" => all = true,
+                "--package" | "-p" => match args.next() {
+                    Some(name) if !name.starts_with('-') => package = Some(name.clone()),
+                    _ => return Err(usage()),
+                },
+                other => match other.strip_prefix("--package=") {
+                    Some(name) if !name.is_empty() => package = Some(name.to_string()),
+                    _ => {
+                        return Err(usage_error(format!(
+                            "unexpected argument for `reset`: {other}"
+                        )));
+                    }
+                },
+            }
+        }
+
+        if all == package.is_some() {
+            return Err(usage());
+        }
+        Ok(Self { package, yes })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let patched = if workspace.patched_root.is_dir() {
+        patched_crates(&workspace.patched_root).map_err(io)?
+    } else {
+        Vec::new()
+    };
+    let crates = match args.package {
+        // A crates.io dependency's patched copy is named after its version too, as in
+        // `serde-1.0.200`, which `--package serde` and `--package serde@1.0.200` also find.
+        Some(package) => {
+            let crates: Vec<_> = patched
+                .into_iter()
+                .filter(|krate| {
+                    *krate == package
+                        || package_spec(krate) == package
+                        || split_registry_key(krate).is_some_and(|(name, _)| name == package)
+                })
+                .collect();
+            if crates.is_empty() {
+                vec![package]
+            } else {
+                crates
+            }
+        }
+        None => patched,
+    };
+    if crates.is_empty() {
+        cargo_status(&workspace.output, "Reset", "0 patched crates");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !args.yes
+        && !confirm(&format!(
+            "Reset {}? They will be stitched and compiled again by the next build.",
+            crates.join(", ")
+        ))
+        .map_err(io)?
+    {
+        cargo_status(&workspace.output, "Cancelled", "nothing was reset");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    for krate in &crates {
+        let dir = patched_dir(krate, &workspace.patched_root);
+        if dir.is_dir() {
+            fs::remove_dir_all(&dir).map_err(io)?;
+            let display = dir.strip_prefix(&workspace.root).unwrap_or(&dir);
+            cargo_status(&workspace.output, "Removed", display.as_str());
+        }
+
+        let status = Command::new("cargo")
+            .args(["clean", "--package", &package_spec(krate)])
+            .current_dir(&workspace.root)
+            .status()
+            .map_err(io)?;
+        if !status.success() {
+            return Err(OneOf::new(CargoFailed(status.code().unwrap_or(1))));
+        }
+    }
+
+    cargo_status(
+        &workspace.output,
+        "Reset",
+        &format!(
+            "{} patched {}",
+            crates.len(),
+            if crates.len() == 1 { "crate" } else { "crates" }
+        ),
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The package spec that `cargo clean --package` takes for the crate whose patched copy
+/// is `krate`: `<name>@<version>` for a crates.io dependency.
+fn package_spec(krate: &str) -> String {
+    match split_registry_key(krate) {
+        Some((name, version)) => format!("{name}@{version}"),
+        None => krate.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_package_or_all() {
+        for given in [
+            &["--package", "config"][..],
+            &["-p", "config"],
+            &["--package=config", "--yes"],
+        ] {
+            let parsed = Args::parse(&args(given)).unwrap();
+            assert_eq!(parsed.package.as_deref(), Some("config"));
+        }
+        assert_eq!(
+            Args::parse(&args(&["--all", "-y"])).unwrap(),
+            Args {
+                package: None,
+                yes: true,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_requires_exactly_one_of_package_and_all() {
+        assert!(Args::parse(&[]).is_err());
+        assert!(Args::parse(&args(&["--yes"])).is_err());
+        assert!(Args::parse(&args(&["--all", "-p", "config"])).is_err());
+        assert!(Args::parse(&args(&["--package"])).is_err());
+        assert!(Args::parse(&args(&["--release", "--all"])).is_err());
+    }
+
+    #[test]
+    fn package_spec_names_the_version_of_crates_io_dependencies() {
+        assert_eq!(package_spec("serde-1.0.200"), "serde@1.0.200");
+        assert_eq!(package_spec("config"), "config");
+    }
+}
@@ -0,0 +1,310 @@
+//! `cargo stitch snapshot [--check] <crate>`: save the source tree that applying a crate's
+//! stitches produces, or check that it is unchanged.
+//!
+//! Without a crate, `cargo stitch snapshot [--name <label>]` copies every patched crate
+//! to `target/cargo-stitch-snapshots/<label>/` instead, for `cargo stitch diff-snapshot`
+//! to compare the patched directory against later.
+//!
+//! The snapshot lives in `stitches/<set>/<crate>/.snapshots/`, next to the stitches, so
+//! that it is committed with them.  Like `insta`'s snapshots, it makes it safe to rework
+//! the stitches themselves: as long as `--check` passes, they still produce the same
+//! source.
+
+use std::fs;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use terrors::OneOf;
+
+use super::clean::patched_crates;
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::error::IoError;
+use crate::fs::{CopyOptions, compare_dirs, copy_dir_recursive, temp_dir};
+use crate::stitch::cargo_status;
+use crate::wrapper::patched_dir;
+
+/// Name of the directory holding a crate's snapshot, inside its stitches directory.
+const SNAPSHOTS_DIR: &str = ".snapshots";
+
+/// Name of the directory, in Cargo's target directory, holding the snapshots of every
+/// patched crate.
+pub(super) const PATCHED_SNAPSHOTS_DIR: &str = "cargo-stitch-snapshots";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    /// The crate whose stitched source to snapshot, or `None` for a copy of every patched
+    /// crate.
+    krate: Option<String>,
+    check: bool,
+    /// The label of a snapshot of every patched crate.
+    name: Option<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let usage =
+            || usage_error("usage: cargo stitch snapshot ([--check] <crate> | [--name <label>])");
+
+        let mut krate = None;
+        let mut check = false;
+        let mut name = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check" => check = true,
+                "--name" => match args.next() {
+                    Some(label) if !label.starts_with('-') => name = Some(label.clone()),
+                    _ => return Err(usage()),
+                },
+                other if other.starts_with("--name=") => {
+                    name = Some(other["--name=".len()..].to_string());
+                }
+                other if other.starts_with('-') || krate.is_some() => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `snapshot`: {other}"
+                    )));
+                }
+                other => krate = Some(other.to_string()),
+            }
+        }
+
+        match (&krate, &name) {
+            (None, _) if check => return Err(usage()),
+            (Some(_), Some(_)) => return Err(usage()),
+            (_, Some(label)) if !is_valid_label(label) => {
+                return Err(usage_error(format!(
+                    "invalid snapshot name `{label}`: it must be a non-empty file name that \
+                     does not start with `.`"
+                )));
+            }
+            _ => {}
+        }
+        Ok(Self { krate, check, name })
+    }
+}
+
+/// Whether `label` can name a directory in `cargo-stitch-snapshots/`.
+pub(super) fn is_valid_label(label: &str) -> bool {
+    !label.is_empty() && !label.starts_with('.') && !label.contains(['/', '\\'])
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    match &args.krate {
+        Some(krate) => snapshot_crate(workspace, krate, args.check),
+        None => snapshot_patched(workspace, args.name.as_deref()),
+    }
+}
+
+/// Save or check the snapshot of what the stitches of `krate` produce.
+fn snapshot_crate(
+    workspace: &Workspace,
+    krate: &str,
+    check: bool,
+) -> Result<ExitCode, SubcommandError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let stitch_set = workspace.stitch_set(krate).map_err(OneOf::broaden)?;
+    let manifest_dir = workspace.member_dir(krate).map_err(OneOf::new)?;
+
+    check_required_tools([&stitch_set], &workspace.output).map_err(OneOf::broaden)?;
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    let current = scratch.join("current");
+    copy_dir_recursive(&manifest_dir, &current, &CopyOptions::default()).map_err(io)?;
+    stitch_set.apply_quietly(&current).map_err(OneOf::broaden)?;
+
+    let snapshot = workspace.stitches_dir.join(krate).join(SNAPSHOTS_DIR);
+    let display = snapshot
+        .strip_prefix(&workspace.root)
+        .unwrap_or(&snapshot)
+        .to_owned();
+
+    if !snapshot.is_dir() {
+        if check {
+            cargo_status(
+                &workspace.output,
+                "Error",
+                &format!(
+                    "{}: no snapshot in {display}; run `cargo stitch snapshot {}` first",
+                    krate, krate
+                ),
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+        save(&current, &snapshot).map_err(io)?;
+        cargo_status(
+            &workspace.output,
+            "Saved",
+            &format!("{}: snapshot in {display}", krate),
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let differing = compare_dirs(&snapshot, &current, &[]).map_err(io)?;
+
+    if differing.is_empty() {
+        cargo_status(
+            &workspace.output,
+            "Fresh",
+            &format!("{}: stitches produce the snapshot in {display}", krate),
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if check {
+        for path in &differing {
+            cargo_status(&workspace.output, "Differs", &format!("{}: {path}", krate));
+        }
+        return Ok(ExitCode::FAILURE);
+    }
+
+    save(&current, &snapshot).map_err(io)?;
+    cargo_status(
+        &workspace.output,
+        "Updated",
+        &format!(
+            "{}: snapshot in {display} ({} {} changed)",
+            krate,
+            differing.len(),
+            if differing.len() == 1 {
+                "file"
+            } else {
+                "files"
+            }
+        ),
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Copy every patched crate in the patched directory to
+/// `<target-dir>/cargo-stitch-snapshots/<name>/`, `name` being the current Unix time unless
+/// given.
+fn snapshot_patched(
+    workspace: &Workspace,
+    name: Option<&str>,
+) -> Result<ExitCode, SubcommandError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+            .to_string(),
+    };
+    let snapshot = workspace.target_dir.join(PATCHED_SNAPSHOTS_DIR).join(&name);
+    let display = snapshot
+        .strip_prefix(&workspace.root)
+        .unwrap_or(&snapshot)
+        .to_owned();
+    if snapshot.exists() {
+        return Err(OneOf::new(IoError(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{display} already exists; pick another --name"),
+        ))));
+    }
+
+    let crates = if workspace.patched_root.is_dir() {
+        patched_crates(&workspace.patched_root).map_err(io)?
+    } else {
+        Vec::new()
+    };
+    if crates.is_empty() {
+        cargo_status(
+            &workspace.output,
+            "Error",
+            "no patched crates to snapshot; run `cargo stitch build` first",
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+
+    // Copy next to the final location, so a failed copy leaves no partial snapshot.
+    let staging = Utf8PathBuf::from(format!("{snapshot}.{}", std::process::id()));
+    let copied = crates.iter().try_for_each(|krate| {
+        copy_dir_recursive(
+            &patched_dir(krate, &workspace.patched_root),
+            &staging.join(krate),
+            &CopyOptions::default(),
+        )
+    });
+    if let Err(e) = copied.and_then(|()| fs::rename(&staging, &snapshot)) {
+        _ = fs::remove_dir_all(&staging);
+        return Err(io(e));
+    }
+
+    cargo_status(
+        &workspace.output,
+        "Saved",
+        &format!(
+            "{} patched {} in {display}",
+            crates.len(),
+            if crates.len() == 1 { "crate" } else { "crates" }
+        ),
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Replace the snapshot in `snapshot` with the tree in `current`.
+fn save(current: &Utf8Path, snapshot: &Utf8Path) -> std::io::Result<()> {
+    // Build the new snapshot next to the old one, so a failed copy leaves it intact.
+    let staging = Utf8PathBuf::from(format!("{snapshot}.{}", std::process::id()));
+    copy_dir_recursive(current, &staging, &CopyOptions::default())?;
+    if snapshot.exists() {
+        fs::remove_dir_all(snapshot)?;
+    }
+    fs::rename(&staging, snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_crate_and_check() {
+        let args = Args::parse(&["--check".to_string(), "config".to_string()]).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                krate: Some("config".to_string()),
+                check: true,
+                name: None,
+            }
+        );
+        assert!(!Args::parse(&["config".to_string()]).unwrap().check);
+    }
+
+    #[test]
+    fn parse_name_without_crate() {
+        assert_eq!(
+            Args::parse(&[]).unwrap(),
+            Args {
+                krate: None,
+                check: false,
+                name: None,
+            }
+        );
+        for given in [&["--name", "before"][..], &["--name=before"]] {
+            let given: Vec<_> = given.iter().map(|s| s.to_string()).collect();
+            assert_eq!(Args::parse(&given).unwrap().name.as_deref(), Some("before"));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_mixed_or_bad_arguments() {
+        let parse =
+            |args: &[&str]| Args::parse(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert!(parse(&["--check"]).is_err());
+        assert!(parse(&["a", "b"]).is_err());
+        assert!(parse(&["--all"]).is_err());
+        assert!(parse(&["config", "--name", "before"]).is_err());
+        assert!(parse(&["--name"]).is_err());
+        for label in ["", ".hidden", "a/b", ".."] {
+            assert!(parse(&["--name", label]).is_err(), "{label:?}");
+        }
+    }
+}
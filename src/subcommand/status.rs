@@ -0,0 +1,301 @@
+//! `cargo stitch status [--json]`: show which crates have a patched copy in the patched
+//! directory, when it was last updated, and whether the next build would re-apply its
+//! stitches, without building anything.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Serialize;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+use crate::fs::workspace_packages;
+use crate::hash::{STAMP_FILE, STATE_FILE, StitchStamp, StitchState};
+use crate::stitch::StitchSet;
+use crate::wrapper::patched_dir;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    json: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut json = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--json" => json = true,
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `status`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { json })
+    }
+}
+
+/// The state of a crate's patched copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum State {
+    /// Made from the current sources and stitches: the next build will reuse it.
+    UpToDate,
+    /// The sources or stitches changed since: the next build will re-apply the stitches.
+    Modified,
+    /// The crate has stitches but no patched copy yet.
+    Missing,
+    /// A patched copy for a crate that no longer has any stitches.
+    Orphaned,
+}
+
+impl State {
+    /// The symbol in front of the crate in `git status` style output.
+    fn symbol(self) -> char {
+        match self {
+            State::UpToDate => '✓',
+            State::Modified => 'M',
+            State::Missing => '?',
+            State::Orphaned => '!',
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PackageStatus {
+    package: String,
+    state: State,
+    patched_dir: Utf8PathBuf,
+    /// When the stitches were last applied to the patched copy, in seconds since the Unix
+    /// epoch, from its state file; or, if it has none, the modification time of its stamp
+    /// file or of the directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated: Option<u64>,
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+    let statuses = package_statuses(workspace, &manifest).map_err(io)?;
+
+    if args.json {
+        let json =
+            serde_json::to_string_pretty(&statuses).map_err(|e| OneOf::new(IoError(e.into())))?;
+        println!("{json}");
+    } else {
+        print!("{}", render(&statuses, SystemTime::now()));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The status of every crate with stitches or a patched copy, by crate name.
+fn package_statuses(
+    workspace: &Workspace,
+    manifest: &HashMap<String, StitchSet>,
+) -> std::io::Result<Vec<PackageStatus>> {
+    let mut names: BTreeMap<String, Option<&StitchSet>> = patched_crates(&workspace.patched_root)?
+        .into_iter()
+        .map(|name| (name, None))
+        .collect();
+    for (name, stitch_set) in manifest {
+        names.insert(name.clone(), Some(stitch_set));
+    }
+
+    let packages = workspace_packages(&workspace.root).unwrap_or_default();
+
+    let mut statuses = Vec::new();
+    for (name, stitch_set) in names {
+        let dir = patched_dir(&name, &workspace.patched_root);
+        let updated = last_updated(&dir);
+        let manifest_dir = packages
+            .iter()
+            .find(|p| p.name.as_str() == name)
+            .and_then(|p| p.manifest_path.parent());
+
+        let state = match stitch_set {
+            None => State::Orphaned,
+            Some(_) if !dir.is_dir() => State::Missing,
+            Some(stitch_set) if is_up_to_date(&dir, manifest_dir, stitch_set, updated)? => {
+                State::UpToDate
+            }
+            Some(_) => State::Modified,
+        };
+
+        statuses.push(PackageStatus {
+            package: name,
+            state,
+            patched_dir: dir,
+            updated: updated
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_secs()),
+        });
+    }
+    Ok(statuses)
+}
+
+/// The names of the patched copies in `patched_root`, skipping the manifests and other
+/// hidden files cargo-stitch keeps there.
+fn patched_crates(patched_root: &Utf8Path) -> std::io::Result<Vec<String>> {
+    let entries = match fs::read_dir(patched_root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str()
+            && !name.starts_with('.')
+        {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// When the stitches were applied to `dir`, as its state file records, or else the
+/// modification time of its stamp file or of `dir` itself.
+fn last_updated(dir: &Utf8Path) -> Option<SystemTime> {
+    if let Some(state) = StitchState::load(&dir.join(STATE_FILE)) {
+        return Some(UNIX_EPOCH + Duration::from_secs(state.applied_at));
+    }
+    fs::metadata(dir.join(STAMP_FILE))
+        .or_else(|_| fs::metadata(dir))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Whether the next build would reuse `dir` as it is, checked the way the wrapper does:
+/// first by stamp, then by the hashes of its state file.  Without the crate's sources (e.g. for a crate that is not
+/// a workspace member), only whether a stitch file is newer than `updated` can tell.
+fn is_up_to_date(
+    dir: &Utf8Path,
+    manifest_dir: Option<&Utf8Path>,
+    stitch_set: &StitchSet,
+    updated: Option<SystemTime>,
+) -> std::io::Result<bool> {
+    let Some(manifest_dir) = manifest_dir else {
+        let Some(updated) = updated else {
+            return Ok(false);
+        };
+        for path in stitch_set.input_paths() {
+            if fs::metadata(path)?.modified()? > updated {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    };
+
+    let stitch_files: Vec<&Utf8Path> = stitch_set.input_paths().collect();
+    let stamp = StitchStamp::compute(manifest_dir, &stitch_files)?;
+    if StitchStamp::load(&dir.join(STAMP_FILE)).as_ref() == Some(&stamp) {
+        return Ok(true);
+    }
+
+    let state = StitchState::compute(manifest_dir, stitch_set)?;
+    Ok(StitchState::load(&dir.join(STATE_FILE)).is_some_and(|stored| stored.matches(&state)))
+}
+
+/// One line per crate, `<symbol> <crate>  <what it means>`, in the style of
+/// `git status --short`.
+fn render(statuses: &[PackageStatus], now: SystemTime) -> String {
+    let width = statuses.iter().map(|s| s.package.len()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    for status in statuses {
+        let updated = status
+            .updated
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+            .map(|time| format_age(now.duration_since(time).unwrap_or_default()));
+        let detail = match (status.state, updated) {
+            (State::UpToDate, Some(age)) => format!("up to date, updated {age}"),
+            (State::UpToDate, None) => "up to date".to_string(),
+            (State::Modified, Some(age)) => format!("changed since the last build {age}"),
+            (State::Modified, None) => "changed since the last build".to_string(),
+            (State::Missing, _) => "not built yet".to_string(),
+            (State::Orphaned, _) => "no stitches".to_string(),
+        };
+        _ = writeln!(
+            out,
+            "{} {:<width$}  {detail}",
+            status.state.symbol(),
+            status.package
+        );
+    }
+    out
+}
+
+/// How long ago something happened, e.g. `5 minutes ago`, in the largest whole unit.
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (count, unit) = match secs {
+        0..60 => return "just now".to_string(),
+        60..3_600 => (secs / 60, "minute"),
+        3_600..86_400 => (secs / 3_600, "hour"),
+        _ => (secs / 86_400, "day"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural} ago")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_json_flag() {
+        assert!(!Args::parse(&[]).unwrap().json);
+        assert!(Args::parse(&["--json".to_string()]).unwrap().json);
+        assert!(Args::parse(&["--bogus".to_string()]).is_err());
+    }
+
+    #[test]
+    fn format_age_in_largest_unit() {
+        assert_eq!(format_age(Duration::from_secs(59)), "just now");
+        assert_eq!(format_age(Duration::from_secs(60)), "1 minute ago");
+        assert_eq!(
+            format_age(Duration::from_secs(3 * 3_600 + 5)),
+            "3 hours ago"
+        );
+        assert_eq!(format_age(Duration::from_secs(2 * 86_400)), "2 days ago");
+    }
+
+    #[test]
+    fn render_one_line_per_crate() {
+        let status = |package: &str, state, updated| PackageStatus {
+            package: package.to_string(),
+            state,
+            patched_dir: Utf8PathBuf::from("target/cargo-stitch").join(package),
+            updated,
+        };
+        let statuses = [
+            status("crate-a", State::UpToDate, Some(1_000)),
+            status("crate-b", State::Modified, Some(1_000)),
+            status("crate-long", State::Missing, None),
+            status("old", State::Orphaned, Some(1_000)),
+        ];
+
+        assert_eq!(
+            render(&statuses, UNIX_EPOCH + Duration::from_secs(1_000 + 120)),
+            "✓ crate-a     up to date, updated 2 minutes ago\n\
+             M crate-b     changed since the last build 2 minutes ago\n\
+             ? crate-long  not built yet\n\
+             ! old         no stitches\n"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! `cargo stitch summarize`: a dashboard of every patched crate and the health of its stitches.
+
+use std::fmt;
+use std::process::ExitCode;
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, dependency_key, is_applied, usage_error};
+use crate::check_required_tools;
+use crate::error::IoError;
+use crate::fs::{
+    CopyOptions, cargo_home, copy_dir_recursive, dependency_packages, temp_dir, workspace_packages,
+};
+use crate::hash::{STAMP_FILE, StitchStamp};
+use crate::stitch::StitchSet;
+use crate::wrapper::patched_dir;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    exit_code: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut exit_code = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--exit-code" => exit_code = true,
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `summarize`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { exit_code })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// All stitches apply and the patched directory reflects them.
+    Ok,
+    /// All stitches apply, but the patched directory is missing or stale.
+    Outdated,
+    /// At least one stitch fails to apply.
+    Failing,
+    /// The stitch directory does not match any workspace member or stitched dependency.
+    Orphaned,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Status::Ok => "ok",
+            Status::Outdated => "outdated",
+            Status::Failing => "failing",
+            Status::Orphaned => "orphaned",
+        };
+        f.pad(s)
+    }
+}
+
+struct Row {
+    krate: String,
+    version: String,
+    stitches: usize,
+    status: Status,
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+
+    check_required_tools(manifest.values(), &workspace.output).map_err(OneOf::broaden)?;
+
+    let packages = workspace_packages(&workspace.root).unwrap_or_default();
+    // The dependencies, only looked for if there are stitches for some.
+    let mut dependencies = None;
+    let registry_src = cargo_home()
+        .map_err(|e| OneOf::new(IoError(e)))?
+        .join("registry")
+        .join("src");
+
+    let mut pkg_names: Vec<_> = manifest.keys().collect();
+    pkg_names.sort();
+
+    let mut rows = Vec::with_capacity(pkg_names.len());
+    for pkg_name in pkg_names {
+        let stitch_set = &manifest[pkg_name];
+        let stitches = stitch_set.file_paths().count();
+
+        let package = match packages.iter().find(|p| p.name.as_str() == pkg_name) {
+            Some(package) => Some(package),
+            None if is_applied(workspace, &packages, pkg_name, stitch_set) => dependencies
+                .get_or_insert_with(|| dependency_packages(&workspace.root).unwrap_or_default())
+                .iter()
+                .find(|p| dependency_key(workspace, p, &registry_src).as_ref() == Some(pkg_name)),
+            None => None,
+        };
+        let Some(package) = package else {
+            rows.push(Row {
+                krate: pkg_name.clone(),
+                version: "-".to_string(),
+                stitches,
+                status: Status::Orphaned,
+            });
+            continue;
+        };
+
+        let manifest_dir = package.manifest_path.parent().unwrap_or(&workspace.root);
+        let status = status_of(pkg_name, manifest_dir, stitch_set, &workspace.patched_root)
+            .map_err(|e| OneOf::new(IoError(e)))?;
+
+        rows.push(Row {
+            krate: pkg_name.clone(),
+            version: package.version.to_string(),
+            stitches,
+            status,
+        });
+    }
+
+    print!("{}", render_table(&rows));
+
+    let healthy = rows.iter().all(|row| row.status == Status::Ok);
+    if args.exit_code && !healthy {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Determine the health of one crate's stitches by applying them to a scratch copy of
+/// its source and checking whether the patched directory is up to date.
+fn status_of(
+    pkg_name: &str,
+    manifest_dir: &Utf8Path,
+    stitch_set: &StitchSet,
+    patched_root: &Utf8Path,
+) -> std::io::Result<Status> {
+    let (_guard, scratch) = temp_dir()?;
+    copy_dir_recursive(manifest_dir, &scratch, &CopyOptions::default())?;
+
+    if stitch_set.apply_quietly(&scratch).is_err() {
+        return Ok(Status::Failing);
+    }
+
+    let inputs: Vec<&Utf8Path> = stitch_set.input_paths().collect();
+    let patched_dir = patched_dir(pkg_name, patched_root);
+
+    let stamp = StitchStamp::compute(manifest_dir, &inputs)?;
+    if StitchStamp::load(&patched_dir.join(STAMP_FILE)) == Some(stamp) {
+        Ok(Status::Ok)
+    } else {
+        Ok(Status::Outdated)
+    }
+}
+
+fn render_table(rows: &[Row]) -> String {
+    let crate_width = rows
+        .iter()
+        .map(|r| r.krate.len())
+        .fold("Crate".len(), usize::max);
+    let version_width = rows
+        .iter()
+        .map(|r| r.version.len())
+        .fold("Version".len(), usize::max);
+
+    let mut out = format!(
+        "{:<crate_width$}  {:<version_width$}  {:>8}  Status\n",
+        "Crate", "Version", "Stitches"
+    );
+    for row in rows {
+        out.push_str(&format!(
+            "{:<crate_width$}  {:<version_width$}  {:>8}  {}\n",
+            row.krate, row.version, row.stitches, row.status
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_defaults() {
+        assert_eq!(Args::parse(&[]).unwrap(), Args { exit_code: false });
+    }
+
+    #[test]
+    fn parse_exit_code() {
+        let args = Args::parse(&["--exit-code".to_string()]).unwrap();
+        assert!(args.exit_code);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_argument() {
+        let err = Args::parse(&["--bogus".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("--bogus"));
+    }
+
+    #[test]
+    fn render_table_aligns_columns() {
+        let rows = vec![
+            Row {
+                krate: "config".to_string(),
+                version: "0.1.0".to_string(),
+                stitches: 3,
+                status: Status::Ok,
+            },
+            Row {
+                krate: "a-much-longer-name".to_string(),
+                version: "10.20.30".to_string(),
+                stitches: 12,
+                status: Status::Failing,
+            },
+        ];
+
+        assert_eq!(
+            render_table(&rows),
+            "Crate               Version   Stitches  Status\n\
+             config              0.1.0            3  ok\n\
+             a-much-longer-name  10.20.30        12  failing\n"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! `cargo stitch trace <crate>`: show what each stitch changes, one step at a time.
+
+use std::fs;
+use std::process::{Command, ExitCode};
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::error::{IoError, MissingTool};
+use crate::fs::{CopyOptions, copy_dir_recursive, diff_dirs, temp_dir};
+use crate::stitch::{ApplyError, StitchSet};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut krate = None;
+
+        for arg in args {
+            match arg.as_str() {
+                other if other.starts_with('-') || krate.is_some() => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `trace`: {other}"
+                    )));
+                }
+                other => krate = Some(other.to_string()),
+            }
+        }
+
+        let krate = krate.ok_or_else(|| usage_error("usage: cargo stitch trace <crate>"))?;
+        Ok(Self { krate })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let stitch_set = workspace.stitch_set(&args.krate).map_err(OneOf::broaden)?;
+
+    check_required_tools([&stitch_set], &workspace.output).map_err(OneOf::broaden)?;
+    if Command::new("diff").arg("--version").output().is_err() {
+        return Err(OneOf::new(MissingTool("diff")));
+    }
+
+    let manifest_dir = workspace.member_dir(&args.krate).map_err(OneOf::new)?;
+
+    print!(
+        "{}",
+        trace(&manifest_dir, &stitch_set).map_err(OneOf::broaden)?
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Apply the stitches of `stitch_set` one at a time to a scratch copy of `manifest_dir`,
+/// returning the diff introduced by each step.
+///
+/// The scratch directory holds the state before the step in `a/` and after it in `b/`,
+/// so the diffs read like `git diff` output.
+fn trace(manifest_dir: &Utf8Path, stitch_set: &StitchSet) -> Result<String, ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    let before = scratch.join("a");
+    let after = scratch.join("b");
+    copy_dir_recursive(manifest_dir, &before, &CopyOptions::default()).map_err(io)?;
+
+    let total = stitch_set.len();
+    let mut out = String::new();
+
+    for (i, stitch) in stitch_set.iter().enumerate() {
+        copy_dir_recursive(&before, &after, &CopyOptions::default()).map_err(io)?;
+        stitch.apply_quietly(&after, stitch_set)?;
+
+        let file_name = stitch.path().file_name().unwrap_or_default();
+        out.push_str(&format!("==> [{}/{total}] {file_name}\n", i + 1));
+
+        let diff = diff_dirs(&before, &after).map_err(io)?;
+        if diff.is_empty() {
+            out.push_str("(no changes)\n");
+        } else {
+            out.push_str(&diff);
+        }
+        out.push('\n');
+
+        fs::remove_dir_all(&before).map_err(io)?;
+        fs::rename(&after, &before).map_err(io)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_crate() {
+        let args = Args::parse(&["config".to_string()]).unwrap();
+        assert_eq!(args.krate, "config");
+    }
+
+    #[test]
+    fn parse_requires_crate() {
+        let err = Args::parse(&[]).unwrap_err();
+        assert!(err.to_string().contains("usage"));
+    }
+
+    #[test]
+    fn parse_rejects_extra_arguments() {
+        let err = Args::parse(&["a".to_string(), "b".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("unexpected argument"));
+
+        let err = Args::parse(&["--bogus".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("--bogus"));
+    }
+}
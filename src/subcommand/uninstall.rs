@@ -0,0 +1,118 @@
+//! `cargo stitch uninstall <crate>`: remove every stitch for a crate, along with its
+//! patched directory.
+//!
+//! This only takes over `uninstall` when `<crate>` has stitches; anything else is
+//! forwarded to `cargo uninstall` as usual.
+
+use std::fs;
+use std::process::ExitCode;
+
+use camino::Utf8Path;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, confirm, usage_error};
+use crate::error::IoError;
+use crate::stitch::cargo_status;
+use crate::wrapper::patched_dir;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    yes: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut krate = None;
+        let mut yes = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--yes" | "-y" => yes = true,
+                other if other.starts_with('-') || krate.is_some() => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `uninstall`: {other}"
+                    )));
+                }
+                other => krate = Some(other.to_string()),
+            }
+        }
+
+        let krate = krate.ok_or_else(|| {
+            usage_error("usage: cargo stitch uninstall <crate> [--yes] [--keep-patched]")
+        })?;
+        Ok(Self { krate, yes })
+    }
+}
+
+/// Returns `true` if `args` (following `uninstall`) name a crate with stitches, rather
+/// than a binary for `cargo uninstall`.
+pub(super) fn names_stitched_crate(workspace: &Workspace, args: &[String]) -> bool {
+    Args::parse(args).is_ok_and(|args| workspace.stitches_dir.join(&args.krate).is_dir())
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let stitches = workspace.stitches_dir.join(&args.krate);
+    let patched = patched_dir(&args.krate, &workspace.patched_root);
+    let remove_patched = !workspace.keep_patched && patched.is_dir();
+
+    let display = |path: &Utf8Path| {
+        path.strip_prefix(&workspace.root)
+            .unwrap_or(path)
+            .to_string()
+    };
+
+    if !args.yes {
+        let mut targets = vec![display(&stitches)];
+        if remove_patched {
+            targets.push(display(&patched));
+        }
+        if !confirm(&format!("Remove {}?", targets.join(" and "))).map_err(io)? {
+            cargo_status(&workspace.output, "Cancelled", "nothing was removed");
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    fs::remove_dir_all(&stitches).map_err(io)?;
+    cargo_status(&workspace.output, "Removed", &display(&stitches));
+
+    if remove_patched {
+        fs::remove_dir_all(&patched).map_err(io)?;
+        cargo_status(&workspace.output, "Removed", &display(&patched));
+    } else if patched.is_dir() {
+        cargo_status(
+            &workspace.output,
+            "Kept",
+            &format!("{} for inspection", display(&patched)),
+        );
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_crate_and_yes() {
+        let args = Args::parse(&["config".to_string(), "-y".to_string()]).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                krate: "config".to_string(),
+                yes: true
+            }
+        );
+    }
+
+    #[test]
+    fn parse_rejects_cargo_uninstall_flags() {
+        assert!(Args::parse(&[]).is_err());
+        assert!(Args::parse(&["--root".to_string(), "dir".to_string()]).is_err());
+        assert!(Args::parse(&["a".to_string(), "b".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! `cargo stitch upgrade <crate> [<version>] [--apply]`: check which of a crate's stitches
+//! still apply to a newer version of it, e.g. after `cargo update`.
+//!
+//! The new version's source is fetched as `cargo stitch fetch` does, and each stitch is
+//! applied on top of those before it that applied, as `cargo stitch verify` does.  Stitch
+//! files are never changed.  With `--apply`, the result is kept in
+//! `target/cargo-stitch/.upgrade/<crate>-<version>/`, to fix the rejected stitches against.
+//!
+//! This only takes over `upgrade` when it names a crate with stitches; anything else is
+//! forwarded to `cargo upgrade` (from cargo-edit) as usual.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::process::ExitCode;
+
+use cargo_metadata::MetadataCommand;
+use terrors::OneOf;
+
+use super::check::{TestCase, check_stitches_in};
+use super::fetch::fetch_source;
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::error::IoError;
+use crate::fs::{CopyOptions, copy_dir_recursive, temp_dir};
+use crate::stitch::cargo_status;
+
+const USAGE: &str = "usage: cargo stitch upgrade <crate> [<version>] [--apply]";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+    /// The version to check against; the one in `Cargo.lock` if not given.
+    version: Option<String>,
+    apply: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut positional = Vec::new();
+        let mut apply = false;
+
+        for arg in args {
+            match arg.as_str() {
+                "--apply" => apply = true,
+                other if other.starts_with('-') || positional.len() == 2 => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `upgrade`: {other}"
+                    )));
+                }
+                other => positional.push(other.to_string()),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let krate = positional.next().ok_or_else(|| usage_error(USAGE))?;
+
+        Ok(Self {
+            krate,
+            version: positional.next(),
+            apply,
+        })
+    }
+}
+
+/// Returns `true` if `args` (following `upgrade`) name a crate with stitches, rather than
+/// options for cargo-edit's `cargo upgrade`.
+pub(super) fn names_stitched_crate(workspace: &Workspace, args: &[String]) -> bool {
+    Args::parse(args).is_ok_and(|args| workspace.stitches_dir.join(&args.krate).is_dir())
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let stitch_set = workspace.stitch_set(&args.krate).map_err(OneOf::broaden)?;
+    check_required_tools([&stitch_set], &workspace.output).map_err(OneOf::broaden)?;
+
+    let version = match args.version {
+        Some(version) => version,
+        None => locked_version(workspace, &args.krate)?,
+    };
+    let source = fetch_source(workspace, &args.krate, &version)?;
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    let dest = if args.apply {
+        let dest = workspace
+            .target_dir
+            .join("cargo-stitch")
+            .join(".upgrade")
+            .join(format!("{}-{version}", args.krate));
+        let _ = fs::remove_dir_all(&dest);
+        dest
+    } else {
+        scratch
+    };
+    copy_dir_recursive(&source, &dest, &CopyOptions::default()).map_err(io)?;
+
+    let cases = check_stitches_in(&args.krate, &dest, &stitch_set, &workspace.root)
+        .map_err(OneOf::broaden)?;
+    print!("{}", render(&args.krate, &version, &cases));
+
+    if args.apply {
+        let applied = cases.iter().filter(|c| c.failure.is_none()).count();
+        cargo_status(
+            &workspace.output,
+            "Applied",
+            &format!(
+                "{applied} of {} stitches to {} v{version} in {dest}",
+                cases.len(),
+                args.krate
+            ),
+        );
+    }
+
+    if cases.iter().any(|case| case.failure.is_some()) {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// The version of the dependency `krate` in the workspace's `Cargo.lock`, the highest if
+/// there are several.
+fn locked_version(workspace: &Workspace, krate: &str) -> Result<String, SubcommandError> {
+    let metadata = MetadataCommand::new()
+        .current_dir(&workspace.root)
+        .exec()
+        .map_err(|e| OneOf::new(usage_error(format!("cargo metadata failed: {e}"))))?;
+
+    metadata
+        .packages
+        .iter()
+        .filter(|p| p.name.as_str() == krate && p.source.is_some())
+        .map(|p| &p.version)
+        .max()
+        .map(ToString::to_string)
+        .ok_or_else(|| {
+            OneOf::new(usage_error(format!(
+                "`{krate}` is not a registry dependency of the workspace; \
+                 pass the version to upgrade to, e.g. `cargo stitch upgrade {krate} <version>`"
+            )))
+        })
+}
+
+/// One line per stitch, `<status> <file name>`, followed by the output of the tool for
+/// the stitches that are rejected, and a summary.
+fn render(krate: &str, version: &str, cases: &[TestCase]) -> String {
+    let mut out = String::new();
+    for case in cases {
+        let file_name = case.name.file_name().unwrap_or(case.name.as_str());
+        let status = if case.failure.is_some() {
+            "FAILED"
+        } else {
+            "ok"
+        };
+        _ = writeln!(out, "{status:<7} {file_name}");
+        for line in case.failure.iter().flat_map(|s| s.lines()) {
+            _ = writeln!(out, "        {line}");
+        }
+    }
+
+    let failed = cases.iter().filter(|c| c.failure.is_some()).count();
+    _ = writeln!(
+        out,
+        "\n{} stitches checked against {krate} v{version}: {} apply, {failed} rejected",
+        cases.len(),
+        cases.len() - failed
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_crate_version_and_apply() {
+        assert_eq!(
+            Args::parse(&args(&["serde"])).unwrap(),
+            Args {
+                krate: "serde".to_string(),
+                version: None,
+                apply: false,
+            }
+        );
+        assert_eq!(
+            Args::parse(&args(&["--apply", "serde", "1.0.200"])).unwrap(),
+            Args {
+                krate: "serde".to_string(),
+                version: Some("1.0.200".to_string()),
+                apply: true,
+            }
+        );
+        assert!(Args::parse(&[]).is_err());
+        assert!(Args::parse(&args(&["serde", "1.0.200", "extra"])).is_err());
+        assert!(Args::parse(&args(&["serde", "--dry-run"])).is_err());
+    }
+
+    #[test]
+    fn render_lists_stitches_and_rejected_hunks() {
+        let case = |name: &str, failure: Option<&str>| TestCase {
+            krate: "serde".to_string(),
+            name: Utf8PathBuf::from(format!("stitches/default/serde/{name}")),
+            failure: failure.map(ToString::to_string),
+            warning: None,
+        };
+        let cases = [
+            case("001-derive.patch", None),
+            case(
+                "002-no-std.patch",
+                Some("failed to apply patch\n1 out of 1 hunk FAILED"),
+            ),
+        ];
+
+        assert_eq!(
+            render("serde", "1.0.200", &cases),
+            "ok      001-derive.patch\n\
+             FAILED  002-no-std.patch\n        \
+             failed to apply patch\n        \
+             1 out of 1 hunk FAILED\n\
+             \n2 stitches checked against serde v1.0.200: 1 apply, 1 rejected\n"
+        );
+    }
+}
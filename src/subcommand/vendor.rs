@@ -0,0 +1,111 @@
+//! `cargo stitch vendor [<cargo vendor args>...]`: run `cargo vendor`, then apply the
+//! stitches of every vendored crate that has some directly to its vendored copy.
+
+use std::env;
+use std::process::{Command, ExitCode};
+
+use camino::Utf8PathBuf;
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace};
+use crate::check_required_tools;
+use crate::error::{CargoFailed, IoError};
+use crate::stitch::{StitchSet, cargo_status};
+use crate::vendor::{VENDOR_DIR, is_stitched_in_place, stitch_in_place, vendored_dirs};
+
+/// `cargo vendor` options that take a value as the next argument.
+const OPTIONS_WITH_VALUES: &[&str] = &[
+    "-s",
+    "--sync",
+    "--manifest-path",
+    "--lockfile-path",
+    "--config",
+    "--color",
+    "-Z",
+];
+
+/// The directory `cargo vendor` writes to with `args`: its positional argument if given,
+/// `vendor` otherwise, relative to the current directory.
+fn vendor_path(args: &[String]) -> &str {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if OPTIONS_WITH_VALUES.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            return arg;
+        }
+    }
+    VENDOR_DIR
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+
+    let status = Command::new("cargo")
+        .arg("vendor")
+        .args(args)
+        .status()
+        .map_err(io)?;
+    if !status.success() {
+        return Err(OneOf::new(CargoFailed(status.code().unwrap_or(1))));
+    }
+
+    let cwd = Utf8PathBuf::try_from(env::current_dir().map_err(io)?)
+        .map_err(|e| io(e.into_io_error()))?;
+    let vendor_dir = cwd.join(vendor_path(args));
+
+    let mut krates: Vec<(&String, &StitchSet)> = manifest.iter().collect();
+    krates.sort_by_key(|(krate, _)| *krate);
+
+    let vendored: Vec<_> = krates
+        .into_iter()
+        .flat_map(|(krate, stitch_set)| {
+            vendored_dirs(&vendor_dir, krate)
+                .into_iter()
+                .map(move |dir| (stitch_set, dir))
+        })
+        .collect();
+
+    check_required_tools(vendored.iter().map(|(set, _)| *set), &workspace.output)
+        .map_err(OneOf::broaden)?;
+
+    for (stitch_set, dir) in vendored {
+        let display = dir.strip_prefix(&cwd).unwrap_or(&dir);
+        if is_stitched_in_place(&dir) {
+            cargo_status(&workspace.output, "Fresh", display.as_str());
+            continue;
+        }
+        stitch_in_place(&dir, stitch_set, &workspace.output).map_err(OneOf::broaden)?;
+        cargo_status(&workspace.output, "Stitched", display.as_str());
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn vendor_path_defaults_to_vendor() {
+        assert_eq!(vendor_path(&[]), "vendor");
+        assert_eq!(vendor_path(&args(&["--versioned-dirs"])), "vendor");
+        assert_eq!(vendor_path(&args(&["-s", "other/Cargo.toml"])), "vendor");
+    }
+
+    #[test]
+    fn vendor_path_skips_option_values() {
+        assert_eq!(vendor_path(&args(&["third-party"])), "third-party");
+        assert_eq!(
+            vendor_path(&args(&["--sync", "other/Cargo.toml", "deps"])),
+            "deps"
+        );
+        assert_eq!(
+            vendor_path(&args(&["--manifest-path=a/Cargo.toml", "deps"])),
+            "deps"
+        );
+    }
+}
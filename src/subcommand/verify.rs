@@ -0,0 +1,149 @@
+//! `cargo stitch verify [--package <crate>]...`: check that every stitch still applies to
+//! the current sources of its crate, without building anything.
+//!
+//! This is the check behind `cargo stitch check --report-format human`, with output meant
+//! to be read after updating a dependency: each stitch that no longer applies is listed
+//! with its crate and the hunks that `patch` (or ast-grep, sed, ...) rejected.
+
+use std::fmt::Write as _;
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::check::{TestCase, check_packages};
+use super::{SubcommandError, Workspace, usage_error};
+use crate::error::IoError;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Args {
+    packages: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        let mut packages = Vec::new();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--package" | "-p" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| usage_error("`--package` requires a crate name"))?;
+                    packages.push(value.clone());
+                }
+                other => {
+                    return Err(usage_error(format!(
+                        "unexpected argument for `verify`: {other}"
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { packages })
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+
+    let manifest = workspace.discover().map_err(OneOf::broaden)?;
+
+    if let Some(unknown) = args.packages.iter().find(|p| !manifest.contains_key(*p)) {
+        return Err(OneOf::new(usage_error(format!(
+            "no stitches found for `{unknown}`"
+        ))));
+    }
+
+    let mut pkg_names: Vec<_> = manifest
+        .keys()
+        .filter(|name| args.packages.is_empty() || args.packages.contains(name))
+        .collect();
+    pkg_names.sort();
+
+    let cases = check_packages(workspace, &manifest, &pkg_names)?;
+    print!("{}", render(&cases));
+
+    if cases.iter().any(|case| case.failure.is_some()) {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// One line per stitch, `<status> <crate> <file name>`, followed by the output of the
+/// tool for the stitches that fail, and a summary.
+fn render(cases: &[TestCase]) -> String {
+    let mut out = String::new();
+    for case in cases {
+        let file_name = case.name.file_name().unwrap_or(case.name.as_str());
+        let status = if case.failure.is_some() {
+            "FAILED"
+        } else {
+            "ok"
+        };
+        _ = writeln!(out, "{status:<7} {}  {file_name}", case.krate);
+        for line in case.failure.iter().flat_map(|s| s.lines()) {
+            _ = writeln!(out, "        {line}");
+        }
+        if let Some(warning) = &case.warning {
+            _ = writeln!(out, "        warning: {warning}");
+        }
+    }
+
+    let failed = cases.iter().filter(|c| c.failure.is_some()).count();
+    _ = writeln!(
+        out,
+        "\n{} stitches verified: {} apply, {failed} rejected",
+        cases.len(),
+        cases.len() - failed
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+
+    use super::*;
+    use crate::subcommand::args;
+
+    #[test]
+    fn parse_packages() {
+        assert_eq!(Args::parse(&[]).unwrap(), Args::default());
+        assert_eq!(
+            Args::parse(&args(&["-p", "crate-a", "--package", "crate-b"])).unwrap(),
+            Args {
+                packages: vec!["crate-a".to_string(), "crate-b".to_string()]
+            }
+        );
+        assert!(Args::parse(&args(&["--package"])).is_err());
+        assert!(Args::parse(&args(&["crate-a"])).is_err());
+    }
+
+    #[test]
+    fn render_lists_crate_file_and_rejected_hunks() {
+        let case = |name: &str, failure: Option<&str>| TestCase {
+            krate: "crate-a".to_string(),
+            name: Utf8PathBuf::from(format!("stitches/default/crate-a/{name}")),
+            failure: failure.map(ToString::to_string),
+            warning: None,
+        };
+        let cases = [
+            case("001-greeting.patch", None),
+            case(
+                "002-exclaim.patch",
+                Some("failed to apply patch\n1 out of 1 hunk FAILED"),
+            ),
+        ];
+
+        assert_eq!(
+            render(&cases),
+            "ok      crate-a  001-greeting.patch\n\
+             FAILED  crate-a  002-exclaim.patch\n        \
+             failed to apply patch\n        \
+             1 out of 1 hunk FAILED\n\
+             \n2 stitches verified: 1 apply, 1 rejected\n"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! `cargo stitch verify-applied <crate>`: check that the patched directory from the last
+//! build is exactly what applying the current stitches produces.
+
+use std::process::ExitCode;
+
+use terrors::OneOf;
+
+use super::{SubcommandError, Workspace, usage_error};
+use crate::check_required_tools;
+use crate::error::IoError;
+use crate::fs::{CopyOptions, compare_dirs, copy_dir_recursive, temp_dir};
+use crate::hash::{STAMP_FILE, STATE_FILE};
+use crate::stitch::cargo_status;
+use crate::wrapper::patched_dir;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    krate: String,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, IoError> {
+        match args {
+            [krate] if !krate.starts_with('-') => Ok(Self {
+                krate: krate.clone(),
+            }),
+            _ => Err(usage_error("usage: cargo stitch verify-applied <crate>")),
+        }
+    }
+}
+
+pub(super) fn run(workspace: &Workspace, args: &[String]) -> Result<ExitCode, SubcommandError> {
+    let args = Args::parse(args).map_err(OneOf::new)?;
+    let io = |e| OneOf::new(IoError(e));
+
+    let stitch_set = workspace.stitch_set(&args.krate).map_err(OneOf::broaden)?;
+    let manifest_dir = workspace.member_dir(&args.krate).map_err(OneOf::new)?;
+
+    let patched_dir = patched_dir(&args.krate, &workspace.patched_root);
+    if !patched_dir.is_dir() {
+        cargo_status(
+            &workspace.output,
+            "Error",
+            &format!("{}: {patched_dir} does not exist; build first", args.krate),
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+
+    check_required_tools([&stitch_set], &workspace.output).map_err(OneOf::broaden)?;
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    copy_dir_recursive(&manifest_dir, &scratch, &CopyOptions::default()).map_err(io)?;
+    stitch_set.apply_quietly(&scratch).map_err(OneOf::broaden)?;
+
+    let differing = compare_dirs(&scratch, &patched_dir, &[STAMP_FILE, STATE_FILE]).map_err(io)?;
+
+    if differing.is_empty() {
+        cargo_status(
+            &workspace.output,
+            "Verified",
+            &format!("{}: patched directory matches its stitches", args.krate),
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    for path in &differing {
+        cargo_status(
+            &workspace.output,
+            "Differs",
+            &format!("{}: {path}", args.krate),
+        );
+    }
+    Ok(ExitCode::FAILURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_crate() {
+        let args = Args::parse(&["config".to_string()]).unwrap();
+        assert_eq!(args.krate, "config");
+    }
+
+    #[test]
+    fn parse_requires_exactly_one_crate() {
+        assert!(Args::parse(&[]).is_err());
+        assert!(Args::parse(&["a".to_string(), "b".to_string()]).is_err());
+        assert!(Args::parse(&["--all".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! The external tools cargo-stitch runs to apply stitches, and the oldest version of each
+//! that it supports.
+
+use std::env;
+use std::io::{self, Read};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::TIMEOUT_ENV;
+use crate::error::StitchTimeout;
+
+/// How long a tool may take to apply one stitch, unless `CARGO_STITCH_TIMEOUT_SECS` says
+/// otherwise.
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+pub struct Tool {
+    pub name: &'static str,
+    /// The oldest version known to work.
+    pub min_version: &'static str,
+    /// The kind of stitch that needs this tool.
+    pub needed_for: &'static str,
+    /// Whether cargo-stitch can do without the tool, which it only falls back to.
+    pub optional: bool,
+}
+
+pub const PATCH: Tool = Tool {
+    name: "patch",
+    min_version: "2.0",
+    needed_for: "`.patch` stitches the built-in applier cannot apply",
+    optional: true,
+};
+
+pub const AST_GREP: Tool = Tool {
+    name: "ast-grep",
+    min_version: "0.20.0",
+    needed_for: "ast-grep rules",
+    optional: false,
+};
+
+pub const SED: Tool = Tool {
+    name: "sed",
+    min_version: "4.2",
+    needed_for: "`.sed` stitches",
+    optional: false,
+};
+
+pub const BASH: Tool = Tool {
+    name: "bash",
+    min_version: "3.2",
+    needed_for: "`.sh` stitches",
+    optional: false,
+};
+
+pub const GIT: Tool = Tool {
+    name: "git",
+    min_version: "2.0",
+    needed_for: "`.patch` stitches with `backend = \"git\"`",
+    optional: false,
+};
+
+pub const TOOLS: &[Tool] = &[PATCH, AST_GREP, SED, BASH, GIT];
+
+/// The first executable named `name` in `$PATH`.
+pub fn find_in_path(name: &str) -> Option<Utf8PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(format!("{name}{}", env::consts::EXE_SUFFIX)))
+        .find(|candidate| candidate.metadata().is_ok_and(|m| is_executable(&m)))
+        .and_then(|candidate| Utf8PathBuf::from_path_buf(candidate).ok())
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+}
+
+/// Windows has no executable bit: a file with the `.exe` suffix is executable.
+#[cfg(not(unix))]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    metadata.is_file()
+}
+
+/// The first line of `<name> --version`, or `None` if the tool cannot be run.
+pub fn version_string(name: &str) -> Option<String> {
+    let output = Command::new(name).arg("--version").output().ok()?;
+    version_line(&output)
+}
+
+/// The first line of the standard output of a `--version` run, if it has one.
+pub fn version_line(output: &Output) -> Option<String> {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().next()?.trim();
+    (!line.is_empty()).then(|| line.to_string())
+}
+
+/// Extract the first dotted version number from a `--version` line, e.g. `[2, 7, 6]`
+/// from `GNU patch 2.7.6`.
+pub fn parse_version(s: &str) -> Option<Vec<u32>> {
+    s.split_whitespace().find_map(|word| {
+        let word = word.trim_start_matches('v');
+        let end = word
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(word.len());
+        let numbers: Vec<u32> = word[..end]
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        (numbers.len() >= 2).then_some(numbers)
+    })
+}
+
+/// Parse a minimum version such as `2.7` or `0.20.0`, which unlike a `--version` line
+/// may have a single component.
+pub fn parse_min_version(s: &str) -> Option<Vec<u32>> {
+    s.trim()
+        .split('.')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()
+}
+
+/// Whether `version` is at least `min`, treating missing components as zero.
+pub fn meets_minimum(version: &[u32], min: &[u32]) -> bool {
+    let len = version.len().max(min.len());
+    let pad = |v: &[u32]| {
+        let mut v = v.to_vec();
+        v.resize(len, 0);
+        v
+    };
+    pad(version) >= pad(min)
+}
+
+/// How long a tool may take to apply one stitch, from `CARGO_STITCH_TIMEOUT_SECS`: 60
+/// seconds by default, or `None` for no limit if it is 0.  A value that is not a number
+/// of seconds is ignored.
+pub fn stitch_timeout() -> Option<Duration> {
+    let secs = env::var(TIMEOUT_ENV)
+        .ok()
+        .and_then(|secs| secs.trim().parse().ok())
+        .unwrap_or(DEFAULT_TIMEOUT_SECS);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Run `cmd` to apply the stitch at `path` with `tool`, like [`Command::output`], but kill
+/// it and fail with a [`StitchTimeout`] if it is still running after `timeout`.
+///
+/// On Unix the tool runs in a process group of its own, and the whole group is killed, so
+/// that the processes it started, e.g. those of a script, do not outlive it.
+pub(crate) fn output_with_timeout(
+    cmd: &mut Command,
+    tool: &'static str,
+    path: &Utf8Path,
+    timeout: Option<Duration>,
+) -> io::Result<Output> {
+    let Some(timeout) = timeout else {
+        return cmd.output();
+    };
+
+    in_own_process_group(cmd);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read both pipes as the tool writes to them, so that it never blocks on a full one.
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = read(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = read(child.stderr.take().map(|p| Box::new(p) as _));
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            kill_process_group(&mut child);
+            let _ = child.wait();
+            // The readers are left behind: a process the tool started outside its group
+            // may still hold the pipes open.
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                StitchTimeout {
+                    tool,
+                    path: path.into(),
+                    elapsed_secs: start.elapsed().as_secs(),
+                },
+            ));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+#[cfg(unix)]
+fn in_own_process_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    cmd.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn in_own_process_group(_cmd: &mut Command) {}
+
+/// Kill `child` and every process in its group, which [`in_own_process_group`] made its
+/// own.
+#[cfg(unix)]
+fn kill_process_group(child: &mut Child) {
+    // The child has not been waited for, so its process group still exists.
+    let Ok(pgid) = libc::pid_t::try_from(child.id()) else {
+        let _ = child.kill();
+        return;
+    };
+    // SAFETY: `killpg` only sends a signal; it does not touch the memory of this process.
+    if unsafe { libc::killpg(pgid, libc::SIGKILL) } != 0 {
+        let _ = child.kill();
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_version_from_version_lines() {
+        assert_eq!(parse_version("GNU patch 2.7.6"), Some(vec![2, 7, 6]));
+        assert_eq!(parse_version("ast-grep 0.38.1"), Some(vec![0, 38, 1]));
+        assert_eq!(parse_version("patch 2.0-12u11-Apple"), Some(vec![2, 0]));
+        assert_eq!(parse_version("tool v1.2"), Some(vec![1, 2]));
+        assert_eq!(parse_version("no version here"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_with_timeout_kills_slow_tools() {
+        let path = Utf8Path::new("001-slow.sh");
+
+        let output = output_with_timeout(
+            Command::new("sh").args(["-c", "echo out; echo err >&2"]),
+            "sh",
+            path,
+            Some(Duration::from_secs(60)),
+        )
+        .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        let e = output_with_timeout(
+            Command::new("sleep").arg("60"),
+            "sleep",
+            path,
+            Some(Duration::from_secs(1)),
+        )
+        .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        let timeout = e.into_inner().unwrap().downcast::<StitchTimeout>().unwrap();
+        assert_eq!(
+            (timeout.tool, timeout.path.to_str()),
+            ("sleep", Some("001-slow.sh"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_with_timeout_kills_what_the_tool_started() {
+        let tmp = tempfile::tempdir().unwrap();
+        let marker = tmp.path().join("marker");
+
+        // The script waits on a subshell that would create `marker` after the timeout.
+        let script = format!("(sleep 2; touch '{}') & wait", marker.display());
+        let e = output_with_timeout(
+            Command::new("sh").args(["-c", &script]),
+            "sh",
+            Utf8Path::new("001-slow.sh"),
+            Some(Duration::from_secs(1)),
+        )
+        .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+        thread::sleep(Duration::from_secs(3));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn meets_minimum_pads_missing_components() {
+        assert!(meets_minimum(&[2, 7, 6], &[2, 0]));
+        assert!(meets_minimum(&[2, 0], &[2, 0, 0]));
+        assert!(!meets_minimum(&[0, 19, 9], &[0, 20, 0]));
+        assert!(meets_minimum(&[0, 100], &[0, 20, 0]));
+    }
+
+    #[test]
+    fn min_versions_parse() {
+        for tool in TOOLS {
+            assert!(
+                parse_min_version(tool.min_version).is_some(),
+                "bad minimum version for {}",
+                tool.name
+            );
+        }
+        assert_eq!(parse_min_version("2"), Some(vec![2]));
+        assert_eq!(parse_min_version("2.x"), None);
+        assert_eq!(parse_min_version(""), None);
+    }
+
+    #[test]
+    fn find_in_path_finds_sh() {
+        assert!(find_in_path("sh").is_some());
+        assert!(find_in_path("no-such-tool-cargo-stitch").is_none());
+    }
+}
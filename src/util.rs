@@ -0,0 +1,160 @@
+use crate::output::OutputContext;
+use crate::stitch::cargo_status;
+
+/// Expand the environment variables in `s`, a value from `stitch.toml` or
+/// `[*.metadata.stitch]`: `$VAR` and `${VAR}` are replaced by the value of `VAR`, and
+/// `$$` by a single `$`.
+///
+/// A variable that is not set is left as it is, with a warning printed as `output` says.
+/// Other syntaxes, such as Windows' `%VAR%`, are not expanded.
+pub(crate) fn expand_env_vars(s: &str, output: &OutputContext) -> String {
+    let (expanded, unset) = expand_vars(s, |name| std::env::var(name).ok());
+    for name in unset {
+        cargo_status(
+            output,
+            "Warning",
+            &format!("environment variable `{name}` is not set, leaving `${name}` as is"),
+        );
+    }
+    expanded
+}
+
+/// [`expand_env_vars`] with `lookup` for the environment, returning the expanded string
+/// and the names of the variables `lookup` did not know.
+///
+/// Substituted values are not expanded again, so a `$` in a variable's value stays as it is.
+fn expand_vars(s: &str, lookup: impl Fn(&str) -> Option<String>) -> (String, Vec<&str>) {
+    let mut out = String::with_capacity(s.len());
+    let mut unset = Vec::new();
+    let mut rest = s;
+
+    while let Some(dollar) = rest.find('$') {
+        out.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+
+        // `${VAR}` or `$VAR`, and what follows it.
+        let (name, reference, next) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) if is_var_name(&braced[..end]) => (
+                    &braced[..end],
+                    &rest[dollar..dollar + end + 3],
+                    &braced[end + 1..],
+                ),
+                _ => ("", "$", after),
+            },
+            None => {
+                let len = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                let name = &after[..len];
+                if is_var_name(name) {
+                    (name, &rest[dollar..dollar + len + 1], &after[len..])
+                } else {
+                    ("", "$", after)
+                }
+            }
+        };
+
+        if name.is_empty() {
+            out.push('$');
+        } else if let Some(value) = lookup(name) {
+            out.push_str(&value);
+        } else {
+            out.push_str(reference);
+            unset.push(name);
+        }
+        rest = next;
+    }
+
+    out.push_str(rest);
+    (out, unset)
+}
+
+/// Whether `name` can be a variable name: ASCII letters, digits and `_`, not starting with
+/// a digit.
+fn is_var_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(s: &str) -> (String, Vec<&str>) {
+        expand_vars(s, |name| match name {
+            "PATCH_FUZZ" => Some("3".to_string()),
+            "HOME" => Some("/home/ci".to_string()),
+            "EMPTY" => Some(String::new()),
+            "DOLLAR" => Some("$HOME".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn expands_both_syntaxes() {
+        assert_eq!(expand("-F$PATCH_FUZZ"), ("-F3".to_string(), vec![]));
+        assert_eq!(
+            expand("${HOME}/stitches"),
+            ("/home/ci/stitches".to_string(), vec![])
+        );
+        assert_eq!(
+            expand("$HOME/stitches"),
+            ("/home/ci/stitches".to_string(), vec![])
+        );
+        assert_eq!(expand("${PATCH_FUZZ}0"), ("30".to_string(), vec![]));
+        assert_eq!(expand("a${EMPTY}b"), ("ab".to_string(), vec![]));
+        assert_eq!(expand("--posix"), ("--posix".to_string(), vec![]));
+    }
+
+    #[test]
+    fn nested_and_adjacent_vars() {
+        assert_eq!(
+            expand("$HOME$PATCH_FUZZ"),
+            ("/home/ci3".to_string(), vec![])
+        );
+        // Values are not expanded again.
+        assert_eq!(expand("${DOLLAR}"), ("$HOME".to_string(), vec![]));
+        // Nor are variable names built from other variables.
+        assert_eq!(
+            expand("${FUZZ_${HOME}}"),
+            ("${FUZZ_/home/ci}".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn missing_vars_are_left_as_is() {
+        assert_eq!(
+            expand("$MISSING/${ALSO_MISSING}-$HOME"),
+            (
+                "$MISSING/${ALSO_MISSING}-/home/ci".to_string(),
+                vec!["MISSING", "ALSO_MISSING"]
+            )
+        );
+    }
+
+    #[test]
+    fn dollar_escapes_and_lone_dollars() {
+        assert_eq!(expand("cost: $$5"), ("cost: $5".to_string(), vec![]));
+        assert_eq!(expand("$$HOME"), ("$HOME".to_string(), vec![]));
+        assert_eq!(expand("$"), ("$".to_string(), vec![]));
+        assert_eq!(
+            expand("$1 ${} ${HOME"),
+            ("$1 ${} ${HOME".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn windows_style_vars_are_not_expanded() {
+        assert_eq!(
+            expand("%HOME%\\stitches"),
+            ("%HOME%\\stitches".to_string(), vec![])
+        );
+    }
+}
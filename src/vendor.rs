@@ -0,0 +1,252 @@
+//! Stitching crates vendored with `cargo vendor`.
+//!
+//! Vendored crates are not workspace members, so cargo only runs the wrapper for them when
+//! it is installed as `RUSTC_WRAPPER`.  The wrapper then patches a copy of the vendored
+//! source, like it does for members.  `cargo stitch vendor` instead stitches the vendored
+//! sources in place, updating `.cargo-checksum.json` so cargo still accepts them, and
+//! leaves a marker so that builds do not apply the stitches a second time.
+
+use std::fs;
+use std::io;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use terrors::OneOf;
+
+use crate::error::IoError;
+use crate::fs::{CopyOptions, compare_dirs, copy_dir_recursive, sync_dir, temp_dir};
+use crate::hash::sha256_hex;
+use crate::output::OutputContext;
+use crate::stitch::{ApplyError, StitchSet};
+
+/// The directory `cargo vendor` writes to by default, relative to the workspace root.
+pub const VENDOR_DIR: &str = "vendor";
+
+/// The file in which cargo records the checksums of a vendored crate's files.
+const CHECKSUM_FILE: &str = ".cargo-checksum.json";
+
+/// Name of the file inside a vendored crate that marks it as stitched in place.  It holds
+/// the SHA-256 of the `.cargo-checksum.json` written at the time, so that a crate which
+/// `cargo vendor` has since copied afresh is not mistaken for a stitched one.
+const APPLIED_FILE: &str = ".cargo-stitch-applied";
+
+/// The directories under `vendor_dir` holding a vendored copy of `krate`: `<krate>/`, or
+/// `<krate>-<version>/` with `cargo vendor --versioned-dirs`.
+pub fn vendored_dirs(vendor_dir: &Utf8Path, krate: &str) -> Vec<Utf8PathBuf> {
+    let Ok(entries) = vendor_dir.read_dir_utf8() else {
+        return Vec::new();
+    };
+
+    let mut dirs: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name();
+            name == krate
+                || name
+                    .strip_prefix(krate)
+                    .and_then(|rest| rest.strip_prefix('-'))
+                    .is_some_and(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.into_path())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Whether the vendored crate in `dir` was stitched in place by `cargo stitch vendor`.
+pub fn is_stitched_in_place(dir: &Utf8Path) -> bool {
+    fs::read_to_string(dir.join(APPLIED_FILE))
+        .is_ok_and(|marker| marker.trim() == checksum_file_hash(dir))
+}
+
+/// Apply `stitch_set` to the vendored crate in `dir`, in place.
+///
+/// The stitches are applied to a scratch copy first, so that a failing stitch leaves `dir`
+/// untouched.  The checksums of the files they change are updated in
+/// `.cargo-checksum.json`.
+pub fn stitch_in_place(
+    dir: &Utf8Path,
+    stitch_set: &StitchSet,
+    output: &OutputContext,
+) -> Result<(), ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let (_guard, scratch) = temp_dir().map_err(io)?;
+    copy_dir_recursive(dir, &scratch, &CopyOptions::default()).map_err(io)?;
+    stitch_set.apply_to_scratch(&scratch, output)?;
+
+    let changed = compare_dirs(dir, &scratch, &[]).map_err(io)?;
+    sync_dir(&scratch, dir).map_err(io)?;
+    update_checksums(dir, &changed).map_err(io)?;
+
+    fs::write(
+        dir.join(APPLIED_FILE),
+        format!("{}\n", checksum_file_hash(dir)),
+    )
+    .map_err(io)
+}
+
+/// Apply `stitch_set` to the vendored crate in `dir`, in place, for `no_copy = true`.
+///
+/// A failing stitch leaves `dir` untouched.  The checksums of every file in
+/// `.cargo-checksum.json` are updated.
+pub fn stitch_in_place_without_copy(
+    dir: &Utf8Path,
+    stitch_set: &StitchSet,
+    output: &OutputContext,
+) -> Result<(), ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    stitch_set.apply(dir, output)?;
+
+    let listed = listed_files(dir).map_err(io)?;
+    update_checksums(dir, &listed).map_err(io)?;
+
+    fs::write(
+        dir.join(APPLIED_FILE),
+        format!("{}\n", checksum_file_hash(dir)),
+    )
+    .map_err(io)
+}
+
+/// The files listed in `dir`'s `.cargo-checksum.json`, relative to `dir`.
+fn listed_files(dir: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>> {
+    let Ok(contents) = fs::read_to_string(dir.join(CHECKSUM_FILE)) else {
+        return Ok(Vec::new());
+    };
+    let checksums: serde_json::Value = serde_json::from_str(&contents)?;
+    Ok(checksums
+        .get("files")
+        .and_then(serde_json::Value::as_object)
+        .map(|files| files.keys().map(Utf8PathBuf::from).collect())
+        .unwrap_or_default())
+}
+
+/// Rewrite the entries for `changed` (paths relative to `dir`) in `dir`'s
+/// `.cargo-checksum.json`, dropping those of files that no longer exist.
+fn update_checksums(dir: &Utf8Path, changed: &[Utf8PathBuf]) -> io::Result<()> {
+    let path = dir.join(CHECKSUM_FILE);
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(());
+    };
+
+    let mut checksums: serde_json::Value = serde_json::from_str(&contents)?;
+    let Some(files) = checksums
+        .get_mut("files")
+        .and_then(serde_json::Value::as_object_mut)
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{path} has no `files` table"),
+        ));
+    };
+
+    for relative in changed {
+        match fs::read(dir.join(relative)) {
+            Ok(data) => {
+                files.insert(relative.to_string(), sha256_hex(&data).into());
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                files.remove(relative.as_str());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    fs::write(&path, serde_json::to_string(&checksums)?)
+}
+
+/// SHA-256 of `dir`'s `.cargo-checksum.json`, or of nothing if it has none.
+fn checksum_file_hash(dir: &Utf8Path) -> String {
+    sha256_hex(&fs::read(dir.join(CHECKSUM_FILE)).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vendored_dirs_matches_plain_and_versioned_dirs() {
+        let (_guard, vendor) = temp_dir().unwrap();
+        for name in ["demo", "demo-0.1.0", "demo-macros", "other"] {
+            fs::create_dir(vendor.join(name)).unwrap();
+        }
+        fs::write(vendor.join("demo-1.0.0"), "").unwrap();
+
+        assert_eq!(
+            vendored_dirs(&vendor, "demo"),
+            vec![vendor.join("demo"), vendor.join("demo-0.1.0")]
+        );
+        assert!(vendored_dirs(&vendor, "missing").is_empty());
+        assert!(vendored_dirs(&vendor.join("nowhere"), "demo").is_empty());
+    }
+
+    #[test]
+    fn update_checksums_rewrites_changed_files() {
+        let (_guard, dir) = temp_dir().unwrap();
+        fs::create_dir(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "patched").unwrap();
+        fs::write(
+            dir.join(CHECKSUM_FILE),
+            r#"{"files":{"src/lib.rs":"old","src/gone.rs":"old","Cargo.toml":"kept"},"package":"pkg"}"#,
+        )
+        .unwrap();
+
+        let changed = [
+            Utf8PathBuf::from("src/lib.rs"),
+            Utf8PathBuf::from("src/gone.rs"),
+        ];
+        update_checksums(&dir, &changed).unwrap();
+
+        let checksums: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join(CHECKSUM_FILE)).unwrap()).unwrap();
+        assert_eq!(
+            checksums,
+            serde_json::json!({
+                "files": {
+                    "src/lib.rs": sha256_hex(b"patched"),
+                    "Cargo.toml": "kept",
+                },
+                "package": "pkg",
+            })
+        );
+    }
+
+    #[test]
+    fn listed_files_reads_checksum_file() {
+        let (_guard, dir) = temp_dir().unwrap();
+        assert!(listed_files(&dir).unwrap().is_empty());
+
+        fs::write(
+            dir.join(CHECKSUM_FILE),
+            r#"{"files":{"src/lib.rs":"a","Cargo.toml":"b"},"package":"pkg"}"#,
+        )
+        .unwrap();
+        let mut listed = listed_files(&dir).unwrap();
+        listed.sort();
+        assert_eq!(
+            listed,
+            [
+                Utf8PathBuf::from("Cargo.toml"),
+                Utf8PathBuf::from("src/lib.rs")
+            ]
+        );
+    }
+
+    #[test]
+    fn marker_is_invalidated_by_new_checksums() {
+        let (_guard, dir) = temp_dir().unwrap();
+        assert!(!is_stitched_in_place(&dir));
+
+        fs::write(dir.join(CHECKSUM_FILE), r#"{"files":{}}"#).unwrap();
+        fs::write(
+            dir.join(APPLIED_FILE),
+            format!("{}\n", checksum_file_hash(&dir)),
+        )
+        .unwrap();
+        assert!(is_stitched_in_place(&dir));
+
+        fs::write(dir.join(CHECKSUM_FILE), r#"{"files":{"a":"b"}}"#).unwrap();
+        assert!(!is_stitched_in_place(&dir));
+    }
+}
@@ -1,36 +1,92 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::os::unix::process::CommandExt;
+use std::io::Write;
 use std::process::Command;
 
 use camino::{Utf8Path, Utf8PathBuf};
-use terrors::OneOf;
+use cargo_metadata::semver::Version;
+use serde_json::value::RawValue;
+use terrors::{E5, OneOf};
+use tracing::{debug, error, info_span};
 
-const PATCHED_CRATES_DIR: &str = "cargo-stitch";
+/// The directory under cargo's target directory holding the patched crate sources.
+pub(crate) const PATCHED_CRATES_DIR: &str = "cargo-stitch";
 
-use crate::error::{AstGrepFailed, IoError, MissingEnvVar, PatchFailed};
-use crate::fs::{copy_dir_recursive, patched_dir_is_up_to_date, write_sentinel};
-use crate::stitch::StitchSet;
-use crate::{STITCH_MANIFEST_ENV, WORKSPACE_ROOT_ENV};
+/// The directory under the patched root where the `.rej` files of the last failed build of
+/// each crate are kept, in `.rejects/<crate>/`.
+const REJECTS_DIR: &str = ".rejects";
+
+use crate::config::env_dir;
+use crate::error::{AstGrepFailed, IoError, MissingEnvVar, PatchFailed, ScriptFailed};
+use crate::fs::{copy_sources, exec_or_spawn_and_exit, sync_dir};
+use crate::hash::{HISTORY_DIR, STAMP_FILE, STATE_FILE, StitchRecord, StitchStamp, StitchState};
+use crate::output::OutputContext;
+use crate::stitch::{ApplyError, StitchSet, cargo_status, keep_rejects};
+use crate::vendor::{is_stitched_in_place, stitch_in_place_without_copy};
+use crate::{
+    KEEP_PATCHED_ENV, PACKAGES_ENV, PATCHED_DIR_ENV, PATCHED_DIR_OVERRIDE_ENV, REGISTRY_SRC_ENV,
+    STITCH_MANIFEST_ENV, VENDOR_DIR_ENV, WORKSPACE_ROOT_ENV, WORKSPACE_ROOT_OVERRIDE_ENV,
+};
 
 /// Execute rustc with the given arguments, replacing the current process.
 /// This function only returns if exec fails; on success it never returns.
 fn exec_rustc(rustc: &str, args: &[String]) -> IoError {
-    IoError(Command::new(rustc).args(args).exec())
+    debug!(rustc = %rustc, args = ?args, "running rustc");
+    let e = exec_or_spawn_and_exit(Command::new(rustc).args(args));
+    IoError(std::io::Error::new(
+        e.kind(),
+        format!("failed to execute rustc `{rustc}`: {e}"),
+    ))
+}
+
+/// Split the wrapper's argv into the rustc to run and the arguments to pass to it.
+///
+/// Cargo invokes `RUSTC_WORKSPACE_WRAPPER` as `<wrapper> <rustc> <rustc args...>`, where
+/// `<rustc>` already honours the user's `RUSTC` setting.  If the rustc path is missing
+/// (e.g. when the wrapper is invoked by hand), fall back to `$RUSTC`, then `rustc`.
+fn split_rustc_args(args: &[String]) -> (String, &[String]) {
+    match args.get(1) {
+        Some(rustc) => (rustc.clone(), &args[2..]),
+        None => (
+            env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()),
+            &[],
+        ),
+    }
 }
 
-type WrapperError = OneOf<(IoError, PatchFailed, AstGrepFailed, MissingEnvVar)>;
+type WrapperError = OneOf<(
+    IoError,
+    PatchFailed,
+    AstGrepFailed,
+    ScriptFailed,
+    MissingEnvVar,
+)>;
 
-pub fn run_wrapper() -> Result<(), WrapperError> {
+/// Report `e` as a JSON `error` event, naming the crate being compiled and the stitch that
+/// failed, if any.
+pub(crate) fn report_error(output: &OutputContext, e: &WrapperError) {
+    let stitch = match e.as_enum() {
+        E5::B(PatchFailed { file, .. })
+        | E5::C(AstGrepFailed { file, .. })
+        | E5::D(ScriptFailed { file, .. }) => Some(file.as_path()),
+        E5::A(_) | E5::E(_) => None,
+    };
+    let package = env::var("CARGO_PKG_NAME").ok();
+    output.error(package.as_deref(), stitch, e);
+}
+
+pub(crate) fn run_wrapper(output: &OutputContext) -> Result<(), WrapperError> {
     let args: Vec<String> = env::args().collect();
-    let rustc = &args[1];
-    let rustc_args = &args[2..];
+    let (rustc, rustc_args) = split_rustc_args(&args);
 
     // No package context (e.g. rustc version probe) — just exec rustc
     let Ok(pkg_name) = env::var("CARGO_PKG_NAME") else {
-        return Err(OneOf::new(exec_rustc(rustc, rustc_args)));
+        debug!("CARGO_PKG_NAME is not set, not stitching");
+        return Err(OneOf::new(exec_rustc(&rustc, rustc_args)));
     };
+    let _span = info_span!("wrapper", pkg_name = %pkg_name).entered();
+    debug!(pkg_name = %pkg_name, "read CARGO_PKG_NAME");
 
     let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") else {
         return Err(OneOf::new(MissingEnvVar("CARGO_MANIFEST_DIR")));
@@ -41,82 +97,164 @@ pub fn run_wrapper() -> Result<(), WrapperError> {
         return Err(OneOf::new(MissingEnvVar(WORKSPACE_ROOT_ENV)));
     };
     let workspace_root = Utf8PathBuf::from(workspace_root);
+    debug!(
+        workspace_root = %workspace_root,
+        manifest_dir = %manifest_dir,
+        "resolved workspace root"
+    );
 
     let Ok(manifest_file) = env::var(STITCH_MANIFEST_ENV) else {
         // Empty manifest: no stitches configured, just run rustc as-is.
-        return Err(OneOf::new(exec_rustc(rustc, rustc_args)));
+        debug!("{STITCH_MANIFEST_ENV} is not set, not stitching");
+        return Err(OneOf::new(exec_rustc(&rustc, rustc_args)));
+    };
+
+    // A crates.io dependency's stitches are in `stitches/<crate>-<version>/`, so that
+    // they are not applied to another version, or to a member of the same name.
+    let registry_src = env::var(REGISTRY_SRC_ENV).ok().map(Utf8PathBuf::from);
+    let from_registry = registry_src
+        .as_deref()
+        .is_some_and(|src| manifest_dir.starts_with(src));
+    let stitch_key = if from_registry {
+        registry_key(
+            &pkg_name,
+            &env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+        )
+    } else {
+        pkg_name.clone()
     };
 
     let manifest_json = fs::read_to_string(&manifest_file).map_err(|e| OneOf::new(IoError(e)))?;
+    let invalid_manifest = |e: serde_json::Error| {
+        error!(manifest = %manifest_file, error = %e, "invalid stitch manifest");
+        OneOf::new(IoError(e.into()))
+    };
 
-    let manifest: HashMap<String, StitchSet> =
-        serde_json::from_str(&manifest_json).map_err(|e| OneOf::new(IoError(e.into())))?;
+    // Only this crate's stitches are deserialized: the others' are left as raw JSON.
+    let manifest: HashMap<String, &RawValue> =
+        serde_json::from_str(&manifest_json).map_err(invalid_manifest)?;
+    debug!(
+        manifest = %manifest_file,
+        crates = manifest.len(),
+        "deserialized stitch manifest"
+    );
 
     // No stitches for this package — just exec rustc
-    let Some(stitch_set) = manifest.get(&pkg_name) else {
-        return Err(OneOf::new(exec_rustc(rustc, rustc_args)));
+    let Some(stitch_set) = manifest.get(&stitch_key) else {
+        debug!(stitch_key = %stitch_key, "no stitches for this crate");
+        return Err(OneOf::new(exec_rustc(&rustc, rustc_args)));
     };
+    let mut stitch_set: StitchSet =
+        serde_json::from_str(stitch_set.get()).map_err(invalid_manifest)?;
+    debug!(
+        stitch_key = %stitch_key,
+        stitches = stitch_set.len(),
+        "found stitch set"
+    );
 
-    let patched_dir = patched_dir(&pkg_name, &workspace_root);
-    let stitch_file_paths: Vec<&Utf8Path> = stitch_set.file_paths().collect();
-
-    // Skip the copy+patch if patched_dir already reflects the current sources and
-    // stitch files.  This avoids redundant I/O when the same crate is compiled
-    // multiple times in one build (e.g. different feature combinations, lib + tests).
-    if !patched_dir_is_up_to_date(&patched_dir, &manifest_dir, &stitch_file_paths) {
-        // Copy source to a per-process temp dir, apply patches there, then atomically
-        // rename into the final location.  This avoids races when the same crate is
-        // compiled concurrently (e.g. with different feature combinations): both
-        // processes produce identical patched output, so whichever rename wins is fine,
-        // and the loser simply discards its temp dir.  Any rustc that already has the
-        // previous patched files open via inodes keeps working even after the rename.
-        let temp_dir = temp_patched_dir(&pkg_name, &workspace_root);
-
-        if temp_dir.exists() {
-            fs::remove_dir_all(&temp_dir).map_err(|e| OneOf::new(IoError(e)))?;
-        }
-
-        copy_dir_recursive(&manifest_dir, &temp_dir).map_err(|e| OneOf::new(IoError(e)))?;
+    // When there are vendored or crates.io crates to stitch, cargo runs the wrapper for
+    // every crate (`RUSTC_WRAPPER`), not just workspace members.  Besides crates.io
+    // dependencies, only stitch those members and the vendored crates that
+    // `cargo stitch vendor` has not already stitched in place; a git dependency that
+    // shares a name with a stitched crate is left alone.
+    //
+    // In containerized builds the workspace may be mounted at a different path than the
+    // one cargo-stitch was started from, so allow overriding the root used to tell.
+    let comparison_root = env::var(WORKSPACE_ROOT_OVERRIDE_ENV)
+        .map(Utf8PathBuf::from)
+        .unwrap_or_else(|_| workspace_root.clone());
+    let vendor_dir = env::var(VENDOR_DIR_ENV).ok().map(Utf8PathBuf::from);
+    if !from_registry
+        && (vendor_dir.is_some() || registry_src.is_some())
+        && !stitches_apply(&manifest_dir, &comparison_root, vendor_dir.as_deref())
+    {
+        debug!("not a workspace member or vendored crate, not stitching");
+        return Err(OneOf::new(exec_rustc(&rustc, rustc_args)));
+    }
 
-        // Apply stitch files in filename order
-        stitch_set.apply(&temp_dir).map_err(OneOf::broaden)?;
+    // With `--package`, the selected packages' dependencies are built from their original
+    // sources, to iterate on one crate's stitches without patching everything else.
+    if let Ok(packages) = env::var(PACKAGES_ENV)
+        && !is_selected(&packages, &pkg_name)
+    {
+        cargo_status(
+            output,
+            "Warning",
+            &format!("building `{pkg_name}` without its stitches: not selected with `--package`"),
+        );
+        return compile_unstitched(&rustc, rustc_args, &packages).map_err(OneOf::new);
+    }
 
-        // Atomically replace the final patched dir.  On Linux, rename(2) fails with
-        // ENOTEMPTY if the destination is a non-empty directory, so we remove it first.
-        // The tiny window between the remove and the rename is acceptable: another
-        // process racing here will also produce an identical result.
-        if patched_dir.exists() {
-            fs::remove_dir_all(&patched_dir).map_err(|e| OneOf::new(IoError(e)))?;
-        }
+    // Stitches that need Cargo features are only applied to builds that enable them all.
+    let features = active_features(rustc_args);
+    stitch_set.retain_features(|feature| features.contains(feature));
+    if stitch_set.is_empty() {
+        debug!(features = ?features, "no stitches for the active features");
+        return Err(OneOf::new(exec_rustc(&rustc, rustc_args)));
+    }
 
-        if let Err(e) = fs::rename(&temp_dir, &patched_dir) {
-            // Another concurrent process beat us to it.  Both produce identical output,
-            // so discard our temp dir and use their result.
-            let _ = fs::remove_dir_all(&temp_dir);
-            if !patched_dir.exists() {
-                return Err(OneOf::new(IoError(e)));
+    // With `no_copy = true`, a vendored crate is stitched where it is, as
+    // `cargo stitch vendor` would, and compiled from there.
+    if stitch_set.no_copy() {
+        match vendor_dir.as_deref() {
+            Some(vendor_dir) if manifest_dir.starts_with(vendor_dir) => {
+                stitch_vendored_in_place(&manifest_dir, &stitch_set, output)
+                    .map_err(OneOf::broaden)?;
+                return Err(OneOf::new(exec_rustc(&rustc, rustc_args)));
             }
+            _ => cargo_status(
+                output,
+                "Warning",
+                &format!(
+                    "ignoring `no_copy = true` for `{pkg_name}`, which is not vendored: \
+                     stitching a copy of it"
+                ),
+            ),
         }
-
-        // Record when this patch run completed so future invocations can skip
-        // the copy+patch if sources and stitch files have not changed since.
-        write_sentinel(&patched_dir).map_err(|e| OneOf::new(IoError(e)))?;
     }
 
+    // Honour a custom target directory (`CARGO_TARGET_DIR`), `patched_dir` in
+    // `[workspace.metadata.stitch]` or `CARGO_STITCH_TARGET_DIR`, as forwarded by the
+    // subcommand.  The latter also works when the wrapper is set up by hand.
+    let patched_root = env::var(PATCHED_DIR_ENV)
+        .map(Utf8PathBuf::from)
+        .ok()
+        .or_else(|| env_dir(PATCHED_DIR_OVERRIDE_ENV).map(|dir| workspace_root.join(dir)))
+        .unwrap_or_else(|| workspace_root.join("target").join(PATCHED_CRATES_DIR));
+
+    let keep_patched = env::var_os(KEEP_PATCHED_ENV).is_some();
+    update_patched_dir(
+        &stitch_key,
+        &patched_root,
+        &manifest_dir,
+        &stitch_set,
+        keep_patched,
+        output,
+    )
+    .map_err(OneOf::broaden)?;
+
+    let patched_dir = patched_dir(&stitch_key, &patched_root);
+
     // Rewrite rustc args: replace manifest_dir with patched_dir
     // Cargo may pass either absolute paths or relative paths (from workspace root),
     // so we need to handle both cases.
+    //
+    // Cargo compiles a package's build script as a crate of its own, with the package's
+    // environment, so its sources are redirected here like the rest of the package's and
+    // stitches to `build.rs` take effect.
     let manifest_dir_str = manifest_dir.as_str();
     let patched_dir_str = patched_dir.as_str();
 
     // Compute the relative path from workspace root to manifest dir for relative path matching.
     // Add a trailing slash to ensure we match path prefixes only (e.g., "config/src/lib.rs"
     // but not just "config" which could be the crate name argument).
-    let relative_manifest_prefix = manifest_dir
-        .strip_prefix(&workspace_root)
-        .ok()
-        .map(|p| format!("{p}/"));
+    let relative_manifest_prefix = relative_manifest_prefix(&manifest_dir, &comparison_root);
 
+    debug!(
+        manifest_dir = %manifest_dir,
+        patched_dir = %patched_dir,
+        "rewriting rustc args"
+    );
     let rewritten_args: Vec<String> = rustc_args
         .iter()
         .map(|arg| {
@@ -136,24 +274,419 @@ pub fn run_wrapper() -> Result<(), WrapperError> {
         })
         .collect();
 
-    Err(OneOf::new(exec_rustc(rustc, &rewritten_args)))
+    Err(OneOf::new(exec_rustc(&rustc, &rewritten_args)))
+}
+
+/// Stitch the vendored crate in `dir` in place, for `no_copy = true`, unless a concurrent
+/// compilation of it already has.
+fn stitch_vendored_in_place(
+    dir: &Utf8Path,
+    stitch_set: &StitchSet,
+    output: &OutputContext,
+) -> Result<(), ApplyError> {
+    let io = |e| OneOf::new(IoError(e));
+
+    let lock = fs::File::create(dir.join(IN_PLACE_LOCK_FILE)).map_err(io)?;
+    lock.lock().map_err(io)?;
+    if is_stitched_in_place(dir) {
+        return Ok(());
+    }
+
+    cargo_status(
+        output,
+        "Warning",
+        &format!(
+            "stitching {dir} in place (`no_copy = true`): its sources are modified; \
+             `cargo stitch clean --restore` vendors them again"
+        ),
+    );
+    stitch_in_place_without_copy(dir, stitch_set, output)
+}
+
+/// The file locked while a vendored crate is stitched in place, next to its sources.
+const IN_PLACE_LOCK_FILE: &str = ".cargo-stitch-lock";
+
+/// Run rustc on the original sources of a crate that has stitches, but was left out by the
+/// `--package` selection `packages`, exiting with rustc's status if it fails.
+///
+/// Cargo would otherwise consider the unstitched build fresh until the crate's sources
+/// change, even in builds that should stitch it.  Recording `PACKAGES_ENV` in the dep-info
+/// rustc writes, as it does for an `env!`, makes cargo compile it again when the selection
+/// changes.
+fn compile_unstitched(rustc: &str, args: &[String], packages: &str) -> Result<(), IoError> {
+    let status = Command::new(rustc).args(args).status().map_err(|e| {
+        IoError(std::io::Error::new(
+            e.kind(),
+            format!("failed to execute rustc `{rustc}`: {e}"),
+        ))
+    })?;
+    if !status.success() {
+        // Rustc has reported the error already; exit as it did, as if the wrapper had
+        // exec'd it.
+        std::process::exit(status.code().unwrap_or(1));
+    }
+
+    if let Some(dep_info) = dep_info_path(args).filter(|path| path.is_file()) {
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&dep_info)
+            .map_err(IoError)?;
+        writeln!(file, "# env-dep:{PACKAGES_ENV}={packages}").map_err(IoError)?;
+    }
+
+    Ok(())
+}
+
+/// The dep-info file rustc writes for `args`, as cargo passes them:
+/// `<out-dir>/<crate-name><extra-filename>.d`.
+fn dep_info_path(args: &[String]) -> Option<Utf8PathBuf> {
+    let value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|i| args.get(i + 1))
+            .map(String::as_str)
+            .or_else(|| {
+                args.iter()
+                    .find_map(|arg| arg.strip_prefix(flag)?.strip_prefix('='))
+            })
+    };
+    let codegen = |option: &str| {
+        args.iter()
+            .zip(args.iter().skip(1))
+            .find_map(|(flag, arg)| (flag == "-C").then(|| arg.strip_prefix(option)).flatten())
+            .or_else(|| {
+                args.iter()
+                    .find_map(|arg| arg.strip_prefix("-C")?.strip_prefix(option))
+            })
+    };
+
+    let out_dir = value("--out-dir")?;
+    let crate_name = value("--crate-name")?;
+    let extra_filename = codegen("extra-filename=").unwrap_or_default();
+    Some(Utf8Path::new(out_dir).join(format!("{crate_name}{extra_filename}.d")))
+}
+
+/// The path of `manifest_dir` relative to `workspace_root`, with a trailing slash, or
+/// `None` if `manifest_dir` is not under `workspace_root`.
+fn relative_manifest_prefix(manifest_dir: &Utf8Path, workspace_root: &Utf8Path) -> Option<String> {
+    manifest_dir
+        .strip_prefix(workspace_root)
+        .ok()
+        .map(|p| format!("{p}/"))
+}
+
+/// Whether `pkg_name` is one of the comma-separated `packages` to stitch.  An empty list
+/// selects every package.
+fn is_selected(packages: &str, pkg_name: &str) -> bool {
+    let mut packages = packages.split(',').map(str::trim).filter(|p| !p.is_empty());
+    packages.clone().next().is_none() || packages.any(|p| p == pkg_name)
+}
+
+/// The Cargo features enabled for the crate being compiled, from the `--cfg feature="…"`
+/// arguments cargo passes to rustc.  These are what cargo resolved: default features,
+/// `--no-default-features` and the features other dependents enable all count.
+fn active_features(rustc_args: &[String]) -> HashSet<&str> {
+    let mut features = HashSet::new();
+    let mut args = rustc_args.iter();
+
+    while let Some(arg) = args.next() {
+        let cfg = match arg.strip_prefix("--cfg") {
+            Some("") => args.next().map(String::as_str),
+            Some(cfg) => cfg.strip_prefix('='),
+            None => None,
+        };
+        if let Some(feature) = cfg
+            .and_then(|cfg| cfg.strip_prefix("feature=\""))
+            .and_then(|feature| feature.strip_suffix('"'))
+        {
+            features.insert(feature);
+        }
+    }
+
+    features
+}
+
+/// The name of the directory under `stitches/<set>/` holding the stitches for version
+/// `version` of the crates.io crate `pkg_name`.
+pub(crate) fn registry_key(pkg_name: &str, version: &str) -> String {
+    format!("{pkg_name}-{version}")
+}
+
+/// Split a `registry_key` into the crate's name and version, or return `None` if `key`
+/// does not end with a version.
+pub(crate) fn split_registry_key(key: &str) -> Option<(&str, &str)> {
+    key.match_indices('-')
+        .map(|(i, _)| (&key[..i], &key[i + 1..]))
+        .find(|(name, version)| !name.is_empty() && Version::parse(version).is_ok())
+}
+
+/// Whether a crate with stitches, whose sources are in `manifest_dir` outside cargo's
+/// registry, should be patched when the wrapper runs for every crate in the build.
+fn stitches_apply(
+    manifest_dir: &Utf8Path,
+    workspace_root: &Utf8Path,
+    vendor_dir: Option<&Utf8Path>,
+) -> bool {
+    match vendor_dir {
+        Some(vendor_dir) if manifest_dir.starts_with(vendor_dir) => {
+            !is_stitched_in_place(manifest_dir)
+        }
+        _ => manifest_dir.starts_with(workspace_root),
+    }
+}
+
+/// Bring the patched directory of `pkg_name` up to date with the sources in
+/// `manifest_dir` and the stitches in `stitch_set`, returning whether the stitches had to
+/// be applied again.
+///
+/// `keep_patched` asks for an existing patched directory to be updated in place, as with
+/// `--keep-patched`; the crate's `stitch.toml` can ask for it too.
+pub(crate) fn update_patched_dir(
+    pkg_name: &str,
+    patched_root: &Utf8Path,
+    manifest_dir: &Utf8Path,
+    stitch_set: &StitchSet,
+    keep_patched: bool,
+    output: &OutputContext,
+) -> Result<bool, ApplyError> {
+    let patched_dir = patched_dir(pkg_name, patched_root);
+    let stitch_file_paths: Vec<&Utf8Path> = stitch_set.input_paths().collect();
+
+    // Skip the copy+patch if patched_dir was made from the current sources and stitch
+    // files, as far as their paths, sizes and mtimes tell.  This avoids redundant I/O when
+    // the same crate is compiled multiple times in one build (e.g. different feature
+    // combinations, lib + tests).  The stamp is taken before patching, so that a change
+    // made in the meantime is picked up by the next invocation.
+    let stamp = StitchStamp::compute(manifest_dir, &stitch_file_paths)
+        .map_err(|e| OneOf::new(IoError(e)))?;
+    let stamp_file = patched_dir.join(STAMP_FILE);
+    if StitchStamp::load(&stamp_file).as_ref() == Some(&stamp) {
+        debug!(patched_dir = %patched_dir, "patched copy is fresh");
+        return Ok(false);
+    }
+
+    // The stamp says something changed, but the contents may not have (e.g. after a
+    // `git checkout` round-trip).  If the inputs hash to what patched_dir was built
+    // from, only the stamp needs refreshing, and the state keeps its `applied_at`.
+    let state =
+        StitchState::compute(manifest_dir, stitch_set).map_err(|e| OneOf::new(IoError(e)))?;
+    let state_file = patched_dir.join(STATE_FILE);
+
+    let rebuilt = StitchState::load(&state_file).is_none_or(|stored| !stored.matches(&state));
+    debug!(patched_dir = %patched_dir, rebuilt, "checked the stitch state");
+    if rebuilt {
+        rebuild_patched_dir(
+            pkg_name,
+            patched_root,
+            manifest_dir,
+            stitch_set,
+            keep_patched,
+            output,
+        )?;
+        state
+            .store(&state_file)
+            .map_err(|e| OneOf::new(IoError(e)))?;
+
+        // The history is only for `cargo stitch log`, so failing to keep it does not
+        // fail the build.
+        let record = StitchRecord {
+            package: pkg_name.to_string(),
+            applied: stitch_set.len(),
+            state,
+        };
+        if let Err(e) = record.store(&patched_root.join(HISTORY_DIR)) {
+            cargo_status(
+                output,
+                "Warning",
+                &format!("could not record the stitch history of {pkg_name}: {e}"),
+            );
+        }
+    }
+
+    // Record what this patch run was made from so future invocations can skip
+    // the copy+patch if sources and stitch files have not changed since.
+    stamp
+        .store(&stamp_file)
+        .map_err(|e| OneOf::new(IoError(e)))?;
+
+    Ok(rebuilt)
+}
+
+/// Copy `manifest_dir` to a temp dir, apply the stitches there, and move the result
+/// into `patched_dir`.
+fn rebuild_patched_dir(
+    pkg_name: &str,
+    patched_root: &Utf8Path,
+    manifest_dir: &Utf8Path,
+    stitch_set: &StitchSet,
+    keep_patched: bool,
+    output: &OutputContext,
+) -> Result<(), ApplyError> {
+    let patched_dir = patched_dir(pkg_name, patched_root);
+
+    // Copy source to a per-process temp dir, apply patches there, then atomically
+    // rename into the final location.  This avoids races when the same crate is
+    // compiled concurrently (e.g. with different feature combinations): both
+    // processes produce identical patched output, so whichever rename wins is fine,
+    // and the loser simply discards its temp dir.  Any rustc that already has the
+    // previous patched files open via inodes keeps working even after the rename.
+    let temp_dir = temp_patched_dir(pkg_name, patched_root);
+
+    // A build interrupted while stitching (e.g. with Ctrl-C) leaves its temp dir behind.
+    // Each temp dir is locked for as long as its process works on it, so those that can
+    // be locked are left over from such builds.
+    fs::create_dir_all(patched_root).map_err(|e| OneOf::new(IoError(e)))?;
+    remove_stale_temp_dirs(pkg_name, patched_root, output);
+    let lock = lock_temp_dir(&temp_dir).map_err(|e| OneOf::new(IoError(e)))?;
+
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir).map_err(|e| OneOf::new(IoError(e)))?;
+    }
+
+    // Apply stitch files in filename order
+    let stitched = copy_sources(manifest_dir, &temp_dir, &stitch_set.copy_options())
+        .map_err(|e| OneOf::new(IoError(e)))
+        .and_then(|()| stitch_set.apply_to_scratch(&temp_dir, output));
+    let rejects_dir = patched_root.join(REJECTS_DIR).join(pkg_name);
+    if let Err(e) = stitched {
+        error!(pkg_name = %pkg_name, error = %e, "failed to stitch crate");
+        let _ = fs::remove_dir_all(&rejects_dir);
+        let e = keep_rejects(e, &temp_dir, &rejects_dir);
+        // The patched directory is untouched, but the half-stitched copy would stay
+        // behind, under a new name on every failing build.
+        if let Err(cleanup) = fs::remove_dir_all(&temp_dir) {
+            cargo_status(
+                output,
+                "Warning",
+                &format!("could not remove {temp_dir}: {cleanup}"),
+            );
+        }
+        return Err(e);
+    }
+
+    if (keep_patched || stitch_set.keep_patched()) && patched_dir.exists() {
+        // Update the existing patched dir in place, rewriting only the files whose
+        // content changed.  Unchanged files keep their mtimes, so incremental
+        // compilation can reuse the artifacts of the previous build.
+        let result = sync_dir(&temp_dir, &patched_dir);
+        let _ = fs::remove_dir_all(&temp_dir);
+        result.map_err(|e| OneOf::new(IoError(e)))?;
+    } else {
+        replace_patched_dir(&temp_dir, &patched_dir).map_err(|e| OneOf::new(IoError(e)))?;
+    }
+
+    // Those of an earlier build that failed no longer apply.
+    let _ = fs::remove_dir_all(&rejects_dir);
+
+    drop(lock);
+    Ok(())
+}
+
+/// The lock on a temp dir of [`rebuild_patched_dir`], whose lock file is removed when it
+/// is dropped, once the temp dir is gone.
+struct TempDirLock {
+    path: Utf8PathBuf,
+    _file: fs::File,
+}
+
+impl Drop for TempDirLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Create the lock file of `temp_dir` and lock it, for as long as the returned lock is
+/// alive.  This happens before `temp_dir` is created, so a temp dir whose lock is free
+/// belongs to no running process.
+fn lock_temp_dir(temp_dir: &Utf8Path) -> std::io::Result<TempDirLock> {
+    let path = temp_lock_file(temp_dir);
+    let file = fs::File::create(&path)?;
+    file.lock()?;
+    Ok(TempDirLock { path, _file: file })
+}
+
+/// Remove the temp dirs of `pkg_name` under `patched_root`, and their lock files, that were
+/// left behind by builds that were interrupted while stitching it.
+fn remove_stale_temp_dirs(pkg_name: &str, patched_root: &Utf8Path, output: &OutputContext) {
+    let Ok(entries) = patched_root.read_dir_utf8() else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if !is_temp_patched_dir(entry.file_name(), pkg_name)
+            || !entry.file_type().is_ok_and(|ft| ft.is_dir())
+        {
+            continue;
+        }
+        let temp_dir = entry.path();
+        let lock_file = temp_lock_file(temp_dir);
+        // The lock is released when `lock` is dropped, after the temp dir is gone.
+        let lock = match fs::File::open(&lock_file) {
+            Ok(lock) if lock.try_lock().is_err() => continue,
+            Ok(lock) => Some(lock),
+            Err(_) => None,
+        };
+        debug!(temp_dir = %temp_dir, "removing a stale temp dir");
+        if let Err(e) = fs::remove_dir_all(temp_dir) {
+            cargo_status(
+                output,
+                "Warning",
+                &format!("could not remove {temp_dir}: {e}"),
+            );
+            continue;
+        }
+        if lock.is_some() {
+            let _ = fs::remove_file(&lock_file);
+        }
+    }
+}
+
+/// Returns `true` if `file_name` is that of a [temp dir](temp_patched_dir) for `pkg_name`.
+fn is_temp_patched_dir(file_name: &str, pkg_name: &str) -> bool {
+    file_name
+        .strip_prefix('.')
+        .and_then(|rest| rest.strip_prefix(pkg_name))
+        .and_then(|rest| rest.strip_prefix('.'))
+        .is_some_and(|pid| !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()))
 }
 
-fn patched_dir(pkg_name: &str, workspace_root: &Utf8Path) -> Utf8PathBuf {
-    workspace_root
-        .join("target")
-        .join(PATCHED_CRATES_DIR)
-        .join(pkg_name)
+/// The file locked while `temp_dir` is in use.
+fn temp_lock_file(temp_dir: &Utf8Path) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{temp_dir}.lock"))
+}
+
+/// Atomically replace `patched_dir` with the freshly patched `temp_dir`.
+fn replace_patched_dir(temp_dir: &Utf8Path, patched_dir: &Utf8Path) -> std::io::Result<()> {
+    // On Linux, rename(2) fails with ENOTEMPTY if the destination is a non-empty
+    // directory, so we remove it first.  The tiny window between the remove and the
+    // rename is acceptable: another process racing here will also produce an identical
+    // result.
+    if patched_dir.exists() {
+        fs::remove_dir_all(patched_dir)?;
+    }
+
+    if let Err(e) = fs::rename(temp_dir, patched_dir) {
+        // Another concurrent process beat us to it.  Both produce identical output,
+        // so discard our temp dir and use their result.
+        let _ = fs::remove_dir_all(temp_dir);
+        if !patched_dir.exists() {
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// The directory holding the patched sources of `pkg_name`, under `patched_root`
+/// (`cargo-stitch` in cargo's target directory, unless configured otherwise).
+pub(crate) fn patched_dir(pkg_name: &str, patched_root: &Utf8Path) -> Utf8PathBuf {
+    patched_root.join(pkg_name)
 }
 
 /// A per-process temporary directory used while building the patched source.
 /// Named with a leading dot and the process ID to avoid colliding with the
 /// final `patched_dir` and with other concurrent compilations of the same crate.
-fn temp_patched_dir(pkg_name: &str, workspace_root: &Utf8Path) -> Utf8PathBuf {
-    workspace_root
-        .join("target")
-        .join(PATCHED_CRATES_DIR)
-        .join(format!(".{pkg_name}.{}", std::process::id()))
+fn temp_patched_dir(pkg_name: &str, patched_root: &Utf8Path) -> Utf8PathBuf {
+    patched_root.join(format!(".{pkg_name}.{}", std::process::id()))
 }
 
 #[cfg(test)]
@@ -162,18 +695,27 @@ mod tests {
 
     #[test]
     fn patched_dir_path_construction() {
-        let root = Utf8Path::new("/workspace");
-        let result = patched_dir("my-crate", root);
+        let patched_root = Utf8Path::new("/workspace/target/cargo-stitch");
+        let result = patched_dir("my-crate", patched_root);
         assert_eq!(
             result,
             Utf8PathBuf::from("/workspace/target/cargo-stitch/my-crate")
         );
     }
 
+    #[test]
+    fn patched_dir_custom_root() {
+        let result = patched_dir("my-crate", Utf8Path::new("/workspace/build/stitched"));
+        assert_eq!(
+            result,
+            Utf8PathBuf::from("/workspace/build/stitched/my-crate")
+        );
+    }
+
     #[test]
     fn temp_patched_dir_includes_pid() {
-        let root = Utf8Path::new("/workspace");
-        let result = temp_patched_dir("my-crate", root);
+        let patched_root = Utf8Path::new("/workspace/target/cargo-stitch");
+        let result = temp_patched_dir("my-crate", patched_root);
         let pid = std::process::id();
         assert_eq!(
             result,
@@ -181,9 +723,199 @@ mod tests {
         );
     }
 
+    #[test]
+    fn is_temp_patched_dir_matches_pkg_and_pid() {
+        assert!(is_temp_patched_dir(".my-crate.1234", "my-crate"));
+        assert!(!is_temp_patched_dir(".my-crate.1234.lock", "my-crate"));
+        assert!(!is_temp_patched_dir(".my-crate-sys.1234", "my-crate"));
+        assert!(!is_temp_patched_dir(".my-crate.", "my-crate"));
+        assert!(!is_temp_patched_dir("my-crate", "my-crate"));
+    }
+
+    #[test]
+    fn remove_stale_temp_dirs_keeps_locked_ones() {
+        let tmp = tempfile::tempdir().unwrap();
+        let patched_root = Utf8Path::from_path(tmp.path()).unwrap();
+        let stale = patched_root.join(".my-crate.1");
+        let unlocked = patched_root.join(".my-crate.2");
+        let in_use = patched_root.join(".my-crate.3");
+        let other = patched_root.join(".other-crate.4");
+        for dir in [&stale, &unlocked, &in_use, &other] {
+            fs::create_dir_all(dir.join("src")).unwrap();
+            fs::write(dir.join("src/lib.rs"), "// half-copied").unwrap();
+        }
+        fs::write(temp_lock_file(&unlocked), "").unwrap();
+        let _lock = lock_temp_dir(&in_use).unwrap();
+
+        remove_stale_temp_dirs("my-crate", patched_root, &OutputContext::default());
+
+        assert!(!stale.exists());
+        assert!(!unlocked.exists());
+        assert!(!temp_lock_file(&unlocked).exists());
+        assert!(in_use.exists());
+        assert!(temp_lock_file(&in_use).exists());
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn split_rustc_args_uses_argv() {
+        let args: Vec<String> = ["cargo-stitch", "/custom/rustc", "--crate-name", "foo"]
+            .map(String::from)
+            .to_vec();
+        let (rustc, rest) = split_rustc_args(&args);
+        assert_eq!(rustc, "/custom/rustc");
+        assert_eq!(rest, ["--crate-name", "foo"]);
+    }
+
+    #[test]
+    fn split_rustc_args_falls_back_without_rustc() {
+        let args = vec!["cargo-stitch".to_string()];
+        let (rustc, rest) = split_rustc_args(&args);
+        let expected = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        assert_eq!(rustc, expected);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn relative_manifest_prefix_under_root() {
+        let prefix = relative_manifest_prefix(
+            Utf8Path::new("/workspace/crates/config"),
+            Utf8Path::new("/workspace"),
+        );
+        assert_eq!(prefix.as_deref(), Some("crates/config/"));
+    }
+
+    #[test]
+    fn relative_manifest_prefix_outside_root() {
+        let prefix = relative_manifest_prefix(
+            Utf8Path::new("/registry/config-0.1.0"),
+            Utf8Path::new("/workspace"),
+        );
+        assert_eq!(prefix, None);
+    }
+
+    #[test]
+    fn stitches_apply_to_members_and_vendored_crates() {
+        let root = Utf8Path::new("/workspace");
+        let vendor = Some(Utf8Path::new("/workspace/vendor"));
+        assert!(stitches_apply(
+            Utf8Path::new("/workspace/crates/config"),
+            root,
+            vendor
+        ));
+        assert!(stitches_apply(
+            Utf8Path::new("/workspace/vendor/config"),
+            root,
+            vendor
+        ));
+        assert!(!stitches_apply(
+            Utf8Path::new("/registry/src/index.crates.io-1949cf8c6b5b557f/config-0.1.0"),
+            root,
+            vendor
+        ));
+    }
+
+    #[test]
+    fn stitches_apply_to_members_without_vendor_dir() {
+        let root = Utf8Path::new("/workspace");
+        assert!(stitches_apply(
+            Utf8Path::new("/workspace/crates/config"),
+            root,
+            None
+        ));
+        assert!(!stitches_apply(
+            Utf8Path::new("/home/me/.cargo/git/checkouts/config-0123456789abcdef/abc1234"),
+            root,
+            None
+        ));
+    }
+
+    #[test]
+    fn is_selected_matches_listed_packages() {
+        assert!(is_selected("config,serde", "serde"));
+        assert!(is_selected(" config ", "config"));
+        assert!(!is_selected("config", "config-derive"));
+        assert!(is_selected("", "config"));
+    }
+
+    #[test]
+    fn active_features_from_rustc_cfgs() {
+        let args: Vec<String> = [
+            "--crate-name",
+            "config",
+            "--cfg",
+            "feature=\"simd\"",
+            "--cfg=feature=\"std\"",
+            "--cfg",
+            "docsrs",
+            "--check-cfg",
+            "cfg(feature, values(\"simd\", \"std\", \"serde\"))",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(active_features(&args), HashSet::from(["simd", "std"]));
+        assert!(active_features(&args[..2]).is_empty());
+    }
+
+    #[test]
+    fn dep_info_path_from_cargo_args() {
+        let args: Vec<String> = [
+            "--crate-name",
+            "config",
+            "--emit=dep-info,metadata,link",
+            "-C",
+            "extra-filename=-0123456789abcdef",
+            "--out-dir",
+            "/ws/target/debug/deps",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            dep_info_path(&args),
+            Some(Utf8PathBuf::from(
+                "/ws/target/debug/deps/config-0123456789abcdef.d"
+            ))
+        );
+
+        let args: Vec<String> = ["--crate-name=config", "--out-dir=out"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            dep_info_path(&args),
+            Some(Utf8PathBuf::from("out/config.d"))
+        );
+        assert_eq!(dep_info_path(&args[..1]), None);
+    }
+
+    #[test]
+    fn registry_key_includes_version() {
+        assert_eq!(registry_key("serde", "1.0.200"), "serde-1.0.200");
+    }
+
+    #[test]
+    fn split_registry_key_at_the_version() {
+        assert_eq!(
+            split_registry_key("serde-1.0.200"),
+            Some(("serde", "1.0.200"))
+        );
+        assert_eq!(
+            split_registry_key("sha-1-0.10.0"),
+            Some(("sha-1", "0.10.0"))
+        );
+        assert_eq!(
+            split_registry_key("serde-json-1.0.0-alpha"),
+            Some(("serde-json", "1.0.0-alpha"))
+        );
+        assert_eq!(split_registry_key("base-64"), None);
+        assert_eq!(split_registry_key("config"), None);
+    }
+
     #[test]
     fn patched_dir_different_packages() {
-        let root = Utf8Path::new("/ws");
-        assert_ne!(patched_dir("a", root), patched_dir("b", root));
+        let patched_root = Utf8Path::new("/ws/target/cargo-stitch");
+        assert_ne!(
+            patched_dir("a", patched_root),
+            patched_dir("b", patched_root)
+        );
     }
 }
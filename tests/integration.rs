@@ -6,6 +6,28 @@ fn cargo_stitch_bin() -> &'static Path {
     Path::new(env!("CARGO_BIN_EXE_cargo-stitch"))
 }
 
+/// Run `cargo stitch` with `args` in the workspace at `root`.
+fn stitch(root: &Path, args: &[&str]) -> std::process::Output {
+    Command::new(cargo_stitch_bin())
+        .arg("stitch")
+        .args(args)
+        .current_dir(root)
+        .output()
+        .unwrap()
+}
+
+/// Run `cargo stitch build` with `args` in the workspace at `root`, which must succeed,
+/// returning what it printed to stderr.
+fn build(root: &Path, args: &[&str]) -> String {
+    let output = stitch(root, &[&["build"][..], args].concat());
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(
+        output.status.success(),
+        "cargo stitch build failed:\n{stderr}"
+    );
+    stderr
+}
+
 fn create_workspace(root: &Path) {
     // Workspace Cargo.toml
     fs::write(
@@ -63,6 +85,32 @@ crate-a = { path = "../crate-a" }
     .unwrap();
 }
 
+/// Make `demo 0.2.0`, whose sources are in the registry cache under `cargo_home` like a
+/// crates.io dependency's, a dependency of `crate-a`.
+fn add_registry_dependency(root: &Path, cargo_home: &Path) {
+    let source = cargo_home.join("registry/src/index.crates.io-1949cf8c6b5b557f/demo-0.2.0");
+    fs::create_dir_all(source.join("src")).unwrap();
+    fs::write(
+        source.join("Cargo.toml"),
+        "[package]\nname = \"demo\"\nversion = \"0.2.0\"\nedition = \"2021\"\n",
+    )
+    .unwrap();
+    fs::write(
+        source.join("src/lib.rs"),
+        "pub fn demo() -> u32 {\n    2\n}\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("crate-a/Cargo.toml"),
+        format!(
+            "[package]\nname = \"crate-a\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\ndemo = {{ path = {:?} }}\n",
+            source.display().to_string()
+        ),
+    )
+    .unwrap();
+}
+
 mod patch {
     use super::*;
 
@@ -115,35 +163,77 @@ mod patch {
         );
     }
 
+    /// A `PATH` with every program of the current one but `patch`, linked from `bin`.
+    fn path_without_patch(bin: &Path) -> std::ffi::OsString {
+        let path = std::env::var_os("PATH").unwrap();
+        for dir in std::env::split_paths(&path) {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let link = bin.join(entry.file_name());
+                if entry.file_name() != "patch" && !link.exists() {
+                    std::os::unix::fs::symlink(entry.path(), link).ok();
+                }
+            }
+        }
+        bin.as_os_str().to_owned()
+    }
+
     #[test]
-    fn build_without_patches() {
+    fn build_without_patch_binary() {
         let tmp = tempfile::tempdir().unwrap();
         let root = tmp.path();
+        let bin = tempfile::tempdir().unwrap();
+        let path = path_without_patch(bin.path());
 
         create_workspace(root);
-        // No patches created
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
 
         let output = Command::new(cargo_stitch_bin())
             .args(["stitch", "build"])
+            .env("PATH", &path)
             .current_dir(root)
             .output()
             .unwrap();
-
         let stderr = String::from_utf8_lossy(&output.stderr);
         assert!(
             output.status.success(),
-            "cargo stitch build failed without patches:\n{stderr}"
+            "cargo stitch build failed:\n{stderr}"
         );
+        let content =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
 
-        // No cargo-stitch directory should exist
+        // A patch whose context no longer matches needs `patch` and its fuzz.
+        fs::write(
+            patch_dir.join("002-stale.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"gone\"\n+    \"stale\"\n }\n",
+        )
+        .unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .env("PATH", &path)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
         assert!(
-            !root.join("target/cargo-stitch").exists(),
-            "target/cargo-stitch dir should not exist when there are no patches"
+            stderr.contains("002-stale.patch") && stderr.contains("`patch` is not installed"),
+            "got:\n{stderr}"
         );
     }
 
     #[test]
-    fn multiple_patches_applied_in_order() {
+    fn build_with_front_matter() {
         let tmp = tempfile::tempdir().unwrap();
         let root = tmp.path();
 
@@ -151,30 +241,18 @@ mod patch {
 
         let patch_dir = root.join("stitches/default/crate-a");
         fs::create_dir_all(&patch_dir).unwrap();
-
-        // First patch: change "hello" to "step1"
         fs::write(
-            patch_dir.join("001-first.patch"),
-            r#"--- a/src/lib.rs
+            patch_dir.join("001-fix.patch"),
+            r#"---
+id: fix
+description: Patch the greeting
+---
+--- a/src/lib.rs
 +++ b/src/lib.rs
 @@ -1,3 +1,3 @@
  pub fn greeting() -> &'static str {
 -    "hello"
-+    "step1"
- }
-"#,
-        )
-        .unwrap();
-
-        // Second patch: change "step1" to "step2"
-        fs::write(
-            patch_dir.join("002-second.patch"),
-            r#"--- a/src/lib.rs
-+++ b/src/lib.rs
-@@ -1,3 +1,3 @@
- pub fn greeting() -> &'static str {
--    "step1"
-+    "step2"
++    "patched"
  }
 "#,
         )
@@ -187,120 +265,156 @@ mod patch {
             .unwrap();
 
         let stderr = String::from_utf8_lossy(&output.stderr);
-        assert!(output.status.success(), "build failed:\n{stderr}");
-
-        let content =
-            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap_or_else(|e| {
-                panic!(
-                    "patched source should exist after build, but was not found at target/cargo-stitch/crate-a/src/lib.rs: {e}",
-                )
-            });
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
 
         assert!(
-            content.contains("\"step2\""),
-            "patches should be applied in order, got:\n{content}"
+            stderr.contains("Patching [patch] 001-fix.patch: \"Patch the greeting\""),
+            "got:\n{stderr}"
         );
-    }
-}
 
-mod set_flag {
-    use super::*;
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+    }
 
     #[test]
-    fn build_with_explicit_set() {
+    fn build_with_format_patch() {
         let tmp = tempfile::tempdir().unwrap();
         let root = tmp.path();
 
         create_workspace(root);
 
-        // Put stitches under stitches/myname/ instead of stitches/default/
-        let patch_dir = root.join("stitches/myname/crate-a");
+        let patch_dir = root.join("stitches/default/crate-a");
         fs::create_dir_all(&patch_dir).unwrap();
         fs::write(
             patch_dir.join("001-fix.patch"),
-            r#"--- a/src/lib.rs
+            r#"From 0123456789abcdef0123456789abcdef01234567 Mon Sep 17 00:00:00 2001
+From: Jane Doe <jane@example.com>
+Date: Tue, 5 Mar 2024 10:00:00 +0100
+Subject: [PATCH] Patch the greeting
+
+Upstream keeps it as it is.
+---
+ src/lib.rs | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
 +++ b/src/lib.rs
 @@ -1,3 +1,3 @@
  pub fn greeting() -> &'static str {
 -    "hello"
-+    "from-set"
++    "patched"
  }
+-- 
+2.44.0
+
 "#,
         )
         .unwrap();
 
+        // With `patch`, then with `git apply`.
+        for config in ["", "[patch]\nbackend = \"git\"\n"] {
+            fs::write(patch_dir.join("stitch.toml"), config).unwrap();
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .current_dir(root)
+                .output()
+                .unwrap();
+
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(
+                output.status.success(),
+                "cargo stitch build failed:\n{stderr}"
+            );
+
+            let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+            let content = fs::read_to_string(&patched_lib).unwrap();
+            assert!(content.contains("\"patched\""), "got:\n{content}");
+            fs::remove_dir_all(root.join("target/cargo-stitch")).unwrap();
+        }
+
         let output = Command::new(cargo_stitch_bin())
-            .args(["stitch", "--set", "myname", "build"])
+            .args(["stitch", "list", "--verbose"])
             .current_dir(root)
             .output()
             .unwrap();
-
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        assert!(
-            output.status.success(),
-            "cargo stitch --set myname build failed:\n{stderr}"
-        );
-
-        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
-        let content = fs::read_to_string(&patched_lib).unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
         assert!(
-            content.contains("\"from-set\""),
-            "patched source should contain the set-specific string, got:\n{content}"
+            stdout.contains("  Patch the greeting\n\n")
+                && stdout.contains("  Upstream keeps it as it is.\n"),
+            "got:\n{stdout}"
         );
     }
 
     #[test]
-    fn build_with_nonexistent_set_fails() {
+    fn build_with_crlf_patch() {
         let tmp = tempfile::tempdir().unwrap();
         let root = tmp.path();
 
         create_workspace(root);
 
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        let patch = r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#;
+        fs::write(patch_dir.join("001-fix.patch"), patch.replace('\n', "\r\n")).unwrap();
+
         let output = Command::new(cargo_stitch_bin())
-            .args(["stitch", "--set", "nonexistent", "build"])
+            .args(["stitch", "build"])
             .current_dir(root)
             .output()
             .unwrap();
 
-        assert!(
-            !output.status.success(),
-            "cargo stitch --set nonexistent should fail"
-        );
-
         let stderr = String::from_utf8_lossy(&output.stderr);
         assert!(
-            stderr.contains("stitch set not found"),
-            "error should mention missing stitch set, got:\n{stderr}"
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
         );
-    }
-}
 
-mod sg_rule {
-    use super::*;
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+        assert!(!content.contains('\r'), "got:\n{content:?}");
+    }
 
     #[test]
-    fn build_with_sg_rule() {
+    fn build_with_custom_target_dir() {
         let tmp = tempfile::tempdir().unwrap();
-        let root = tmp.path();
+        let root = tmp.path().join("ws");
+        fs::create_dir_all(&root).unwrap();
 
-        create_workspace(root);
+        create_workspace(&root);
 
-        let rule_dir = root.join("stitches/default/crate-a");
-        fs::create_dir_all(&rule_dir).unwrap();
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
         fs::write(
-            rule_dir.join("001-rename.yaml"),
-            r#"id: rename-greeting
-language: Rust
-rule:
-  pattern: '"hello"'
-fix: '"rewritten"'
+            patch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
 "#,
         )
         .unwrap();
 
         let output = Command::new(cargo_stitch_bin())
             .args(["stitch", "build"])
-            .current_dir(root)
+            .env("CARGO_TARGET_DIR", "../custom-target")
+            .current_dir(&root)
             .output()
             .unwrap();
 
@@ -310,54 +424,78 @@ fix: '"rewritten"'
             "cargo stitch build failed:\n{stderr}"
         );
 
-        // Verify ast-grep rule was applied
-        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
-        assert!(patched_lib.exists(), "patched source should exist");
-
+        let patched_lib = tmp
+            .path()
+            .join("custom-target/cargo-stitch/crate-a/src/lib.rs");
         let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
         assert!(
-            content.contains("\"rewritten\""),
-            "ast-grep rule should have rewritten the string, got:\n{content}"
+            !root.join("target").exists(),
+            "nothing should be written to the default target dir"
+        );
+    }
+
+    #[test]
+    fn build_without_patches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        // No patches created
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed without patches:\n{stderr}"
         );
+
+        // No cargo-stitch directory should exist
         assert!(
-            !content.contains("\"hello\""),
-            "original string should not remain after ast-grep rule"
+            !root.join("target/cargo-stitch").exists(),
+            "target/cargo-stitch dir should not exist when there are no patches"
         );
     }
 
     #[test]
-    fn build_with_patch_and_sg_rule() {
+    fn multiple_patches_applied_in_order() {
         let tmp = tempfile::tempdir().unwrap();
         let root = tmp.path();
 
         create_workspace(root);
 
-        let stitch_dir = root.join("stitches/default/crate-a");
-        fs::create_dir_all(&stitch_dir).unwrap();
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
 
-        // Patch changes "hello" to "patched"
+        // First patch: change "hello" to "step1"
         fs::write(
-            stitch_dir.join("001-fix.patch"),
+            patch_dir.join("001-first.patch"),
             r#"--- a/src/lib.rs
 +++ b/src/lib.rs
 @@ -1,3 +1,3 @@
  pub fn greeting() -> &'static str {
 -    "hello"
-+    "patched"
++    "step1"
  }
 "#,
         )
         .unwrap();
 
-        // ast-grep rule changes "patched" to "both"
-        // This verifies patches run first, then ast-grep rules
+        // Second patch: change "step1" to "step2"
         fs::write(
-            stitch_dir.join("002-rename.yaml"),
-            r#"id: rename-patched
-language: Rust
-rule:
-  pattern: '"patched"'
-fix: '"both"'
+            patch_dir.join("002-second.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "step1"
++    "step2"
+ }
 "#,
         )
         .unwrap();
@@ -369,25 +507,5981 @@ fix: '"both"'
             .unwrap();
 
         let stderr = String::from_utf8_lossy(&output.stderr);
-        assert!(
-            output.status.success(),
-            "cargo stitch build failed:\n{stderr}"
-        );
+        assert!(output.status.success(), "build failed:\n{stderr}");
 
-        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
-        let content = fs::read_to_string(&patched_lib).unwrap_or_else(|e| {
-            panic!(
-                "patched source should exist after build, but was not found at {patched_lib:?}: {e}",
-            )
-        });
+        let content =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap_or_else(|e| {
+                panic!(
+                    "patched source should exist after build, but was not found at target/cargo-stitch/crate-a/src/lib.rs: {e}",
+                )
+            });
 
         assert!(
-            content.contains("\"both\""),
-            "patch should apply first, then ast-grep rule should rewrite, got:\n{content}"
+            content.contains("\"step2\""),
+            "patches should be applied in order, got:\n{content}"
         );
-        assert!(
-            !content.contains("\"hello\"") && !content.contains("\"patched\""),
-            "neither original nor intermediate string should remain, got:\n{content}"
+    }
+}
+
+mod order {
+    use super::*;
+
+    #[test]
+    fn stitch_toml_order_overrides_filename_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+
+        // Only applies after 002: changes "step1" to "step2"
+        fs::write(
+            patch_dir.join("001-second.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "step1"
++    "step2"
+ }
+"#,
+        )
+        .unwrap();
+
+        // Must run first: changes "hello" to "step1"
+        fs::write(
+            patch_dir.join("002-first.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "step1"
+ }
+"#,
+        )
+        .unwrap();
+
+        fs::write(
+            patch_dir.join("stitch.toml"),
+            r#"[order]
+stitches = ["002-first.patch"]
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let content =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(
+            content.contains("\"step2\""),
+            "stitch.toml order should be respected, got:\n{content}"
+        );
+    }
+
+    #[test]
+    fn stitch_order_file_orders_subdirectories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(patch_dir.join("features")).unwrap();
+        fs::create_dir_all(patch_dir.join("security")).unwrap();
+
+        // Only applies after security/001-first.patch.
+        fs::write(
+            patch_dir.join("features/001-second.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"step1\"\n+    \"step2\"\n }\n",
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("security/001-first.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"step1\"\n }\n",
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join(".stitch-order"),
+            "security/001-first.patch\n",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "list"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(
+            stdout,
+            "crate-a  1  patch    security/001-first.patch\n\
+             crate-a  2  patch    features/001-second.patch\n"
+        );
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let content =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(content.contains("\"step2\""), "got:\n{content}");
+    }
+
+    #[test]
+    fn stitch_ignore_file_leaves_out_matching_stitches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(patch_dir.join("perf")).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
+        // Would fail the build if it were applied after 001-fix.patch.
+        fs::write(
+            patch_dir.join("perf/002-experiment.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"fast\"\n }\n",
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join(".stitch-ignore"),
+            "# Not ready yet.\n*-experiment.patch\n",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+        assert!(!stderr.contains("002-experiment.patch"), "got:\n{stderr}");
+
+        let content =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+    }
+}
+
+mod patch_options {
+    use super::*;
+
+    /// A patch with paths relative to the crate, as `diff` writes without `a/` and `b/`.
+    const UNPREFIXED_PATCH: &str = r#"--- src/lib.rs
++++ src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#;
+
+    #[test]
+    fn stitch_toml_patch_section_sets_strip_and_extra_args() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(patch_dir.join("001-greeting.patch"), UNPREFIXED_PATCH).unwrap();
+        fs::write(
+            patch_dir.join("stitch.toml"),
+            "future_option = 1\n\n[patch]\nstrip = 0\nextra_args = [\"--posix\"]\n",
+        )
+        .unwrap();
+
+        let stderr = build(root, &[]);
+        assert!(
+            stderr.lines().any(|line| line.contains("Warning")
+                && line.ends_with("stitch.toml: unknown key `future_option` is ignored")),
+            "got:\n{stderr}"
+        );
+
+        let content =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+    }
+
+    #[test]
+    fn strip_header_overrides_strip_for_one_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            format!("# Made with `git diff --no-prefix`\n# stitch-strip: 0\n{UNPREFIXED_PATCH}"),
+        )
+        .unwrap();
+
+        build(root, &[]);
+        let content =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+    }
+
+    #[test]
+    fn failing_stitch_leaves_previous_patched_copy_alone() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(patch_dir.join("001-greeting.patch"), UNPREFIXED_PATCH).unwrap();
+        fs::write(patch_dir.join("stitch.toml"), "[patch]\nstrip = 0\n").unwrap();
+        build(root, &[]);
+
+        fs::write(patch_dir.join("002-broken.patch"), UNPREFIXED_PATCH).unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+
+        let patched = root.join("target/cargo-stitch");
+        let content = fs::read_to_string(patched.join("crate-a/src/lib.rs")).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+
+        // No half-stitched copy is left behind.
+        let leftovers: Vec<_> = fs::read_dir(&patched)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with(".crate-a."))
+            .collect();
+        assert!(leftovers.is_empty(), "got: {leftovers:?}");
+    }
+
+    #[test]
+    fn failing_patch_keeps_its_rejects() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-bad.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"does not match\"\n+    \"never\"\n }\n",
+        )
+        .unwrap();
+        let output = stitch(root, &["build"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+
+        // The copy `patch` saved them in is gone, so they are moved out of it.
+        let reject = root.join("target/cargo-stitch/.rejects/crate-a/src/lib.rs.rej");
+        assert!(
+            stderr.contains(&format!("rejects saved to {}", reject.display())),
+            "got:\n{stderr}"
+        );
+        let contents = fs::read_to_string(&reject).unwrap();
+        assert!(
+            contents.contains("-    \"does not match\""),
+            "got:\n{contents}"
+        );
+
+        // Once the stitches apply, they are of no use.
+        fs::write(
+            patch_dir.join("001-bad.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
+        build(root, &[]);
+        assert!(!root.join("target/cargo-stitch/.rejects/crate-a").exists());
+    }
+
+    #[test]
+    fn disabled_crate_is_not_stitched() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(patch_dir.join("001-greeting.patch"), "not a patch\n").unwrap();
+        fs::write(patch_dir.join("stitch.toml"), "enabled = false\n").unwrap();
+
+        build(root, &[]);
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+    }
+}
+
+mod keep_patched {
+    use super::*;
+
+    #[test]
+    fn keep_patched_preserves_unchanged_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        fs::write(root.join("crate-a/src/other.rs"), "// untouched\n").unwrap();
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let build = || {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build", "--keep-patched"])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "build failed:\n{stderr}");
+        };
+
+        build();
+
+        let patched = root.join("target/cargo-stitch/crate-a");
+        let mtime = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+        let other_before = mtime(&patched.join("src/other.rs"));
+
+        // Change an unrelated file so the crate has to be re-patched.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        fs::write(root.join("crate-a/src/extra.rs"), "// new\n").unwrap();
+
+        build();
+
+        assert_eq!(mtime(&patched.join("src/other.rs")), other_before);
+        assert!(patched.join("src/extra.rs").exists());
+        let content = fs::read_to_string(patched.join("src/lib.rs")).unwrap();
+        assert!(
+            content.contains("\"patched\""),
+            "patch should still be applied, got:\n{content}"
+        );
+    }
+}
+
+mod content_hash {
+    use super::*;
+
+    #[test]
+    fn touching_sources_without_changes_skips_repatch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let build = || {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "build failed:\n{stderr}");
+            stderr.into_owned()
+        };
+
+        build();
+
+        // Rewrite the source with identical contents: newer mtime, same hash.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let lib_rs = fs::read_to_string(root.join("crate-a/src/lib.rs")).unwrap();
+        fs::write(root.join("crate-a/src/lib.rs"), lib_rs).unwrap();
+
+        let stderr = build();
+        assert!(
+            !stderr.contains("001-greeting.patch"),
+            "stitches should not be re-applied, got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn unchanged_inputs_keep_patched_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        let patch = r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#;
+        fs::write(patch_dir.join("001-greeting.patch"), patch).unwrap();
+
+        let build = || {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "build failed:\n{stderr}");
+            stderr.into_owned()
+        };
+
+        build();
+
+        let patched = root.join("target/cargo-stitch/crate-a");
+        let mtime = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+        let dir_before = mtime(&patched);
+        let lib_before = mtime(&patched.join("src/lib.rs"));
+
+        // Make cargo compile crate-a again, and give the stitch a newer mtime so that only
+        // the content hash can tell nothing changed.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let status = Command::new("cargo")
+            .args(["clean", "-p", "crate-a"])
+            .current_dir(root)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+        fs::write(patch_dir.join("001-greeting.patch"), patch).unwrap();
+
+        let stderr = build();
+        assert!(stderr.contains("Compiling crate-a"), "got:\n{stderr}");
+        assert!(!stderr.contains("Patching"), "got:\n{stderr}");
+        assert_eq!(mtime(&patched), dir_before);
+        assert_eq!(mtime(&patched.join("src/lib.rs")), lib_before);
+    }
+
+    #[test]
+    fn removing_a_stitch_file_repatches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("002-exclaim.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "patched"
++    "patched!"
+ }
+"#,
+        )
+        .unwrap();
+
+        let build = || {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "build failed:\n{stderr}");
+        };
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+
+        build();
+        assert!(
+            fs::read_to_string(&patched_lib)
+                .unwrap()
+                .contains("\"patched!\"")
+        );
+
+        // Nothing left has a newer mtime than the last patch run.
+        fs::remove_file(patch_dir.join("002-exclaim.patch")).unwrap();
+
+        build();
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+    }
+}
+
+mod set_flag {
+    use super::*;
+
+    #[test]
+    fn build_with_explicit_set() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        // Put stitches under stitches/myname/ instead of stitches/default/
+        let patch_dir = root.join("stitches/myname/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "from-set"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "--set", "myname", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch --set myname build failed:\n{stderr}"
+        );
+
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(
+            content.contains("\"from-set\""),
+            "patched source should contain the set-specific string, got:\n{content}"
+        );
+    }
+
+    #[test]
+    fn build_with_nonexistent_set_fails() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "--set", "nonexistent", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        assert!(
+            !output.status.success(),
+            "cargo stitch --set nonexistent should fail"
+        );
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("stitch set not found"),
+            "error should mention missing stitch set, got:\n{stderr}"
+        );
+    }
+}
+
+mod sg_rule {
+    use super::*;
+
+    #[test]
+    fn build_with_sg_rule() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let rule_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&rule_dir).unwrap();
+        fs::write(
+            rule_dir.join("001-rename.yaml"),
+            r#"id: rename-greeting
+language: Rust
+rule:
+  pattern: '"hello"'
+fix: '"rewritten"'
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+
+        // Verify ast-grep rule was applied
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        assert!(patched_lib.exists(), "patched source should exist");
+
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(
+            content.contains("\"rewritten\""),
+            "ast-grep rule should have rewritten the string, got:\n{content}"
+        );
+        assert!(
+            !content.contains("\"hello\""),
+            "original string should not remain after ast-grep rule"
+        );
+    }
+
+    #[test]
+    fn build_with_toml_sg_rule() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let rule_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&rule_dir).unwrap();
+        fs::write(
+            rule_dir.join("001-rename.toml"),
+            r#"id = "rename-greeting"
+language = "Rust"
+fix = '"rewritten"'
+
+[rule]
+pattern = '"hello"'
+"#,
+        )
+        .unwrap();
+        // Not a rule: the settings of the stitches next to it.
+        fs::write(rule_dir.join("stitch.toml"), "keep_patched = false\n").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "list"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("001-rename.toml"), "got:\n{stdout}");
+        assert!(!stdout.contains("stitch.toml"), "got:\n{stdout}");
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+
+        let content =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(
+            content.contains("\"rewritten\""),
+            "the TOML rule should have rewritten the string, got:\n{content}"
+        );
+    }
+
+    #[test]
+    fn build_with_patch_and_sg_rule() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+
+        // Patch changes "hello" to "patched"
+        fs::write(
+            stitch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        // ast-grep rule changes "patched" to "both"
+        // This verifies patches run first, then ast-grep rules
+        fs::write(
+            stitch_dir.join("002-rename.yaml"),
+            r#"id: rename-patched
+language: Rust
+rule:
+  pattern: '"patched"'
+fix: '"both"'
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        let content = fs::read_to_string(&patched_lib).unwrap_or_else(|e| {
+            panic!(
+                "patched source should exist after build, but was not found at {patched_lib:?}: {e}",
+            )
+        });
+
+        assert!(
+            content.contains("\"both\""),
+            "patch should apply first, then ast-grep rule should rewrite, got:\n{content}"
+        );
+        assert!(
+            !content.contains("\"hello\"") && !content.contains("\"patched\""),
+            "neither original nor intermediate string should remain, got:\n{content}"
+        );
+    }
+}
+
+mod git_backend {
+    use super::*;
+
+    #[test]
+    fn build_with_git_apply() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let stitches = root.join("stitches");
+        fs::create_dir_all(stitches.join("default/crate-a")).unwrap();
+        fs::write(stitches.join("stitch.toml"), "[patch]\nbackend = \"git\"\n").unwrap();
+        // A file creation, with the `new file mode` header of `git diff`.
+        fs::write(
+            stitches.join("default/crate-a/001-add-file.patch"),
+            r#"diff --git a/src/extra.rs b/src/extra.rs
+new file mode 100644
+--- /dev/null
++++ b/src/extra.rs
+@@ -0,0 +1 @@
++pub const EXTRA: u8 = 1;
+"#,
+        )
+        .unwrap();
+
+        // Inside a git repository, as workspaces usually are.
+        let status = Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(root)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let patched = root.join("target/cargo-stitch/crate-a/src/extra.rs");
+        assert_eq!(
+            fs::read_to_string(patched).unwrap(),
+            "pub const EXTRA: u8 = 1;\n"
+        );
+        assert!(!root.join("crate-a/src/extra.rs").exists());
+    }
+
+    #[test]
+    fn failing_git_apply_fails_the_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(
+            stitch_dir.join("stitch.toml"),
+            "[patch]\nbackend = \"git\"\n",
+        )
+        .unwrap();
+        fs::write(
+            stitch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "goodbye"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "build should fail:\n{stderr}");
+        assert!(
+            stderr.contains("failed to apply patch") && stderr.contains("patch does not apply"),
+            "expected git apply's error, got:\n{stderr}"
+        );
+    }
+}
+
+mod sed_rule {
+    use super::*;
+
+    #[test]
+    fn build_with_patch_and_sed_rule() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+
+        fs::write(
+            stitch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        // Extended regex syntax: `+` and groups without backslashes.
+        fs::write(
+            stitch_dir.join("002-rename.sed"),
+            "s/\"(pat)ched+\"/\"\\1-sed\"/\n",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Substituting") && line.ends_with("002-rename.sed")),
+            "expected a status line for the sed script, got:\n{stderr}"
+        );
+
+        let patched = root.join("target/cargo-stitch/crate-a");
+        let content = fs::read_to_string(patched.join("src/lib.rs")).unwrap();
+        assert!(
+            content.contains("\"pat-sed\""),
+            "patch should apply first, then the sed script, got:\n{content}"
+        );
+
+        let backups: Vec<_> = fs::read_dir(patched.join("src"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name != "lib.rs")
+            .collect();
+        assert!(backups.is_empty(), "sed left files behind: {backups:?}");
+
+        let original = fs::read_to_string(root.join("crate-a/src/lib.rs")).unwrap();
+        assert!(original.contains("\"hello\""), "sources must be untouched");
+    }
+
+    #[test]
+    fn failing_sed_rule_fails_the_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(stitch_dir.join("001-broken.sed"), "s/unterminated\n").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "build should fail:\n{stderr}");
+        assert!(
+            stderr.contains("failed to run sed script") && stderr.contains("001-broken.sed"),
+            "expected the failing script to be named, got:\n{stderr}"
+        );
+    }
+}
+
+mod tool_versions {
+    use super::*;
+
+    fn workspace_with_sed_stitch(root: &Path) {
+        create_workspace(root);
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(stitch_dir.join("001-rename.sed"), "s/hello/sed/\n").unwrap();
+    }
+
+    #[test]
+    fn verbose_build_reports_tool_versions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        workspace_with_sed_stitch(root);
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "--verbose", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.trim_start().starts_with("Using") && line.contains("sed")),
+            "expected the sed version in verbose mode, got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn tool_older_than_tools_minimum_fails_the_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        workspace_with_sed_stitch(root);
+        // The workspace-wide minimum applies to every crate that does not set its own.
+        fs::write(
+            root.join("stitches/stitch.toml"),
+            "[tools]\nsed = \"999\"\n",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "build should fail:\n{stderr}");
+        assert!(
+            stderr.contains("sed is too old") && stderr.contains("need sed 999 or later"),
+            "expected an incompatible version error, got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn old_optional_tool_only_warns() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(
+            stitch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(stitch_dir.join("stitch.toml"), "[tools]\npatch = \"999\"\n").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+        assert!(
+            stderr.contains("Warning") && stderr.contains("patch is too old"),
+            "expected a warning about patch, got:\n{stderr}"
+        );
+    }
+}
+
+mod script {
+    use super::*;
+
+    fn build(root: &Path) -> std::process::Output {
+        Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .env_remove("STITCH_WORKSPACE_ROOT")
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn build_runs_script_in_patched_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(
+            stitch_dir.join("001-generate.sh"),
+            r#"set -eu
+test "$STITCH_PKG_NAME" = crate-a
+test "$(pwd -P)" = "$(cd "$STITCH_PATCHED_DIR" && pwd -P)"
+test -f "$STITCH_WORKSPACE_ROOT/stitches/default/crate-a/001-generate.sh"
+test -z "$(ls -A "$HOME")"
+echo "pub const STITCHED_BY: &str = \"$STITCH_PKG_NAME\";" >> src/lib.rs
+"#,
+        )
+        .unwrap();
+
+        let output = build(root);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Running") && line.ends_with("001-generate.sh")),
+            "expected a status line for the script, got:\n{stderr}"
+        );
+
+        let patched =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(
+            patched.contains(r#"pub const STITCHED_BY: &str = "crate-a";"#),
+            "script should have appended to the patched source, got:\n{patched}"
+        );
+        let original = fs::read_to_string(root.join("crate-a/src/lib.rs")).unwrap();
+        assert!(
+            !original.contains("STITCHED_BY"),
+            "sources must be untouched"
+        );
+    }
+
+    #[test]
+    fn failing_script_fails_the_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(
+            stitch_dir.join("001-fail.sh"),
+            "echo 'cannot stitch this' >&2\nexit 3\n",
+        )
+        .unwrap();
+
+        let output = build(root);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "build should fail:\n{stderr}");
+        assert!(
+            stderr.contains("failed to run bash script")
+                && stderr.contains("001-fail.sh")
+                && stderr.contains("cannot stitch this"),
+            "expected the failing script and its output, got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn hanging_script_is_killed_after_the_timeout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(stitch_dir.join("001-hang.sh"), "sleep 60\n").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .env("CARGO_STITCH_TIMEOUT_SECS", "1")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "build should fail:\n{stderr}");
+        assert!(
+            stderr.contains("bash was killed after 1s applying") && stderr.contains("001-hang.sh"),
+            "expected a timeout for the script, got:\n{stderr}"
+        );
+    }
+}
+
+mod status {
+    use super::*;
+
+    fn status(root: &Path, args: &[&str]) -> String {
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "status"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        assert!(output.status.success(), "got:\n{stdout}");
+        stdout
+    }
+
+    #[test]
+    fn status_tracks_patched_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let a = root.join("stitches/default/crate-a");
+        let b = root.join("stitches/default/crate-b");
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("001-greeting.sed"), "s/hello/patched/\n").unwrap();
+        fs::write(b.join("001-world.sed"), "s/world/there/\n").unwrap();
+
+        assert_eq!(
+            status(root, &[]),
+            "? crate-a  not built yet\n? crate-b  not built yet\n"
+        );
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build", "-p", "crate-a"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "build failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let state: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(root.join("target/cargo-stitch/crate-a/.stitch-state.json"))
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(state["applied_at"].is_u64(), "got:\n{state}");
+        assert_eq!(state["source_hash"].as_str().map(str::len), Some(64));
+        let stitches = state["stitches"].as_array().unwrap();
+        assert_eq!(stitches.len(), 1, "got:\n{state}");
+        assert!(
+            stitches[0]["path"]
+                .as_str()
+                .unwrap()
+                .ends_with("001-greeting.sed")
+        );
+        assert_eq!(stitches[0]["sha256"].as_str().map(str::len), Some(64));
+
+        let stdout = status(root, &[]);
+        assert!(
+            stdout.starts_with("✓ crate-a  up to date, updated "),
+            "got:\n{stdout}"
+        );
+        assert!(
+            stdout.ends_with("? crate-b  not built yet\n"),
+            "got:\n{stdout}"
+        );
+
+        fs::write(a.join("001-greeting.sed"), "s/hello/changed/\n").unwrap();
+        let stdout = status(root, &[]);
+        assert!(
+            stdout.starts_with("M crate-a  changed since the last build "),
+            "got:\n{stdout}"
+        );
+
+        fs::remove_dir_all(&a).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&status(root, &["--json"])).unwrap();
+        assert_eq!(json[0]["package"], "crate-a");
+        assert_eq!(json[0]["state"], "orphaned");
+        assert!(json[0]["updated"].is_u64());
+        assert_eq!(json[1]["package"], "crate-b");
+        assert_eq!(json[1]["state"], "missing");
+        assert!(json[1].get("updated").is_none());
+    }
+}
+
+mod summarize {
+    use super::*;
+
+    fn write_patch(root: &Path) {
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn summarize_reports_outdated_then_ok() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        write_patch(root);
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "summarize", "--exit-code"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            !output.status.success(),
+            "summarize --exit-code should fail before the first build:\n{stdout}"
+        );
+        assert!(stdout.contains("Crate"), "missing table header:\n{stdout}");
+        assert!(
+            stdout
+                .lines()
+                .any(|l| l.starts_with("crate-a") && l.ends_with("outdated")),
+            "crate-a should be outdated before building, got:\n{stdout}"
+        );
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "summarize", "--exit-code"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "summarize --exit-code should pass after building:\n{stdout}"
+        );
+        assert!(
+            stdout
+                .lines()
+                .any(|l| l.starts_with("crate-a") && l.contains("0.1.0") && l.ends_with("ok")),
+            "crate-a should be ok after building, got:\n{stdout}"
+        );
+    }
+
+    #[test]
+    fn summarize_reports_crates_io_dependency_stitches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        let cargo_home = tmp.path().join("cargo-home");
+        fs::create_dir_all(&root).unwrap();
+        create_workspace(&root);
+        add_registry_dependency(&root, &cargo_home);
+
+        let stitches = root.join("stitches/default/demo-0.2.0");
+        fs::create_dir_all(&stitches).unwrap();
+        fs::write(stitches.join("stitch.toml"), "external = true\n").unwrap();
+        fs::write(
+            stitches.join("001-demo.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn demo() -> u32 {\n-    2\n+    20\n }\n",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "summarize"])
+            .env("CARGO_HOME", &cargo_home)
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "got:\n{stdout}");
+        assert!(
+            stdout
+                .lines()
+                .any(|l| l.starts_with("demo-0.2.0  0.2.0") && l.ends_with("outdated")),
+            "demo-0.2.0 should be outdated before building, got:\n{stdout}"
+        );
+        assert!(!stdout.contains("orphaned"), "got:\n{stdout}");
+    }
+
+    #[test]
+    fn summarize_reports_failing_stitch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-broken.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "does not match"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "summarize"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "summarize without --exit-code should succeed"
+        );
+        assert!(
+            stdout
+                .lines()
+                .any(|l| l.starts_with("crate-a") && l.ends_with("failing")),
+            "crate-a should be failing, got:\n{stdout}"
+        );
+    }
+}
+
+mod lint {
+    use super::*;
+
+    #[test]
+    fn lint_fix_normalizes_stitch_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("Fix_Greeting.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"   \n+    \"patched\"\n }",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "lint"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "lint should report issues");
+        assert!(
+            stderr.contains("should be named `001-fix-greeting.patch`"),
+            "got:\n{stderr}"
+        );
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "lint", "--fix"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "lint --fix failed:\n{stderr}");
+        assert!(stderr.contains("Fixed"), "got:\n{stderr}");
+
+        let fixed = fs::read_to_string(patch_dir.join("001-fix-greeting.patch")).unwrap();
+        assert!(fixed.ends_with(" }\n"), "got:\n{fixed}");
+        assert!(fixed.contains("-    \"hello\"\n"), "got:\n{fixed}");
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "lint"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "lint should pass after --fix");
+    }
+    #[test]
+    fn lint_reports_every_malformed_stitch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            "+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("002-rule.yaml"),
+            "id: rule\nrule:\n  pattern: $A\n",
+        )
+        .unwrap();
+        fs::write(patch_dir.join("003-rename.sed"), "s/hello/unterminated\n").unwrap();
+        fs::write(
+            patch_dir.join("004-latin1.patch"),
+            b"--- a/x\n+++ b/x\n\xe9\n",
+        )
+        .unwrap();
+        fs::write(patch_dir.join("005-rule.toml"), "id = \n").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "lint"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(1), "got:\n{stderr}");
+        for expected in [
+            "001-fix.patch: `+++ b/src/lib.rs` has no `---` header before it",
+            "002-rule.yaml: ast-grep rule has no `language`",
+            "003-rename.sed: is not a valid sed script: ",
+            "004-latin1.patch: is not valid UTF-8",
+            "005-rule.toml: is not valid TOML: ",
+        ] {
+            assert!(
+                stderr.contains(expected),
+                "missing {expected:?} in:\n{stderr}"
+            );
+        }
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "lint", "--fix"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "only the header can be fixed");
+        let fixed = fs::read_to_string(patch_dir.join("001-fix.patch")).unwrap();
+        assert!(
+            fixed.starts_with("--- a/src/lib.rs\n+++ b/src/lib.rs\n"),
+            "got:\n{fixed}"
+        );
+    }
+
+    #[test]
+    fn duplicate_rule_ids_are_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let rule = "id: no-unwrap\nlanguage: Rust\nrule:\n  pattern: $A.unwrap()\nfix: $A.expect(\"ok\")\n";
+        for krate in ["crate-a", "crate-b"] {
+            let dir = root.join("stitches/default").join(krate);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("001-no-unwrap.yaml"), rule).unwrap();
+        }
+
+        for command in ["check-unique-ids", "lint"] {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", command])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(!output.status.success(), "{command} should fail");
+            assert!(
+                stderr.lines().any(|line| line.ends_with(
+                    "crate-a/001-no-unwrap.yaml: ast-grep rule id `no-unwrap` is also used in crate-b/001-no-unwrap.yaml"
+                )),
+                "{command} got:\n{stderr}"
+            );
+        }
+
+        fs::write(
+            root.join("stitches/default/crate-b/001-no-unwrap.yaml"),
+            rule.replace("no-unwrap", "no-unwrap-b"),
+        )
+        .unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "check-unique-ids"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    fn conflicting_patches_are_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        // Both patches were made against the original sources.
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        for (name, greeting) in [
+            ("001-patched.patch", "patched"),
+            ("002-bonjour.patch", "bonjour"),
+        ] {
+            fs::write(
+                patch_dir.join(name),
+                format!(
+                    "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n \
+                     pub fn greeting() -> &'static str {{\n-    \"hello\"\n+    \"{greeting}\"\n }}\n"
+                ),
+            )
+            .unwrap();
+        }
+        let message = "crate-a/002-bonjour.patch: changes line 2 of src/lib.rs, which \
+                       001-patched.patch changes too; it will likely fail to apply";
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "lint"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.lines().any(|line| line.ends_with(message)),
+            "got:\n{stderr}"
+        );
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "check", "--report-format", "tap"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("changes line 2 of src/lib.rs, which 001-patched.patch changes too"),
+            "got:\n{stdout}"
+        );
+
+        // Once the second patch is made on top of the first, they no longer conflict.
+        fs::write(
+            patch_dir.join("002-bonjour.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n \
+             pub fn greeting() -> &'static str {\n-    \"patched\"\n+    \"bonjour\"\n }\n",
+        )
+        .unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "lint"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+mod trace {
+    use super::*;
+
+    #[test]
+    fn trace_shows_each_stitch_step() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-first.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "step1"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("002-second.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "step1"
++    "step2"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "trace", "crate-a"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "trace failed:\n{stderr}");
+
+        let (first, second) = stdout
+            .split_once("==> [2/2] 002-second.patch")
+            .expect("second step header");
+        assert!(
+            first.contains("==> [1/2] 001-first.patch"),
+            "got:\n{stdout}"
+        );
+        assert!(first.contains("-    \"hello\""), "got:\n{stdout}");
+        assert!(first.contains("+    \"step1\""), "got:\n{stdout}");
+        assert!(second.contains("-    \"step1\""), "got:\n{stdout}");
+        assert!(second.contains("+    \"step2\""), "got:\n{stdout}");
+
+        // Tracing must not touch the patched directory used for builds.
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+    }
+}
+
+mod check_report {
+    use super::*;
+
+    #[test]
+    fn check_writes_junit_report() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-good.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("002-bad.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "does not match"
++    "never"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args([
+                "stitch",
+                "check",
+                "--report-format",
+                "junit",
+                "--report-output",
+                "report.xml",
+            ])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "a failing stitch should fail");
+
+        let report = fs::read_to_string(root.join("report.xml")).unwrap();
+        assert!(
+            report.contains("tests=\"2\" failures=\"1\""),
+            "got:\n{report}"
+        );
+        assert!(
+            report.contains(
+                "<testcase name=\"stitches/default/crate-a/001-good.patch\" classname=\"crate-a\"/>"
+            ),
+            "got:\n{report}"
+        );
+        assert!(
+            report.contains("<failure message=\"failed to apply patch:"),
+            "got:\n{report}"
+        );
+
+        // Without report flags, `check` is still `cargo check`.
+        fs::remove_file(patch_dir.join("002-bad.patch")).unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "check"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "cargo check failed:\n{stderr}");
+        assert!(stderr.contains("Checking crate-a"), "got:\n{stderr}");
+    }
+
+    #[test]
+    fn check_strict_fails_on_warnings() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-noop.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "hello"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "check", "--report-format", "human"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "got:\n{stdout}");
+        assert!(
+            stdout.contains("warning: stitch produces no changes"),
+            "got:\n{stdout}"
+        );
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "check", "--strict"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!output.status.success(), "--strict should fail on warnings");
+        assert!(
+            stdout.contains("warning: stitch produces no changes"),
+            "got:\n{stdout}"
+        );
+    }
+
+    #[test]
+    fn check_since_only_checks_changed_crates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(root)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?} failed");
+        };
+
+        let patch = r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#;
+        let stitches = root.join("stitches/default");
+        fs::create_dir_all(stitches.join("crate-a")).unwrap();
+        fs::create_dir_all(stitches.join("crate-b")).unwrap();
+        fs::write(stitches.join("crate-a/001-greeting.patch"), patch).unwrap();
+        fs::write(stitches.join("crate-b/001-unchanged.patch"), "not a patch").unwrap();
+
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "initial"]);
+
+        fs::write(
+            stitches.join("crate-a/001-greeting.patch"),
+            patch.replace("patched", "changed"),
+        )
+        .unwrap();
+        git(&["commit", "-q", "-am", "change crate-a"]);
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "check", "--since", "HEAD~1"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "got:\n{stdout}");
+        assert!(
+            stdout.contains("ok      stitches/default/crate-a/001-greeting.patch"),
+            "got:\n{stdout}"
+        );
+        assert!(!stdout.contains("crate-b"), "got:\n{stdout}");
+    }
+
+    #[test]
+    fn check_registry_dependency_stitches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        let cargo_home = tmp.path().join("cargo-home");
+        fs::create_dir_all(&root).unwrap();
+        create_workspace(&root);
+
+        add_registry_dependency(&root, &cargo_home);
+
+        let stitches = root.join("stitches/default/demo-0.2.0");
+        fs::create_dir_all(&stitches).unwrap();
+        fs::write(
+            stitches.join("001-demo.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn demo() -> u32 {\n-    2\n+    20\n }\n",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "--external", "check", "--report-format", "human"])
+            .env("CARGO_HOME", &cargo_home)
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "got:\n{stdout}");
+        assert!(
+            stdout.contains("ok      stitches/default/demo-0.2.0/001-demo.patch"),
+            "got:\n{stdout}"
+        );
+    }
+
+    #[test]
+    fn check_stitches_from_library() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-good.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        if let Err(e) = cargo_stitch::check_stitches(root) {
+            panic!("stitches should apply: {e}");
+        }
+
+        fs::write(
+            patch_dir.join("002-bad.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "does not match"
++    "never"
+ }
+"#,
+        )
+        .unwrap();
+
+        let Err(err) = cargo_stitch::check_stitches(root) else {
+            panic!("a failing stitch should fail");
+        };
+        let Ok(failed) = err.narrow::<cargo_stitch::PatchFailed, _>() else {
+            panic!("expected a patch failure");
+        };
+        assert!(failed.file.ends_with("002-bad.patch"), "got: {failed}");
+
+        // Nothing is built, and the crate's own source is left alone.
+        assert!(!root.join("target").exists());
+        let source = fs::read_to_string(root.join("crate-a/src/lib.rs")).unwrap();
+        assert!(source.contains("\"hello\""), "got:\n{source}");
+    }
+
+    #[test]
+    fn check_stitches_reports_cargo_metadata_failure() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        fs::create_dir_all(root.join("stitches/default/crate-a")).unwrap();
+        fs::write(
+            root.join("stitches/default/crate-a/001-greeting.sed"),
+            "s/hello/patched/\n",
+        )
+        .unwrap();
+        fs::write(root.join("crate-b/Cargo.toml"), "[package\n").unwrap();
+
+        let Err(err) = cargo_stitch::check_stitches(root) else {
+            panic!("a broken workspace should fail");
+        };
+        let Ok(err) = err.narrow::<cargo_stitch::IoError, _>() else {
+            panic!("expected an I/O error");
+        };
+        let message = err.to_string();
+        assert!(message.contains("cargo metadata"), "got: {message}");
+        assert!(!message.contains("not a member"), "got: {message}");
+    }
+
+    #[test]
+    fn validate_stitch_from_library() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        let good = patch_dir.join("001-good.patch");
+        fs::write(
+            &good,
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        let bad = patch_dir.join("002-bad.patch");
+        fs::write(
+            &bad,
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "does not match"
++    "never"
+ }
+"#,
+        )
+        .unwrap();
+
+        let src_dir = root.join("crate-a");
+        let result = match cargo_stitch::validate_stitch(root, &good, &src_dir) {
+            Ok(result) => result,
+            Err(e) => panic!("stitch should apply: {e}"),
+        };
+        assert!(result.applied);
+        assert_eq!(result.changed_files, vec![Path::new("src/lib.rs")]);
+
+        let Err(err) = cargo_stitch::validate_stitch(root, &bad, &src_dir) else {
+            panic!("a failing stitch should fail");
+        };
+        assert!(err.narrow::<cargo_stitch::PatchFailed, _>().is_ok());
+
+        assert!(cargo_stitch::validate_stitch(root, &root.join("Cargo.toml"), &src_dir).is_err());
+
+        assert!(!root.join("target").exists());
+        let source = fs::read_to_string(src_dir.join("src/lib.rs")).unwrap();
+        assert!(source.contains("\"hello\""), "got:\n{source}");
+    }
+
+    #[test]
+    fn discover_stitches_from_library() {
+        use cargo_stitch::StitchKind;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(patch_dir.join("001-fix.patch"), "# nothing yet\n").unwrap();
+        fs::write(patch_dir.join("002-rename.sed"), "s/hello/hi/\n").unwrap();
+        let disabled_dir = root.join("stitches/default/crate-b");
+        fs::create_dir_all(&disabled_dir).unwrap();
+        fs::write(disabled_dir.join("001-fix.patch"), "# nothing yet\n").unwrap();
+        fs::write(disabled_dir.join("stitch.toml"), "enabled = false\n").unwrap();
+
+        let stitches = match cargo_stitch::discover_stitches(root) {
+            Ok(stitches) => stitches,
+            Err(e) => panic!("stitches should be found: {e}"),
+        };
+        assert_eq!(stitches.keys().collect::<Vec<_>>(), ["crate-a"]);
+
+        let set = &stitches["crate-a"];
+        assert_eq!(set.len(), 2);
+        assert!(!set.is_empty());
+        let found: Vec<_> = set
+            .iter()
+            .map(|stitch| (stitch.kind(), stitch.path().file_name().unwrap()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (StitchKind::Patch, "001-fix.patch"),
+                (StitchKind::SedRule, "002-rename.sed")
+            ]
+        );
+        assert_eq!(StitchKind::SgRule.to_string(), "sg-rule");
+    }
+}
+
+mod verify {
+    use super::*;
+
+    #[test]
+    fn verify_lists_rejected_stitches_without_building() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-good.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
+
+        let verify = || {
+            Command::new(cargo_stitch_bin())
+                .args(["stitch", "verify"])
+                .current_dir(root)
+                .output()
+                .unwrap()
+        };
+
+        let output = verify();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "got:\n{stdout}");
+        assert!(
+            stdout.contains("ok      crate-a  001-good.patch"),
+            "got:\n{stdout}"
+        );
+
+        fs::write(
+            patch_dir.join("002-bad.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"does not match\"\n+    \"never\"\n }\n",
+        )
+        .unwrap();
+
+        let output = verify();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(output.status.code(), Some(1), "got:\n{stdout}");
+        assert!(
+            stdout.contains("FAILED  crate-a  002-bad.patch"),
+            "got:\n{stdout}"
+        );
+        // The rejected hunk itself, from the `.rej` file `patch` leaves behind.
+        assert!(stdout.contains("src/lib.rs.rej:"), "got:\n{stdout}");
+        assert!(stdout.contains("-    \"does not match\""), "got:\n{stdout}");
+        assert!(
+            stdout.contains("2 stitches verified: 1 apply, 1 rejected"),
+            "got:\n{stdout}"
+        );
+        assert!(
+            !root.join("target/cargo-stitch").exists(),
+            "verify should not build anything"
+        );
+    }
+}
+
+mod registry {
+    use super::*;
+
+    #[test]
+    fn publish_then_fetch_round_trips_through_file_registry() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        fs::create_dir_all(&root).unwrap();
+        create_workspace(&root);
+
+        // curl does not create directories for file:// uploads.
+        let registry_dir = tmp.path().join("registry");
+        fs::create_dir_all(registry_dir.join("crate-a")).unwrap();
+        let registry = format!("file://{}", registry_dir.display());
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        let patch = "--- a/src/lib.rs\n+++ b/src/lib.rs\n";
+        fs::write(patch_dir.join("001-fix.patch"), patch).unwrap();
+        fs::write(patch_dir.join("stitch.toml"), "keep_patched = true\n").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "publish-stitches", "crate-a", "--registry"])
+            .arg(&registry)
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "publish failed:\n{stderr}");
+        assert!(registry_dir.join("crate-a/0.1.0.tar.gz").is_file());
+
+        fs::remove_dir_all(&patch_dir).unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "fetch-stitches", "crate-a", "0.1.0", "--registry"])
+            .arg(&registry)
+            .current_dir(&root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "fetch failed:\n{stderr}");
+
+        assert_eq!(
+            fs::read_to_string(patch_dir.join("001-fix.patch")).unwrap(),
+            patch
+        );
+        assert!(patch_dir.join("stitch.toml").is_file());
+    }
+}
+
+mod external {
+    use super::*;
+
+    fn demo_patch(value: u32) -> String {
+        format!(
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn demo() -> u32 {{\n-    2\n+    {value}\n }}\n"
+        )
+    }
+
+    #[test]
+    fn edited_stitch_is_applied_by_the_next_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        let cargo_home = tmp.path().join("cargo-home");
+        fs::create_dir_all(&root).unwrap();
+        create_workspace(&root);
+        add_registry_dependency(&root, &cargo_home);
+
+        let stitches = root.join("stitches/default/demo-0.2.0");
+        fs::create_dir_all(&stitches).unwrap();
+        fs::write(stitches.join("stitch.toml"), "external = true\n").unwrap();
+        fs::write(stitches.join("001-demo.patch"), demo_patch(20)).unwrap();
+
+        let build = || {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .env("CARGO_HOME", &cargo_home)
+                .current_dir(&root)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            assert!(output.status.success(), "build failed:\n{stderr}");
+            stderr
+        };
+        let patched = root.join("target/cargo-stitch/demo-0.2.0/src/lib.rs");
+
+        build();
+        assert!(fs::read_to_string(&patched).unwrap().contains("    20\n"));
+
+        // Cargo does not rebuild the dependency by itself: its sources did not change.
+        fs::write(stitches.join("001-demo.patch"), demo_patch(30)).unwrap();
+        let stderr = build();
+        assert!(
+            stderr.contains("Cleaning demo@0.2.0, whose stitches changed"),
+            "got:\n{stderr}"
+        );
+        assert!(fs::read_to_string(&patched).unwrap().contains("    30\n"));
+
+        // Nothing changed since, so there is nothing to clean.
+        let stderr = build();
+        assert!(!stderr.contains("Cleaning"), "got:\n{stderr}");
+    }
+}
+
+mod verify_applied {
+    use super::*;
+
+    #[test]
+    fn verify_applied_detects_modified_stitches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        let write_patch = |replacement: &str| {
+            fs::write(
+                patch_dir.join("001-greeting.patch"),
+                format!(
+                    "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {{\n-    \"hello\"\n+    \"{replacement}\"\n }}\n"
+                ),
+            )
+            .unwrap();
+        };
+        write_patch("patched");
+
+        let verify = || {
+            Command::new(cargo_stitch_bin())
+                .args(["stitch", "verify-applied", "crate-a"])
+                .current_dir(root)
+                .output()
+                .unwrap()
+        };
+
+        // Nothing built yet.
+        assert!(!verify().status.success());
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let output = verify();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "verify failed:\n{stderr}");
+
+        write_patch("changed");
+
+        let output = verify();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "verify should fail:\n{stderr}");
+        assert!(stderr.contains("crate-a: src/lib.rs"), "got:\n{stderr}");
+    }
+}
+
+mod workspace_metadata {
+    use super::*;
+
+    #[test]
+    fn build_with_workspace_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        let manifest = fs::read_to_string(root.join("Cargo.toml")).unwrap();
+        let write_manifest = |settings: &str| {
+            fs::write(
+                root.join("Cargo.toml"),
+                format!("{manifest}\n[workspace.metadata.stitch]\n{settings}"),
+            )
+            .unwrap();
+        };
+        write_manifest(
+            "stitches_dir = \"patches\"\npatched_dir = \"build/stitched\"\ndefault_strip = 0\n",
+        );
+
+        // No `a/` and `b/` prefixes, as `default_strip = 0` expects.
+        let patch_dir = root.join("patches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            "--- src/lib.rs\n+++ src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
+
+        let build = || {
+            Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .current_dir(root)
+                .output()
+                .unwrap()
+        };
+
+        let output = build();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+
+        let patched_lib = root.join("build/stitched/crate-a/src/lib.rs");
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+
+        // `stitches/stitch.toml` wins over `default_strip`, with a warning.
+        fs::write(root.join("patches/stitch.toml"), "[patch]\nstrip = 0\n").unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "list"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch list failed:\n{stderr}"
+        );
+        assert!(
+            stderr.contains("takes precedence over `default_strip = 0`"),
+            "got:\n{stderr}"
+        );
+        fs::remove_file(root.join("patches/stitch.toml")).unwrap();
+
+        // Only the crates in `enabled_packages` are stitched.
+        fs::remove_dir_all(root.join("build")).unwrap();
+        write_manifest(
+            "stitches_dir = \"patches\"\npatched_dir = \"build/stitched\"\nenabled_packages = [\"crate-b\"]\n",
+        );
+        let output = build();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+        assert!(!root.join("build/stitched/crate-a").exists());
+    }
+
+    #[test]
+    fn patches_dir_env_overrides_stitches_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        let manifest = fs::read_to_string(root.join("Cargo.toml")).unwrap();
+        fs::write(
+            root.join("Cargo.toml"),
+            format!("{manifest}\n[workspace.metadata.stitch]\nstitches_dir = \"patches\"\n"),
+        )
+        .unwrap();
+
+        let elsewhere = tempfile::tempdir().unwrap();
+        for dir in [root.join("vendor-patches"), elsewhere.path().to_path_buf()] {
+            let patch_dir = dir.join("default/crate-a");
+            fs::create_dir_all(&patch_dir).unwrap();
+            fs::write(
+                patch_dir.join("001-fix.patch"),
+                "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+            )
+            .unwrap();
+        }
+
+        // Relative to the workspace root, even from a member's directory, or absolute.
+        for (dir, cwd) in [
+            (Path::new("vendor-patches"), root.join("crate-b")),
+            (elsewhere.path(), root.to_path_buf()),
+        ] {
+            fs::remove_dir_all(root.join("target/cargo-stitch")).ok();
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .env("CARGO_STITCH_PATCHES_DIR", dir)
+                .current_dir(&cwd)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(
+                output.status.success(),
+                "cargo stitch build failed:\n{stderr}"
+            );
+
+            let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+            let content = fs::read_to_string(&patched_lib).unwrap();
+            assert!(content.contains("\"patched\""), "got:\n{content}");
+        }
+    }
+
+    #[test]
+    fn patches_path_env_adds_stitch_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch = |to: &str| {
+            format!(
+                "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {{\n-    \"hello\"\n+    \"{to}\"\n }}\n"
+            )
+        };
+        let local = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&local).unwrap();
+        fs::write(local.join("001-fix.patch"), patch("local")).unwrap();
+
+        // `user` comes first, so its `001-fix.patch` replaces the workspace's.
+        let user = tempfile::tempdir().unwrap();
+        let shared = root.join("shared-stitches");
+        for (dir, to) in [(user.path(), "user"), (shared.as_path(), "shared")] {
+            let patch_dir = dir.join("default/crate-a");
+            fs::create_dir_all(&patch_dir).unwrap();
+            fs::write(patch_dir.join("001-fix.patch"), patch(to)).unwrap();
+        }
+
+        let path = std::env::join_paths([user.path(), Path::new(""), Path::new("shared-stitches")])
+            .unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .env("CARGO_STITCH_PATCHES_PATH", &path)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(content.contains("\"user\""), "got:\n{content}");
+    }
+
+    #[test]
+    fn target_dir_env_overrides_patched_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        let manifest = fs::read_to_string(root.join("Cargo.toml")).unwrap();
+        fs::write(
+            root.join("Cargo.toml"),
+            format!("{manifest}\n[workspace.metadata.stitch]\npatched_dir = \"build/stitched\"\n"),
+        )
+        .unwrap();
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
+
+        // Relative to the workspace root, even from a member's directory.
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .env("CARGO_STITCH_TARGET_DIR", "out/stitched")
+            .current_dir(root.join("crate-b"))
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+
+        let patched_lib = root.join("out/stitched/crate-a/src/lib.rs");
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+        assert!(!root.join("build/stitched").exists());
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+    }
+
+    #[test]
+    fn build_with_package_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        let manifest = fs::read_to_string(root.join("crate-a/Cargo.toml")).unwrap();
+        let write_manifest = |settings: &str| {
+            fs::write(
+                root.join("crate-a/Cargo.toml"),
+                format!("{manifest}\n[package.metadata.stitch]\n{settings}"),
+            )
+            .unwrap();
+        };
+        write_manifest("strip = 0\nextra_patch_args = [\"--fuzz=0\"]\n");
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            "--- src/lib.rs\n+++ src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
+
+        let build = || {
+            Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .current_dir(root)
+                .output()
+                .unwrap()
+        };
+
+        let output = build();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        let content = fs::read_to_string(&patched_lib).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+
+        // The crate's `stitch.toml` wins, with a warning.
+        fs::write(patch_dir.join("stitch.toml"), "[patch]\nstrip = 1\n").unwrap();
+        let output = build();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            !output.status.success(),
+            "`-p1` should not apply:\n{stderr}"
+        );
+        assert!(
+            stderr.contains("sets `strip`, which takes precedence over [package.metadata.stitch]"),
+            "got:\n{stderr}"
+        );
+        fs::remove_file(patch_dir.join("stitch.toml")).unwrap();
+
+        fs::remove_dir_all(root.join("target")).unwrap();
+        write_manifest("enabled = false\n");
+        let output = build();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{stderr}"
+        );
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+    }
+}
+
+mod hardlink {
+    use super::*;
+
+    #[test]
+    fn hardlinked_build_leaves_sources_untouched() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        fs::create_dir_all(root.join("stitches")).unwrap();
+        fs::write(
+            root.join("stitches/stitch.toml"),
+            "[copy]\nhardlink = true\n",
+        )
+        .unwrap();
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let patched =
+            fs::read_to_string(root.join("target/cargo-stitch/crate-a/src/lib.rs")).unwrap();
+        assert!(patched.contains("\"patched\""), "got:\n{patched}");
+
+        let original = fs::read_to_string(root.join("crate-a/src/lib.rs")).unwrap();
+        assert!(
+            original.contains("\"hello\""),
+            "source was modified:\n{original}"
+        );
+
+        // Files the stitches did not touch are shared with the source.
+        let inode = |p: &Path| {
+            use std::os::unix::fs::MetadataExt;
+            fs::metadata(p).unwrap().ino()
+        };
+        assert_eq!(
+            inode(&root.join("crate-a/Cargo.toml")),
+            inode(&root.join("target/cargo-stitch/crate-a/Cargo.toml"))
+        );
+    }
+}
+
+mod reflink {
+    use super::*;
+
+    // With the `reflink` feature, sources are cloned where the file system supports it
+    // and copied elsewhere; either way, the patched copy must match them.
+    #[test]
+    fn patched_copy_matches_sources() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let a = root.join("crate-a");
+        fs::create_dir_all(a.join("src/generated")).unwrap();
+        for i in 0..20 {
+            fs::write(
+                a.join(format!("src/generated/file_{i}.txt")),
+                format!("{i}\n").repeat(1000),
+            )
+            .unwrap();
+        }
+        fs::write(a.join("gen.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(a.join("gen.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let patched = root.join("target/cargo-stitch/crate-a");
+        for i in 0..20 {
+            let file = format!("src/generated/file_{i}.txt");
+            assert_eq!(
+                fs::read(patched.join(&file)).unwrap(),
+                fs::read(a.join(&file)).unwrap(),
+                "{file} differs"
+            );
+        }
+        let mode = fs::metadata(patched.join("gen.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        let lib = fs::read_to_string(patched.join("src/lib.rs")).unwrap();
+        assert!(lib.contains("\"patched\""), "got:\n{lib}");
+        let original = fs::read_to_string(a.join("src/lib.rs")).unwrap();
+        assert!(
+            original.contains("\"hello\""),
+            "source was modified:\n{original}"
+        );
+    }
+}
+
+mod symlinks {
+    use super::*;
+
+    #[test]
+    fn patched_copy_keeps_symlinked_sources() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        // crate-a's `shared` module is a symlink to a file outside the crate.
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::write(
+            root.join("shared/shared.rs"),
+            "pub const NAME: &str = \"shared\";\n",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("../../shared/shared.rs", root.join("crate-a/src/shared.rs"))
+            .unwrap();
+        fs::write(
+            root.join("crate-a/src/lib.rs"),
+            r#"pub mod shared;
+
+pub fn greeting() -> &'static str {
+    "hello"
+}
+"#,
+        )
+        .unwrap();
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -3,3 +3,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let link = root.join("target/cargo-stitch/crate-a/src/shared.rs");
+        assert!(
+            fs::symlink_metadata(&link).unwrap().is_symlink(),
+            "the symlink was copied as a file"
+        );
+        assert_eq!(
+            fs::canonicalize(&link).unwrap(),
+            fs::canonicalize(root.join("shared/shared.rs")).unwrap()
+        );
+    }
+}
+
+mod check_ordering {
+    use super::*;
+
+    #[test]
+    fn check_ordering_reports_dependent_and_independent_pairs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        fs::write(
+            root.join("crate-a/src/extra.rs"),
+            "pub fn extra() -> u8 {\n    1\n}\n",
+        )
+        .unwrap();
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-first.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "step1"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("002-second.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "step1"
++    "step2"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("003-extra.patch"),
+            "--- a/src/extra.rs\n+++ b/src/extra.rs\n@@ -1,3 +1,3 @@\n pub fn extra() -> u8 {\n-    1\n+    2\n }\n",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "check-ordering", "crate-a", "--exit-code"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr.contains("001-first.patch <-> 002-second.patch: swapped order fails to apply"),
+            "got:\n{stderr}"
+        );
+        assert!(
+            stderr.lines().any(|line| line.contains("Independent")
+                && line.ends_with("002-second.patch <-> 003-extra.patch")),
+            "got:\n{stderr}"
+        );
+    }
+}
+
+mod reorder {
+    use super::*;
+
+    #[test]
+    fn reorder_cascades_renames() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(patch_dir.join("001-first.patch"), "first").unwrap();
+        fs::write(patch_dir.join("002-second.patch"), "second").unwrap();
+        fs::write(patch_dir.join("003-third.patch"), "third").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "reorder", "crate-a", "003", "001"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr.lines().any(|line| line.contains("Renamed")
+                && line.ends_with("003-third.patch -> 001-third.patch")),
+            "got:\n{stderr}"
+        );
+
+        let read = |name: &str| fs::read_to_string(patch_dir.join(name)).unwrap();
+        assert_eq!(read("001-third.patch"), "third");
+        assert_eq!(read("002-first.patch"), "first");
+        assert_eq!(read("003-second.patch"), "second");
+        assert_eq!(fs::read_dir(&patch_dir).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn reorder_to_same_number_is_a_no_op() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(patch_dir.join("002-second.patch"), "second").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "reorder", "crate-a", "2", "002"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(stderr.contains("no change needed"), "got:\n{stderr}");
+        assert!(patch_dir.join("002-second.patch").exists());
+    }
+}
+
+mod apply {
+    use super::*;
+
+    #[test]
+    fn apply_stitches_every_crate_and_reports_all_failures() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let a = root.join("stitches/default/crate-a");
+        let b = root.join("stitches/default/crate-b");
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(
+            a.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        let bad = r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn message() -> String {
+-    format!("{} everyone", crate_a::greeting())
++    format!("{} all", crate_a::greeting())
+ }
+"#;
+        fs::write(b.join("001-message.patch"), bad).unwrap();
+
+        let apply = || {
+            Command::new(cargo_stitch_bin())
+                .args(["stitch", "apply"])
+                .current_dir(root)
+                .output()
+                .unwrap()
+        };
+
+        // crate-b's failure does not stop crate-a from being stitched.
+        let output = apply();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Error")
+                    && line.contains("crate-b: failed to apply patch")),
+            "got:\n{stderr}"
+        );
+        let patched = root.join("target/cargo-stitch");
+        let content = fs::read_to_string(patched.join("crate-a/src/lib.rs")).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+
+        fs::write(
+            b.join("001-message.patch"),
+            bad.replace("everyone", "world"),
+        )
+        .unwrap();
+        let output = apply();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Fresh") && line.ends_with(" crate-a")),
+            "got:\n{stderr}"
+        );
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Stitched") && line.ends_with(" crate-b")),
+            "got:\n{stderr}"
+        );
+
+        // The build uses the patched copies as they are.
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+        assert!(!stderr.contains("Patching"), "got:\n{stderr}");
+    }
+}
+
+mod apply_to {
+    use super::*;
+
+    #[test]
+    fn apply_to_stitches_the_given_directory_in_place() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(
+            stitch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let copy = root.join("copy");
+        fs::create_dir_all(copy.join("src")).unwrap();
+        fs::copy(root.join("crate-a/src/lib.rs"), copy.join("src/lib.rs")).unwrap();
+
+        let apply_to = |dir: &str| {
+            Command::new(cargo_stitch_bin())
+                .args(["stitch", "apply-to", dir, "crate-a"])
+                .current_dir(root)
+                .output()
+                .unwrap()
+        };
+
+        let output = apply_to("copy");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Applying") && line.ends_with("crate-a to copy")),
+            "got:\n{stderr}"
+        );
+        let content = fs::read_to_string(copy.join("src/lib.rs")).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+        assert!(
+            !root.join("target/cargo-stitch").exists(),
+            "nothing should be copied to the patched directory"
+        );
+
+        // Applying again fails, as the patch no longer matches.
+        let output = apply_to("copy");
+        assert!(!output.status.success());
+
+        let output = apply_to("missing");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains("missing is not a directory"),
+            "got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn apply_to_leaves_the_directory_alone_when_a_stitch_fails() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(
+            stitch_dir.join("001-greeting.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n",
+        )
+        .unwrap();
+        fs::write(
+            stitch_dir.join("002-broken.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"does not match\"\n+    \"never\"\n }\n",
+        )
+        .unwrap();
+
+        let copy = root.join("copy");
+        fs::create_dir_all(copy.join("src")).unwrap();
+        fs::copy(root.join("crate-a/src/lib.rs"), copy.join("src/lib.rs")).unwrap();
+
+        let output = stitch(root, &["apply-to", "copy", "crate-a"]);
+        assert!(!output.status.success());
+
+        // The first stitch applied, but not to `copy`.
+        let content = fs::read_to_string(copy.join("src/lib.rs")).unwrap();
+        assert!(content.contains("\"hello\""), "got:\n{content}");
+    }
+}
+
+mod clean {
+    use super::*;
+
+    fn clean(root: &Path, args: &[&str]) -> String {
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "clean"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(output.status.success(), "got:\n{stderr}");
+        stderr
+    }
+
+    #[test]
+    fn clean_removes_patched_crates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patched = root.join("target/cargo-stitch");
+        for dir in ["crate-a/src", "crate-b/src", ".fetch/serde-1.0.0"] {
+            fs::create_dir_all(patched.join(dir)).unwrap();
+        }
+        fs::write(patched.join(".manifest-0.json"), "{}").unwrap();
+
+        let stderr = clean(root, &["--package", "crate-a"]);
+        assert!(
+            stderr.lines().any(|line| line.contains("Removed")
+                && line.ends_with(" 1 patched crate from target/cargo-stitch/crate-a")),
+            "got:\n{stderr}"
+        );
+        assert!(!patched.join("crate-a").exists());
+        assert!(patched.join("crate-b").exists());
+
+        let stderr = clean(root, &[]);
+        assert!(
+            stderr.lines().any(|line| line.contains("Removed")
+                && line.ends_with(" 1 patched crate from target/cargo-stitch")),
+            "got:\n{stderr}"
+        );
+        assert!(!patched.exists());
+        assert!(root.join("target").exists());
+
+        // Nothing left to remove is not an error.
+        let stderr = clean(root, &[]);
+        assert!(
+            stderr.lines().any(|line| line.contains("Removed")
+                && line.ends_with(" 0 patched crates from target/cargo-stitch")),
+            "got:\n{stderr}"
+        );
+    }
+
+    /// The test workspace, with a patch for `crate-a`.
+    fn create_workspace_with_patch(root: &Path) {
+        create_workspace(root);
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+    }
+
+    fn log(root: &Path, args: &[&str]) -> String {
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "log"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn clean_keeps_history_unless_asked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace_with_patch(root);
+        build(root, &[]);
+
+        let stdout = log(root, &[]);
+        let lines: Vec<_> = stdout.lines().collect();
+        assert_eq!(lines.len(), 1, "got:\n{stdout}");
+        assert!(
+            lines[0].ends_with("  crate-a  1 stitch applied"),
+            "got:\n{stdout}"
+        );
+        assert!(log(root, &["-p", "crate-b"]).is_empty());
+
+        let json: serde_json::Value = serde_json::from_str(&log(root, &["--json"])).unwrap();
+        assert_eq!(json[0]["package"], "crate-a");
+        assert_eq!(json[0]["applied"], 1);
+        assert!(json[0]["applied_at"].is_u64(), "got: {json}");
+
+        clean(root, &[]);
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+        assert_eq!(log(root, &[]), stdout);
+
+        clean(root, &["--including-history"]);
+        assert!(log(root, &[]).is_empty());
+    }
+
+    #[test]
+    fn reset_removes_patched_crates_and_their_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace_with_patch(root);
+        build(root, &[]);
+        assert!(root.join("target/cargo-stitch/crate-a").is_dir());
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "reset", "--all", "--yes"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Removed")
+                    && line.ends_with("target/cargo-stitch/crate-a")),
+            "got:\n{stderr}"
+        );
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Reset") && line.ends_with(" 1 patched crate")),
+            "got:\n{stderr}"
+        );
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+
+        let stderr = build(root, &[]);
+        assert!(stderr.contains("Compiling crate-a"), "got:\n{stderr}");
+        assert!(root.join("target/cargo-stitch/crate-a").is_dir());
+    }
+}
+
+mod diff {
+    use super::*;
+
+    #[test]
+    fn diff_shows_combined_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("002-exclaim.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "patched"
++    "patched!"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "diff"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(output.status.code(), Some(1), "got:\n{stdout}");
+        assert!(
+            stdout.contains("--- a/crate-a/src/lib.rs"),
+            "got:\n{stdout}"
+        );
+        assert!(stdout.contains("\n-    \"hello\"\n"), "got:\n{stdout}");
+        assert!(stdout.contains("\n+    \"patched!\"\n"), "got:\n{stdout}");
+        assert!(!stdout.contains("+    \"patched\"\n"), "got:\n{stdout}");
+        assert!(!root.join("target").exists(), "nothing should be built");
+
+        // A crate without changes, and a stitch that does not apply.
+        fs::write(patch_dir.join("002-exclaim.patch"), "").unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "does not match"
++    "never"
+ }
+"#,
+        )
+        .unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "diff", "--package", "crate-a"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(2), "got:\n{stderr}");
+        assert!(stderr.contains("001-greeting.patch"), "got:\n{stderr}");
+
+        fs::remove_file(patch_dir.join("001-greeting.patch")).unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "diff", "--package", "crate-a"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert!(output.stdout.is_empty());
+    }
+}
+
+mod list {
+    use super::*;
+
+    #[test]
+    fn list_prints_stitches_in_application_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let a = root.join("stitches/default/crate-a");
+        let b = root.join("stitches/default/crate-b");
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(b.join("001-rename.yaml"), "id: rename\n").unwrap();
+        fs::write(a.join("001-first.patch"), "").unwrap();
+        fs::write(a.join("002-second.patch"), "").unwrap();
+        fs::write(
+            a.join("stitch.toml"),
+            "[order]\nstitches = [\"002-second.patch\"]\n",
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "list"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "got:\n{stdout}");
+        assert_eq!(
+            stdout,
+            "crate-a  1  patch    002-second.patch\n\
+             crate-a  2  patch    001-first.patch\n\
+             crate-b  1  sg-rule  001-rename.yaml\n"
+        );
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "list", "--json"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let stitches = json["crate-a"]["stitches"].as_array().unwrap();
+        assert_eq!(stitches.len(), 2);
+        assert_eq!(stitches[0]["type"], "Patch");
+        assert!(
+            stitches[0]["path"]
+                .as_str()
+                .unwrap()
+                .ends_with("002-second.patch")
+        );
+        assert_eq!(json["crate-b"]["stitches"][0]["type"], "SgRule");
+    }
+
+    #[test]
+    fn list_shows_front_matter() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let a = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&a).unwrap();
+        fs::write(
+            a.join("001-first.patch"),
+            "---\n\
+             id: first\n\
+             description: Make the greeting louder\n\
+             issue_url: https://example.com/issues/3\n\
+             ---\n",
+        )
+        .unwrap();
+        fs::write(a.join("002-second.patch"), "").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "list", "--verbose"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "got:\n{stdout}");
+        assert_eq!(
+            stdout,
+            "crate-a  1  patch    001-first.patch\n\
+             \x20                    Make the greeting louder\n\
+             crate-a  2  patch    002-second.patch\n"
+        );
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "list", "--json"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let stitches = json["crate-a"]["stitches"].as_array().unwrap();
+        assert_eq!(stitches[0]["meta"]["id"], "first");
+        assert_eq!(
+            stitches[0]["meta"]["issue_url"],
+            "https://example.com/issues/3"
+        );
+        assert!(stitches[0]["meta"].get("author").is_none());
+        assert!(stitches[1].get("meta").is_none());
+    }
+
+    #[test]
+    fn list_checksums_feed_the_checksums_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+
+        let a = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&a).unwrap();
+        fs::write(a.join("001-greeting.sed"), "s/hello/patched/\n").unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "list", "--checksums"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "got:\n{stdout}");
+        assert!(stdout.starts_with("# crate-a\n"), "got:\n{stdout}");
+        assert!(stdout.ends_with("  001-greeting.sed\n"), "got:\n{stdout}");
+        fs::write(a.join(".checksums"), stdout.as_bytes()).unwrap();
+
+        let build = || {
+            Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .current_dir(root)
+                .output()
+                .unwrap()
+        };
+        let output = build();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+        assert!(!stderr.contains("no checksum"), "got:\n{stderr}");
+
+        fs::write(a.join("001-greeting.sed"), "s/hello/tampered/\n").unwrap();
+        let output = build();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains("checksum mismatch for ") && stderr.contains("001-greeting.sed"),
+            "got:\n{stderr}"
+        );
+    }
+}
+
+mod json_diagnostics {
+    use super::*;
+
+    const FIX_PATCH: &str = r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#;
+
+    /// The JSON events among the lines of `stderr`, which also has cargo's own output.
+    fn events(stderr: &str) -> Vec<serde_json::Value> {
+        stderr
+            .lines()
+            .filter(|line| line.starts_with('{'))
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn json_flag_reports_applied_stitches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(stitch_dir.join("001-fix.patch"), FIX_PATCH).unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "--json", "build"])
+            .current_dir(root)
+            .env_remove("CARGO_STITCH_JSON")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+        assert!(
+            !stderr.contains("Patching"),
+            "status lines should be JSON, got:\n{stderr}"
+        );
+
+        let events = events(&stderr);
+        assert!(
+            events.contains(&serde_json::json!({
+                "type": "stitch-applied",
+                "package": "crate-a",
+                "stitch": "001-fix.patch",
+                "kind": "patch",
+            })),
+            "expected a stitch-applied event, got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn json_env_reports_failing_stitch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        create_workspace(root);
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(stitch_dir.join("001-fix.patch"), FIX_PATCH).unwrap();
+        fs::write(
+            stitch_dir.join("002-broken.patch"),
+            FIX_PATCH.replace("hello", "goodbye"),
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .env("CARGO_STITCH_JSON", "1")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "build should fail:\n{stderr}");
+
+        let events = events(&stderr);
+        let error = events
+            .iter()
+            .find(|e| e["type"] == "error" && e["package"] == "crate-a")
+            .unwrap_or_else(|| panic!("expected an error event for crate-a, got:\n{stderr}"));
+        assert_eq!(error["stitch"], "002-broken.patch");
+        assert!(
+            error["message"]
+                .as_str()
+                .unwrap()
+                .contains("002-broken.patch")
+        );
+        assert!(
+            !stderr.contains("cargo-stitch: "),
+            "errors should be JSON, got:\n{stderr}"
+        );
+    }
+}
+
+mod init {
+    use super::*;
+
+    fn init(root: &Path, args: &[&str]) -> std::process::Output {
+        Command::new(cargo_stitch_bin())
+            .args(["stitch", "init"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn init_scaffolds_a_stitch_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let output = init(root, &["crate-a"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(stderr.contains("Created stitches/default/crate-a/README.md"));
+
+        let dir = root.join("stitches/default/crate-a");
+        let readme = fs::read_to_string(dir.join("README.md")).unwrap();
+        assert!(
+            readme.contains("# Stitches for `crate-a`"),
+            "got:\n{readme}"
+        );
+        assert!(dir.join("001-example.patch.sample").is_file());
+
+        // The scaffold neither breaks the build nor upsets `lint`.
+        for command in ["build", "lint"] {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", command])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "`cargo stitch {command}` failed:\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        // An existing directory is left alone without `--force`.
+        fs::write(dir.join("README.md"), "mine\n").unwrap();
+        let output = init(root, &["crate-a"]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--force"));
+        assert_eq!(fs::read_to_string(dir.join("README.md")).unwrap(), "mine\n");
+
+        let output = init(root, &["crate-a", "--force"]);
+        assert!(output.status.success());
+        assert_ne!(fs::read_to_string(dir.join("README.md")).unwrap(), "mine\n");
+    }
+}
+
+mod fmt {
+    use super::*;
+
+    fn fmt(root: &Path, args: &[&str]) -> std::process::Output {
+        Command::new(cargo_stitch_bin())
+            .args(["stitch", "fmt"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn fmt_check_then_format_in_place() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&dir).unwrap();
+        let patch = dir.join("001-fix.patch");
+        fs::write(
+            &patch,
+            "--- a/src/lib.rs\t2024-03-01 12:00:00.000000000 +0100\r\n\
+             +++ b/src/lib.rs\t2024-03-01 12:05:00.000000000 +0100\r\n\
+             @@ -1,3 +1,3 @@\r\n pub fn greeting() -> &'static str {  \r\n\
+             -    \"hello\"\r\n+    \"patched\"\r\n }\r\n\r\n",
+        )
+        .unwrap();
+
+        let output = fmt(root, &["crate-a", "--check"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr.contains("stitches/default/crate-a/001-fix.patch: is not formatted"),
+            "got:\n{stderr}"
+        );
+
+        let output = fmt(root, &["crate-a"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr.contains("Formatted stitches/default/crate-a/001-fix.patch"),
+            "got:\n{stderr}"
+        );
+        assert_eq!(
+            fs::read_to_string(&patch).unwrap(),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn greeting() -> &'static str {\n-    \"hello\"\n+    \"patched\"\n }\n"
+        );
+        assert!(fmt(root, &["crate-a", "--check"]).status.success());
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "cargo stitch build failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+mod new {
+    use super::*;
+
+    fn new(root: &Path, args: &[&str]) -> std::process::Output {
+        Command::new(cargo_stitch_bin())
+            .args(["stitch", "new"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn new_creates_the_next_numbered_stitch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("002-existing.sed"), "s/hello/patched/\n").unwrap();
+
+        let output = new(root, &["crate-a", "Fix Overflow", "--with-metadata"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr.contains("Created stitches/default/crate-a/003-fix-overflow.patch"),
+            "got:\n{stderr}"
+        );
+        let patch = fs::read_to_string(dir.join("003-fix-overflow.patch")).unwrap();
+        assert!(
+            patch.starts_with("---\nid: fix-overflow\n"),
+            "got:\n{patch}"
+        );
+
+        let output = new(root, &["crate-a", "rename", "--type", "sg"]);
+        assert!(output.status.success());
+        let rule = fs::read_to_string(dir.join("004-rename.yaml")).unwrap();
+        assert!(rule.contains("\nid: rename\n"), "got:\n{rule}");
+
+        // The new patch changes nothing until it is filled in, and does not upset `lint`.
+        fs::remove_file(dir.join("004-rename.yaml")).unwrap();
+        for command in ["build", "lint"] {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", command])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "`cargo stitch {command}` failed:\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+}
+
+mod capture {
+    use super::*;
+
+    #[test]
+    fn capture_turns_edits_into_the_next_patch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = stitch(root, &["capture", "crate-a"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains("run `cargo stitch build` first"),
+            "got:\n{stderr}"
+        );
+
+        assert!(stitch(root, &["build"]).status.success());
+
+        // Edit the patched copy by hand, and capture only that edit.
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        let lib = fs::read_to_string(&patched_lib).unwrap();
+        fs::write(&patched_lib, lib.replace("\"patched\"", "\"captured\"")).unwrap();
+
+        let output = stitch(root, &["capture", "crate-a", "--name", "capture"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr.contains("Created stitches/default/crate-a/002-capture.patch"),
+            "got:\n{stderr}"
+        );
+
+        let patch = fs::read_to_string(patch_dir.join("002-capture.patch")).unwrap();
+        assert!(patch.contains("-    \"patched\""), "got:\n{patch}");
+        assert!(patch.contains("+    \"captured\""), "got:\n{patch}");
+        assert!(!patch.contains("hello"), "got:\n{patch}");
+
+        // A clean build applies the captured patch on top of the first one.
+        fs::remove_dir_all(root.join("target/cargo-stitch")).unwrap();
+        let output = stitch(root, &["build"]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let lib = fs::read_to_string(&patched_lib).unwrap();
+        assert!(lib.contains("\"captured\""), "got:\n{lib}");
+
+        let output = stitch(root, &["capture", "crate-a"]);
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("no changes to capture"));
+    }
+}
+
+mod export {
+    use super::*;
+
+    #[test]
+    fn export_copies_the_patched_copy() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        let out = root.join("exported");
+        let out = out.to_str().unwrap();
+
+        let output = stitch(root, &["export", "crate-a", out]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains("run `cargo stitch build` first"),
+            "got:\n{stderr}"
+        );
+
+        let output = stitch(root, &["export", "crate-a", out, "--build"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(stderr.contains("Exported crate-a to "), "got:\n{stderr}");
+
+        let lib = fs::read_to_string(root.join("exported/src/lib.rs")).unwrap();
+        assert!(lib.contains("\"patched\""), "got:\n{lib}");
+        assert!(!root.join("exported/.stitch-state.json").exists());
+        assert!(!root.join("exported/.stitch-stamp").exists());
+
+        let output = stitch(root, &["export", "crate-a", out]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains("--overwrite"), "got:\n{stderr}");
+
+        fs::write(root.join("exported/stale.rs"), "").unwrap();
+        let output = stitch(root, &["export", "crate-a", out, "--overwrite"]);
+        assert!(output.status.success());
+        assert!(!root.join("exported/stale.rs").exists());
+    }
+}
+
+mod test_command {
+    use super::*;
+
+    #[test]
+    fn test_runs_every_target_against_the_patched_sources() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        // Each target fails to build or pass until the stitches are applied.
+        let a = root.join("crate-a");
+        fs::write(
+            a.join("src/lib.rs"),
+            r#"pub fn greeting() -> &'static str {
+    "hello"
+}
+
+#[test]
+fn greets() {
+    assert_eq!(greeting(), "patched");
+}
+"#,
+        )
+        .unwrap();
+        for dir in ["tests", "benches", "examples"] {
+            fs::create_dir_all(a.join(dir)).unwrap();
+        }
+        fs::write(
+            a.join("tests/greeting.rs"),
+            r#"#[test]
+fn greets_from_tests() {
+    assert_eq!(crate_a::greeting(), "hello!");
+}
+"#,
+        )
+        .unwrap();
+        fs::write(
+            a.join("benches/bench.rs"),
+            r#"#[test]
+fn greets_from_benches() {
+    assert!(false, "unpatched bench");
+}
+"#,
+        )
+        .unwrap();
+        fs::write(
+            a.join("examples/show.rs"),
+            r#"compile_error!("unpatched example");
+
+fn main() {}
+"#,
+        )
+        .unwrap();
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix-tests.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+--- a/tests/greeting.rs
++++ b/tests/greeting.rs
+@@ -1,4 +1,4 @@
+ #[test]
+ fn greets_from_tests() {
+-    assert_eq!(crate_a::greeting(), "hello!");
++    assert_eq!(crate_a::greeting(), "patched");
+ }
+--- a/benches/bench.rs
++++ b/benches/bench.rs
+@@ -1,4 +1,4 @@
+ #[test]
+ fn greets_from_benches() {
+-    assert!(false, "unpatched bench");
++    assert!(true, "patched bench");
+ }
+--- a/examples/show.rs
++++ b/examples/show.rs
+@@ -1,3 +1,2 @@
+-compile_error!("unpatched example");
+ 
+ fn main() {}
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new("cargo")
+            .args(["test", "--all-targets", "-p", "crate-a"])
+            .env("CARGO_TARGET_DIR", root.join("unstitched-target"))
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "test", "--all-targets", "-p", "crate-a"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "{stdout}\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        for test in ["greets", "greets_from_tests", "greets_from_benches"] {
+            assert!(
+                stdout.contains(&format!("test {test} ... ok")),
+                "got:\n{stdout}"
+            );
+        }
+    }
+}
+
+mod rust_log {
+    use super::*;
+
+    #[test]
+    fn rust_log_traces_the_wrapper() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .env("RUST_LOG", "cargo_stitch=debug")
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        for event in [
+            "read CARGO_PKG_NAME pkg_name=crate-a",
+            "deserialized stitch manifest",
+            "applied stitch stitch=",
+            "rewriting rustc args",
+        ] {
+            assert!(stderr.contains(event), "no `{event}` in:\n{stderr}");
+        }
+
+        // Without `RUST_LOG`, nothing is traced.
+        fs::remove_dir_all(root.join("target")).unwrap();
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .env_remove("RUST_LOG")
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(!stderr.contains("read CARGO_PKG_NAME"), "got:\n{stderr}");
+    }
+}
+
+mod package {
+    use super::*;
+
+    #[test]
+    fn package_flag_only_stitches_the_selected_package() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let a = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&a).unwrap();
+        fs::write(
+            a.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        let b = root.join("stitches/default/crate-b");
+        fs::create_dir_all(&b).unwrap();
+        fs::write(
+            b.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn message() -> String {
+-    format!("{} world", crate_a::greeting())
++    format!("{} there", crate_a::greeting())
+ }
+"#,
+        )
+        .unwrap();
+
+        // crate-b is stitched, but not crate-a, which it depends on.
+        let stderr = build(root, &["-p", "crate-b"]);
+        assert!(
+            stderr.contains("building `crate-a` without its stitches"),
+            "got:\n{stderr}"
+        );
+        assert!(root.join("target/cargo-stitch/crate-b").is_dir());
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+
+        // Cargo compiles crate-a again, stitched, rather than reusing the unstitched build.
+        build(root, &["--package=crate-a"]);
+        assert!(root.join("target/cargo-stitch/crate-a").is_dir());
+    }
+}
+
+mod help {
+    use super::*;
+
+    const TOPICS: &[&str] = &[
+        "apply",
+        "apply-to",
+        "bench",
+        "build",
+        "capture",
+        "check",
+        "check-ordering",
+        "check-tools",
+        "check-unique-ids",
+        "clean",
+        "completions",
+        "diff",
+        "diff-snapshot",
+        "env",
+        "export",
+        "fetch",
+        "fetch-stitches",
+        "fmt",
+        "init",
+        "install",
+        "lint",
+        "list",
+        "log",
+        "new",
+        "publish-stitches",
+        "reorder",
+        "reset",
+        "snapshot",
+        "status",
+        "summarize",
+        "test",
+        "trace",
+        "uninstall",
+        "upgrade",
+        "vendor",
+        "verify",
+        "verify-applied",
+    ];
+
+    /// The workspace the examples in `cargo stitch help` are written against.
+    fn create_example_workspace(root: &Path) {
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("002-exclaim.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "patched"
++    "patched!"
+ }
+"#,
+        )
+        .unwrap();
+    }
+
+    fn help(topic: &str) -> String {
+        let tmp = tempfile::tempdir().unwrap();
+        create_workspace(tmp.path());
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "help", topic])
+            .current_dir(tmp.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "no help for `{topic}`");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// The examples in `help` that show their output: each command with its expected
+    /// output lines.
+    fn examples(help: &str) -> Vec<(String, Vec<String>)> {
+        let mut examples: Vec<(String, Vec<String>)> = Vec::new();
+        let mut in_output = false;
+
+        for line in help.lines().map(str::trim) {
+            if let Some(command) = line.strip_prefix("$ ") {
+                examples.push((command.to_string(), Vec::new()));
+                in_output = true;
+            } else if line.is_empty() {
+                in_output = false;
+            } else if in_output {
+                examples.last_mut().unwrap().1.push(line.to_string());
+            }
+        }
+
+        examples.retain(|(_, output)| !output.is_empty());
+        examples
+    }
+
+    /// Strip ANSI escapes and collapse whitespace.
+    fn normalize(line: &str) -> String {
+        let mut out = String::new();
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            } else {
+                out.push(c);
+            }
+        }
+        out.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Whether `line` matches `pattern`, where `...` in `pattern` matches anything.
+    fn matches(pattern: &str, line: &str) -> bool {
+        let parts: Vec<_> = pattern.split("...").collect();
+        let [first, middle @ .., last] = parts.as_slice() else {
+            return pattern == line;
+        };
+        let Some(mut rest) = line.strip_prefix(first) else {
+            return false;
+        };
+        for part in middle {
+            match rest.find(part) {
+                Some(i) => rest = &rest[i + part.len()..],
+                None => return false,
+            }
+        }
+        rest.ends_with(last)
+    }
+
+    #[test]
+    fn help_examples_match_actual_output() {
+        for topic in TOPICS {
+            let tmp = tempfile::tempdir().unwrap();
+            let root = tmp.path();
+            create_example_workspace(root);
+
+            for (command, expected) in examples(&help(topic)) {
+                let args: Vec<_> = command.split_whitespace().skip(1).collect();
+                let output = Command::new(cargo_stitch_bin())
+                    .args(&args)
+                    .current_dir(root)
+                    .output()
+                    .unwrap();
+
+                let actual: Vec<_> = String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .chain(String::from_utf8_lossy(&output.stderr).lines())
+                    .map(normalize)
+                    .collect();
+
+                // Expected lines must appear in order; `...` lines stand for any output.
+                let mut lines = actual.iter();
+                for pattern in expected.iter().map(|l| normalize(l)) {
+                    if pattern == "..." {
+                        continue;
+                    }
+                    assert!(
+                        lines.any(|line| matches(&pattern, line)),
+                        "`cargo stitch help {topic}`: `{command}` did not print `{pattern}`, got:\n{}",
+                        actual.join("\n")
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn help_without_topic_lists_commands() {
+        let tmp = tempfile::tempdir().unwrap();
+        create_workspace(tmp.path());
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "help"])
+            .current_dir(tmp.path())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success());
+        for topic in TOPICS {
+            assert!(stdout.contains(topic), "got:\n{stdout}");
+        }
+    }
+
+    #[test]
+    fn help_flag_on_stitch_command() {
+        let tmp = tempfile::tempdir().unwrap();
+        create_workspace(tmp.path());
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "trace", "--help"])
+            .current_dir(tmp.path())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success());
+        assert!(
+            stdout.starts_with("cargo stitch trace <crate>"),
+            "got:\n{stdout}"
+        );
+    }
+
+    #[test]
+    fn help_outside_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        for args in [&["help"][..], &["help", "build"][..]] {
+            let output = stitch(tmp.path(), args);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "{args:?} failed:\n{stderr}");
+            assert!(!output.stdout.is_empty());
+        }
+    }
+}
+
+mod workspace_root {
+    use super::*;
+
+    /// The stitch files cargo-stitch finds when run from `dir`, as `list --json` shows
+    /// them, with `cargo metadata` unavailable so that only the `Cargo.toml` traversal can
+    /// find the root.
+    fn stitches_seen_from(dir: &Path) -> Vec<String> {
+        list_stitches(
+            Command::new(cargo_stitch_bin()).env("CARGO", "/nonexistent/cargo"),
+            dir,
+        )
+    }
+
+    /// The paths of the stitches `stitch list --json` shows when `cmd` runs it from `dir`.
+    fn list_stitches(cmd: &mut Command, dir: &Path) -> Vec<String> {
+        let output = cmd
+            .args(["stitch", "list", "--json"])
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json.as_object()
+            .unwrap()
+            .values()
+            .flat_map(|set| set["stitches"].as_array().unwrap())
+            .map(|stitch| stitch["path"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    /// Add a stitch for `krate` to the workspace at `root`, returning its path.
+    fn add_stitch(root: &Path, krate: &str) -> String {
+        let dir = root.join("stitches/default").join(krate);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("001-fix.patch");
+        fs::write(&path, "# Changes nothing yet.\n").unwrap();
+        path.display().to_string()
+    }
+
+    fn write_package(dir: &Path, name: &str, extra: &str) {
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n{extra}"
+            ),
+        )
+        .unwrap();
+        fs::write(dir.join("src/lib.rs"), "").unwrap();
+    }
+
+    #[test]
+    fn single_member_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        write_package(root, "solo", "\n[workspace]\n");
+        let stitch = add_stitch(root, "solo");
+
+        assert_eq!(stitches_seen_from(&root.join("src")), [stitch]);
+    }
+
+    #[test]
+    fn multi_member_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+        let stitch = add_stitch(root, "crate-a");
+
+        assert_eq!(stitches_seen_from(&root.join("crate-b")), [stitch]);
+    }
+
+    #[test]
+    fn virtual_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"member\"]\nresolver = \"2\"\n",
+        )
+        .unwrap();
+        write_package(&root.join("member"), "member", "");
+        let stitch = add_stitch(root, "member");
+
+        assert_eq!(stitches_seen_from(root), [stitch.clone()]);
+        assert_eq!(stitches_seen_from(&root.join("member")), [stitch]);
+    }
+
+    #[test]
+    fn deeply_nested_member() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/group/deep\"]\nresolver = \"2\"\n",
+        )
+        .unwrap();
+        let member = root.join("crates/group/deep");
+        write_package(&member, "deep", "");
+        let stitch = add_stitch(root, "deep");
+
+        assert_eq!(stitches_seen_from(&member.join("src")), [stitch]);
+    }
+
+    #[test]
+    fn nested_workspaces() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"outer-member\"]\nexclude = [\"inner\"]\nresolver = \"2\"\n",
+        )
+        .unwrap();
+        write_package(&root.join("outer-member"), "outer-member", "");
+        let inner = root.join("inner");
+        fs::create_dir_all(&inner).unwrap();
+        fs::write(
+            inner.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"inner-member\"]\nresolver = \"2\"\n",
+        )
+        .unwrap();
+        write_package(&inner.join("inner-member"), "inner-member", "");
+        let outer_stitch = add_stitch(root, "outer-member");
+        let inner_stitch = add_stitch(&inner, "inner-member");
+
+        // The nearest workspace wins, as with cargo.
+        assert_eq!(
+            stitches_seen_from(&inner.join("inner-member/src")),
+            [inner_stitch]
+        );
+        assert_eq!(
+            stitches_seen_from(&root.join("outer-member")),
+            [outer_stitch]
+        );
+    }
+
+    #[test]
+    fn cargo_workspace_root_env() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+        let stitch = add_stitch(root, "crate-a");
+        let crate_b_stitch = add_stitch(&root.join("crate-b"), "crate-b");
+
+        let seen_with = |value: &Path| {
+            list_stitches(
+                Command::new(cargo_stitch_bin()).env("CARGO_WORKSPACE_ROOT", value),
+                &root.join("crate-b"),
+            )
+        };
+
+        // A workspace holding the current directory is used as it is, even one cargo
+        // would not pick; one that does not hold it is ignored.
+        assert_eq!(seen_with(&root.join("crate-b")), [crate_b_stitch]);
+        let elsewhere = tempfile::tempdir().unwrap();
+        fs::write(elsewhere.path().join("Cargo.toml"), "[workspace]\n").unwrap();
+        assert_eq!(seen_with(elsewhere.path()), [stitch]);
+    }
+
+    #[test]
+    fn package_without_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("standalone");
+        write_package(&root, "standalone", "");
+        let stitch = add_stitch(&root, "standalone");
+
+        assert_eq!(stitches_seen_from(&root.join("src")), [stitch]);
+    }
+}
+
+mod uninstall {
+    use super::*;
+    use std::io::Write;
+    use std::process::Stdio;
+
+    fn build_with_patch(root: &Path) {
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(root.join("target/cargo-stitch/crate-a").is_dir());
+    }
+
+    #[test]
+    fn uninstall_removes_stitches_and_patched_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        build_with_patch(root);
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "uninstall", "crate-a", "--yes"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Removed") && line.ends_with("stitches/default/crate-a")),
+            "got:\n{stderr}"
+        );
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Removed")
+                    && line.ends_with("target/cargo-stitch/crate-a")),
+            "got:\n{stderr}"
+        );
+        assert!(!root.join("stitches/default/crate-a").exists());
+        assert!(!root.join("target/cargo-stitch/crate-a").exists());
+    }
+
+    #[test]
+    fn uninstall_keep_patched_and_confirmation() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        build_with_patch(root);
+
+        let uninstall = |answer: &str| {
+            let mut child = Command::new(cargo_stitch_bin())
+                .args(["stitch", "uninstall", "crate-a", "--keep-patched"])
+                .current_dir(root)
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(answer.as_bytes())
+                .unwrap();
+            child.wait_with_output().unwrap()
+        };
+
+        let output = uninstall("n\n");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "got:\n{stderr}");
+        assert!(stderr.contains("[y/N]"), "got:\n{stderr}");
+        assert!(root.join("stitches/default/crate-a").is_dir());
+
+        let output = uninstall("y\n");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(stderr.contains("for inspection"), "got:\n{stderr}");
+        assert!(!root.join("stitches/default/crate-a").exists());
+        assert!(root.join("target/cargo-stitch/crate-a").is_dir());
+    }
+}
+
+mod fetch {
+    use super::*;
+
+    /// A `$CARGO_HOME` whose registry cache holds `demo` v0.1.0, either unpacked or as a
+    /// `.crate` file.
+    fn create_cargo_home(cargo_home: &Path, unpacked: bool) {
+        let source = cargo_home.join("staging/demo-0.1.0");
+        fs::create_dir_all(source.join("src")).unwrap();
+        fs::write(source.join("src/lib.rs"), "pub fn demo() {}\n").unwrap();
+
+        let index = "index.crates.io-1949cf8c6b5b557f";
+        if unpacked {
+            let src = cargo_home.join("registry/src").join(index);
+            fs::create_dir_all(&src).unwrap();
+            fs::rename(&source, src.join("demo-0.1.0")).unwrap();
+        } else {
+            let cache = cargo_home.join("registry/cache").join(index);
+            fs::create_dir_all(&cache).unwrap();
+            let status = Command::new("tar")
+                .arg("-czf")
+                .arg(cache.join("demo-0.1.0.crate"))
+                .arg("-C")
+                .arg(cargo_home.join("staging"))
+                .arg("demo-0.1.0")
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        fs::remove_dir_all(cargo_home.join("staging")).unwrap();
+    }
+
+    fn fetch(root: &Path, cargo_home: &Path) -> (String, String) {
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "fetch", "demo", "0.1.0"])
+            .env("CARGO_HOME", cargo_home)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(output.status.success(), "got:\n{stderr}");
+        (stdout, stderr)
+    }
+
+    #[test]
+    fn fetch_copies_unpacked_registry_source() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        let cargo_home = tmp.path().join("cargo-home");
+        fs::create_dir_all(&root).unwrap();
+        create_workspace(&root);
+        create_cargo_home(&cargo_home, true);
+
+        let (stdout, stderr) = fetch(&root, &cargo_home);
+        let dest = root.join("target/cargo-stitch/.fetch/demo-0.1.0");
+        assert_eq!(stdout.trim(), dest.to_str().unwrap());
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Copied") && line.contains("demo v0.1.0 from")),
+            "got:\n{stderr}"
+        );
+        assert_eq!(
+            fs::read_to_string(dest.join("src/lib.rs")).unwrap(),
+            "pub fn demo() {}\n"
+        );
+
+        // A second fetch reuses the unpacked source.
+        let (stdout, stderr) = fetch(&root, &cargo_home);
+        assert_eq!(stdout.trim(), dest.to_str().unwrap());
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Fresh") && line.ends_with("demo v0.1.0")),
+            "got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn fetch_extracts_cached_crate_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        let cargo_home = tmp.path().join("cargo-home");
+        fs::create_dir_all(&root).unwrap();
+        create_workspace(&root);
+        create_cargo_home(&cargo_home, false);
+
+        let (stdout, stderr) = fetch(&root, &cargo_home);
+        let dest = root.join("target/cargo-stitch/.fetch/demo-0.1.0");
+        assert_eq!(stdout.trim(), dest.to_str().unwrap());
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Extracted") && line.contains("demo v0.1.0 from")),
+            "got:\n{stderr}"
+        );
+        assert!(dest.join("src/lib.rs").is_file());
+        assert_eq!(
+            fs::read_dir(root.join("target/cargo-stitch/.fetch"))
+                .unwrap()
+                .count(),
+            1,
+            "scratch directory left behind"
+        );
+    }
+}
+
+mod vendor {
+    use super::*;
+
+    /// Add a dependency of `crate-b` on `demo`, taken from a directory source at
+    /// `<root>/<source_dir>`, along with a stitch for `demo` that `crate-b` needs to
+    /// compile.
+    fn create_vendored_workspace(root: &Path, source_dir: &str) {
+        create_workspace(root);
+
+        let demo = root.join(source_dir).join("demo");
+        fs::create_dir_all(demo.join("src")).unwrap();
+        fs::write(
+            demo.join("Cargo.toml"),
+            r#"[package]
+name = "demo"
+version = "0.1.0"
+edition = "2021"
+"#,
+        )
+        .unwrap();
+        fs::write(demo.join("src/lib.rs"), "pub fn greeting() {}\n").unwrap();
+        fs::write(
+            demo.join(".cargo-checksum.json"),
+            format!(r#"{{"files":{{}},"package":"{}"}}"#, "0".repeat(64)),
+        )
+        .unwrap();
+
+        fs::create_dir_all(root.join(".cargo")).unwrap();
+        fs::write(
+            root.join(".cargo/config.toml"),
+            format!(
+                r#"[source.crates-io]
+replace-with = "local"
+
+[source.local]
+directory = "{source_dir}"
+"#
+            ),
+        )
+        .unwrap();
+
+        let b = root.join("crate-b");
+        let manifest = fs::read_to_string(b.join("Cargo.toml")).unwrap();
+        fs::write(
+            b.join("Cargo.toml"),
+            format!("{manifest}demo = \"0.1.0\"\n"),
+        )
+        .unwrap();
+        let lib = fs::read_to_string(b.join("src/lib.rs")).unwrap();
+        fs::write(
+            b.join("src/lib.rs"),
+            format!("{lib}\npub fn demo() {{\n    demo::stitched()\n}}\n"),
+        )
+        .unwrap();
+
+        let stitches = root.join("stitches/default/demo");
+        fs::create_dir_all(&stitches).unwrap();
+        fs::write(
+            stitches.join("001-stitched.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1 +1,2 @@
+ pub fn greeting() {}
++pub fn stitched() {}
+"#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn build_stitches_a_copy_of_a_vendored_crate() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_vendored_workspace(root, "vendor");
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build", "--offline"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        assert!(
+            fs::read_to_string(root.join("target/cargo-stitch/demo/src/lib.rs"))
+                .unwrap()
+                .contains("pub fn stitched()")
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("vendor/demo/src/lib.rs")).unwrap(),
+            "pub fn greeting() {}\n"
+        );
+    }
+
+    #[test]
+    fn edited_stitch_is_applied_to_a_vendored_crate_by_the_next_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_vendored_workspace(root, "vendor");
+        build(root, &["--offline"]);
+
+        // Cargo does not rebuild the vendored crate by itself: its sources did not change.
+        fs::write(
+            root.join("stitches/default/demo/001-stitched.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1 +1,3 @@
+ pub fn greeting() {}
++pub fn stitched() {}
++pub fn restitched() {}
+"#,
+        )
+        .unwrap();
+        let stderr = build(root, &["--offline"]);
+        assert!(
+            stderr.contains("Cleaning demo@0.1.0, whose stitches changed"),
+            "got:\n{stderr}"
+        );
+        assert!(
+            fs::read_to_string(root.join("target/cargo-stitch/demo/src/lib.rs"))
+                .unwrap()
+                .contains("pub fn restitched()")
+        );
+    }
+
+    #[test]
+    fn vendor_stitches_vendored_crates_in_place() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_vendored_workspace(root, "local");
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "vendor", "--respect-source-config"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "got:\n{stderr}");
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("Stitched") && line.ends_with("vendor/demo")),
+            "got:\n{stderr}"
+        );
+        assert!(
+            fs::read_to_string(root.join("vendor/demo/src/lib.rs"))
+                .unwrap()
+                .contains("pub fn stitched()")
+        );
+
+        // Switch to the vendored sources: cargo must accept the updated checksums, and
+        // the stitches must not be applied a second time.
+        fs::write(
+            root.join(".cargo/config.toml"),
+            r#"[source.crates-io]
+replace-with = "vendored-sources"
+
+[source.vendored-sources]
+directory = "vendor"
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build", "--offline"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(!root.join("target/cargo-stitch/demo").exists());
+    }
+
+    #[test]
+    fn no_copy_stitches_a_vendored_crate_in_place() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_vendored_workspace(root, "vendor");
+        fs::write(
+            root.join("stitches/default/demo/stitch.toml"),
+            "no_copy = true\n",
+        )
+        .unwrap();
+
+        let build = || {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build", "--offline"])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            assert!(output.status.success(), "got:\n{stderr}");
+            stderr
+        };
+
+        let stderr = build();
+        assert!(
+            stderr.contains("in place (`no_copy = true`): its sources are modified"),
+            "got:\n{stderr}"
+        );
+        assert!(
+            fs::read_to_string(root.join("vendor/demo/src/lib.rs"))
+                .unwrap()
+                .contains("pub fn stitched()")
+        );
+        assert!(!root.join("target/cargo-stitch/demo").exists());
+
+        // Cargo accepts the stitched sources, and they are not stitched a second time.
+        build();
+        assert_eq!(
+            fs::read_to_string(root.join("vendor/demo/src/lib.rs"))
+                .unwrap()
+                .matches("pub fn stitched()")
+                .count(),
+            1
+        );
+    }
+}
+
+mod snapshot {
+    use super::*;
+
+    fn snapshot(root: &Path, args: &[&str]) -> (bool, String) {
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "snapshot"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        (
+            output.status.success(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    }
+
+    fn has_status(stderr: &str, status: &str, message: &str) -> bool {
+        stderr
+            .lines()
+            .any(|line| line.contains(status) && line.ends_with(message))
+    }
+
+    #[test]
+    fn snapshot_save_check_and_update() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        let patch = |replacement: &str| {
+            fs::write(
+                patch_dir.join("001-fix.patch"),
+                format!(
+                    r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {{
+-    "hello"
++    "{replacement}"
+ }}
+"#
+                ),
+            )
+            .unwrap();
+        };
+        patch("patched");
+
+        let (ok, stderr) = snapshot(root, &["--check", "crate-a"]);
+        assert!(!ok, "got:\n{stderr}");
+        assert!(stderr.contains("no snapshot"), "got:\n{stderr}");
+
+        let (ok, stderr) = snapshot(root, &["crate-a"]);
+        assert!(ok, "got:\n{stderr}");
+        assert!(
+            has_status(
+                &stderr,
+                "Saved",
+                "crate-a: snapshot in stitches/default/crate-a/.snapshots"
+            ),
+            "got:\n{stderr}"
+        );
+        let saved = patch_dir.join(".snapshots/src/lib.rs");
+        assert!(fs::read_to_string(&saved).unwrap().contains("\"patched\""));
+        assert!(patch_dir.join(".snapshots/Cargo.toml").is_file());
+
+        let (ok, stderr) = snapshot(root, &["--check", "crate-a"]);
+        assert!(ok, "got:\n{stderr}");
+        assert!(stderr.contains("Fresh"), "got:\n{stderr}");
+
+        // A stitch that changes its output fails the check, until the snapshot is updated.
+        patch("changed");
+        let (ok, stderr) = snapshot(root, &["--check", "crate-a"]);
+        assert!(!ok, "got:\n{stderr}");
+        assert!(
+            has_status(&stderr, "Differs", "crate-a: src/lib.rs"),
+            "got:\n{stderr}"
+        );
+        assert!(fs::read_to_string(&saved).unwrap().contains("\"patched\""));
+
+        let (ok, stderr) = snapshot(root, &["crate-a"]);
+        assert!(ok, "got:\n{stderr}");
+        assert!(stderr.contains("(1 file changed)"), "got:\n{stderr}");
+        assert!(fs::read_to_string(&saved).unwrap().contains("\"changed\""));
+
+        // The snapshot is not mistaken for stitches.
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "lint"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    fn snapshot_patched_crates_and_diff() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        let build_with = |replacement: &str| {
+            fs::write(
+                patch_dir.join("001-fix.patch"),
+                format!(
+                    r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {{
+-    "hello"
++    "{replacement}"
+ }}
+"#
+                ),
+            )
+            .unwrap();
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "build"])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "got:\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        };
+        let diff_snapshot = |label: &str| {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "diff-snapshot", label])
+                .current_dir(root)
+                .output()
+                .unwrap();
+            (
+                output.status.success(),
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            )
+        };
+
+        let (ok, stderr) = snapshot(root, &[]);
+        assert!(!ok, "got:\n{stderr}");
+        assert!(stderr.contains("no patched crates"), "got:\n{stderr}");
+
+        build_with("patched");
+        let (ok, stderr) = snapshot(root, &["--name", "before"]);
+        assert!(ok, "got:\n{stderr}");
+        assert!(
+            has_status(
+                &stderr,
+                "Saved",
+                "1 patched crate in target/cargo-stitch-snapshots/before"
+            ),
+            "got:\n{stderr}"
+        );
+        let saved = root.join("target/cargo-stitch-snapshots/before/crate-a/src/lib.rs");
+        assert!(fs::read_to_string(saved).unwrap().contains("\"patched\""));
+
+        let (ok, stderr) = snapshot(root, &["--name", "before"]);
+        assert!(!ok, "got:\n{stderr}");
+        assert!(stderr.contains("already exists"), "got:\n{stderr}");
+
+        let (ok, _, stderr) = diff_snapshot("before");
+        assert!(ok, "got:\n{stderr}");
+        assert!(stderr.contains("Fresh"), "got:\n{stderr}");
+
+        // Dotfiles other than cargo-stitch's own are compared too.
+        let gitignore = root.join("target/cargo-stitch/crate-a/.gitignore");
+        fs::write(&gitignore, "/target\n").unwrap();
+        let (ok, stdout, stderr) = diff_snapshot("before");
+        assert!(!ok, "got:\n{stderr}");
+        assert!(stdout.contains(".gitignore"), "got:\n{stdout}");
+        fs::remove_file(gitignore).unwrap();
+
+        build_with("changed");
+        let (ok, stdout, stderr) = diff_snapshot("before");
+        assert!(!ok, "got:\n{stderr}");
+        assert!(stdout.contains("-    \"patched\""), "got:\n{stdout}");
+        assert!(stdout.contains("+    \"changed\""), "got:\n{stdout}");
+        assert!(!stdout.contains(".stitch-state.json"), "got:\n{stdout}");
+
+        let (ok, _, stderr) = diff_snapshot("missing");
+        assert!(!ok);
+        assert!(stderr.contains("no snapshot"), "got:\n{stderr}");
+    }
+}
+
+mod env {
+    use super::*;
+
+    /// The variables `cargo stitch env` prints for the workspace at `root`, with `args`
+    /// before the command.
+    fn env_vars(root: &Path, args: &[&str]) -> Vec<(String, String)> {
+        let output = Command::new(cargo_stitch_bin())
+            .arg("stitch")
+            .args(args)
+            .arg("env")
+            .current_dir(root)
+            .env("CARGO_HOME", root.join("cargo-home"))
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (key, value) = line.split_once('=').unwrap();
+                (key.to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn external_crates_use_rustc_wrapper() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let stitch_dir = root.join("stitches/default/itoa-1.0.11");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(stitch_dir.join("001-fix.patch"), "").unwrap();
+
+        let registry_src = root.join("cargo-home/registry/src");
+        let expected = (
+            "__CARGO_STITCH_REGISTRY_SRC".to_string(),
+            registry_src.to_str().unwrap().to_string(),
+        );
+
+        let vars = env_vars(root, &[]);
+        assert_eq!(vars[0].0, "RUSTC_WORKSPACE_WRAPPER");
+        assert!(!vars.contains(&expected));
+
+        let vars = env_vars(root, &["--external"]);
+        assert_eq!(vars[0].0, "RUSTC_WRAPPER");
+        assert!(vars.contains(&expected), "got: {vars:?}");
+
+        fs::write(stitch_dir.join("stitch.toml"), "external = true\n").unwrap();
+        let vars = env_vars(root, &[]);
+        assert_eq!(vars[0].0, "RUSTC_WRAPPER");
+        assert!(vars.contains(&expected), "got: {vars:?}");
+    }
+
+    #[test]
+    fn env_prints_the_variables_of_a_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-fix.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let env = |args: &[&str]| {
+            let output = Command::new(cargo_stitch_bin())
+                .args(["stitch", "env"])
+                .args(args)
+                .current_dir(root)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "got:\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        let stdout = env(&["--keep-patched"]);
+        let vars: Vec<(&str, &str)> = stdout
+            .lines()
+            .map(|line| line.split_once('=').unwrap())
+            .collect();
+        let keys: Vec<_> = vars.iter().map(|(key, _)| *key).collect();
+        assert_eq!(
+            keys,
+            [
+                "RUSTC_WORKSPACE_WRAPPER",
+                "__CARGO_STITCH_WRAP",
+                "__CARGO_STITCH_WORKSPACE_ROOT",
+                "__CARGO_STITCH_KEEP_PATCHED",
+                "__CARGO_STITCH_MANIFEST",
+            ]
+        );
+        let manifest = Path::new(vars[4].1);
+        assert!(manifest.is_file(), "got:\n{stdout}");
+
+        // The variables are enough to build with the stitches, using plain cargo.
+        let output = Command::new("cargo")
+            .arg("build")
+            .envs(vars.iter().copied())
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(root.join("target/cargo-stitch/crate-a").is_dir());
+
+        let stdout = env(&["--export"]);
+        assert!(
+            stdout.lines().all(|line| line.starts_with("export ")),
+            "got:\n{stdout}"
+        );
+        assert!(stdout.contains("export __CARGO_STITCH_WRAP=1\n"));
+    }
+
+    #[test]
+    fn large_manifests_are_passed_by_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        // More stitches than fit in one environment variable on Linux (128 KiB).
+        let long_name = "a-rather-long-name-".repeat(10);
+        let mut patch_dir = root.join("stitches/default/crate-a");
+        for _ in 0..4 {
+            patch_dir.push(&long_name);
+        }
+        fs::create_dir_all(&patch_dir).unwrap();
+        for i in 0..160 {
+            fs::write(
+                patch_dir.join(format!("{i:03}-{long_name}.sed")),
+                "s/\"hello\"/\"patched\"/\n",
+            )
+            .unwrap();
+        }
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let vars = env_vars(root, &[]);
+        let (_, manifest) = vars
+            .iter()
+            .find(|(key, _)| key == "__CARGO_STITCH_MANIFEST")
+            .unwrap();
+        assert!(fs::metadata(manifest).unwrap().len() > 128 * 1024);
+    }
+}
+
+mod verbosity {
+    use super::*;
+
+    const FIX_PATCH: &str = r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#;
+
+    fn build(root: &Path, flag: &str) -> String {
+        let stitch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&stitch_dir).unwrap();
+        fs::write(stitch_dir.join("001-fix.patch"), FIX_PATCH).unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", flag, "build"])
+            .current_dir(root)
+            .env_remove("CARGO_STITCH_VERBOSITY")
+            .env_remove("CARGO_STITCH_COLOR")
+            .env_remove("CARGO_TERM_COLOR")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(output.status.success(), "build failed:\n{stderr}");
+        stderr
+    }
+
+    #[test]
+    fn quiet_hides_status_lines() {
+        let tmp = tempfile::tempdir().unwrap();
+        create_workspace(tmp.path());
+
+        let stderr = build(tmp.path(), "--quiet");
+        assert!(
+            !stderr.contains("Patching"),
+            "expected no status lines, got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn verbose_shows_tool_output() {
+        let tmp = tempfile::tempdir().unwrap();
+        create_workspace(tmp.path());
+
+        let stderr = build(tmp.path(), "-v");
+        assert!(stderr.contains("Patching"), "got:\n{stderr}");
+        assert!(
+            stderr.contains("patching file"),
+            "expected patch's own output, got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn color_only_when_asked_for_off_a_terminal() {
+        let tmp = tempfile::tempdir().unwrap();
+        create_workspace(tmp.path());
+
+        let stderr = build(tmp.path(), "--color=always");
+        assert!(stderr.contains("\x1b[1;33m"), "got:\n{stderr}");
+
+        // stderr is a pipe here, so colour is off by default
+        fs::remove_dir_all(tmp.path().join("target/cargo-stitch")).unwrap();
+        let stderr = build(tmp.path(), "--verbose");
+        assert!(stderr.contains("Patching"), "got:\n{stderr}");
+        assert!(!stderr.contains("\x1b[1;33m"), "got:\n{stderr}");
+    }
+}
+
+mod completions {
+    use super::*;
+
+    #[test]
+    fn completions_print_a_script_for_the_shell() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let output = stitch(root, &["completions", "bash"]);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{output:?}");
+        assert!(stdout.contains("cargo-stitch"), "got:\n{stdout}");
+        assert!(stdout.contains("apply-to"), "got:\n{stdout}");
+        assert!(stdout.contains("--keep-patched"), "got:\n{stdout}");
+    }
+
+    #[test]
+    fn completions_reject_unknown_shells() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let output = stitch(root, &["completions", "tcsh"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains("unsupported shell `tcsh`"),
+            "got:\n{stderr}"
+        );
+    }
+
+    #[test]
+    fn completions_outside_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let output = stitch(tmp.path(), &["completions", "bash"]);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{output:?}");
+        assert!(stdout.contains("cargo-stitch"), "got:\n{stdout}");
+    }
+}
+
+mod upgrade {
+    use super::*;
+
+    /// A workspace with stitches for `demo`, the first of which still applies to v0.2.0
+    /// in the registry cache of `cargo_home` and the second of which does not.
+    fn create_upgrade(root: &Path, cargo_home: &Path) {
+        create_workspace(root);
+
+        let source = cargo_home.join("registry/src/index.crates.io-1949cf8c6b5b557f/demo-0.2.0");
+        fs::create_dir_all(source.join("src")).unwrap();
+        fs::write(
+            source.join("src/lib.rs"),
+            "pub fn demo() -> u32 {\n    2\n}\n\npub fn other() -> u32 {\n    3\n}\n",
+        )
+        .unwrap();
+
+        let stitches = root.join("stitches/default/demo");
+        fs::create_dir_all(&stitches).unwrap();
+        fs::write(
+            stitches.join("001-demo.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n pub fn demo() -> u32 {\n-    2\n+    20\n }\n",
+        )
+        .unwrap();
+        fs::write(
+            stitches.join("002-other.patch"),
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -5,3 +5,3 @@\n pub fn other() -> u32 {\n-    1\n+    10\n }\n",
+        )
+        .unwrap();
+    }
+
+    fn upgrade(root: &Path, cargo_home: &Path, args: &[&str]) -> std::process::Output {
+        Command::new(cargo_stitch_bin())
+            .args(["stitch", "upgrade"])
+            .args(args)
+            .env("CARGO_HOME", cargo_home)
+            .current_dir(root)
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn upgrade_reports_which_stitches_apply() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        let cargo_home = tmp.path().join("cargo-home");
+        fs::create_dir_all(&root).unwrap();
+        create_upgrade(&root, &cargo_home);
+        let before =
+            fs::read_to_string(root.join("stitches/default/demo/002-other.patch")).unwrap();
+
+        let output = upgrade(&root, &cargo_home, &["demo", "0.2.0"]);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!output.status.success());
+        assert!(stdout.contains("ok      001-demo.patch"), "got:\n{stdout}");
+        assert!(stdout.contains("FAILED  002-other.patch"), "got:\n{stdout}");
+        assert!(
+            stdout.contains("2 stitches checked against demo v0.2.0: 1 apply, 1 rejected"),
+            "got:\n{stdout}"
+        );
+
+        assert_eq!(
+            fs::read_to_string(root.join("stitches/default/demo/002-other.patch")).unwrap(),
+            before
+        );
+        assert!(!root.join("target/cargo-stitch/.upgrade").exists());
+    }
+
+    #[test]
+    fn upgrade_apply_keeps_the_stitches_that_apply() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        let cargo_home = tmp.path().join("cargo-home");
+        fs::create_dir_all(&root).unwrap();
+        create_upgrade(&root, &cargo_home);
+
+        let output = upgrade(&root, &cargo_home, &["demo", "0.2.0", "--apply"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains("Applied 1 of 2 stitches to demo v0.2.0"),
+            "got:\n{stderr}"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("target/cargo-stitch/.upgrade/demo-0.2.0/src/lib.rs"))
+                .unwrap(),
+            "pub fn demo() -> u32 {\n    20\n}\n\npub fn other() -> u32 {\n    3\n}\n"
+        );
+    }
+
+    #[test]
+    fn upgrade_without_a_locked_version_asks_for_one() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("ws");
+        let cargo_home = tmp.path().join("cargo-home");
+        fs::create_dir_all(&root).unwrap();
+        create_upgrade(&root, &cargo_home);
+
+        let output = upgrade(&root, &cargo_home, &["demo"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains("`demo` is not a registry dependency of the workspace"),
+            "got:\n{stderr}"
+        );
+    }
+}
+
+mod features {
+    use super::*;
+
+    #[test]
+    fn stitches_needing_features_apply_only_with_them() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+        fs::write(
+            root.join("crate-a/Cargo.toml"),
+            r#"[package]
+name = "crate-a"
+version = "0.1.0"
+edition = "2021"
+
+[features]
+loud = []
+"#,
+        )
+        .unwrap();
+
+        let a = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&a).unwrap();
+        fs::write(
+            a.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            a.join("002-exclaim.patch"),
+            r#"---
+features: [loud]
+---
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "patched"
++    "patched!"
+ }
+"#,
+        )
+        .unwrap();
+        let patched = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+
+        build(root, &["-p", "crate-a"]);
+        assert!(
+            fs::read_to_string(&patched)
+                .unwrap()
+                .contains("\"patched\"")
+        );
+
+        build(root, &["-p", "crate-a", "--features", "loud"]);
+        assert!(
+            fs::read_to_string(&patched)
+                .unwrap()
+                .contains("\"patched!\"")
+        );
+
+        build(root, &["-p", "crate-a"]);
+        assert!(
+            fs::read_to_string(&patched)
+                .unwrap()
+                .contains("\"patched\"")
+        );
+    }
+
+    #[test]
+    fn default_features_count_as_enabled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+        fs::write(
+            root.join("crate-a/Cargo.toml"),
+            r#"[package]
+name = "crate-a"
+version = "0.1.0"
+edition = "2021"
+
+[features]
+default = ["loud"]
+loud = []
+"#,
+        )
+        .unwrap();
+
+        let a = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&a).unwrap();
+        fs::write(
+            a.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            a.join("002-exclaim.patch"),
+            r#"---
+features: [loud]
+---
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "patched"
++    "patched!"
+ }
+"#,
+        )
+        .unwrap();
+        let patched = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+
+        build(root, &["-p", "crate-a"]);
+        let content = fs::read_to_string(&patched).unwrap();
+        assert!(content.contains("\"patched!\""), "got:\n{content}");
+
+        build(root, &["-p", "crate-a", "--no-default-features"]);
+        let content = fs::read_to_string(&patched).unwrap();
+        assert!(content.contains("\"patched\""), "got:\n{content}");
+    }
+}
+
+mod bench {
+    use super::*;
+
+    #[test]
+    fn bench_times_each_stitch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        fs::write(
+            patch_dir.join("002-exclaim.sed"),
+            "s/\"patched\"/\"patched!\"/\n",
+        )
+        .unwrap();
+
+        let output = stitch(root, &["bench", "crate-a", "--iterations", "3"]);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "got:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(
+            stdout.contains("running 2 stitches, 3 iterations"),
+            "got:\n{stdout}"
+        );
+        for name in ["001-greeting.patch", "002-exclaim.sed", "total"] {
+            assert!(
+                stdout
+                    .lines()
+                    .any(|line| line.starts_with(&format!("bench {name} "))
+                        && line.contains(" ms")),
+                "got:\n{stdout}"
+            );
+        }
+
+        // The sources of the crate are left alone.
+        assert!(
+            fs::read_to_string(root.join("crate-a/src/lib.rs"))
+                .unwrap()
+                .contains("\"hello\"")
+        );
+    }
+}
+
+mod parallel_copy {
+    use super::*;
+
+    #[test]
+    fn large_crates_are_copied_in_full() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        // Enough files for the sources to be copied on several threads.
+        for i in 0..300 {
+            let dir = root.join(format!("crate-a/data/{}", i % 10));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("{i}.txt")), i.to_string()).unwrap();
+        }
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "build"])
+            .env("CARGO_STITCH_JOBS", "4")
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+
+        let patched = root.join("target/cargo-stitch/crate-a");
+        assert!(
+            fs::read_to_string(patched.join("src/lib.rs"))
+                .unwrap()
+                .contains("\"patched\"")
+        );
+        for i in 0..300 {
+            assert_eq!(
+                fs::read_to_string(patched.join(format!("data/{}/{i}.txt", i % 10))).unwrap(),
+                i.to_string()
+            );
+        }
+    }
+}
+
+mod orphaned_stitches {
+    use super::*;
+
+    #[test]
+    fn stitches_for_non_members_are_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/old-crate");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(patch_dir.join("001-rename.sed"), "s/old/new/\n").unwrap();
+
+        let output = stitch(root, &["build"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "build failed:\n{stderr}");
+        assert!(
+            stderr.contains(
+                "stitches/default/old-crate has stitches for `old-crate`, \
+                 which is not a member of the workspace"
+            ),
+            "got:\n{stderr}"
+        );
+
+        let output = stitch(root, &["--strict", "build"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains(
+                "stitches for crates that are not members of the workspace: \
+                 stitches/default/old-crate"
+            ),
+            "got:\n{stderr}"
+        );
+    }
+}
+
+mod build_script {
+    use super::*;
+
+    #[test]
+    fn patched_build_script_takes_effect() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let a = root.join("crate-a");
+        fs::write(
+            a.join("build.rs"),
+            r#"fn main() {
+    println!("cargo:rustc-env=GREETING=hello");
+}
+"#,
+        )
+        .unwrap();
+        fs::write(
+            a.join("src/main.rs"),
+            "fn main() {\n    println!(\"{}\", env!(\"GREETING\"));\n}\n",
+        )
+        .unwrap();
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-build-script.patch"),
+            r#"--- a/build.rs
++++ b/build.rs
+@@ -1,3 +1,3 @@
+ fn main() {
+-    println!("cargo:rustc-env=GREETING=hello");
++    println!("cargo:rustc-env=GREETING=patched");
+ }
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new(cargo_stitch_bin())
+            .args(["stitch", "run", "-q", "-p", "crate-a"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "run failed:\n{stderr}");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "patched\n");
+
+        // The build script of the crate is left alone.
+        assert!(
+            fs::read_to_string(a.join("build.rs"))
+                .unwrap()
+                .contains("GREETING=hello")
+        );
+    }
+}
+
+mod interrupted_build {
+    use super::*;
+
+    /// The entries of `target/cargo-stitch/` for the temp dirs of `crate-a`.
+    fn temp_dirs(root: &Path) -> Vec<String> {
+        fs::read_dir(root.join("target/cargo-stitch"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with(".crate-a."))
+            .collect()
+    }
+
+    #[test]
+    fn interrupted_stitching_leaves_the_patched_dir_whole() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        create_workspace(root);
+
+        let patch_dir = root.join("stitches/default/crate-a");
+        fs::create_dir_all(&patch_dir).unwrap();
+        fs::write(
+            patch_dir.join("001-greeting.patch"),
+            r#"--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ pub fn greeting() -> &'static str {
+-    "hello"
++    "patched"
+ }
+"#,
+        )
+        .unwrap();
+        let output = stitch(root, &["build"]);
+        assert!(
+            output.status.success(),
+            "build failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        // Kill the wrapper halfway through stitching, as Ctrl-C would.
+        let script = patch_dir.join("002-interrupt.sh");
+        fs::write(&script, "kill -KILL $PPID\n").unwrap();
+        let output = stitch(root, &["build"]);
+        assert!(!output.status.success());
+
+        let patched_lib = root.join("target/cargo-stitch/crate-a/src/lib.rs");
+        assert!(
+            fs::read_to_string(&patched_lib)
+                .unwrap()
+                .contains("\"patched\"")
+        );
+        assert!(!temp_dirs(root).is_empty());
+
+        // The next build cleans up after the interrupted one.
+        fs::remove_file(&script).unwrap();
+        fs::write(
+            patch_dir.join("002-exclaim.sed"),
+            "s/\"patched\"/\"patched!\"/\n",
+        )
+        .unwrap();
+        let output = stitch(root, &["build"]);
+        assert!(
+            output.status.success(),
+            "build failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(
+            fs::read_to_string(&patched_lib)
+                .unwrap()
+                .contains("\"patched!\"")
         );
+        assert_eq!(temp_dirs(root), Vec::<String>::new());
     }
 }